@@ -0,0 +1,343 @@
+//
+// autocmd.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::path::Path;
+
+use vimscript::regex::{self, Regex};
+use vimscript::VimScriptCtx;
+
+use crate::glob;
+use crate::VimInner;
+
+/// The events autocommands can be defined for
+const EVENTS: &[&str] = &[
+    "BufAdd",
+    "BufDelete",
+    "BufEnter",
+    "BufFilePost",
+    "BufFilePre",
+    "BufHidden",
+    "BufLeave",
+    "BufModifiedSet",
+    "BufNew",
+    "BufNewFile",
+    "BufRead",
+    "BufReadCmd",
+    "BufReadPost",
+    "BufReadPre",
+    "BufUnload",
+    "BufWinEnter",
+    "BufWinLeave",
+    "BufWipeout",
+    "BufWrite",
+    "BufWriteCmd",
+    "BufWritePost",
+    "BufWritePre",
+    "CmdlineChanged",
+    "CmdlineEnter",
+    "CmdlineLeave",
+    "CmdwinEnter",
+    "CmdwinLeave",
+    "ColorScheme",
+    "ColorSchemePre",
+    "CompleteChanged",
+    "CompleteDone",
+    "CompleteDonePre",
+    "CursorHold",
+    "CursorHoldI",
+    "CursorMoved",
+    "CursorMovedI",
+    "DirChanged",
+    "FileType",
+    "FilterReadPost",
+    "FilterReadPre",
+    "FilterWritePost",
+    "FilterWritePre",
+    "FocusGained",
+    "FocusLost",
+    "FuncUndefined",
+    "InsertChange",
+    "InsertCharPre",
+    "InsertEnter",
+    "InsertLeave",
+    "InsertLeavePre",
+    "ModeChanged",
+    "OptionSet",
+    "QuickFixCmdPost",
+    "QuickFixCmdPre",
+    "QuitPre",
+    "ShellCmdPost",
+    "ShellFilterPost",
+    "SourcePost",
+    "SourcePre",
+    "Syntax",
+    "TextChanged",
+    "TextChangedI",
+    "TextChangedP",
+    "TextYankPost",
+    "User",
+    "VimEnter",
+    "VimLeave",
+    "VimLeavePre",
+    "VimResized",
+    "WinClosed",
+    "WinEnter",
+    "WinLeave",
+    "WinNew",
+];
+
+/// The name of an event as it is defined, since event names ignore case
+fn event(name: &str) -> Result<&'static str, String> {
+    EVENTS
+        .iter()
+        .find(|e| e.eq_ignore_ascii_case(name))
+        .copied()
+        .ok_or_else(|| format!("E216: No such group or event: {name}"))
+}
+
+/// Whether `name` matches the pattern of an autocommand. A pattern without a `/` only has to
+/// match the last part of a file name.
+fn matches(pattern: &str, name: &str) -> bool {
+    let name = match pattern.contains('/') {
+        true => name,
+        false => Path::new(name)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(name),
+    };
+    Regex::new(&glob::pattern(pattern), &regex::Options::default())
+        .is_ok_and(|re| re.is_match(name))
+}
+
+struct AutoCmd {
+    group: Option<String>,
+    event: &'static str,
+    pattern: String,
+    /// Removed after it runs once, with `++once`
+    once: bool,
+    /// Triggers autocommands itself, with `++nested`
+    nested: bool,
+    cmd: String,
+}
+
+#[derive(Default)]
+pub struct AutoCmds {
+    cmds: Vec<AutoCmd>,
+    groups: Vec<String>,
+    /// The group of `:augroup`, which autocommands are added to
+    group: Option<String>,
+    /// Set while autocommands run, since they only trigger others with `++nested`
+    running: bool,
+}
+
+impl AutoCmds {
+    /// `:augroup {name}`, `:augroup END` and `:augroup! {name}`
+    pub fn augroup(&mut self, bang: bool, name: &str) -> Result<Option<String>, String> {
+        let name = name.trim();
+        match name {
+            "" if bang => Err("E471: Argument required".into()),
+            "" => Ok(Some(self.groups.join("  "))),
+            _ if bang => {
+                if self.group.as_deref() == Some(name) {
+                    return Err("E936: Cannot delete the current group".into());
+                }
+                let len = self.groups.len();
+                self.groups.retain(|g| g != name);
+                if len == self.groups.len() {
+                    return Err(format!("E367: No such group: \"{name}\""));
+                }
+                self.cmds.retain(|a| a.group.as_deref() != Some(name));
+                Ok(None)
+            }
+            _ if name.eq_ignore_ascii_case("end") => {
+                self.group = None;
+                Ok(None)
+            }
+            _ => {
+                if !self.groups.iter().any(|g| g == name) {
+                    self.groups.push(name.to_string());
+                }
+                self.group = Some(name.to_string());
+                Ok(None)
+            }
+        }
+    }
+
+    /// Splits a group off the start of `args`, when it is the name of one
+    fn split_group<'a>(&self, args: &'a str) -> (Option<String>, &'a str) {
+        let args = args.trim_start();
+        let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        if self.groups.iter().any(|g| g == first) && event(first).is_err() {
+            (Some(first.to_string()), rest.trim_start())
+        } else {
+            (self.group.clone(), args)
+        }
+    }
+
+    /// `:autocmd[!] [group] [{event}] [{pat}] [++once] [++nested] [{cmd}]`, which adds, removes
+    /// or lists autocommands
+    pub fn command(&mut self, bang: bool, args: &str) -> Result<Option<String>, String> {
+        let (group, args) = self.split_group(args);
+        let mut words = args.splitn(3, char::is_whitespace);
+        let events = match words.next().filter(|e| !e.is_empty()) {
+            Some("*") => None,
+            Some(names) => Some(names.split(',').map(event).collect::<Result<Vec<_>, _>>()?),
+            None => None,
+        };
+        let pattern = words.next().filter(|p| !p.is_empty());
+        let mut cmd = words.next().unwrap_or_default().trim_start();
+        let (mut once, mut nested) = (false, false);
+        loop {
+            let (word, rest) = cmd.split_once(char::is_whitespace).unwrap_or((cmd, ""));
+            match word {
+                "++once" => once = true,
+                "++nested" | "nested" => nested = true,
+                _ => break,
+            }
+            cmd = rest.trim_start();
+        }
+        let selected = |a: &AutoCmd| {
+            (group.is_none() || a.group == group)
+                && events.as_ref().is_none_or(|e| e.contains(&a.event))
+                && pattern.is_none_or(|p| a.pattern == p)
+        };
+        if bang {
+            self.cmds.retain(|a| !selected(a));
+        }
+        if cmd.is_empty() {
+            if bang {
+                return Ok(None);
+            }
+            let mut text = String::from("--- Autocommands ---");
+            let mut last = None;
+            for a in self.cmds.iter().filter(|a| selected(a)) {
+                if last != Some((&a.group, a.event)) {
+                    match &a.group {
+                        Some(group) => text.push_str(&format!("\n{group}  {}", a.event)),
+                        None => text.push_str(&format!("\n{}", a.event)),
+                    }
+                    last = Some((&a.group, a.event));
+                }
+                text.push_str(&format!("\n    {:<9} {}", a.pattern, a.cmd));
+            }
+            return Ok(Some(text));
+        }
+        let (Some(events), Some(pattern)) = (events, pattern) else {
+            return Err("E471: Argument required".into());
+        };
+        for pattern in pattern.split(',') {
+            for event in events.iter() {
+                self.cmds.push(AutoCmd {
+                    group: group.clone(),
+                    event,
+                    pattern: pattern.to_string(),
+                    once,
+                    nested,
+                    cmd: cmd.to_string(),
+                });
+            }
+        }
+        Ok(None)
+    }
+
+    /// The commands of the autocommands of `event` that match `name`, of `group` if it is given.
+    /// Autocommands that only run once are removed.
+    fn take(&mut self, group: Option<&str>, event: &str, name: &str) -> Vec<String> {
+        let nested = self.running;
+        let mut cmds = vec![];
+        self.cmds.retain(|a| {
+            let run = a.event == event
+                && group.is_none_or(|g| a.group.as_deref() == Some(g))
+                && (!nested || a.nested)
+                && matches(&a.pattern, name);
+            if run {
+                cmds.push(a.cmd.clone());
+            }
+            !(run && a.once)
+        });
+        cmds
+    }
+}
+
+/// Runs the autocommands of `event` whose pattern matches `name`, which is a file name, or
+/// something else for some events, like the command for `QuickFixCmdPre`
+pub fn apply(v: &mut VimInner, ctx: &mut VimScriptCtx<VimInner>, event: &str, name: &str) {
+    run(v, ctx, None, event, name);
+}
+
+/// Runs the autocommands, and returns whether there were any
+fn run(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    group: Option<&str>,
+    event: &str,
+    name: &str,
+) -> bool {
+    let cmds = v.autocmds.take(group, event, name);
+    let running = std::mem::replace(&mut v.autocmds.running, true);
+    for cmd in cmds.iter() {
+        if let Err(e) = ctx.run(cmd, v) {
+            v.message(format!("{e}"));
+        }
+    }
+    v.autocmds.running = running;
+    !cmds.is_empty()
+}
+
+/// `:doautocmd [group] {event} [fname]`, which runs autocommands as if the event happened for
+/// the file, or the current one
+pub fn doautocmd(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    args: &str,
+) -> Result<(), String> {
+    let (group, args) = v.autocmds.split_group(args);
+    let (name, file) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let event = event(name)?;
+    let file = match file.trim() {
+        "" => v
+            .get_focus()
+            .buffer()
+            .read()
+            .filename()
+            .map_or(String::new(), |f| f.display().to_string()),
+        file => file.to_string(),
+    };
+    if !run(v, ctx, group.as_deref(), event, &file) {
+        v.message("No matching autocommands".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert!(matches("*.rs", "src/lib.rs"));
+        assert!(!matches("*.rs", "lib.rsx"));
+        assert!(matches("src/*.rs", "src/lib.rs"));
+        assert!(matches("make", "make"));
+    }
+
+    #[test]
+    fn commands() {
+        let mut au = AutoCmds::default();
+        au.augroup(false, "g").unwrap();
+        au.command(false, "QuickFixCmdPost make ++once copen").unwrap();
+        au.command(false, "bufread,BufNewFile *.c set cin").unwrap();
+        au.augroup(false, "END").unwrap();
+        assert!(au.command(false, "Nope * x").is_err());
+        assert_eq!(au.take(None, "QuickFixCmdPost", "grep"), Vec::<String>::new());
+        assert_eq!(au.take(Some("g"), "QuickFixCmdPost", "make"), ["copen"]);
+        assert_eq!(au.take(None, "QuickFixCmdPost", "make"), Vec::<String>::new());
+        assert_eq!(au.take(None, "BufRead", "a.c"), ["set cin"]);
+        au.command(true, "g BufRead").unwrap();
+        assert_eq!(au.take(None, "BufRead", "a.c"), Vec::<String>::new());
+        assert_eq!(au.take(None, "BufNewFile", "a.c"), ["set cin"]);
+    }
+}
@@ -5,40 +5,89 @@
 //
 
 use std::{
-    fmt::Display,
+    collections::HashMap,
     fs::File,
     io::{self, BufRead, BufReader, Write},
-    ops::{Deref, DerefMut, Index, IndexMut},
-    path::PathBuf,
+    ops::{Deref, DerefMut, Index, IndexMut, Range},
+    path::{Path, PathBuf},
     sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
 };
 
-use crossterm::style::ContentStyle;
+use unicode_segmentation::GraphemeCursor;
+use unicode_width::UnicodeWidthChar;
+use vimscript::regex::Haystack;
 use vimscript::{IdProcuder, Id};
 
-use crate::{Result, options::{BufOptions, Opts}};
+use crate::{
+    fold::FoldLevel,
+    color::Style,
+    highlight::Highlights,
+    options::{BufOptions, Opts},
+    syntax::Syntax,
+    util::Pos,
+    width::WidthOpts,
+    Result,
+};
 
 pub trait BufferSelect {
     fn select(&self, buffer: &Buffer) -> bool;
 }
 
+/// The signs of a line, with the highlight group of their text and their priority
 #[derive(Debug, Default)]
 pub struct Signs {
-    lst: Vec<(char, ContentStyle, isize)>,
+    lst: Vec<(char, Option<usize>, isize)>,
 }
 
-impl Display for Signs {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for (view, style, _) in self.lst.iter().take(2) {
-            write!(f, "{}", style.apply(view))?;
+impl Signs {
+    /// Draws the signs in the SignColumn group, padded to `width`
+    pub fn draw<W: Write>(
+        &self,
+        term: &mut W,
+        width: usize,
+        highlights: &Highlights,
+    ) -> Result<()> {
+        let column = highlights.style_of("SignColumn");
+        let mut written = 0;
+        for (view, group, _) in self.lst.iter().take(2) {
+            let mut style = group.map_or(column, |g| highlights.style(g));
+            style.bg = style.bg.or(column.bg);
+            write!(term, "{}", style.apply(view))?;
+            written += 1;
         }
+        let fill = format!("{:width$}", "", width = width.saturating_sub(written));
+        write!(term, "{}", column.apply(fill))?;
         Ok(())
     }
 }
 
+/// Highlighting drawn on top of the syntax highlighting of a line
+#[derive(Debug, Default)]
+pub struct Overlay {
+    /// Byte ranges and their highlight group, each drawn over the ones before it
+    pub spans: Vec<(Range<usize>, usize)>,
+    /// Byte ranges that are shown as a single character, or not at all
+    pub conceal: Vec<(Range<usize>, Option<char>)>,
+    /// Group of the whole row, drawn under the syntax highlighting
+    pub line: Option<usize>,
+    /// Screen columns of the line and their group, drawn over everything else
+    pub columns: Vec<(usize, usize)>,
+}
+
+impl Overlay {
+    /// `style` with the groups of the columns in `cols` on top
+    fn columns(&self, cols: Range<usize>, style: Style, highlights: &Highlights) -> Style {
+        self.columns
+            .iter()
+            .filter(|(c, _)| cols.contains(c))
+            .fold(style, |style, (_, g)| style.combine(highlights.style(*g)))
+    }
+}
+
 pub struct Line {
     text: String,
-    style: Vec<(usize, ContentStyle)>,
+    /// The end of each part of the line, and its highlight group
+    style: Vec<(usize, Option<usize>)>,
     signs: Signs,
 }
 
@@ -47,23 +96,114 @@ impl Line {
         Self::new(String::new())
     }
 
-    fn new(text: String) -> Self {
+    pub(crate) fn new(text: String) -> Self {
         Self {
-            style: vec![(text.len(), ContentStyle::default())],
+            style: vec![(text.len(), None)],
             text,
             signs: Signs::default(),
         }
     }
 
-    pub fn draw<W: Write>(&self, term: &mut W, width: usize) -> Result<()> {
-        write!(term, "{:width$}", self.text)?;
+    /// Draws the screen columns `cols` of this line, padded to `width`
+    pub fn draw<W: Write>(
+        &self,
+        term: &mut W,
+        width_opts: &WidthOpts,
+        cols: Range<usize>,
+        width: usize,
+        highlights: &Highlights,
+        overlay: &Overlay,
+    ) -> Result<()> {
+        let leftcol = cols.start;
+        let rightcol = cols.end.min(leftcol + width);
+        let mut written = 0;
+        // Text is written in runs with the same style
+        let mut run = String::new();
+        let normal = highlights.text_style(None);
+        let base = overlay
+            .line
+            .map_or(normal, |g| normal.combine(highlights.style(g)));
+        let mut run_style = base;
+        let mut spans = self.style.iter().peekable();
+        for cell in width_opts.cells(&self.text) {
+            if cell.col + cell.width <= leftcol {
+                continue;
+            } else if cell.col >= rightcol {
+                break;
+            }
+            while spans.next_if(|(end, _)| *end <= cell.byte).is_some() {}
+            let concealed = overlay.conceal.iter().find(|(r, _)| r.contains(&cell.byte));
+            let mut style = match spans.peek().and_then(|(_, g)| *g) {
+                Some(group) => base.combine(highlights.style(group)),
+                None => base,
+            };
+            let span = overlay
+                .spans
+                .iter()
+                .rev()
+                .find(|(r, _)| r.contains(&cell.byte));
+            if let Some((_, group)) = span {
+                style = style.combine(highlights.style(*group));
+            }
+            style = overlay.columns(cell.col..cell.col + cell.width.max(1), style, highlights);
+            if concealed.is_some() {
+                style = highlights.text_style(highlights.get("Conceal"));
+            }
+            if style != run_style {
+                write!(term, "{}", run_style.apply(&run))?;
+                run.clear();
+                run_style = style;
+            }
+            if let Some((range, c)) = concealed {
+                // Concealed text is replaced by a single character at its start
+                if let (true, Some(c)) = (range.start == cell.byte, c) {
+                    run.push(*c);
+                    written += 1;
+                }
+            } else if cell.col < leftcol || cell.col + cell.width > rightcol {
+                // Only part of a tab or wide character is visible
+                let visible = (cell.col + cell.width).min(rightcol) - cell.col.max(leftcol);
+                let fill = match cell.text {
+                    "\t" => ' ',
+                    _ if cell.col < leftcol => '<',
+                    _ => '>',
+                };
+                for _ in 0..visible {
+                    run.push(fill);
+                }
+                written += visible;
+            } else {
+                run += &cell.display();
+                written += cell.width;
+            }
+        }
+        // The rest of the row can still have highlighted columns
+        for col in leftcol + written..leftcol + width {
+            let style = overlay.columns(col..col + 1, base, highlights);
+            if style != run_style {
+                write!(term, "{}", run_style.apply(&run))?;
+                run.clear();
+                run_style = style;
+            }
+            run.push(' ');
+        }
+        write!(term, "{}", run_style.apply(&run))?;
         Ok(())
     }
 
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
     pub fn len(&self) -> usize {
         self.text.len()
     }
 
+    /// Number of screen columns needed to display this line
+    pub fn width(&self, width_opts: &WidthOpts) -> usize {
+        width_opts.display_width(&self.text, 0)
+    }
+
     pub fn first_char(&self) -> usize {
         self.text
             .find(|c: char| !c.is_whitespace())
@@ -74,19 +214,53 @@ impl Line {
         self.style.last_mut().unwrap().0 = self.text.len();
     }
 
+    /// The highlight group of the text at byte `col`
+    pub fn group_at(&self, col: usize) -> Option<usize> {
+        self.style.iter().find(|(end, _)| *end > col).and_then(|(_, g)| *g)
+    }
+
+    /// Sets the highlighting of the line, as the end of each part and its highlight group.
+    /// Returns whether it changed.
+    pub(crate) fn set_style(&mut self, style: Vec<(usize, Option<usize>)>) -> bool {
+        let changed = style != self.style;
+        self.style = style;
+        changed
+    }
+
+    /// Start of the grapheme before the one at `pos`
     pub fn prev(&self, pos: usize) -> usize {
-        self.text.floor_char_boundary(pos.saturating_sub(1))
+        let pos = self.text.floor_char_boundary(pos);
+        GraphemeCursor::new(pos, self.text.len(), true)
+            .prev_boundary(&self.text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(0)
     }
 
+    /// Start of the grapheme after the one at `pos`. Unless `past_end` is set, this stops on the
+    /// last grapheme of the line.
     pub fn next(&self, pos: usize, past_end: bool) -> usize {
-        if pos.saturating_add(1) >= self.text.len() {
-            if past_end {
-                self.text.len()
-            } else {
-                self.text.floor_char_boundary(self.text.len() - 1)
-            }
+        let pos = self.text.floor_char_boundary(pos);
+        let next = GraphemeCursor::new(pos, self.text.len(), true)
+            .next_boundary(&self.text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(self.text.len());
+        if next >= self.text.len() && !past_end {
+            self.prev(self.text.len())
         } else {
-            self.text.ceil_char_boundary(pos.saturating_add(1))
+            next
+        }
+    }
+
+    /// Start of the grapheme that contains `pos`
+    pub fn floor_grapheme(&self, pos: usize) -> usize {
+        let pos = self.text.floor_char_boundary(pos);
+        let mut cursor = GraphemeCursor::new(pos, self.text.len(), true);
+        if pos >= self.text.len() || cursor.is_boundary(&self.text, 0).unwrap_or(true) {
+            pos
+        } else {
+            cursor.prev_boundary(&self.text, 0).ok().flatten().unwrap_or(0)
         }
     }
 
@@ -95,10 +269,65 @@ impl Line {
     }
 }
 
+/// A change to the text, as the lines that were replaced and the number of lines that replaced them
+#[derive(Debug, Clone)]
+struct Change {
+    start: usize,
+    old: Vec<String>,
+    new: usize,
+}
+
+/// A change to the number or order of the lines, which the things on lines follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineChange {
+    /// Lines added at a line (positive) or removed from it (negative)
+    Shift(usize, isize),
+    /// `n` lines moved from `from`, so that they start at `to` once they are moved
+    Move { from: usize, n: usize, to: usize },
+}
+
+impl LineChange {
+    /// The line that `line` is on after the change, or `None` if it was removed
+    pub fn follow(&self, line: usize) -> Option<usize> {
+        match *self {
+            Self::Shift(at, delta) if (at..at + (-delta).max(0) as usize).contains(&line) => None,
+            Self::Shift(at, delta) if line >= at => Some((line as isize + delta) as usize),
+            Self::Shift(..) => Some(line),
+            Self::Move { from, n, to } if (from..from + n).contains(&line) => {
+                Some(line - from + to)
+            }
+            Self::Move { from, n, to } => {
+                let line = if line >= from + n { line - n } else { line };
+                Some(if line >= to { line + n } else { line })
+            }
+        }
+    }
+
+    /// The change as lines removed and added, for things that don't follow moved lines
+    pub fn shifts(&self) -> Vec<(usize, isize)> {
+        match *self {
+            Self::Shift(line, delta) => vec![(line, delta)],
+            Self::Move { from, n, to } => vec![(from, -(n as isize)), (to, n as isize)],
+        }
+    }
+}
+
 pub struct Buffer {
     data: Vec<Line>,
     filename: Option<PathBuf>,
     options: BufOptions,
+    /// Incremented on every change to the text
+    tick: usize,
+    /// Changes to the number or order of the lines, and the tick of each change
+    line_changes: Vec<(usize, LineChange)>,
+    syntax: Syntax,
+    /// Changes since the last undo step was made, in the order they happened
+    pending: Vec<Change>,
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+    /// Marks `a` to `z` and the ends of the last visual area (`<` and `>`), which move with the
+    /// lines they are on
+    marks: HashMap<char, Pos>,
 }
 
 impl Buffer {
@@ -107,6 +336,13 @@ impl Buffer {
             data: vec![Line::empty()],
             filename: None,
             options: BufOptions::new(),
+            tick: 0,
+            line_changes: vec![],
+            syntax: Syntax::default(),
+            pending: vec![],
+            undo: vec![],
+            redo: vec![],
+            marks: HashMap::new(),
         }
     }
 
@@ -119,6 +355,13 @@ impl Buffer {
                 .collect::<Result<Vec<Line>>>()?,
             filename: Some(path),
             options: BufOptions::new(),
+            tick: 0,
+            line_changes: vec![],
+            syntax: Syntax::default(),
+            pending: vec![],
+            undo: vec![],
+            redo: vec![],
+            marks: HashMap::new(),
         })
     }
 
@@ -130,6 +373,10 @@ impl Buffer {
         &mut self.options
     }
 
+    pub fn filename(&self) -> Option<&Path> {
+        self.filename.as_deref()
+    }
+
     pub fn write_file(&mut self) -> Result<()> {
         let mut file = File::create(
             self.filename
@@ -161,11 +408,102 @@ impl Buffer {
         self.data.len()
     }
 
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    /// The lines added, removed or moved since `tick`, in the order it happened
+    pub fn line_changes_since(&self, tick: usize) -> impl Iterator<Item = LineChange> + '_ {
+        let start = self.line_changes.partition_point(|(t, _)| *t <= tick);
+        self.line_changes[start..].iter().map(|(_, c)| *c)
+    }
+
+    /// Drops the line changes up to `tick`, once nothing asks for the changes since an older tick
+    pub fn forget_changes_before(&mut self, tick: usize) {
+        let end = self.line_changes.partition_point(|(t, _)| *t <= tick);
+        self.line_changes.drain(..end);
+    }
+
+    pub fn syntax(&self) -> &Syntax {
+        &self.syntax
+    }
+
+    pub fn syntax_mut(&mut self) -> &mut Syntax {
+        &mut self.syntax
+    }
+
+    fn synmaxcol(&self) -> usize {
+        self.options.synmaxcol.max(0) as usize
+    }
+
+    /// Applies syntax highlighting to `lines`. Returns whether any of them changed.
+    pub fn highlight(&mut self, lines: Range<usize>, highlights: &Highlights) -> bool {
+        let synmaxcol = self.synmaxcol();
+        self.syntax
+            .highlight(&mut self.data, lines, highlights, synmaxcol)
+    }
+
+    /// The fold level of every line from its syntax items
+    pub fn syntax_fold_levels(&mut self) -> Vec<FoldLevel> {
+        let synmaxcol = self.synmaxcol();
+        self.syntax.fold_levels(&mut self.data, synmaxcol)
+    }
+
+    /// Saves the `old` lines at `start` before they are replaced by `new` lines, unless they were
+    /// added by the last change
+    fn record(&mut self, start: usize, old: usize, new: usize) {
+        if let Some(last) = self.pending.last_mut() {
+            if last.start <= start && start + old <= last.start + last.new {
+                last.new = last.new + new - old;
+                return;
+            }
+        }
+        let old = self.data[start..start + old].iter().map(|l| l.text.clone()).collect();
+        self.pending.push(Change { start, old, new });
+    }
+
+    fn changed(&mut self, line: usize) {
+        self.record(line, 1, 1);
+        self.tick += 1;
+        self.syntax.changed(line, line);
+    }
+
+    fn lines_changed(&mut self, line: usize, delta: isize) {
+        self.syntax.lines_changed(line, delta);
+        self.line_change(LineChange::Shift(line, delta));
+    }
+
+    /// Saves a change to the number or order of the lines, and moves the marks with it
+    fn line_change(&mut self, change: LineChange) {
+        self.tick += 1;
+        self.line_changes.push((self.tick, change));
+        self.marks.retain(|_, m| match change.follow(m.1) {
+            Some(line) => {
+                m.1 = line;
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Position of mark `c`, as (column, line)
+    pub fn mark(&self, c: char) -> Option<Pos> {
+        self.marks.get(&c).copied()
+    }
+
+    pub fn set_mark(&mut self, c: char, pos: Pos) {
+        self.marks.insert(c, pos);
+    }
+
     pub fn append_line(&mut self, text: String) {
+        self.record(self.data.len(), 0, 1);
+        self.lines_changed(self.data.len(), 1);
         self.data.push(Line::new(text));
     }
 
     pub fn insert_line(&mut self, line: usize, text: String) {
+        self.record(line, 0, 1);
+        self.lines_changed(line, 1);
         self.data.insert(line, Line::new(text));
     }
 
@@ -173,15 +511,19 @@ impl Buffer {
         debug_assert!(line <= self.data.len());
         if line == self.data.len() {
             debug_assert!(col == 0);
+            self.record(line, 0, 1);
+            self.lines_changed(line, 1);
             self.data.push(Line::new(String::from(ch)));
         } else {
             debug_assert!(col <= self.data[line].text.len());
+            self.changed(line);
             self.data[line].text.insert(col, ch);
             self.data[line].update();
         }
     }
 
     pub fn replace_char(&mut self, line: usize, col: usize, ch: char) {
+        self.changed(line);
         let line = &mut self.data[line];
         if col < line.text.len() {
             line.text.remove(col);
@@ -191,21 +533,137 @@ impl Buffer {
     }
 
     pub fn remove_char(&mut self, line: usize, col: usize) {
+        self.changed(line);
         self.data[line].text.remove(col);
         self.data[line].update();
     }
 
+    /// Removes the grapheme that starts at `col`. With `delcombine`, only the last combining
+    /// character is removed if there is one. Returns the number of bytes removed.
+    pub fn remove_grapheme(&mut self, line: usize, col: usize, delcombine: bool) -> usize {
+        self.changed(line);
+        let line = &mut self.data[line];
+        let end = line.next(col, true);
+        let start = match line.text[col..end].char_indices().last() {
+            Some((i, c)) if delcombine && i > 0 && c.width() == Some(0) => col + i,
+            _ => col,
+        };
+        line.text.replace_range(start..end, "");
+        line.update();
+        end - start
+    }
+
     pub fn split_line(&mut self, line: usize, col: usize) {
+        self.record(line, 1, 2);
+        self.lines_changed(line + 1, 1);
         let text = self.data[line].text.split_off(col);
         self.data.insert(line + 1, Line::new(text));
         self.data[line].update();
     }
 
     pub fn join_line(&mut self, line: usize) {
+        self.record(line, 2, 1);
+        self.lines_changed(line + 1, -1);
         let next = self.data.remove(line + 1);
         self.data[line].text += next.text.as_str();
         self.data[line].update();
     }
+
+    /// Replaces `lines` with `text`. A buffer is never left without lines, so replacing every
+    /// line with nothing leaves a single empty line.
+    pub fn replace_lines(&mut self, lines: Range<usize>, mut text: Vec<String>) {
+        if text.is_empty() && lines.len() == self.data.len() {
+            text.push(String::new());
+        }
+        self.record(lines.start, lines.len(), text.len());
+        self.splice(lines, text);
+    }
+
+    /// Moves `lines` so that they start at line `to` once they are moved. Marks on the lines move
+    /// with them.
+    pub fn move_lines(&mut self, lines: Range<usize>, to: usize) {
+        let (from, n) = (lines.start, lines.len());
+        let span = from.min(to)..from.max(to) + n;
+        self.record(span.start, span.len(), span.len());
+        let moved: Vec<_> = self.data.drain(lines).collect();
+        self.data.splice(to..to, moved);
+        self.syntax.changed(span.start, span.end - 1);
+        self.line_change(LineChange::Move { from, n, to });
+    }
+
+    /// Replaces `lines` without saving them for undo
+    fn splice(&mut self, lines: Range<usize>, text: Vec<String>) -> Vec<String> {
+        let (start, old, new) = (lines.start, lines.len(), text.len());
+        let both = old.min(new);
+        if both > 0 {
+            self.tick += 1;
+            self.syntax.changed(start, start + both - 1);
+        }
+        if new != old {
+            self.lines_changed(start + both, new as isize - old as isize);
+        }
+        self.data
+            .splice(lines, text.into_iter().map(Line::new))
+            .map(|l| l.text)
+            .collect()
+    }
+
+    /// Ends the current undo step. Returns whether it had any changes.
+    pub fn commit_undo(&mut self) -> bool {
+        if self.pending.is_empty() {
+            return false;
+        }
+        self.undo.push(std::mem::take(&mut self.pending));
+        self.redo.clear();
+        true
+    }
+
+    /// Number of undo steps, which is the number of the last one
+    pub fn undo_seq(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Reverts `changes`, and returns the changes that redo them
+    fn revert(&mut self, changes: Vec<Change>) -> Vec<Change> {
+        changes
+            .into_iter()
+            .rev()
+            .map(|c| {
+                let new = c.old.len();
+                let old = self.splice(c.start..c.start + c.new, c.old);
+                Change { start: c.start, old, new }
+            })
+            .collect()
+    }
+
+    /// Undoes the last undo step. Returns its first changed line, the number of lines it added and
+    /// the number of changes in it.
+    pub fn undo(&mut self) -> Option<(usize, isize, usize)> {
+        self.commit_undo();
+        let step = self.undo.pop()?;
+        let ret = summary(&step, true);
+        let redo = self.revert(step);
+        self.redo.push(redo);
+        Some(ret)
+    }
+
+    /// Redoes the last undone step, like `undo()`
+    pub fn redo(&mut self) -> Option<(usize, isize, usize)> {
+        self.commit_undo();
+        let step = self.redo.pop()?;
+        let undo = self.revert(step);
+        let ret = summary(&undo, false);
+        self.undo.push(undo);
+        Some(ret)
+    }
+}
+
+/// The first line changed by `changes`, the number of lines reverting them adds (or applying them
+/// adds when they are not `reverted`) and the number of changes
+fn summary(changes: &[Change], reverted: bool) -> (usize, isize, usize) {
+    let first = changes.iter().map(|c| c.start).min().unwrap_or(0);
+    let delta: isize = changes.iter().map(|c| c.old.len() as isize - c.new as isize).sum();
+    (first, if reverted { delta } else { -delta }, changes.len())
 }
 
 impl Index<usize> for Buffer {
@@ -222,6 +680,17 @@ impl IndexMut<usize> for Buffer {
     }
 }
 
+/// Search patterns match in the text of the buffer
+impl Haystack for Buffer {
+    fn line_count(&self) -> usize {
+        self.data.len()
+    }
+
+    fn line(&self, n: usize) -> &str {
+        &self.data[n].text
+    }
+}
+
 pub struct BufferRef {
     id: Id,
     inner: Arc<RwLock<Buffer>>,
@@ -309,3 +778,110 @@ impl<'s> DerefMut for BufferWrite<'s> {
         self.inner.deref_mut()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grapheme_motion() {
+        let line = Line::new("ae\u{301}\u{1f1fa}\u{1f1f8}b".into());
+        assert_eq!(line.next(0, false), 1);
+        assert_eq!(line.next(1, false), 4);
+        assert_eq!(line.next(4, false), 12);
+        assert_eq!(line.next(12, false), 12);
+        assert_eq!(line.next(12, true), 13);
+        assert_eq!(line.prev(12), 4);
+        assert_eq!(line.prev(4), 1);
+        assert_eq!(line.floor_grapheme(2), 1);
+        assert_eq!(line.floor_grapheme(8), 4);
+    }
+
+    #[test]
+    fn remove_grapheme() {
+        let mut buffer = Buffer::empty();
+        buffer.insert_line(0, "ae\u{301}\u{300}b".into());
+        assert_eq!(buffer.remove_grapheme(0, 1, true), 2);
+        assert_eq!(buffer[0].text(), "ae\u{301}b");
+        assert_eq!(buffer.remove_grapheme(0, 1, false), 3);
+        assert_eq!(buffer[0].text(), "ab");
+    }
+
+    #[test]
+    fn undo() {
+        let mut buffer = Buffer::empty();
+        let text = |b: &Buffer| (0..b.len()).map(|l| b[l].text().to_string()).collect::<Vec<_>>();
+        buffer.insert_char(0, 0, 'a');
+        buffer.insert_char(0, 1, 'b');
+        buffer.split_line(0, 1);
+        assert!(buffer.commit_undo());
+        buffer.replace_lines(0..2, vec!["x".into(), "y".into(), "z".into()]);
+        buffer.join_line(1);
+        assert_eq!(text(&buffer), ["x", "yz"]);
+        assert_eq!(buffer.undo(), Some((0, 0, 1)));
+        assert_eq!(text(&buffer), ["a", "b"]);
+        assert_eq!(buffer.undo(), Some((0, -1, 1)));
+        assert_eq!(text(&buffer), [""]);
+        assert_eq!(buffer.undo(), None);
+        assert_eq!(buffer.redo(), Some((0, 1, 1)));
+        assert_eq!(buffer.redo(), Some((0, 0, 1)));
+        assert_eq!(text(&buffer), ["x", "yz"]);
+        assert_eq!(buffer.redo(), None);
+    }
+
+    #[test]
+    fn line_changes() {
+        let mut buffer = Buffer::empty();
+        buffer.insert_line(0, "a".into());
+        let tick = buffer.tick();
+        buffer.insert_line(0, "b".into());
+        buffer.replace_lines(0..2, vec![]);
+        assert_eq!(buffer.line_changes_since(0).count(), 3);
+        buffer.forget_changes_before(tick);
+        assert_eq!(buffer.line_changes.len(), 2);
+        let changes: Vec<_> = buffer.line_changes_since(tick).collect();
+        assert_eq!(changes, [LineChange::Shift(0, 1), LineChange::Shift(0, -2)]);
+        buffer.forget_changes_before(buffer.tick());
+        assert!(buffer.line_changes.is_empty());
+    }
+
+    #[test]
+    fn marks() {
+        let mut buffer = Buffer::empty();
+        buffer.replace_lines(0..1, ["a", "b", "c", "d"].map(String::from).to_vec());
+        buffer.set_mark('a', Pos(0, 1));
+        buffer.set_mark('b', Pos(0, 3));
+        buffer.insert_line(0, "x".into());
+        assert_eq!(buffer.mark('b'), Some(Pos(0, 4)));
+        buffer.replace_lines(1..3, vec![]);
+        assert_eq!(buffer.mark('a'), None);
+        assert_eq!(buffer.mark('b'), Some(Pos(0, 2)));
+    }
+
+    #[test]
+    fn move_lines() {
+        let mut buffer = Buffer::empty();
+        let text = |b: &Buffer| (0..b.len()).map(|l| b[l].text().to_string()).collect::<Vec<_>>();
+        buffer.replace_lines(0..1, ["a", "b", "c", "d", "e"].map(String::from).to_vec());
+        buffer.commit_undo();
+        buffer.set_mark('a', Pos(0, 1));
+        buffer.set_mark('b', Pos(0, 3));
+        buffer.move_lines(3..5, 0);
+        assert_eq!(text(&buffer), ["d", "e", "a", "b", "c"]);
+        assert_eq!(buffer.mark('a'), Some(Pos(0, 3)));
+        assert_eq!(buffer.mark('b'), Some(Pos(0, 0)));
+        buffer.move_lines(0..1, 4);
+        assert_eq!(text(&buffer), ["e", "a", "b", "c", "d"]);
+        assert_eq!(buffer.mark('a'), Some(Pos(0, 2)));
+        assert_eq!(buffer.mark('b'), Some(Pos(0, 4)));
+        buffer.commit_undo();
+        assert!(buffer.undo().is_some());
+        assert_eq!(text(&buffer), ["a", "b", "c", "d", "e"]);
+
+        let change = LineChange::Move { from: 1, n: 2, to: 3 };
+        let lines: Vec<_> = (0..6).map(|l| change.follow(l)).collect();
+        assert_eq!(lines, [0, 3, 4, 1, 2, 5].map(Some));
+        assert_eq!(LineChange::Shift(2, -2).follow(3), None);
+        assert_eq!(LineChange::Shift(2, -2).follow(4), Some(2));
+    }
+}
@@ -2,7 +2,22 @@ use std::{convert::TryInto, sync::Arc};
 
 use vimscript::{BuiltinFunction, Value, VimError, VimScriptCtx};
 
-use crate::VimInner;
+use crate::{
+    cli::{self, history},
+    complete,
+    fold,
+    highlight::{Attrs, Colors},
+    errorformat::ErrorFormat,
+    matches::{Match, MatchPos, Target},
+    quickfix::{self, Action, Entry, Which},
+    search,
+    shell,
+    syntax::Pattern,
+    tags::{self, Tag},
+    util::Pos,
+    width::WidthOpts,
+    VimInner,
+};
 
 struct Builtin<F>(F);
 
@@ -41,7 +56,398 @@ macro_rules! nargs {
     };
 }
 
+/// A line number argument, which can also be "." or "$". Returns the index of the line, if it is in
+/// the buffer.
+fn lnum(ctx: &VimScriptCtx<VimInner>, state: &VimInner, v: &Value) -> Option<usize> {
+    let win = state.get_focus();
+    let len = win.buffer().read().len();
+    let lnum = match v.to_string(ctx).as_str() {
+        "." => win.cursor().row() + 1,
+        "$" => len,
+        _ => v.to_int(ctx).ok()?.max(0) as usize,
+    };
+    (1..=len).contains(&lnum).then(|| lnum - 1)
+}
+
+/// The entry `key` of a dict
+fn dict_get(dict: &Value, key: &str) -> Option<Value> {
+    match dict {
+        Value::Object(o) => o.lock().unwrap().get(key).cloned(),
+        _ => None,
+    }
+}
+
+/// A position given to `matchaddpos()`: a line number, or a list of line, column and length
+fn match_pos(ctx: &VimScriptCtx<VimInner>, v: Value) -> Result<Option<MatchPos>, VimError> {
+    let parts = match v {
+        Value::List(_) => v
+            .into_iter()
+            .map(|p| p.to_int(ctx))
+            .collect::<Result<Vec<_>, _>>()?,
+        v => vec![v.to_int(ctx)?],
+    };
+    let (line, col, len) = match parts[..] {
+        [line] => (line, None, 0),
+        [line, col] => (line, Some(col), 1),
+        [line, col, len] => (line, Some(col), len),
+        _ => return Err(VimError::Expected("List with 1 to 3 numbers")),
+    };
+    Ok((line > 0).then(|| MatchPos {
+        line: line as usize - 1,
+        col: col.map(|c| c.max(1) as usize - 1),
+        len: len.max(0) as usize,
+    }))
+}
+
+/// Adds a match to the current window, with the optional priority, id and dict arguments of
+/// `matchadd()`
+fn add_match(
+    ctx: &VimScriptCtx<VimInner>,
+    state: &mut VimInner,
+    group: &str,
+    target: Target,
+    rest: &[Value],
+) -> Result<Value, VimError> {
+    let priority = match rest.first() {
+        Some(p) => p.to_int(ctx)?,
+        None => 10,
+    };
+    let id = match rest.get(1) {
+        Some(id) => id.to_int(ctx)?,
+        None => -1,
+    };
+    let conceal = rest
+        .get(2)
+        .and_then(|d| dict_get(d, "conceal"))
+        .and_then(|c| c.to_string(ctx).chars().next());
+    let matches = state.get_focus_mut().matches_mut();
+    Ok(Value::Integer(
+        match matches.add(group, target, priority, id, conceal) {
+            Ok(id) => id as isize,
+            Err(e) => {
+                state.message(e);
+                -1
+            }
+        },
+    ))
+}
+
+/// The dict `getmatches()` returns for a match
+fn match_dict(m: &Match) -> Value {
+    let mut dict = vec![
+        ("group".to_string(), Value::str(&m.group)),
+        ("priority".to_string(), Value::Integer(m.priority)),
+        ("id".to_string(), Value::Integer(m.id as isize)),
+    ];
+    match &m.target {
+        Target::Pattern { source, .. } => dict.push(("pattern".to_string(), Value::str(source))),
+        Target::Pos(pos) => {
+            for (i, p) in pos.iter().enumerate() {
+                let mut parts = vec![Value::Integer(p.line as isize + 1)];
+                if let Some(col) = p.col {
+                    parts.push(Value::Integer(col as isize + 1));
+                    parts.push(Value::Integer(p.len as isize));
+                }
+                dict.push((format!("pos{}", i + 1), Value::list(parts)));
+            }
+        }
+    }
+    if let Some(c) = m.conceal {
+        dict.push(("conceal".to_string(), Value::str(c)));
+    }
+    Value::object(dict)
+}
+
+/// A match from a dict returned by `getmatches()`
+fn dict_match(ctx: &VimScriptCtx<VimInner>, dict: &Value) -> Result<Match, String> {
+    let err = || "E474: Invalid argument".to_string();
+    let get = |key| dict_get(dict, key).ok_or_else(err);
+    let target = match dict_get(dict, "pattern") {
+        Some(source) => {
+            let source = source.to_string(ctx);
+            Target::Pattern {
+                pattern: Box::new(Pattern::search(&source, false)?),
+                source,
+            }
+        }
+        None => {
+            let mut pos = vec![];
+            while let Some(p) = dict_get(dict, &format!("pos{}", pos.len() + 1)) {
+                pos.push(match_pos(ctx, p).ok().flatten().ok_or_else(err)?);
+            }
+            Target::Pos(pos)
+        }
+    };
+    let int = |key| get(key).and_then(|v| v.to_int(ctx).map_err(|_| err()));
+    Ok(Match {
+        id: int("id")?.max(1) as usize,
+        group: get("group")?.to_string(ctx),
+        target,
+        priority: int("priority")?,
+        conceal: dict_get(dict, "conceal").and_then(|c| c.to_string(ctx).chars().next()),
+    })
+}
+
+/// The dict `getqflist()` returns for an entry
+fn entry_dict(state: &VimInner, e: &Entry) -> Value {
+    let bufnr = e
+        .filename
+        .as_ref()
+        .and_then(|f| state.find_buffer(f))
+        .map_or(0, |b| b.id().number() + 1);
+    let int = |n: usize| Value::Integer(n as isize);
+    Value::object([
+        ("bufnr", int(bufnr)),
+        ("filename", Value::str(e.filename.clone().unwrap_or_default())),
+        ("module", Value::str("")),
+        ("lnum", int(e.lnum)),
+        ("end_lnum", int(e.end_lnum)),
+        ("col", int(e.col)),
+        ("end_col", int(e.end_col)),
+        ("vcol", int(e.vcol as usize)),
+        ("nr", Value::Integer(e.nr)),
+        ("pattern", Value::str(&e.pattern)),
+        ("text", Value::str(&e.text)),
+        ("type", Value::str(e.kind.map(String::from).unwrap_or_default())),
+        ("valid", int(e.valid as usize)),
+    ])
+}
+
+/// A tag as `taglist()` gives it, with its extension fields as items
+fn tag_dict(tag: &Tag) -> Value {
+    let mut items = vec![
+        ("name".to_string(), Value::str(&tag.name)),
+        ("filename".to_string(), Value::str(&tag.filename)),
+        ("cmd".to_string(), Value::str(&tag.cmd)),
+        ("kind".to_string(), Value::str(&tag.kind)),
+        ("static".to_string(), Value::Integer(tag.is_static as isize)),
+    ];
+    for (key, value) in tag.fields.iter() {
+        items.push((key.clone(), Value::str(value)));
+    }
+    Value::object(items)
+}
+
+/// An entry from a dict given to `setqflist()`, where the file can also be given by `bufnr`
+fn dict_entry(ctx: &VimScriptCtx<VimInner>, state: &VimInner, dict: &Value) -> Entry {
+    let int = |key| dict_get(dict, key).and_then(|v| v.to_int(ctx).ok()).unwrap_or(0);
+    let string = |key| dict_get(dict, key).map(|v| v.to_string(ctx)).unwrap_or_default();
+    let filename = match dict_get(dict, "filename") {
+        Some(name) => Some(name.to_string(ctx)),
+        None => state
+            .buffer_nr(int("bufnr").max(0) as usize)
+            .and_then(|b| b.read().filename().map(|f| f.display().to_string())),
+    };
+    let mut entry = Entry {
+        filename,
+        lnum: int("lnum").max(0) as usize,
+        end_lnum: int("end_lnum").max(0) as usize,
+        col: int("col").max(0) as usize,
+        end_col: int("end_col").max(0) as usize,
+        vcol: int("vcol") != 0,
+        nr: int("nr"),
+        pattern: string("pattern"),
+        text: string("text"),
+        kind: string("type").chars().next(),
+        valid: false,
+    };
+    entry.valid = match dict_get(dict, "valid") {
+        Some(valid) => valid.to_int(ctx).is_ok_and(|v| v != 0),
+        None => entry.filename.is_some() || entry.lnum > 0 || !entry.pattern.is_empty(),
+    };
+    entry
+}
+
+/// Reads the `lines` of a dict given to `setqflist()` or `getqflist()`, with its `efm` or
+/// 'errorformat'
+fn what_lines(ctx: &VimScriptCtx<VimInner>, state: &VimInner, what: &Value) -> Option<Vec<Entry>> {
+    let lines: Vec<_> = dict_get(what, "lines")?
+        .into_iter()
+        .map(|l| l.to_string(ctx))
+        .collect();
+    let efm = dict_get(what, "efm").map_or_else(|| state.options().errorformat.clone(), |e| {
+        e.to_string(ctx)
+    });
+    match ErrorFormat::new(&efm) {
+        Ok(efm) => Some(efm.parse(lines.iter().map(String::as_str))),
+        Err(_) => Some(vec![]),
+    }
+}
+
+/// `setqflist({list} [, {action} [, {what}]])`, which makes a new list, or adds to the current
+/// one or replaces it
+fn set_list(
+    ctx: &VimScriptCtx<VimInner>,
+    state: &mut VimInner,
+    which: Which,
+    args: &[Value],
+) -> Result<(), String> {
+    let (items, action, what) = match args {
+        [items] => (items, None, None),
+        [items, action] => (items, Some(action), None),
+        [items, action, what] => (items, Some(action), Some(what)),
+        _ => return Err("E118: Too many arguments for function: setqflist".into()),
+    };
+    let action = match action.map(|a| a.to_string(ctx)).as_deref() {
+        None | Some("" | " ") => Action::New,
+        Some("a") => Action::Add,
+        Some("r") => Action::Replace,
+        Some("f") => {
+            state.quickfix.stack_mut(which).clear();
+            quickfix::refresh(state, which);
+            return Ok(());
+        }
+        Some(a) => return Err(format!("E927: Invalid action: '{a}'")),
+    };
+    let get = |key| what.and_then(|w| dict_get(w, key));
+    // With `what`, the entries are its `items` or `lines`, and the list argument is not used
+    let items = match what {
+        Some(what) => get("items").ok_or(what),
+        None => Ok(items.clone()),
+    };
+    let entries = match items {
+        Ok(items) => Some(items.into_iter().map(|d| dict_entry(ctx, state, &d)).collect()),
+        Err(what) => what_lines(ctx, state, what),
+    };
+    let title = get("title").map(|t| t.to_string(ctx));
+    let stack = state.quickfix.stack_mut(which);
+    if action == Action::New || entries.is_some() {
+        let default = || ":setqflist()".to_string();
+        stack.set(action, title.clone().unwrap_or_else(default), entries.unwrap_or_default());
+    }
+    if let Some(list) = stack.list_mut() {
+        if let Some(title) = title {
+            list.title = title;
+        }
+        if let Some(idx) = get("idx").and_then(|i| i.to_int(ctx).ok()) {
+            list.idx = (idx.max(1) as usize - 1).min(list.entries.len().saturating_sub(1));
+        }
+    }
+    quickfix::refresh(state, which);
+    Ok(())
+}
+
+/// `getqflist([{what}])`, which gives the entries of the current list, or the properties of a
+/// list that are in `what`
+fn get_list(
+    ctx: &VimScriptCtx<VimInner>,
+    state: &VimInner,
+    which: Which,
+    args: &[Value],
+) -> Result<Value, VimError> {
+    let stack = state.quickfix.stack(which);
+    let items = |list: Option<&quickfix::List>| {
+        let entries = list.map_or(&[][..], |l| &l.entries[..]);
+        Value::list(entries.iter().map(|e| entry_dict(state, e)).collect::<Vec<_>>())
+    };
+    let what = match args {
+        [] => return Ok(items(stack.and_then(|s| s.list()))),
+        [what] => what,
+        _ => return Err(VimError::WrongArgCount(1)),
+    };
+    if let Some(entries) = what_lines(ctx, state, what) {
+        let items = entries.iter().map(|e| entry_dict(state, e)).collect::<Vec<_>>();
+        return Ok(Value::object([("items", Value::list(items))]));
+    }
+    let nr = match dict_get(what, "nr") {
+        Some(nr) if nr.to_string(ctx) == "$" => stack.map_or(0, |s| s.len()),
+        Some(nr) => match nr.to_int(ctx).unwrap_or(0) {
+            0 => stack.map_or(0, |s| s.nr()),
+            nr => nr.max(0) as usize,
+        },
+        None => stack.map_or(0, |s| s.nr()),
+    };
+    let list = stack.and_then(|s| s.get(nr));
+    let all = dict_get(what, "all").is_some();
+    let has = |key| all || dict_get(what, key).is_some();
+    let mut dict = vec![];
+    if has("title") {
+        dict.push(("title", Value::str(list.map_or("", |l| l.title.as_str()))));
+    }
+    if has("items") {
+        dict.push(("items", items(list)));
+    }
+    if has("idx") {
+        dict.push(("idx", Value::Integer(list.map_or(0, |l| l.idx as isize + 1))));
+    }
+    if has("size") {
+        dict.push(("size", Value::Integer(list.map_or(0, |l| l.entries.len() as isize))));
+    }
+    if has("nr") {
+        dict.push(("nr", Value::Integer(list.map_or(0, |_| nr as isize))));
+    }
+    if has("winid") {
+        // Window IDs start at 1000, like they do in Vim
+        let id = stack.and_then(|s| s.window()).map_or(0, |id| id.number() + 1000);
+        dict.push(("winid", Value::Integer(id as isize)));
+    }
+    Ok(Value::object(dict))
+}
+
+/// Runs the command of `system()` and `systemlist()`, with the input they are given, which is a
+/// string or a list of lines
+fn system(
+    v: Vec<Value>,
+    ctx: &mut VimScriptCtx<VimInner>,
+    state: &mut VimInner,
+) -> Result<String, VimError> {
+    let (cmd, input) = match v.as_slice() {
+        [cmd] => (cmd, None),
+        [cmd, input] => (cmd, Some(input)),
+        _ => return Err(VimError::WrongArgCount(2)),
+    };
+    let input = input.map(|input| match input {
+        Value::List(_) => input.clone().into_iter().map(|l| l.to_string(ctx) + "\n").collect(),
+        input => input.to_string(ctx),
+    });
+    let cmd = cmd.to_string(ctx);
+    shell::run(state, ctx, &cmd, input.as_deref()).map_err(VimError::Shell)
+}
+
 pub fn builtin_functions(ctx: &mut VimScriptCtx<VimInner>) {
+    // String manipulation:					*string-functions*
+    ctx.builtin(
+        "strwidth",
+        nargs!(|ctx, state, a| Value::Integer(
+            WidthOpts::new(state.options()).str_width(&a.to_string(ctx)) as isize
+        )),
+    );
+    // 	strwidth()		size of string when displayed
+    ctx.builtin(
+        "strdisplaywidth",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let (s, col) = match v.as_slice() {
+                [s] => (s, 0),
+                [s, col] => (s, col.to_int(ctx)?.max(0) as usize),
+                _ => return Err(VimError::WrongArgCount(2)),
+            };
+            let width = WidthOpts::new(state.options()).display_width(&s.to_string(ctx), col);
+            Ok(Value::Integer(width as isize))
+        })),
+    );
+    // 	strdisplaywidth()	size of string when displayed, deals with tabs
+    ctx.builtin(
+        "submatch",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let (n, list) = match v.as_slice() {
+                [n] => (n, false),
+                [n, list] => (n, list.to_bool(ctx)?),
+                _ => return Err(VimError::WrongArgCount(2)),
+            };
+            let n = n.to_int(ctx)?;
+            if !(0..=9).contains(&n) {
+                return Err(VimError::IllegalArgument("E935: Invalid submatch number"));
+            }
+            let text = state.substitute.submatch(n as usize).unwrap_or_default();
+            Ok(if list {
+                Value::list(text.split('\n').map(Value::str))
+            } else {
+                Value::str(text)
+            })
+        })),
+    );
+    // 	submatch()		get a specific match in ":s" and substitute()
+    //
     // Cursor and mark position:		*cursor-functions* *mark-functions*
     ctx.builtin(
         "col",
@@ -60,6 +466,36 @@ pub fn builtin_functions(ctx: &mut VimScriptCtx<VimInner>) {
         }),
     );
     // 	col()			column number of the cursor or a mark
+    ctx.builtin(
+        "virtcol",
+        nargs!(|ctx, state, a| {
+            let win = state.get_focus();
+            let width = WidthOpts::new(state.options());
+            let buffer = win.buffer().read();
+            let pos = if a == "." {
+                Some(Pos(win.cursor().col(), win.cursor().row()))
+            } else if a == "$" {
+                let row = win.cursor().row();
+                Some(Pos(buffer[row].len(), row))
+            } else if a.starts_with('\'') {
+                // An unset mark gives 0, like any other invalid position
+                a.to_string(ctx).chars().nth(1).and_then(|c| buffer.mark(c))
+            } else {
+                None
+            };
+            match pos {
+                Some(Pos(col, row)) if row < buffer.len() => {
+                    let text = buffer[row].text();
+                    let col = width
+                        .cells(text)
+                        .find(|c| c.byte + c.text.len() > col)
+                        .map_or(width.display_width(text, 0) + 1, |c| c.col + c.width);
+                    Value::Integer(col as isize)
+                }
+                _ => Value::Integer(0),
+            }
+        }),
+    );
     // 	virtcol()		screen column of the cursor or a mark
     ctx.builtin(
         "line",
@@ -100,9 +536,38 @@ pub fn builtin_functions(ctx: &mut VimScriptCtx<VimInner>) {
     // 	setcursorcharpos()	set character position of the cursor
     //
     // Working with text in the current buffer:		*text-functions*
+    ctx.builtin(
+        "getline",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let buffer = state.get_focus().buffer().read();
+            match v.as_slice() {
+                [l] => Ok(Value::str(
+                    lnum(ctx, state, l).map_or("", |l| buffer[l].text()),
+                )),
+                [start, end] => Ok(match (lnum(ctx, state, start), lnum(ctx, state, end)) {
+                    (Some(start), Some(end)) => {
+                        Value::list((start..=end).map(|l| Value::str(buffer[l].text())))
+                    }
+                    _ => Value::list(Vec::<Value>::new()),
+                }),
+                _ => Err(VimError::WrongArgCount(2)),
+            }
+        })),
+    );
     // 	getline()		get a line or list of lines from the buffer
     // 	setline()		replace a line in the buffer
     // 	append()		append line or list of lines in the buffer
+    ctx.builtin(
+        "indent",
+        nargs!(|ctx, state, l| Value::Integer(match lnum(ctx, state, l) {
+            Some(l) => {
+                let text = state.get_focus().buffer().read()[l].text().to_string();
+                let indent = &text[..text.len() - text.trim_start().len()];
+                WidthOpts::new(state.options()).display_width(indent, 0) as isize
+            }
+            None => -1,
+        })),
+    );
     // 	indent()		indent of a specific line
     // 	cindent()		indent according to C indenting
     // 	lispindent()		indent according to Lisp indenting
@@ -143,7 +608,22 @@ pub fn builtin_functions(ctx: &mut VimScriptCtx<VimInner>) {
     // 	chdir()			change current working directory
     // 	delete()		delete a file
     // 	rename()		rename a file
+    let _ = ctx.insert_var("v:shell_error", Value::Integer(0));
+    ctx.builtin(
+        "system",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let text = system(v, ctx, state)?;
+            Ok(Value::str(text))
+        })),
+    );
     // 	system()		get the result of a shell command as a string
+    ctx.builtin(
+        "systemlist",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let text = system(v, ctx, state)?;
+            Ok(Value::list(text.lines().map(Value::str)))
+        })),
+    );
     // 	systemlist()		get the result of a shell command as a list
     // 	environ()		get all environment variables
     // 	getenv()		get one environment variable
@@ -209,41 +689,378 @@ pub fn builtin_functions(ctx: &mut VimScriptCtx<VimInner>) {
     // 	fullcommand()		get full command name
     //
     // Quickfix and location lists:			*quickfix-functions*
+    ctx.builtin(
+        "getqflist",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            get_list(ctx, state, Which::QuickFix, &v)
+        })),
+    );
     // 	getqflist()		list of quickfix errors
+    ctx.builtin(
+        "setqflist",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            Ok(Value::Integer(match set_list(ctx, state, Which::QuickFix, &v) {
+                Ok(()) => 0,
+                Err(e) => {
+                    state.message(e);
+                    -1
+                }
+            }))
+        })),
+    );
     // 	setqflist()		modify a quickfix list
     // 	getloclist()		list of location list items
     // 	setloclist()		modify a location list
     //
     // Insert mode completion:				*completion-functions*
+    ctx.builtin(
+        "complete",
+        nargs!(|ctx, state, col, matches| {
+            let col = col.to_int(ctx)?.max(1) as usize - 1;
+            let items = matches
+                .clone()
+                .into_iter()
+                .filter_map(|i| complete::Item::from_value(ctx, &i))
+                .collect();
+            if let Err(e) = complete::set(state, ctx, col, items) {
+                state.message(e);
+            }
+            Ok::<_, VimError>(Value::str(""))
+        }),
+    );
     // 	complete()		set found matches
     // 	complete_add()		add to found matches
     // 	complete_check()	check if completion should be aborted
+    ctx.builtin(
+        "complete_info",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            // Only the items in the list {what} are given
+            let keys: Option<Vec<_>> = match v.as_slice() {
+                [] => None,
+                [what] => Some(what.clone().into_iter().map(|k| k.to_string(ctx)).collect()),
+                _ => return Err(VimError::WrongArgCount(1)),
+            };
+            Ok(Value::object(
+                complete::info(state)
+                    .into_iter()
+                    .filter(|(k, _)| keys.as_ref().is_none_or(|keys| keys.iter().any(|w| w == k))),
+            ))
+        })),
+    );
     // 	complete_info()		get current completion information
+    ctx.builtin(
+        "pumvisible",
+        nargs!(|ctx, state| Value::Integer(complete::pum_visible(state) as isize)),
+    );
     // 	pumvisible()		check if the popup menu is displayed
     // 	pum_getpos()		position and size of popup menu if visible
     //
     // Folding:					*folding-functions*
+    ctx.builtin(
+        "foldclosed",
+        nargs!(|ctx, state, l| Value::Integer(
+            lnum(ctx, state, l)
+                .and_then(|l| state.get_focus().fold_closed(l))
+                .map_or(-1, |f| *f.start() as isize + 1)
+        )),
+    );
     // 	foldclosed()		check for a closed fold at a specific line
+    ctx.builtin(
+        "foldclosedend",
+        nargs!(|ctx, state, l| Value::Integer(
+            lnum(ctx, state, l)
+                .and_then(|l| state.get_focus().fold_closed(l))
+                .map_or(-1, |f| *f.end() as isize + 1)
+        )),
+    );
     // 	foldclosedend()		like foldclosed() but return the last line
+    ctx.builtin(
+        "foldlevel",
+        nargs!(|ctx, state, l| Value::Integer(
+            lnum(ctx, state, l).map_or(0, |l| state.get_focus().fold_level(l) as isize)
+        )),
+    );
     // 	foldlevel()		check for the fold level at a specific line
+    ctx.builtin(
+        "foldtext",
+        nargs!(|ctx, state| {
+            let (start, end, level) = match (
+                ctx.lookup("v:foldstart").and_then(|v| v.to_int(ctx)),
+                ctx.lookup("v:foldend").and_then(|v| v.to_int(ctx)),
+                ctx.lookup("v:foldlevel").and_then(|v| v.to_int(ctx)),
+            ) {
+                (Ok(start), Ok(end), Ok(level)) => (start.max(1) as usize, end.max(1) as usize, level.max(0) as usize),
+                _ => return Ok(Value::str("")),
+            };
+            let win = state.get_focus();
+            let buffer = win.buffer().read();
+            Value::str(buffer.get_line(start - 1).map_or(String::new(), |line| {
+                fold::fold_text(
+                    line.text(),
+                    end + 1 - start.min(end),
+                    level,
+                    &win.options().foldmarker,
+                    &buffer.options().commentstring,
+                )
+            }))
+        }),
+    );
     // 	foldtext()		generate the line displayed for a closed fold
     // 	foldtextresult()	get the text displayed for a closed fold
     //
     // Syntax and highlighting:	  *syntax-functions* *highlighting-functions*
+    ctx.builtin(
+        "clearmatches",
+        nargs!(|ctx, state| {
+            state.get_focus_mut().matches_mut().clear();
+            Value::Integer(0)
+        }),
+    );
     // 	clearmatches()		clear all matches defined by |matchadd()| and
+    ctx.builtin(
+        "getmatches",
+        nargs!(|ctx, state| Value::list(
+            state
+                .get_focus()
+                .matches()
+                .list()
+                .iter()
+                .map(match_dict)
+                .collect::<Vec<_>>()
+        )),
+    );
     // 	getmatches()		get all matches defined by |matchadd()| and
+    ctx.builtin(
+        "hlexists",
+        nargs!(|ctx, state, a| Value::Integer(
+            state.highlights().get(&a.to_string(ctx)).is_some() as isize
+        )),
+    );
     // 	hlexists()		check if a highlight group exists
+    ctx.builtin(
+        "hlID",
+        nargs!(|ctx, state, a| Value::Integer(
+            state
+                .highlights()
+                .get(&a.to_string(ctx))
+                .map_or(0, |id| id as isize + 1)
+        )),
+    );
     // 	hlID()			get ID of a highlight group
+    ctx.builtin(
+        "synID",
+        nargs!(|ctx, state, l, c, trans| {
+            let col = c.to_int(ctx)?;
+            let trans = trans.to_bool(ctx)?;
+            let line = match lnum(ctx, state, l) {
+                Some(line) if col > 0 => line,
+                _ => return Ok(Value::Integer(0)),
+            };
+            let highlights = state.highlights();
+            let id = state.get_focus().buffer().with_write(|b| {
+                b.highlight(line..line + 1, highlights);
+                b[line].group_at(col as usize - 1)
+            });
+            let id = if trans { id.map(|id| highlights.resolve(id)) } else { id };
+            Ok::<_, VimError>(Value::Integer(id.map_or(0, |id| id as isize + 1)))
+        }),
+    );
     // 	synID()			get syntax ID at a specific position
+    ctx.builtin(
+        "synIDattr",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let default = if state.highlights().termguicolors() { "gui" } else { "cterm" };
+            let (id, what, mode) = match v.as_slice() {
+                [id, what] => (id, what.to_string(ctx), default.to_string()),
+                [id, what, mode] => (id, what.to_string(ctx), mode.to_string(ctx)),
+                _ => return Err(VimError::WrongArgCount(3)),
+            };
+            let highlights = state.highlights();
+            let id = match id.to_int(ctx)? {
+                id if id > 0 && (id as usize) <= highlights.len() => id as usize - 1,
+                _ => return Ok(Value::str("")),
+            };
+            let (term, cterm, gui) = highlights.settings(id);
+            let (attrs, colors) = match mode.as_str() {
+                "gui" => (gui.attrs, Some(gui)),
+                "term" => (term, None),
+                _ => (cterm.attrs, Some(cterm)),
+            };
+            let color = |c: fn(&Colors) -> &Option<String>| {
+                colors.and_then(|colors| c(colors).clone()).unwrap_or_default()
+            };
+            let ret = match what.as_str() {
+                "name" => highlights.name(id).to_string(),
+                "fg" | "fg#" => color(|c| &c.fg),
+                "bg" | "bg#" => color(|c| &c.bg),
+                "sp" | "sp#" | "ul" | "ul#" => color(|c| &c.sp),
+                attr if attrs.has(attr) => "1".into(),
+                _ => String::new(),
+            };
+            Ok(Value::Str(ret))
+        })),
+    );
     // 	synIDattr()		get a specific attribute of a syntax ID
+    ctx.builtin(
+        "synIDtrans",
+        nargs!(|ctx, state, id| {
+            let highlights = state.highlights();
+            Ok::<_, VimError>(match id.to_int(ctx)? {
+                id if id > 0 && (id as usize) <= highlights.len() => {
+                    Value::Integer(highlights.resolve(id as usize - 1) as isize + 1)
+                }
+                _ => Value::Integer(0),
+            })
+        }),
+    );
     // 	synIDtrans()		get translated syntax ID
+    ctx.builtin(
+        "hlget",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let (name, resolve) = match v.as_slice() {
+                [] => (None, false),
+                [name] => (Some(name.to_string(ctx)), false),
+                [name, resolve] => (Some(name.to_string(ctx)), resolve.to_bool(ctx)?),
+                _ => return Err(VimError::WrongArgCount(2)),
+            };
+            let highlights = state.highlights();
+            let ids: Vec<usize> = match name {
+                Some(name) => highlights.get(&name).into_iter().collect(),
+                None => (0..highlights.len()).collect(),
+            };
+            let attrs = |attrs: Attrs| Value::object(attrs.names().map(|a| (a, Value::TRUE)));
+            let groups = ids.into_iter().map(|id| {
+                let mut dict = vec![
+                    ("name", Value::str(highlights.name(id))),
+                    ("id", Value::Integer(id as isize + 1)),
+                ];
+                let group = if resolve { highlights.resolve(id) } else { id };
+                if let Some(link) = highlights.link_of(group) {
+                    dict.push(("linksto", Value::str(highlights.name(link))));
+                } else if !highlights.has_settings(group) {
+                    dict.push(("cleared", Value::TRUE));
+                }
+                let (term, cterm, gui) = highlights.settings(group);
+                for (key, a) in [("term", term), ("cterm", cterm.attrs), ("gui", gui.attrs)] {
+                    if !a.is_empty() {
+                        dict.push((key, attrs(a)));
+                    }
+                }
+                let colors = [
+                    ("ctermfg", &cterm.fg),
+                    ("ctermbg", &cterm.bg),
+                    ("ctermul", &cterm.sp),
+                    ("guifg", &gui.fg),
+                    ("guibg", &gui.bg),
+                    ("guisp", &gui.sp),
+                ];
+                for (key, color) in colors {
+                    if let Some(color) = color {
+                        dict.push((key, Value::str(color)));
+                    }
+                }
+                Value::object(dict)
+            });
+            Ok(Value::list(groups.collect::<Vec<_>>()))
+        })),
+    );
+    // 	hlget()			get highlight group attributes
     // 	synstack()		get list of syntax IDs at a specific position
     // 	synconcealed()		get info about concealing
     // 	diff_hlID()		get highlight ID for diff mode at a position
+    ctx.builtin(
+        "matchadd",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let (group, source, rest) = match v.as_slice() {
+                [group, pat, rest @ ..] if rest.len() <= 3 => {
+                    (group.to_string(ctx), pat.to_string(ctx), rest)
+                }
+                _ => return Err(VimError::WrongArgCount(5)),
+            };
+            let target = match Pattern::search(&source, false) {
+                Ok(pattern) => Target::Pattern {
+                    source,
+                    pattern: Box::new(pattern),
+                },
+                Err(e) => {
+                    state.message(e);
+                    return Ok(Value::Integer(-1));
+                }
+            };
+            add_match(ctx, state, &group, target, rest)
+        })),
+    );
     // 	matchadd()		define a pattern to highlight (a "match")
+    ctx.builtin(
+        "matchaddpos",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let (group, pos, rest) = match v.as_slice() {
+                [group, pos, rest @ ..] if rest.len() <= 3 => (group.to_string(ctx), pos, rest),
+                _ => return Err(VimError::WrongArgCount(5)),
+            };
+            let mut list = vec![];
+            for p in pos.clone() {
+                list.extend(match_pos(ctx, p)?);
+            }
+            add_match(ctx, state, &group, Target::Pos(list), rest)
+        })),
+    );
     // 	matchaddpos()		define a list of positions to highlight
+    ctx.builtin(
+        "matcharg",
+        nargs!(|ctx, state, nr| {
+            let nr = nr.to_int(ctx)?;
+            if !(1..=3).contains(&nr) {
+                return Ok(Value::list(Vec::<Value>::new()));
+            }
+            let (group, source) = match state.get_focus().matches().get(nr as usize) {
+                Some(Match {
+                    group,
+                    target: Target::Pattern { source, .. },
+                    ..
+                }) => (group.clone(), source.clone()),
+                _ => (String::new(), String::new()),
+            };
+            Ok::<_, VimError>(Value::list([Value::str(group), Value::str(source)]))
+        }),
+    );
     // 	matcharg()		get info about |:match| arguments
+    ctx.builtin(
+        "matchdelete",
+        nargs!(|ctx, state, id| {
+            let id = id.to_int(ctx)?;
+            Ok::<_, VimError>(Value::Integer(
+                match state.get_focus_mut().matches_mut().delete(id) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        state.message(e);
+                        -1
+                    }
+                },
+            ))
+        }),
+    );
     // 	matchdelete()		delete a match defined by |matchadd()| or a
+    ctx.builtin(
+        "setmatches",
+        nargs!(|ctx, state, list| {
+            let list: Result<Vec<Match>, String> = list
+                .clone()
+                .into_iter()
+                .map(|d| dict_match(ctx, &d))
+                .collect();
+            Value::Integer(match list {
+                Ok(list) => {
+                    state.get_focus_mut().matches_mut().set_list(list);
+                    0
+                }
+                Err(e) => {
+                    state.message(e);
+                    -1
+                }
+            })
+        }),
+    );
     // 	setmatches()		restore a list of matches saved by
     //
     // Spelling:					*spell-functions*
@@ -252,9 +1069,63 @@ pub fn builtin_functions(ctx: &mut VimScriptCtx<VimInner>) {
     // 	soundfold()		return the sound-a-like equivalent of a word
     //
     // History:					*history-functions*
+    ctx.builtin(
+        "histadd",
+        nargs!(|ctx, state, name, item| {
+            let added = history::Kind::parse(&name.to_string(ctx)).is_some_and(|kind| {
+                let max = state.options().history.max(0) as usize;
+                state.cli.history_mut().add(kind, &item.to_string(ctx), max)
+            });
+            Value::Integer(added as isize)
+        }),
+    );
     // 	histadd()		add an item to a history
+    ctx.builtin(
+        "histdel",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let (name, item) = match v.as_slice() {
+                [name] => (name, None),
+                [name, item] => (name, Some(item)),
+                _ => return Err(VimError::WrongArgCount(1)),
+            };
+            let Some(kind) = history::Kind::parse(&name.to_string(ctx)) else {
+                return Ok(Value::Integer(0));
+            };
+            // A number is the index of an entry, and a string a pattern the entries match
+            let removed = match item {
+                None => state.cli.history_mut().retain(kind, |_| false),
+                Some(Value::Integer(index)) => state.cli.history_mut().remove(kind, *index),
+                Some(pat) => {
+                    let re = search::compile(state, &pat.to_string(ctx), false)
+                        .map_err(VimError::Pattern)?;
+                    state.cli.history_mut().retain(kind, |text| !re.is_match(text))
+                }
+            };
+            Ok(Value::Integer(removed as isize))
+        })),
+    );
     // 	histdel()		delete an item from a history
+    ctx.builtin(
+        "histget",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let (name, index) = match v.as_slice() {
+                [name] => (name, -1),
+                [name, index] => (name, index.to_int(ctx)?),
+                _ => return Err(VimError::WrongArgCount(1)),
+            };
+            let text = history::Kind::parse(&name.to_string(ctx))
+                .and_then(|kind| state.cli.history().get(kind, index));
+            Ok(Value::str(text.unwrap_or("")))
+        })),
+    );
     // 	histget()		get an item from a history
+    ctx.builtin(
+        "histnr",
+        nargs!(|ctx, state, name| Value::Integer(
+            history::Kind::parse(&name.to_string(ctx))
+                .map_or(-1, |kind| state.cli.history().nr(kind) as isize)
+        )),
+    );
     // 	histnr()		get highest index of a history list
     //
     // Interactive:					*interactive-functions*
@@ -264,6 +1135,18 @@ pub fn builtin_functions(ctx: &mut VimScriptCtx<VimInner>) {
     // 	getchar()		get a character from the user
     // 	getcharmod()		get modifiers for the last typed character
     // 	feedkeys()		put characters in the typeahead queue
+    ctx.builtin(
+        "input",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            // The completion argument is ignored, since only the `:` line is completed
+            let (prompt, text) = match v.as_slice() {
+                [prompt] => (prompt.to_string(ctx), String::new()),
+                [prompt, text] | [prompt, text, _] => (prompt.to_string(ctx), text.to_string(ctx)),
+                _ => return Err(VimError::WrongArgCount(1)),
+            };
+            Ok(Value::str(cli::input(state, &prompt, &text)?))
+        })),
+    );
     // 	input()			get a line from the user
     // 	inputlist()		let the user pick an entry from a list
     // 	inputsecret()		get a line from the user without showing it
@@ -322,7 +1205,28 @@ pub fn builtin_functions(ctx: &mut VimScriptCtx<VimInner>) {
     // 	sign_unplacelist()	unplace a list of signs
     //
     // Tags:						*tag-functions*
+    ctx.builtin(
+        "taglist",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            // The file name only changes the order of the tags in Vim, so it is ignored
+            let pat = match v.as_slice() {
+                [pat] | [pat, _] => pat.to_string(ctx),
+                _ => return Err(VimError::WrongArgCount(1)),
+            };
+            let tags = tags::find(state, &format!("/{pat}")).map_err(VimError::Pattern)?;
+            Ok(Value::list(tags.iter().map(tag_dict).collect::<Vec<_>>()))
+        })),
+    );
     // 	taglist()		get list of matching tags
+    ctx.builtin(
+        "tagfiles",
+        nargs!(|ctx, state| Value::list(
+            tags::files(state)
+                .iter()
+                .map(|f| Value::str(f.display().to_string()))
+                .collect::<Vec<_>>()
+        )),
+    );
     // 	tagfiles()		get a list of tags files
     // 	gettagstack()		get the tag stack of a window
     // 	settagstack()		modify the tag stack of a window
@@ -345,6 +1249,17 @@ pub fn builtin_functions(ctx: &mut VimScriptCtx<VimInner>) {
     // 	undofile()		get the name of the undo file
     // 	undotree()		return the state of the undo tree
     //
+    ctx.builtin(
+        "getreg",
+        Arc::new(Builtin(|v: Vec<Value>, ctx: &mut VimScriptCtx<VimInner>, state: &mut VimInner| {
+            let name = match v.first() {
+                Some(name) => name.to_string(ctx).chars().next().unwrap_or('"'),
+                None => '"',
+            };
+            let text = state.registers().get(name).map(|r| r.text());
+            Ok(Value::str(text.unwrap_or_default()))
+        })),
+    );
     // 	getreg()		get contents of a register
     // 	getreginfo()		get information about a register
     // 	getregtype()		get type of a register
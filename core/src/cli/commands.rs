@@ -4,18 +4,27 @@
 // Distributed under terms of the MIT license.
 //
 
-use vimscript::{CmdRange, VimScriptCtx, Command};
+use vimscript::{CmdRange, VimScriptCtx, Command, Value};
 
-use crate::VimInner;
+use crate::autocmd;
+use crate::cursor::Motion;
+use crate::edit;
+use crate::global;
+use crate::quickfix::{self, Action, Target, Which};
+use crate::shell;
+use crate::substitute::{self, Kind};
+use crate::syntax::split_pattern;
+use crate::tags;
+use crate::{Vim, VimInner};
 use std::sync::Arc;
 
 struct Cmd<F>(F);
-impl<F: Fn(CmdRange<'_>, bool, &str, &mut VimScriptCtx<VimInner>, &mut VimInner)> Command<VimInner>
+impl<F: Fn(CmdRange, bool, &str, &mut VimScriptCtx<VimInner>, &mut VimInner)> Command<VimInner>
     for Cmd<F>
 {
     fn execute(
         &self,
-        range: CmdRange<'_>,
+        range: CmdRange,
         bang: bool,
         commands: &str,
         ctx: &mut VimScriptCtx<VimInner>,
@@ -25,10 +34,57 @@ impl<F: Fn(CmdRange<'_>, bool, &str, &mut VimScriptCtx<VimInner>, &mut VimInner)
     }
 }
 
+/// The first and last line of `range`, which defaults to the cursor line
+pub(crate) fn range_lines(range: CmdRange, v: &VimInner) -> (usize, usize) {
+    let win = v.get_focus();
+    let cur = win.cursor().row();
+    let last = win.buffer().read().len() - 1;
+    let (start, end) = match range {
+        CmdRange::CurrentLine => (cur, cur),
+        CmdRange::Range { start, end } => (start.saturating_sub(1), end.saturating_sub(1)),
+    };
+    (start.min(end).min(last), start.max(end).min(last))
+}
+
+/// The lines of `range`, or `N` lines from its last line when `args` starts with a count, and
+/// the rest of `args`
+pub(crate) fn count_lines<'a>(
+    range: CmdRange,
+    args: &'a str,
+    v: &VimInner,
+) -> Result<((usize, usize), &'a str), &'static str> {
+    let (first, last) = range_lines(range, v);
+    let args = args.trim_start();
+    let end = args.find(|c: char| !c.is_ascii_digit()).unwrap_or(args.len());
+    match args[..end].parse::<usize>() {
+        Ok(0) => Err("E939: Positive count required"),
+        Ok(n) => {
+            let len = v.get_focus().buffer().read().len();
+            Ok(((last, (last + n - 1).min(len - 1)), args[end..].trim_start()))
+        }
+        Err(_) => Ok(((first, last), args)),
+    }
+}
+
+/// The count of a quickfix command, given before it or as its argument
+fn count(range: CmdRange, args: &str) -> Option<usize> {
+    match range {
+        CmdRange::Range { end, .. } => Some(end),
+        CmdRange::CurrentLine => args.trim().parse().ok(),
+    }
+}
+
+/// Shows the error of a command that failed
+fn report(v: &mut VimInner, res: Result<(), String>) {
+    if let Err(e) = res {
+        v.message(e);
+    }
+}
+
 fn multi<'a>(
     reg: &mut VimScriptCtx<VimInner>,
     iter: impl IntoIterator<Item = &'a str>,
-    f: impl Fn(CmdRange<'_>, bool, &str, &mut VimScriptCtx<VimInner>, &mut VimInner) + 'static,
+    f: impl Fn(CmdRange, bool, &str, &mut VimScriptCtx<VimInner>, &mut VimInner) + 'static,
 ) {
     let cmd: Arc<dyn Command<VimInner>> = Arc::new(Cmd(f));
     for name in iter {
@@ -47,5 +103,394 @@ pub fn default(reg: &mut VimScriptCtx<VimInner>) {
     multi(reg, ["setg", "setglobal"], crate::options::set_global);
     multi(reg, ["wq"], |_range, _bang, _args, ctx, v| {
         ctx.run("write | quit", v).unwrap();
-    })
+    });
+    multi(
+        reg,
+        ["sy", "syn", "synt", "synta", "syntax"],
+        |_range, _bang, args, ctx, v| match args.trim() {
+            "on" | "enable" => {
+                v.set_syntax_on(true);
+                let _ = ctx.insert_var("g:syntax_on", Value::Integer(1));
+            }
+            "off" | "manual" => {
+                v.set_syntax_on(false);
+                let _ = ctx.remove_var("g:syntax_on");
+            }
+            "reset" => v.highlights_mut().reset_syntax(),
+            _ => {
+                let (res, groups) = v.get_focus().buffer().with_write(|b| {
+                    let res = b.syntax_mut().command(args);
+                    (res, b.syntax().groups().to_vec())
+                });
+                // Every group of a syntax item is a highlight group
+                for group in groups {
+                    v.highlights_mut().id(&group);
+                }
+                match res {
+                    Ok(Some(msg)) => v.show_lines(msg.lines().map(String::from).collect()),
+                    Ok(None) => (),
+                    Err(e) => v.message(e),
+                }
+            }
+        },
+    );
+    multi(reg, ["hi", "highlight"], |_range, bang, args, _ctx, v| {
+        v.sync_background();
+        match v.highlights_mut().command(bang, args) {
+            Ok(Some(msg)) => v.show_lines(msg.lines().map(String::from).collect()),
+            Ok(None) => (),
+            Err(e) => v.message(e),
+        }
+    });
+    let autocmd = ["au", "aut", "auto", "autoc", "autocm", "autocmd"];
+    multi(reg, autocmd, |_range, bang, args, _ctx, v| {
+        match v.autocmds.command(bang, args) {
+            Ok(Some(msg)) => v.show_lines(msg.lines().map(String::from).collect()),
+            Ok(None) => (),
+            Err(e) => v.message(e),
+        }
+    });
+    multi(reg, ["aug", "augr", "augro", "augrou", "augroup"], |_range, bang, args, _ctx, v| {
+        match v.autocmds.augroup(bang, args) {
+            Ok(Some(msg)) => v.message(msg),
+            Ok(None) => (),
+            Err(e) => v.message(e),
+        }
+    });
+    multi(reg, ["ta", "tag"], |range, _bang, args, _ctx, v| {
+        let count = match range {
+            CmdRange::Range { end, .. } => end,
+            CmdRange::CurrentLine => 1,
+        };
+        let res = tags::tag(v, count, args.trim());
+        report(v, res);
+    });
+    multi(reg, ["po", "pop"], |range, _bang, args, _ctx, v| {
+        let res = tags::pop(v, count(range, args).unwrap_or(1));
+        report(v, res);
+    });
+    multi(reg, ["ts", "tse", "tsel", "tsele", "tselec", "tselect"], |_range, _bang, args, _ctx, v| {
+        let res = tags::select(v, args.trim(), true);
+        report(v, res);
+    });
+    multi(reg, ["tj", "tju", "tjum", "tjump"], |_range, _bang, args, _ctx, v| {
+        let res = tags::select(v, args.trim(), false);
+        report(v, res);
+    });
+    multi(reg, ["tags"], |_range, _bang, _args, _ctx, v| {
+        let res = tags::list(v);
+        report(v, res);
+    });
+    multi(reg, ["his", "hist", "histo", "histor", "history"], |_range, _bang, args, _ctx, v| {
+        let res = super::history::list(v, args);
+        report(v, res);
+    });
+    let doautocmd = ["do", "doa", "doau", "doaut", "doauto", "doautoc", "doautocm", "doautocmd"];
+    multi(reg, doautocmd, |_range, _bang, args, ctx, v| {
+        let res = autocmd::doautocmd(v, ctx, args);
+        report(v, res);
+    });
+    multi(reg, ["colo", "colorscheme"], |_range, _bang, args, ctx, v| {
+        let name = args.trim();
+        if name.is_empty() {
+            let current = match ctx.lookup("g:colors_name") {
+                Ok(name) => name.to_string(ctx),
+                Err(_) => "default".into(),
+            };
+            v.message(current);
+            return;
+        }
+        match v.source_rtp(ctx, format!("colors/{name}.vim")) {
+            Ok(true) => (),
+            Ok(false) => v.message(format!("E185: Cannot find color scheme '{name}'")),
+            Err(e) => v.message(format!("{e:?}")),
+        }
+    });
+    multi(reg, ["mat", "matc", "match"], |range, _bang, args, _ctx, v| {
+        // `:2match` and `:3match` are parsed as a count
+        let n = match range {
+            CmdRange::CurrentLine => 1,
+            CmdRange::Range { start, end } if start == end && (1..=3).contains(&start) => start,
+            _ => return v.message("E16: Invalid range".into()),
+        };
+        let args = args.trim();
+        let pat = if args.is_empty() || args.eq_ignore_ascii_case("none") {
+            None
+        } else {
+            let (group, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            let rest = rest.trim_start();
+            if rest.is_empty() {
+                return v.message(format!("E475: Invalid argument: {args}"));
+            }
+            match split_pattern(rest) {
+                (pat, "") => Some((group, pat)),
+                (_, trailing) => {
+                    return v.message(format!("E488: Trailing characters: {trailing}"));
+                }
+            }
+        };
+        if let Err(e) = v.get_focus_mut().matches_mut().set_match(n, pat) {
+            v.message(e);
+        }
+    });
+    multi(
+        reg,
+        ["noh", "nohl", "nohls", "nohlse", "nohlsea", "nohlsear", "nohlsearc", "nohlsearch"],
+        |_range, _bang, _args, _ctx, v| crate::search::nohlsearch(v),
+    );
+    multi(
+        reg,
+        [
+            "s", "su", "sub", "subs", "subst", "substi", "substit", "substitu", "substitut",
+            "substitute",
+        ],
+        |range, _bang, args, ctx, v| {
+            let lines = range_lines(range, v);
+            substitute::command(v, ctx, lines, args, Kind::Substitute);
+        },
+    );
+    multi(reg, ["&"], |range, _bang, args, ctx, v| {
+        let lines = range_lines(range, v);
+        substitute::command(v, ctx, lines, args, Kind::Repeat);
+    });
+    multi(reg, ["~"], |range, _bang, args, ctx, v| {
+        let lines = range_lines(range, v);
+        substitute::command(v, ctx, lines, args, Kind::RepeatSearch);
+    });
+    multi(reg, ["g", "gl", "glo", "glob", "globa", "global"], |range, bang, args, ctx, v| {
+        let lines = match range {
+            CmdRange::CurrentLine => (0, v.get_focus().buffer().read().len() - 1),
+            range => range_lines(range, v),
+        };
+        global::command(v, ctx, lines, args, bang);
+    });
+    let vglobal = ["v", "vg", "vgl", "vglo", "vglob", "vgloba", "vglobal"];
+    multi(reg, vglobal, |range, _bang, args, ctx, v| {
+        let lines = match range {
+            CmdRange::CurrentLine => (0, v.get_focus().buffer().read().len() - 1),
+            range => range_lines(range, v),
+        };
+        global::command(v, ctx, lines, args, true);
+    });
+    multi(reg, ["norm", "norma", "normal"], |range, _bang, args, ctx, v| {
+        let (first, last) = range_lines(range, v);
+        if range == CmdRange::CurrentLine {
+            Vim::new(v, ctx).normal(args);
+            return;
+        }
+        for line in first..=last {
+            if line >= v.get_focus().buffer().read().len() {
+                break;
+            }
+            let win = v.get_focus_mut();
+            win.cursor_apply(Motion::SetRow(line));
+            win.cursor_apply(Motion::SetCol(0));
+            Vim::new(v, ctx).normal(args);
+        }
+    });
+    // A range without a command moves to its last line, and `:!cmd` runs a shell command, which
+    // filters the lines of the range when there is one
+    multi(reg, [""], |range, bang, args, ctx, v| {
+        if bang {
+            let res = match range {
+                CmdRange::CurrentLine => shell::execute(v, ctx, args),
+                range => shell::filter(v, ctx, range_lines(range, v), args),
+            };
+            return report(v, res);
+        }
+        let (_, last) = range_lines(range, v);
+        let col = v.get_focus().buffer().read()[last].first_char();
+        let win = v.get_focus_mut();
+        win.cursor_apply(Motion::SetRow(last));
+        win.cursor_apply(Motion::SetCol(col));
+    });
+    multi(reg, ["d", "de", "del", "dele", "delet", "delete"], |range, _bang, args, _ctx, v| {
+        let res = edit::delete(v, range, args);
+        report(v, res);
+    });
+    multi(reg, ["y", "ya", "yan", "yank"], |range, _bang, args, _ctx, v| {
+        let res = edit::yank(v, range, args);
+        report(v, res);
+    });
+    multi(reg, ["m", "mo", "mov", "move"], |range, _bang, args, _ctx, v| {
+        let res = edit::move_lines(v, range, args);
+        report(v, res);
+    });
+    multi(reg, ["t", "co", "cop", "copy"], |range, _bang, args, _ctx, v| {
+        let res = edit::copy(v, range, args);
+        report(v, res);
+    });
+    multi(reg, ["j", "jo", "joi", "join"], |range, bang, args, _ctx, v| {
+        let res = edit::join(v, range, bang, args);
+        report(v, res);
+    });
+    multi(reg, [">"], |range, _bang, args, _ctx, v| {
+        let res = edit::shift(v, range, '>', args);
+        report(v, res);
+    });
+    multi(reg, ["<"], |range, _bang, args, _ctx, v| {
+        let res = edit::shift(v, range, '<', args);
+        report(v, res);
+    });
+    multi(reg, ["p", "pr", "pri", "prin", "print"], |range, _bang, args, _ctx, v| {
+        let res = edit::print(v, range, false, args);
+        report(v, res);
+    });
+    multi(reg, ["nu", "num", "numb", "numbe", "number", "#"], |range, _bang, args, _ctx, v| {
+        let res = edit::print(v, range, true, args);
+        report(v, res);
+    });
+    multi(reg, ["pu", "put"], |range, bang, args, ctx, v| {
+        let res = edit::put(v, ctx, range, bang, args);
+        report(v, res);
+    });
+    multi(reg, ["sh", "she", "shel", "shell"], |_range, _bang, _args, _ctx, v| {
+        let res = shell::shell(v);
+        report(v, res);
+    });
+    multi(reg, ["r", "re", "rea", "read"], |range, bang, args, ctx, v| {
+        let res = edit::read(v, ctx, range, bang, args);
+        report(v, res);
+    });
+    multi(reg, ["u", "un", "und", "undo"], |_range, _bang, _args, _ctx, v| v.undo(false, 1));
+    multi(reg, ["red", "redo"], |_range, _bang, _args, _ctx, v| v.undo(true, 1));
+    multi(reg, ["fo", "fold"], |range, _bang, _args, _ctx, v| {
+        let (start, end) = range_lines(range, v);
+        if let Err(e) = v.get_focus_mut().create_fold(start, end) {
+            v.message(e.to_string());
+        }
+    });
+    multi(reg, ["foldo", "foldopen"], |range, bang, _args, _ctx, v| {
+        let (start, end) = range_lines(range, v);
+        v.get_focus_mut().set_folds_in(start..=end, true, bang);
+    });
+    multi(reg, ["foldc", "foldclose"], |range, bang, _args, _ctx, v| {
+        let (start, end) = range_lines(range, v);
+        v.get_focus_mut().set_folds_in(start..=end, false, bang);
+    });
+    quickfix_commands(reg);
+}
+
+/// The abbreviations of a command, from the shortest one that can be used
+fn abbreviations(name: &'static str, min: usize) -> impl Iterator<Item = &'static str> {
+    (min..=name.len()).map(move |n| &name[..n])
+}
+
+/// A quickfix command, which is given the list it uses
+type QuickFixCmd = fn(
+    CmdRange,
+    bool,
+    &str,
+    &mut VimScriptCtx<VimInner>,
+    &mut VimInner,
+    Which,
+) -> Result<(), String>;
+
+/// Registers a quickfix command, and the same command for location lists, which starts with an
+/// `l` instead of a `c`
+fn both(
+    reg: &mut VimScriptCtx<VimInner>,
+    (qf, qf_min): (&'static str, usize),
+    (loc, loc_min): (&'static str, usize),
+    f: QuickFixCmd,
+) {
+    let names = [(false, abbreviations(qf, qf_min)), (true, abbreviations(loc, loc_min))];
+    for (is_loc, names) in names {
+        multi(reg, names, move |range, bang, args, ctx, v| {
+            let which = Which::new(v, is_loc);
+            let res = f(range, bang, args, ctx, v, which);
+            report(v, res);
+        });
+    }
+}
+
+fn quickfix_commands(reg: &mut VimScriptCtx<VimInner>) {
+    let next: QuickFixCmd = |range, _bang, args, _ctx, v, which| {
+        quickfix::go(v, which, Target::Next(count(range, args).unwrap_or(1)))
+    };
+    both(reg, ("cnext", 2), ("lnext", 3), next);
+    let prev: QuickFixCmd = |range, _bang, args, _ctx, v, which| {
+        quickfix::go(v, which, Target::Prev(count(range, args).unwrap_or(1)))
+    };
+    both(reg, ("cprevious", 2), ("lprevious", 2), prev);
+    both(reg, ("cNext", 2), ("lNext", 2), prev);
+    both(reg, ("cc", 2), ("ll", 2), |range, _bang, args, _ctx, v, which| {
+        quickfix::go(v, which, Target::Nr(count(range, args).unwrap_or(0)))
+    });
+    let first: QuickFixCmd = |range, _bang, args, _ctx, v, which| {
+        quickfix::go(v, which, Target::Nr(count(range, args).unwrap_or(1)))
+    };
+    both(reg, ("cfirst", 4), ("lfirst", 4), first);
+    both(reg, ("crewind", 2), ("lrewind", 2), first);
+    both(reg, ("clast", 3), ("llast", 3), |range, _bang, args, _ctx, v, which| {
+        quickfix::go(v, which, count(range, args).map_or(Target::Last, Target::Nr))
+    });
+    // `:cfile` makes a new list and jumps to its first error, `:cgetfile` doesn't jump, and
+    // `:caddfile` adds to the current list
+    both(reg, ("cfile", 2), ("lfile", 2), |_range, _bang, args, _ctx, v, which| {
+        quickfix::file(v, which, Action::New, true, args)
+    });
+    both(reg, ("cgetfile", 2), ("lgetfile", 2), |_range, _bang, args, _ctx, v, which| {
+        quickfix::file(v, which, Action::New, false, args)
+    });
+    both(reg, ("caddfile", 5), ("laddfile", 5), |_range, _bang, args, _ctx, v, which| {
+        quickfix::file(v, which, Action::Add, false, args)
+    });
+    both(reg, ("cbuffer", 2), ("lbuffer", 2), |range, _bang, args, _ctx, v, which| {
+        quickfix::buffer(v, which, Action::New, true, range, args)
+    });
+    both(reg, ("cgetbuffer", 5), ("lgetbuffer", 5), |range, _bang, args, _ctx, v, which| {
+        quickfix::buffer(v, which, Action::New, false, range, args)
+    });
+    both(reg, ("caddbuffer", 5), ("laddbuffer", 5), |range, _bang, args, _ctx, v, which| {
+        quickfix::buffer(v, which, Action::Add, false, range, args)
+    });
+    both(reg, ("cexpr", 3), ("lexpr", 3), |_range, _bang, args, ctx, v, which| {
+        quickfix::expr(v, ctx, which, Action::New, true, args)
+    });
+    both(reg, ("cgetexpr", 5), ("lgetexpr", 5), |_range, _bang, args, ctx, v, which| {
+        quickfix::expr(v, ctx, which, Action::New, false, args)
+    });
+    both(reg, ("caddexpr", 3), ("laddexpr", 3), |_range, _bang, args, ctx, v, which| {
+        quickfix::expr(v, ctx, which, Action::Add, false, args)
+    });
+    both(reg, ("colder", 3), ("lolder", 3), |range, _bang, args, _ctx, v, which| {
+        quickfix::history(v, which, true, count(range, args).unwrap_or(1))
+    });
+    both(reg, ("cnewer", 4), ("lnewer", 4), |range, _bang, args, _ctx, v, which| {
+        quickfix::history(v, which, false, count(range, args).unwrap_or(1))
+    });
+    both(reg, ("copen", 4), ("lopen", 3), |_range, _bang, args, _ctx, v, which| {
+        quickfix::open(v, which, args)
+    });
+    both(reg, ("cclose", 3), ("lclose", 3), |_range, _bang, _args, _ctx, v, which| {
+        quickfix::close(v, which)
+    });
+    both(reg, ("cwindow", 2), ("lwindow", 2), |_range, _bang, args, _ctx, v, which| {
+        quickfix::window(v, which, args)
+    });
+    both(reg, ("clist", 2), ("llist", 3), |_range, bang, _args, _ctx, v, which| {
+        quickfix::list(v, which, bang)
+    });
+    both(reg, ("make", 3), ("lmake", 4), |_range, bang, args, ctx, v, which| {
+        quickfix::make(v, ctx, which, !bang, args)
+    });
+    both(reg, ("grep", 2), ("lgrep", 3), |_range, bang, args, ctx, v, which| {
+        quickfix::grep(v, ctx, which, Action::New, !bang, args)
+    });
+    both(reg, ("grepadd", 5), ("lgrepadd", 6), |_range, bang, args, ctx, v, which| {
+        quickfix::grep(v, ctx, which, Action::Add, !bang, args)
+    });
+    both(reg, ("vimgrep", 3), ("lvimgrep", 2), |_range, bang, args, ctx, v, which| {
+        quickfix::vimgrep(v, ctx, which, Action::New, !bang, args)
+    });
+    both(reg, ("vimgrepadd", 8), ("lvimgrepadd", 9), |_range, bang, args, ctx, v, which| {
+        quickfix::vimgrep(v, ctx, which, Action::Add, !bang, args)
+    });
+    both(reg, ("cdo", 3), ("ldo", 3), |range, _bang, args, ctx, v, which| {
+        quickfix::each(v, ctx, which, false, range, args)
+    });
+    both(reg, ("cfdo", 4), ("lfdo", 4), |range, _bang, args, ctx, v, which| {
+        quickfix::each(v, ctx, which, true, range, args)
+    });
 }
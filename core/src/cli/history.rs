@@ -0,0 +1,216 @@
+//
+// history.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::collections::VecDeque;
+
+use enum_map::{Enum, EnumMap};
+
+use crate::VimInner;
+
+/// The separate histories, for `:` commands, search patterns, expressions and `input()`
+#[derive(Debug, Enum, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Cmd,
+    Search,
+    Expr,
+    Input,
+}
+
+impl Kind {
+    pub const ALL: [Self; 4] = [Self::Cmd, Self::Search, Self::Expr, Self::Input];
+
+    /// The history `name` is for, which is its character or the start of its name
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            ":" => Some(Self::Cmd),
+            "/" | "?" => Some(Self::Search),
+            "=" => Some(Self::Expr),
+            "@" => Some(Self::Input),
+            "" => None,
+            name => Self::ALL.iter().copied().find(|k| k.name().starts_with(name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cmd => "cmd",
+            Self::Search => "search",
+            Self::Expr => "expr",
+            Self::Input => "input",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Entries are numbered from 1 in the order they were added
+    pub nr: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Default)]
+struct List {
+    entries: VecDeque<Entry>,
+    last: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    lists: EnumMap<Kind, List>,
+}
+
+impl History {
+    /// Adds `text` as the newest entry, moving an older copy of it, and keeping at most `max`
+    /// entries. Empty lines aren't added.
+    pub fn add(&mut self, kind: Kind, text: &str, max: usize) -> bool {
+        if text.is_empty() || max == 0 {
+            return false;
+        }
+        let list = &mut self.lists[kind];
+        list.entries.retain(|e| e.text != text);
+        list.last += 1;
+        list.entries.push_back(Entry { nr: list.last, text: text.to_string() });
+        while list.entries.len() > max {
+            list.entries.pop_front();
+        }
+        true
+    }
+
+    pub fn entries(&self, kind: Kind) -> &VecDeque<Entry> {
+        &self.lists[kind].entries
+    }
+
+    /// The number of the newest entry, or 0 when there are none
+    pub fn nr(&self, kind: Kind) -> usize {
+        self.lists[kind].entries.back().map_or(0, |e| e.nr)
+    }
+
+    /// The position of the entry numbered `index`, where a negative index counts from the newest
+    /// entry, which is -1
+    fn position(&self, kind: Kind, index: isize) -> Option<usize> {
+        let entries = &self.lists[kind].entries;
+        if index < 0 {
+            entries.len().checked_sub(index.unsigned_abs())
+        } else {
+            entries.iter().position(|e| e.nr == index as usize)
+        }
+    }
+
+    pub fn get(&self, kind: Kind, index: isize) -> Option<&str> {
+        let pos = self.position(kind, index)?;
+        Some(self.lists[kind].entries[pos].text.as_str())
+    }
+
+    pub fn remove(&mut self, kind: Kind, index: isize) -> bool {
+        let pos = self.position(kind, index);
+        pos.and_then(|pos| self.lists[kind].entries.remove(pos)).is_some()
+    }
+
+    /// Keeps the entries `f` is true for, and returns whether any were removed
+    pub fn retain(&mut self, kind: Kind, mut f: impl FnMut(&str) -> bool) -> bool {
+        let entries = &mut self.lists[kind].entries;
+        let len = entries.len();
+        entries.retain(|e| f(&e.text));
+        entries.len() != len
+    }
+
+    /// The position of the entry before (`back`) or after `pos` that starts with `prefix`. The
+    /// position after the newest entry is the line that was being typed.
+    pub fn step(&self, kind: Kind, pos: usize, prefix: &str, back: bool) -> Option<usize> {
+        let entries = &self.lists[kind].entries;
+        let matches = |i: &usize| entries[*i].text.starts_with(prefix);
+        if back {
+            (0..pos.min(entries.len())).rev().find(matches)
+        } else if pos < entries.len() {
+            (pos + 1..entries.len()).find(matches).or(Some(entries.len()))
+        } else {
+            None
+        }
+    }
+}
+
+/// `:history [{name}] [{first}][, [{last}]]`, which lists the entries of a history, or of all of
+/// them for `all`. The newest entry is marked with `>`.
+pub fn list(v: &mut VimInner, args: &str) -> Result<(), String> {
+    let args = args.trim();
+    let split = args.find(|c: char| c.is_ascii_digit() || c == '-' || c == ',');
+    let (name, range) = args.split_at(split.unwrap_or(args.len()));
+    let kinds = match name.trim() {
+        "" => vec![Kind::Cmd],
+        "all" => Kind::ALL.to_vec(),
+        name => match Kind::parse(name) {
+            Some(kind) => vec![kind],
+            None => return Err(format!("E488: Trailing characters: {name}")),
+        },
+    };
+    let bound = |s: &str| match s.trim() {
+        "" => Ok(None),
+        s => s.parse::<isize>().map(Some).map_err(|_| format!("E488: Trailing characters: {s}")),
+    };
+    let (first, last) = range.split_once(',').unwrap_or((range, range));
+    let (first, last) = (bound(first)?, bound(last)?);
+    let mut lines = vec![];
+    for kind in kinds {
+        let entries = v.cli.history().entries(kind);
+        // A negative index counts back from the newest entry
+        let nr = |i: isize| match entries.len().checked_sub(i.unsigned_abs()) {
+            _ if i >= 0 => i as usize,
+            pos => pos.map_or(0, |p| entries[p].nr),
+        };
+        let (first, last) = (first.map_or(0, nr), last.map_or(usize::MAX, nr));
+        lines.push(format!("      #  {} history", kind.name()));
+        for (i, entry) in entries.iter().enumerate() {
+            if (first..=last).contains(&entry.nr) {
+                let mark = if i + 1 == entries.len() { '>' } else { ' ' };
+                lines.push(format!("{mark}{:6}  {}", entry.nr, entry.text));
+            }
+        }
+    }
+    v.show_lines(lines);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let mut h = History::default();
+        assert!(!h.add(Kind::Cmd, "", 3));
+        for text in ["ls", "set ts=4", "ls", "echo 1", "set sw=2"] {
+            h.add(Kind::Cmd, text, 3);
+        }
+        let texts: Vec<_> = h.entries(Kind::Cmd).iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, ["ls", "echo 1", "set sw=2"]);
+        assert_eq!(h.nr(Kind::Cmd), 5);
+        assert_eq!(h.nr(Kind::Search), 0);
+        assert_eq!(h.get(Kind::Cmd, -1), Some("set sw=2"));
+        assert_eq!(h.get(Kind::Cmd, 3), Some("ls"));
+        assert_eq!(h.get(Kind::Cmd, 1), None);
+        assert!(h.remove(Kind::Cmd, 4));
+        assert!(h.retain(Kind::Cmd, |t| !t.starts_with("set")));
+        assert_eq!(h.get(Kind::Cmd, -1), Some("ls"));
+        assert_eq!(Kind::parse("se"), Some(Kind::Search));
+        assert_eq!(Kind::parse("@"), Some(Kind::Input));
+        assert_eq!(Kind::parse("x"), None);
+    }
+
+    #[test]
+    fn steps() {
+        let mut h = History::default();
+        for text in ["set ts=4", "ls", "set sw=2"] {
+            h.add(Kind::Cmd, text, 10);
+        }
+        assert_eq!(h.step(Kind::Cmd, 3, "se", true), Some(2));
+        assert_eq!(h.step(Kind::Cmd, 2, "se", true), Some(0));
+        assert_eq!(h.step(Kind::Cmd, 0, "se", true), None);
+        assert_eq!(h.step(Kind::Cmd, 0, "se", false), Some(2));
+        assert_eq!(h.step(Kind::Cmd, 2, "se", false), Some(3));
+        assert_eq!(h.step(Kind::Cmd, 3, "se", false), None);
+        assert_eq!(h.step(Kind::Cmd, 2, "", true), Some(1));
+    }
+}
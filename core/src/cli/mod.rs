@@ -5,22 +5,33 @@
 //
 
 pub(crate) mod commands;
+pub(crate) mod history;
+pub(crate) mod more;
+pub(crate) mod wild;
 
 use std::fmt::Debug;
 
 use crossterm::{
     cursor::CursorShape,
-    event::{KeyEvent, KeyModifiers},
+    event::{KeyCode, KeyEvent, KeyModifiers},
     terminal::{Clear, ClearType},
     QueueableCommand,
 };
 use enum_map::Enum;
 
-use crate::{cursor::Cursor, keymap::Action, util::Area, EventReader, Renderable};
+use crate::{
+    cursor::Cursor, highlight::Highlights, keymap::Action, options::Options, search,
+    util::Area, width::WidthOpts, EventReader, Renderable, VimInner,
+};
 
 #[derive(Debug, Enum, PartialEq, Eq, Clone, Copy)]
 pub enum Cli {
     Command,
+    /// A search forward (`/`) or backward (`?`)
+    Search,
+    SearchBack,
+    /// A line asked for by `input()`, after its prompt
+    Input,
     Message,
 }
 
@@ -28,25 +39,70 @@ impl Cli {
     pub fn character(&self) -> char {
         match self {
             Self::Command => ':',
-            Self::Message => ' ',
+            Self::Search => '/',
+            Self::SearchBack => '?',
+            Self::Input | Self::Message => ' ',
+        }
+    }
+
+    /// The history of the lines typed
+    pub fn history(&self) -> Option<history::Kind> {
+        match self {
+            Self::Command => Some(history::Kind::Cmd),
+            Self::Search | Self::SearchBack => Some(history::Kind::Search),
+            Self::Input => Some(history::Kind::Input),
+            Self::Message => None,
         }
     }
 }
 
 pub enum CliAction {
     Esc,
-    Execute(String),
+    Execute(Cli, String),
+    /// The text was edited
+    Changed,
+    /// A key that completes the text
+    Wild(wild::Key),
     None,
 }
 
+/// Asks for a line for `input()`, and gives it once <CR> is typed. <Esc> gives an empty line.
+pub fn input(v: &mut VimInner, prompt: &str, text: &str) -> crate::Result<String> {
+    let state = v.state;
+    v.start_cli(Cli::Input);
+    v.cli.start_input(prompt, text);
+    let line = loop {
+        let key = v.wait_key()?;
+        match v.cli.on_key(key) {
+            CliAction::Execute(ty, line) => {
+                v.add_history(ty, &line);
+                break line;
+            }
+            CliAction::Esc => break String::new(),
+            _ => (),
+        }
+    };
+    v.cli.end();
+    v.state = state;
+    Ok(line)
+}
+
 impl Action for CliAction {
     fn run(&self, state: &mut crate::Vim) {
         match self {
             Self::None => (),
             Self::Esc => state.end_cli(),
-            Self::Execute(line) => {
+            Self::Changed => search::preview(state),
+            Self::Wild(key) => wild::key(state.inner, state.ctx, *key),
+            Self::Execute(ty, line) => {
                 state.end_cli();
-                state.execute(line);
+                state.add_history(*ty, line);
+                match ty {
+                    Cli::Search | Cli::SearchBack => {
+                        search::command(state, *ty == Cli::Search, line)
+                    }
+                    _ => state.execute(line),
+                }
             },
         }
     }
@@ -56,6 +112,19 @@ pub struct CliState {
     cur: Cli,
     cmd: (String, String),
     area: Area,
+    width: WidthOpts,
+    wild: Option<wild::Wild>,
+    /// The rows above the command line the matches were drawn on
+    wild_rows: usize,
+    /// The rows that are cleared before the windows are drawn
+    clear_rows: usize,
+    /// The prompt of `input()`
+    prompt: String,
+    history: history::History,
+    /// The line that was typed, and the position in the history while going through it
+    recall: Option<((String, String), usize)>,
+    /// The listing shown above the command line
+    more: more::More,
 }
 
 impl CliState {
@@ -64,16 +133,64 @@ impl CliState {
             cur: Cli::Message,
             cmd: Default::default(),
             area: Area::default(),
+            width: WidthOpts::default(),
+            wild: None,
+            wild_rows: 0,
+            clear_rows: 0,
+            prompt: String::new(),
+            history: history::History::default(),
+            recall: None,
+            more: more::More::default(),
         }
     }
 
     pub fn start(&mut self, ty: Cli) {
         self.cur = ty;
         self.cmd = Default::default();
+        self.wild = None;
+        self.recall = None;
+    }
+
+    /// Starts the line of `input()`, with `text` already typed
+    pub fn start_input(&mut self, prompt: &str, text: &str) {
+        self.start(Cli::Input);
+        self.prompt = prompt.to_string();
+        self.cmd.0 = text.to_string();
+    }
+
+    pub fn history(&self) -> &history::History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut history::History {
+        &mut self.history
+    }
+
+    /// What is shown before the text
+    fn prompt(&self) -> String {
+        match self.cur {
+            Cli::Input => self.prompt.clone(),
+            cur => cur.character().to_string(),
+        }
+    }
+
+    /// Types `text` at the cursor
+    pub fn insert(&mut self, text: &str) {
+        self.cmd.0.push_str(text);
     }
 
     pub fn end(&mut self) {
         self.cur = Cli::Message;
+        self.wild = None;
+    }
+
+    pub fn ty(&self) -> Cli {
+        self.cur
+    }
+
+    /// The text typed so far
+    pub fn text(&self) -> String {
+        format!("{}{}", self.cmd.0, self.cmd.1)
     }
 
     pub fn get_message(&self) -> &str {
@@ -84,9 +201,103 @@ impl CliState {
         }
     }
 
+    /// Shows a message, which is added to the listing when one is shown
     pub fn message(&mut self, message: String) {
+        if !self.more.is_empty() && self.cur == Cli::Message {
+            if !message.is_empty() {
+                self.more.push([message]);
+            }
+            return;
+        }
         self.cur = Cli::Message;
         self.cmd = (message, String::new());
+        self.wild = None;
+    }
+
+    /// Shows `lines` above the command line until a key is typed, after the message that is shown.
+    /// A single line is shown as a message.
+    pub fn show_lines(&mut self, mut lines: Vec<String>) {
+        if self.more.is_empty() && lines.len() <= 1 {
+            return self.message(lines.pop().unwrap_or_default());
+        }
+        let message = std::mem::take(&mut self.cmd.0);
+        if self.cur == Cli::Message && !message.is_empty() {
+            self.more.push([message]);
+        }
+        self.message(String::new());
+        self.more.push(lines);
+    }
+
+    /// Whether a listing is shown, which waits for a key
+    pub fn listing(&self) -> bool {
+        !self.more.is_empty()
+    }
+
+    /// Takes the lines of the listing away without showing them
+    pub fn take_lines(&mut self) -> Vec<String> {
+        self.more.take()
+    }
+
+    /// Handles a key typed while a listing is shown, and returns whether it was used. The rows
+    /// of the listing are cleared once it ends.
+    pub fn more_key(&mut self, key: KeyEvent) -> bool {
+        let used = self.more.key(key);
+        if self.more.is_empty() {
+            self.clear_rows = self.area.y;
+        }
+        used
+    }
+
+    /// The completion action of a key on the `:` line. Any other key ends the completion.
+    fn wild_key(&mut self, key: KeyEvent) -> Option<CliAction> {
+        if self.cur != Cli::Command {
+            return None;
+        }
+        let ctrl = key.modifiers == KeyModifiers::CONTROL;
+        let wild_key = match (key.code, &self.wild) {
+            (KeyCode::Tab, _) => wild::Key::Tab(true),
+            (KeyCode::BackTab, _) => wild::Key::Tab(false),
+            (KeyCode::Char('d'), _) if ctrl => wild::Key::List,
+            (_, None) => return None,
+            (KeyCode::Char('n'), _) if ctrl => wild::Key::Move(1),
+            (KeyCode::Char('p'), _) if ctrl => wild::Key::Move(-1),
+            (KeyCode::Char('e'), _) if ctrl => wild::Key::Cancel,
+            (KeyCode::Char('y'), _) if ctrl => wild::Key::Accept,
+            (KeyCode::Right, Some(w)) if w.menu() => wild::Key::Move(1),
+            (KeyCode::Left, Some(w)) if w.menu() => wild::Key::Move(-1),
+            (KeyCode::Down, Some(w)) if w.pum() => wild::Key::Move(1),
+            (KeyCode::Up, Some(w)) if w.pum() => wild::Key::Move(-1),
+            _ => {
+                self.wild = None;
+                return None;
+            }
+        };
+        Some(CliAction::Wild(wild_key))
+    }
+
+    /// Replaces the line with an older (`back`) or newer entry of its history. With `filter`, only
+    /// the entries that start with the text typed before the cursor are used.
+    fn recall(
+        &mut self,
+        recall: Option<((String, String), usize)>,
+        back: bool,
+        filter: bool,
+    ) -> CliAction {
+        let Some(kind) = self.cur.history() else {
+            return CliAction::None;
+        };
+        let (typed, mut pos) =
+            recall.unwrap_or_else(|| (self.cmd.clone(), self.history.entries(kind).len()));
+        let prefix = if filter { typed.0.as_str() } else { "" };
+        if let Some(new) = self.history.step(kind, pos, prefix, back) {
+            pos = new;
+            self.cmd = match self.history.entries(kind).get(pos) {
+                Some(entry) => (entry.text.clone(), String::new()),
+                None => typed.clone(),
+            };
+        }
+        self.recall = Some((typed, pos));
+        CliAction::Changed
     }
 }
 
@@ -94,19 +305,37 @@ impl EventReader for CliState {
     type Act = CliAction;
 
     fn on_key(&mut self, key: crossterm::event::KeyEvent) -> Self::Act {
+        if let Some(act) = self.wild_key(key) {
+            return act;
+        }
+        let recall = self.recall.take();
         let KeyEvent { code, modifiers } = key;
-        if modifiers == KeyModifiers::empty() {
+        match (code, modifiers) {
+            (KeyCode::Up, KeyModifiers::SHIFT) | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                return self.recall(recall, true, false)
+            }
+            (KeyCode::Down, KeyModifiers::SHIFT) | (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
+                return self.recall(recall, false, false)
+            }
+            _ => (),
+        }
+        // Capitals and symbols are reported with shift
+        let shifted_char =
+            modifiers == KeyModifiers::SHIFT && matches!(code, crossterm::event::KeyCode::Char(_));
+        if modifiers == KeyModifiers::empty() || shifted_char {
             match code {
                 crossterm::event::KeyCode::Char(ch) => {
                     self.cmd.0.push(ch);
+                    return CliAction::Changed;
                 }
                 crossterm::event::KeyCode::Backspace => {
                     self.cmd.0.pop();
+                    return CliAction::Changed;
                 }
                 crossterm::event::KeyCode::Enter => {
                     self.cmd.0.push_str(self.cmd.1.as_str());
                     self.cmd.1.clear();
-                    return CliAction::Execute(std::mem::take(&mut self.cmd.0));
+                    return CliAction::Execute(self.cur, std::mem::take(&mut self.cmd.0));
                 }
                 crossterm::event::KeyCode::Left => {
                     if let Some(ch) = self.cmd.0.pop() {
@@ -118,8 +347,8 @@ impl EventReader for CliState {
                         self.cmd.0.push(self.cmd.1.remove(0));
                     }
                 }
-                crossterm::event::KeyCode::Up => todo!("History"),
-                crossterm::event::KeyCode::Down => todo!("History"),
+                crossterm::event::KeyCode::Up => return self.recall(recall, true, true),
+                crossterm::event::KeyCode::Down => return self.recall(recall, false, true),
                 crossterm::event::KeyCode::Home => {
                     self.cmd.1.insert_str(0, self.cmd.0.as_str());
                     self.cmd.0.clear();
@@ -128,13 +357,15 @@ impl EventReader for CliState {
                     self.cmd.0.push_str(self.cmd.1.as_str());
                     self.cmd.1.clear();
                 }
-                crossterm::event::KeyCode::PageUp => todo!(),
-                crossterm::event::KeyCode::PageDown => todo!(),
-                crossterm::event::KeyCode::Tab => todo!("Completion"),
-                crossterm::event::KeyCode::BackTab => todo!("Completion"),
+                crossterm::event::KeyCode::PageUp => return self.recall(recall, true, false),
+                crossterm::event::KeyCode::PageDown => return self.recall(recall, false, false),
+                // Only the `:` line is completed
+                crossterm::event::KeyCode::Tab => (),
+                crossterm::event::KeyCode::BackTab => (),
                 crossterm::event::KeyCode::Delete => {
                     if !self.cmd.1.is_empty() {
                         self.cmd.1.remove(0);
+                        return CliAction::Changed;
                     }
                 }
                 crossterm::event::KeyCode::Insert => (),
@@ -165,17 +396,43 @@ impl Renderable for CliState {
     }
 
     fn cursor_pos(&self) -> Cursor {
+        if self.listing() {
+            let x = self.width.str_width(self.more.prompt());
+            return Cursor::from_params(self.area.x + x, self.area.y, CursorShape::Line);
+        }
+        let start = self.width.display_width(&self.prompt(), 0);
         Cursor::from_params(
-            self.area.x + 1 + self.cmd.0.len(),
+            self.area.x + start + self.width.display_width(&self.cmd.0, start),
             self.area.y,
             CursorShape::Line,
         )
     }
 
-    fn draw<W: std::io::Write>(&mut self, term: &mut W) -> crossterm::Result<()> {
+    fn draw<W: std::io::Write>(
+        &mut self,
+        term: &mut W,
+        options: &Options,
+        highlights: &Highlights,
+    ) -> crossterm::Result<()> {
+        self.width = WidthOpts::new(options);
+        if self.listing() {
+            self.more.draw(self.area, &self.width, term)?;
+            self.area.pos().move_cursor(term)?;
+            term.queue(Clear(ClearType::CurrentLine))?;
+            let prompt = highlights.style_of("MoreMsg").apply(self.more.prompt());
+            return write!(term, "{prompt}");
+        }
         self.area.pos().move_cursor(term)?;
         term.queue(Clear(ClearType::CurrentLine))?;
-        write!(term, "{}{}{}", self.cur.character(), self.cmd.0, self.cmd.1)?;
+        let prompt = self.prompt();
+        write!(term, "{prompt}")?;
+        let start = self.width.display_width(&prompt, 0);
+        let end = start + self.width.display_width(&self.cmd.0, start);
+        for cell in
+            self.width.cells_from(&self.cmd.0, start).chain(self.width.cells_from(&self.cmd.1, end))
+        {
+            write!(term, "{}", cell.display())?;
+        }
         Ok(())
     }
 }
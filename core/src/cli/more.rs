@@ -0,0 +1,162 @@
+//
+// more.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::io::Write;
+
+use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::QueueableCommand;
+
+use crate::util::{Area, Pos};
+use crate::width::WidthOpts;
+use crate::Result;
+
+const ENTER: &str = "Press ENTER or type command to continue";
+const MORE: &str = "-- More --";
+
+/// The lines of a listing like `:history` or `:clist`, shown above the command line until a key
+/// is typed. Lines that don't fit on the screen are shown a page at a time.
+#[derive(Default)]
+pub struct More {
+    lines: Vec<String>,
+    /// The first row of the wrapped lines on the screen
+    top: usize,
+    /// The number of rows the wrapped lines take, and the number on the screen
+    total: usize,
+    shown: usize,
+}
+
+impl More {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn push(&mut self, lines: impl IntoIterator<Item = String>) {
+        self.lines.extend(lines);
+    }
+
+    /// Takes the lines away, which ends the listing
+    pub fn take(&mut self) -> Vec<String> {
+        self.top = 0;
+        self.total = 0;
+        self.shown = 0;
+        std::mem::take(&mut self.lines)
+    }
+
+    /// Whether there are rows after the ones on the screen
+    fn paging(&self) -> bool {
+        self.top + self.shown < self.total
+    }
+
+    /// The prompt shown on the command line
+    pub fn prompt(&self) -> &'static str {
+        match self.paging() {
+            true => MORE,
+            false => ENTER,
+        }
+    }
+
+    /// Handles a key typed while the lines are shown, and returns whether it was used. At
+    /// `-- More --` <CR> and j show one more row, <Space> and f the next page, and any other key
+    /// ends the listing. Once the last row is shown any key ends it, and keys other than <CR>,
+    /// <Space> and <Esc> are run like they were typed after it.
+    pub fn key(&mut self, key: KeyEvent) -> bool {
+        if self.paging() {
+            match key.code {
+                KeyCode::Enter | KeyCode::Down | KeyCode::Char('j') => self.top += 1,
+                KeyCode::Char(' ') | KeyCode::PageDown | KeyCode::Char('f') => {
+                    self.top += self.shown
+                }
+                _ => drop(self.take()),
+            }
+            true
+        } else {
+            self.take();
+            matches!(key.code, KeyCode::Enter | KeyCode::Char(' ') | KeyCode::Esc)
+        }
+    }
+
+    /// Draws the lines on the rows above the command line at `area`. When they fit they end
+    /// right above it, and otherwise they fill the rows from the top of the screen.
+    pub fn draw<W: Write>(&mut self, area: Area, width: &WidthOpts, term: &mut W) -> Result<()> {
+        let rows = wrap(&self.lines, width, area.w);
+        self.total = rows.len();
+        self.shown = self.total.min(area.y);
+        self.top = self.top.min(self.total - self.shown);
+        let first = area.y - self.shown;
+        for (i, row) in rows[self.top..self.top + self.shown].iter().enumerate() {
+            Pos(0, first + i).move_cursor(term)?;
+            term.queue(Clear(ClearType::CurrentLine))?;
+            write!(term, "{row}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits `lines` into the rows they take on a screen `w` columns wide
+fn wrap(lines: &[String], width: &WidthOpts, w: usize) -> Vec<String> {
+    let mut rows = vec![];
+    for line in lines {
+        let mut row = String::new();
+        let mut col = 0;
+        for cell in width.cells(line) {
+            if col + cell.width > w && col > 0 {
+                rows.push(std::mem::take(&mut row));
+                col = 0;
+            }
+            row.push_str(&cell.display());
+            col += cell.width;
+        }
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn key(c: KeyCode) -> KeyEvent {
+        KeyEvent::new(c, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn wraps() {
+        let width = WidthOpts::default();
+        let lines = vec!["abcdef".to_string(), "".to_string(), "ab".to_string()];
+        assert_eq!(wrap(&lines, &width, 4), ["abcd", "ef", "", "ab"]);
+        assert_eq!(wrap(&["a\tb\nc".to_string()], &width, 80), ["a       b^Jc"]);
+    }
+
+    #[test]
+    fn pages() {
+        let area = Pos(0, 23).area(80, 1);
+        let width = WidthOpts::default();
+        let mut more = More::default();
+        more.push((0..30).map(|i| i.to_string()));
+        more.draw(area, &width, &mut vec![]).unwrap();
+        assert_eq!(more.prompt(), MORE);
+        assert!(more.key(key(KeyCode::Enter)));
+        assert!(more.key(key(KeyCode::Char(' '))));
+        more.draw(area, &width, &mut vec![]).unwrap();
+        assert_eq!((more.top, more.shown), (7, 23));
+        assert_eq!(more.prompt(), ENTER);
+        assert!(!more.key(key(KeyCode::Char(':'))));
+        assert!(more.is_empty());
+
+        more.push(["a".to_string(), "b".to_string()]);
+        more.draw(area, &width, &mut vec![]).unwrap();
+        assert_eq!((more.top, more.shown), (0, 2));
+        assert!(more.key(key(KeyCode::Enter)));
+        assert!(more.is_empty());
+
+        more.push((0..30).map(|i| i.to_string()));
+        more.draw(area, &width, &mut vec![]).unwrap();
+        assert!(more.key(key(KeyCode::Char('q'))));
+        assert!(more.is_empty());
+    }
+}
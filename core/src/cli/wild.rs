@@ -0,0 +1,713 @@
+//
+// wild.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::env;
+use std::io::Write;
+
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::QueueableCommand;
+
+use vimscript::regex::{self, Regex};
+use vimscript::{ValueRef, VimScriptCtx};
+
+use crate::complete::{fit, has_prefix};
+use crate::glob;
+use crate::options::{BufOptions, Options, Opts, WinOptions};
+use crate::util::Pos;
+use crate::width::WidthOpts;
+use crate::{Result, VimInner};
+
+/// What the text before the cursor is completed as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind<'a> {
+    Command,
+    Option,
+    /// An option after `no` or `inv`
+    BoolOption,
+    /// The value of an option, after `=`
+    OptionValue(&'a str),
+    File,
+    Dir,
+    Buffer,
+    Highlight,
+    Function,
+    /// The functions that were defined with `:function`
+    UserFunction,
+    /// Functions and variables
+    Expression,
+    Variable,
+}
+
+/// The commands whose arguments are completed, with the length of their shortest abbreviation
+const ARGS: &[(&str, usize, Kind<'static>)] = &[
+    ("set", 2, Kind::Option),
+    ("setlocal", 4, Kind::Option),
+    ("setglobal", 4, Kind::Option),
+    ("edit", 1, Kind::File),
+    ("split", 2, Kind::File),
+    ("vsplit", 2, Kind::File),
+    ("new", 3, Kind::File),
+    ("vnew", 3, Kind::File),
+    ("tabedit", 4, Kind::File),
+    ("write", 1, Kind::File),
+    ("wq", 2, Kind::File),
+    ("read", 1, Kind::File),
+    ("source", 2, Kind::File),
+    ("cfile", 2, Kind::File),
+    ("lfile", 2, Kind::File),
+    ("cgetfile", 2, Kind::File),
+    ("lgetfile", 2, Kind::File),
+    ("caddfile", 5, Kind::File),
+    ("laddfile", 5, Kind::File),
+    ("make", 3, Kind::File),
+    ("lmake", 4, Kind::File),
+    ("grep", 2, Kind::File),
+    ("lgrep", 3, Kind::File),
+    ("grepadd", 5, Kind::File),
+    ("lgrepadd", 6, Kind::File),
+    ("vimgrep", 3, Kind::File),
+    ("lvimgrep", 2, Kind::File),
+    ("vimgrepadd", 8, Kind::File),
+    ("lvimgrepadd", 9, Kind::File),
+    ("cd", 2, Kind::Dir),
+    ("lcd", 2, Kind::Dir),
+    ("tcd", 2, Kind::Dir),
+    ("buffer", 1, Kind::Buffer),
+    ("sbuffer", 2, Kind::Buffer),
+    ("bdelete", 2, Kind::Buffer),
+    ("bunload", 3, Kind::Buffer),
+    ("bwipeout", 2, Kind::Buffer),
+    ("highlight", 2, Kind::Highlight),
+    ("match", 3, Kind::Highlight),
+    ("call", 3, Kind::Function),
+    ("function", 2, Kind::UserFunction),
+    ("delfunction", 4, Kind::UserFunction),
+    ("echo", 2, Kind::Expression),
+    ("execute", 3, Kind::Expression),
+    ("let", 3, Kind::Expression),
+    ("if", 2, Kind::Expression),
+    ("elseif", 5, Kind::Expression),
+    ("while", 2, Kind::Expression),
+    ("return", 3, Kind::Expression),
+    ("cexpr", 3, Kind::Expression),
+    ("lexpr", 3, Kind::Expression),
+    ("unlet", 3, Kind::Variable),
+];
+
+/// The length of the range before a command, or None when the cursor is in a pattern or mark
+/// of the range
+fn range_len(line: &str) -> Option<usize> {
+    let start = line.len() - line.trim_start_matches([' ', '\t', ':']).len();
+    // A pattern that isn't closed yet takes the rest of the line, so a newline is added to see
+    // whether the cursor is still in it
+    let probe = format!("{}\n", &line[start..]);
+    let (_, rest) = vimscript::split_range(&probe).ok()?;
+    (!rest.is_empty()).then(|| line.len() + 1 - rest.len())
+}
+
+/// The start of the last argument, after a space that isn't escaped with a backslash
+fn last_arg(line: &str, from: usize) -> usize {
+    let mut start = from;
+    let mut escaped = false;
+    for (i, c) in line[from..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c.is_whitespace() => start = from + i + c.len_utf8(),
+            _ => (),
+        }
+    }
+    start
+}
+
+/// Finds what the text before the cursor is completed as, and where that text starts
+fn context(line: &str) -> Option<(usize, Kind<'_>)> {
+    // Only the command after the last `|` is completed
+    let cmd = line.rfind('|').map_or(0, |i| i + 1);
+    let name = cmd + range_len(&line[cmd..])?;
+    let args = line[name..]
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .map_or(line.len(), |i| name + i);
+    if args == line.len() {
+        let is_name = line[name..].chars().all(|c| c.is_ascii_alphabetic());
+        return is_name.then_some((name, Kind::Command));
+    }
+    let typed = &line[name..args];
+    let &(_, _, kind) = ARGS
+        .iter()
+        .find(|(full, min, _)| typed.len() >= *min && full.starts_with(typed))?;
+    let args = args + line[args..].starts_with('!') as usize;
+    let word = last_arg(line, args);
+    let text = &line[word..];
+    Some(match kind {
+        Kind::Option => match text.find('=') {
+            Some(eq) => {
+                let option = text[..eq].trim_end_matches(['+', '-', '^']);
+                (word + eq + 1, Kind::OptionValue(option))
+            }
+            None if text.starts_with("inv") => (word + 3, Kind::BoolOption),
+            None if text.starts_with("no") => (word + 2, Kind::BoolOption),
+            None => (word, Kind::Option),
+        },
+        // Buffer names can have spaces
+        Kind::Buffer => (line.len() - line[args..].trim_start().len(), kind),
+        Kind::Function | Kind::UserFunction | Kind::Expression | Kind::Variable => {
+            let ident = line[args..]
+                .char_indices()
+                .rev()
+                .take_while(|(_, c)| c.is_alphanumeric() || matches!(c, '_' | ':' | '#'))
+                .last()
+                .map_or(line.len(), |(i, _)| args + i);
+            (ident, kind)
+        }
+        kind => (word, kind),
+    })
+}
+
+fn escape(name: &str) -> String {
+    name.replace(' ', "\\ ")
+}
+
+/// The value of option `name`, looked up like `:set` does, and whether it is a boolean
+fn option(v: &VimInner, name: &str) -> Option<(String, bool)> {
+    let show = |val: ValueRef| (val.to_string(), matches!(val, ValueRef::Bool(_)));
+    v.options()
+        .get(name)
+        .map(show)
+        .or_else(|_| v.get_focus().options().get(name).map(show))
+        .or_else(|_| {
+            v.get_focus()
+                .buffer()
+                .with_read(|b| b.options().get(name).map(show))
+        })
+        .ok()
+}
+
+/// The files and directories whose names start with `text`, without the ones that match
+/// 'wildignore', and with the ones that end in 'suffixes' last
+fn files(v: &VimInner, text: &str, dirs: bool) -> Vec<String> {
+    let opts = v.options();
+    let ignorecase = opts.wildignorecase || opts.fileignorecase;
+    let re_opts = regex::Options {
+        ignorecase,
+        ..regex::Options::default()
+    };
+    let ignore: Vec<_> = opts
+        .wildignore
+        .split(',')
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| Regex::new(&glob::pattern(p), &re_opts).ok())
+        .collect();
+    let home = env::var("HOME").unwrap_or_default();
+    let text = text.replace("\\ ", " ");
+    let (mut ret, mut last) = (vec![], vec![]);
+    for path in glob::glob(&format!("{text}*"), ignorecase) {
+        let is_dir = path.is_dir();
+        let mut name = path.display().to_string();
+        if (dirs && !is_dir) || ignore.iter().any(|re| re.is_match(&name)) {
+            continue;
+        }
+        if text.starts_with('~') && !home.is_empty() {
+            name = name.replacen(&home, "~", 1);
+        }
+        if is_dir {
+            name.push('/');
+        }
+        let suffix = opts
+            .suffixes
+            .split(',')
+            .any(|s| !s.is_empty() && name.ends_with(s));
+        match suffix && !is_dir {
+            true => last.push(escape(&name)),
+            false => ret.push(escape(&name)),
+        }
+    }
+    ret.extend(last);
+    ret
+}
+
+/// The names of the buffers that contain `text`. With `lastused` the current buffer is last,
+/// after the buffers in a window, since those were used more recently than hidden ones.
+fn buffers(v: &VimInner, text: &str, lastused: bool) -> Vec<String> {
+    let mut buffers = v.buffers.clone();
+    if lastused {
+        let current = v.get_focus().buffer().id();
+        let mut shown = vec![];
+        v.windows.for_each(&mut |w| shown.push(w.buffer().id()));
+        buffers.sort_by_key(|b| (b.id() == current, !shown.contains(&b.id())));
+    }
+    buffers
+        .iter()
+        .filter_map(|b| Some(b.read().filename()?.display().to_string()))
+        .filter(|name| name.contains(text))
+        .map(|name| escape(&name))
+        .collect()
+}
+
+/// The matches of `text`, which is completed as `kind`
+fn matches(
+    v: &VimInner,
+    ctx: &VimScriptCtx<VimInner>,
+    kind: Kind,
+    text: &str,
+    lastused: bool,
+) -> Vec<String> {
+    let functions = || {
+        ctx.function_names()
+            .filter(|f| f.starts_with(text))
+            .map(|f| format!("{f}("))
+    };
+    let variables = || {
+        ctx.variable_names()
+            .filter(|n| n.starts_with(text))
+            .map(String::from)
+    };
+    let mut ret: Vec<String> = match kind {
+        Kind::Command => ctx
+            .command_names()
+            .into_iter()
+            .filter(|c| c.starts_with(text) && c.starts_with(|c: char| c.is_alphabetic()))
+            .map(String::from)
+            .collect(),
+        Kind::Option | Kind::BoolOption => Options::list()
+            .iter()
+            .chain(WinOptions::list())
+            .chain(BufOptions::list())
+            .filter(|o| o.starts_with(text))
+            .filter(|o| kind == Kind::Option || option(v, o).is_some_and(|(_, b)| b))
+            .map(|o| o.to_string())
+            .collect(),
+        Kind::OptionValue(name) => {
+            return option(v, name)
+                .map(|(val, _)| escape(&val))
+                .filter(|val| val.starts_with(text))
+                .into_iter()
+                .collect();
+        }
+        Kind::File | Kind::Dir => return files(v, text, kind == Kind::Dir),
+        Kind::Buffer => return buffers(v, text, lastused),
+        Kind::Highlight => (0..v.highlights.len())
+            .map(|id| v.highlights.name(id))
+            .filter(|name| !name.is_empty() && has_prefix(name, text, true))
+            .map(String::from)
+            .collect(),
+        Kind::Function => functions().collect(),
+        // Other functions must start with a capital or a namespace
+        Kind::UserFunction => ctx
+            .function_names()
+            .filter(|f| f.starts_with(text) && !f.starts_with(char::is_lowercase))
+            .map(String::from)
+            .collect(),
+        Kind::Expression => functions().chain(variables()).collect(),
+        Kind::Variable => variables().collect(),
+    };
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+/// The text all of `matches` start with
+fn longest(matches: &[String]) -> String {
+    let Some(first) = matches.first() else {
+        return String::new();
+    };
+    let mut len = first.len();
+    for m in &matches[1..] {
+        len = first
+            .char_indices()
+            .zip(m.chars())
+            .take_while(|((i, a), b)| *i < len && a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8());
+    }
+    first[..len].to_string()
+}
+
+/// A part of 'wildmode'
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Full,
+    Longest,
+    List,
+    LastUsed,
+}
+
+/// The steps each <Tab> takes, where the last one is repeated
+fn stages(wildmode: &str) -> Vec<Vec<Step>> {
+    let stages: Vec<Vec<_>> = wildmode
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|stage| {
+            stage
+                .split(':')
+                .filter_map(|step| match step {
+                    "full" => Some(Step::Full),
+                    "longest" => Some(Step::Longest),
+                    "list" => Some(Step::List),
+                    "lastused" => Some(Step::LastUsed),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+    match stages.is_empty() {
+        true => vec![vec![Step::Full]],
+        false => stages,
+    }
+}
+
+/// How the matches are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shown {
+    Nothing,
+    /// The 'wildmenu' on the status line
+    Menu,
+    /// A popup menu, with 'wildoptions' "pum"
+    Pum,
+    /// Every match, above the command line
+    List,
+}
+
+/// The completion of the text before the cursor on the command line
+#[derive(Debug)]
+pub struct Wild {
+    /// Where the completed text starts
+    start: usize,
+    /// The text before a match was put in
+    original: String,
+    matches: Vec<String>,
+    selected: Option<usize>,
+    /// The part of 'wildmode' the next <Tab> uses
+    stage: usize,
+    shown: Shown,
+    /// Files are shown without their directory
+    files: bool,
+}
+
+impl Wild {
+    /// Whether the matches are in the 'wildmenu', where <Left> and <Right> select them
+    pub fn menu(&self) -> bool {
+        self.shown == Shown::Menu
+    }
+
+    /// Whether the matches are in a popup menu, where <Up> and <Down> select them
+    pub fn pum(&self) -> bool {
+        self.shown == Shown::Pum
+    }
+
+    /// The name that is shown for a match
+    fn name<'a>(&self, m: &'a str) -> &'a str {
+        let dir = m.strip_suffix('/').unwrap_or(m);
+        match self.files {
+            true => &m[dir.rfind('/').map_or(0, |i| i + 1)..],
+            false => m,
+        }
+    }
+}
+
+/// The keys that complete the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// <Tab> or <S-Tab>, which take the next step of 'wildmode'
+    Tab(bool),
+    /// CTRL-D, which lists the matches
+    List,
+    /// Selects a later or earlier match
+    Move(isize),
+    /// CTRL-E, which puts back the original text
+    Cancel,
+    /// CTRL-Y, which keeps the selected match
+    Accept,
+}
+
+/// Finds the matches of the text before the cursor
+fn begin(v: &VimInner, ctx: &VimScriptCtx<VimInner>) -> Option<Wild> {
+    let line = &v.cli.cmd.0;
+    let (start, kind) = context(line)?;
+    let lastused = stages(&v.options().wildmode)
+        .iter()
+        .flatten()
+        .any(|s| *s == Step::LastUsed);
+    let matches = matches(v, ctx, kind, &line[start..], lastused);
+    if matches.is_empty() {
+        return None;
+    }
+    Some(Wild {
+        start,
+        original: line[start..].to_string(),
+        matches,
+        selected: None,
+        stage: 0,
+        shown: Shown::Nothing,
+        files: matches!(kind, Kind::File | Kind::Dir),
+    })
+}
+
+/// Puts `text` in place of the completed text
+fn set_text(v: &mut VimInner, text: &str) {
+    let cli = &mut v.cli;
+    if let Some(w) = &cli.wild {
+        cli.cmd.0.truncate(w.start);
+        cli.cmd.0.push_str(text);
+    }
+}
+
+/// Moves the selection by `by` matches, where the original text comes after the last match
+fn select(v: &mut VimInner, by: isize) {
+    let Some(w) = &mut v.cli.wild else {
+        return;
+    };
+    let len = w.matches.len() as isize + 1;
+    let pos = w.selected.map_or(len - 1, |i| i as isize);
+    let pos = (pos + by).rem_euclid(len) as usize;
+    w.selected = (pos < w.matches.len()).then_some(pos);
+    let text = w
+        .selected
+        .map_or(w.original.clone(), |i| w.matches[i].clone());
+    set_text(v, &text);
+}
+
+/// Takes the next step of 'wildmode'
+fn tab(v: &mut VimInner, ctx: &VimScriptCtx<VimInner>, forward: bool) {
+    if v.cli.wild.is_none() {
+        v.cli.wild = begin(v, ctx);
+    }
+    let stages = stages(&v.options().wildmode);
+    let menu = v.options().wildmenu;
+    let pum = menu && v.options().wildoptions.split(',').any(|o| o == "pum");
+    let Some(w) = &mut v.cli.wild else {
+        return;
+    };
+    let steps = &stages[w.stage.min(stages.len() - 1)];
+    w.stage += 1;
+    if let [only] = w.matches.as_slice() {
+        let only = only.clone();
+        set_text(v, &only);
+        v.cli.wild = None;
+        return;
+    }
+    let full = steps
+        .iter()
+        .any(|s| matches!(s, Step::Full | Step::LastUsed));
+    let longest_step = steps.contains(&Step::Longest);
+    w.shown = match () {
+        _ if steps.contains(&Step::List) => Shown::List,
+        _ if menu && full && pum => Shown::Pum,
+        _ if menu && full => Shown::Menu,
+        _ => Shown::Nothing,
+    };
+    if longest_step {
+        let longest = longest(&w.matches);
+        if longest.len() > w.original.len() {
+            w.original = longest.clone();
+            set_text(v, &longest);
+        }
+    } else if full {
+        select(v, if forward { 1 } else { -1 });
+    }
+}
+
+/// Handles a key that completes the command line
+pub fn key(v: &mut VimInner, ctx: &VimScriptCtx<VimInner>, key: Key) {
+    match key {
+        Key::Tab(forward) => tab(v, ctx, forward),
+        Key::List => {
+            if v.cli.wild.is_none() {
+                v.cli.wild = begin(v, ctx);
+            }
+            if let Some(w) = &mut v.cli.wild {
+                w.shown = Shown::List;
+            }
+        }
+        Key::Move(by) => select(v, by),
+        Key::Cancel => {
+            let original = v.cli.wild.as_ref().map(|w| w.original.clone());
+            if let Some(original) = original {
+                set_text(v, &original);
+            }
+            v.cli.wild = None;
+        }
+        Key::Accept => v.cli.wild = None,
+    }
+}
+
+/// Draws the windows under the matches again, before a key can change them
+pub fn hide(v: &mut VimInner) {
+    if v.cli.wild_rows > 0 {
+        v.cli.clear_rows = v.cli.wild_rows;
+        v.cli.wild_rows = 0;
+        v.windows.redraw_all();
+    }
+}
+
+/// Clears the rows the matches were on, since the windows don't draw every column
+pub fn clear<W: Write>(v: &mut VimInner, term: &mut W) -> Result<()> {
+    let bottom = v.cli.area.y;
+    for row in bottom.saturating_sub(v.cli.clear_rows)..bottom {
+        Pos(0, row).move_cursor(term)?;
+        term.queue(Clear(ClearType::CurrentLine))?;
+    }
+    v.cli.clear_rows = 0;
+    Ok(())
+}
+
+/// Draws the matches above the command line
+pub fn draw<W: Write>(v: &mut VimInner, term: &mut W) -> Result<()> {
+    let rows = match &v.cli.wild {
+        Some(w) => draw_matches(v, w, term)?,
+        None => 0,
+    };
+    v.cli.wild_rows = rows;
+    Ok(())
+}
+
+/// Draws the matches in the way 'wildmode' and 'wildmenu' show them, and returns the number of
+/// rows they are on
+fn draw_matches<W: Write>(v: &VimInner, w: &Wild, term: &mut W) -> Result<usize> {
+    let width = WidthOpts::new(v.options());
+    let screen_w = v.size.0 as usize;
+    let bottom = v.cli.area.y;
+    let names: Vec<_> = w.matches.iter().map(|m| w.name(m)).collect();
+    let widest = names.iter().map(|n| width.str_width(n)).max().unwrap_or(0);
+    Ok(match w.shown {
+        Shown::Nothing => 0,
+        Shown::Menu if bottom > 0 => {
+            // The matches are split into pages that fit on the line, with room for `<` and `>`
+            let avail = screen_w.saturating_sub(4).max(1);
+            let mut pages = vec![0];
+            let mut used = 0;
+            for (i, name) in names.iter().enumerate() {
+                let w = width.str_width(name).min(avail) + 2;
+                if used + w > avail + 2 && used > 0 {
+                    pages.push(i);
+                    used = 0;
+                }
+                used += w;
+            }
+            let sel = w.selected.unwrap_or(0);
+            let page = pages.iter().rposition(|&p| p <= sel).unwrap_or(0);
+            let end = pages.get(page + 1).copied().unwrap_or(names.len());
+            let status = v.highlights.style_of("StatusLine");
+            Pos(0, bottom - 1).move_cursor(term)?;
+            let mut used = 0;
+            if page > 0 {
+                write!(term, "{}", status.apply("< "))?;
+                used += 2;
+            }
+            for (i, name) in names.iter().enumerate().take(end).skip(pages[page]) {
+                let name = fit(name, width.str_width(name).min(avail), &width);
+                let group = match w.selected == Some(i) {
+                    true => "WildMenu",
+                    false => "StatusLine",
+                };
+                write!(term, "{}", v.highlights.style_of(group).apply(&name))?;
+                write!(term, "{}", status.apply("  "))?;
+                used += width.str_width(&name) + 2;
+            }
+            if end < names.len() {
+                write!(term, "{}", status.apply(">"))?;
+                used += 1;
+            }
+            let rest = " ".repeat(screen_w.saturating_sub(used));
+            write!(term, "{}", status.apply(rest))?;
+            1
+        }
+        Shown::Menu => 0,
+        Shown::Pum => {
+            let mut height = names.len().min(bottom);
+            if v.options().pumheight > 0 {
+                height = height.min(v.options().pumheight as usize);
+            }
+            if height == 0 {
+                return Ok(0);
+            }
+            let scrollbar = names.len() > height;
+            let inner = widest
+                .max(13)
+                .min(screen_w.saturating_sub(2 + scrollbar as usize));
+            let x = 1 + width.display_width(&v.cli.cmd.0[..w.start], 1);
+            let x = x.min(screen_w.saturating_sub(inner + 2 + scrollbar as usize));
+            let first = match w.selected {
+                Some(i) if i >= height => i + 1 - height,
+                _ => 0,
+            };
+            let thumb_len = (height * height / names.len()).max(1);
+            let thumb = first * height / names.len();
+            for (row, name) in names[first..].iter().take(height).enumerate() {
+                let group = match w.selected == Some(first + row) {
+                    true => "PmenuSel",
+                    false => "Pmenu",
+                };
+                Pos(x, bottom - height + row).move_cursor(term)?;
+                let text = format!(" {} ", fit(name, inner, &width));
+                write!(term, "{}", v.highlights.style_of(group).apply(text))?;
+                if scrollbar {
+                    let group = match (thumb..thumb + thumb_len).contains(&row) {
+                        true => "PmenuThumb",
+                        false => "PmenuSbar",
+                    };
+                    write!(term, "{}", v.highlights.style_of(group).apply(" "))?;
+                }
+            }
+            height
+        }
+        Shown::List => {
+            // The matches go down the columns, and the rows that don't fit are left out
+            let col_w = (widest + 2).min(screen_w.max(1));
+            let columns = (screen_w / col_w).max(1);
+            let rows = names.len().div_ceil(columns);
+            let shown = rows.min(bottom);
+            let normal = v.highlights.style_of("Normal");
+            for row in 0..shown {
+                let mut line = String::new();
+                for col in 0..columns {
+                    if let Some(name) = names.get(col * rows + row) {
+                        line.push_str(&fit(name, col_w, &width));
+                    }
+                }
+                Pos(0, bottom - shown + row).move_cursor(term)?;
+                write!(term, "{}", normal.apply(fit(&line, screen_w, &width)))?;
+            }
+            shown
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contexts() {
+        assert_eq!(context("se"), Some((0, Kind::Command)));
+        assert_eq!(context("'<,'>no"), Some((5, Kind::Command)));
+        assert_eq!(context("/ab"), None);
+        assert_eq!(context("/a\\/b"), None);
+        assert_eq!(context("'"), None);
+        assert_eq!(context(":/a\\/b/,\\?d"), Some((10, Kind::Command)));
+        assert_eq!(context("\\&;+2 no"), Some((6, Kind::Command)));
+        assert_eq!(context("set ts=4 nonu"), Some((11, Kind::BoolOption)));
+        assert_eq!(context("se sw="), Some((6, Kind::OptionValue("sw"))));
+        assert_eq!(context("w! my\\ fi"), Some((3, Kind::File)));
+        assert_eq!(context("call str"), Some((5, Kind::Function)));
+        assert_eq!(context("echo 1 + g:"), Some((9, Kind::Expression)));
+        assert_eq!(context("hi link Foo B"), Some((12, Kind::Highlight)));
+        assert_eq!(context("normal x"), None);
+    }
+
+    #[test]
+    fn steps() {
+        let m = |words: &[&str]| -> Vec<String> { words.iter().map(|w| w.to_string()).collect() };
+        assert_eq!(longest(&m(&["foobar", "foobaz", "foo"])), "foo");
+        assert_eq!(longest(&m(&["éa", "éb"])), "é");
+        assert_eq!(longest(&m(&["a", "b"])), "");
+        assert_eq!(
+            stages("longest:full,full"),
+            [vec![Step::Longest, Step::Full], vec![Step::Full]]
+        );
+        assert_eq!(stages(""), [vec![Step::Full]]);
+    }
+}
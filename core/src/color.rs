@@ -0,0 +1,427 @@
+//
+// color.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::{
+    fmt::{Display, Write},
+    path::PathBuf,
+};
+
+use crossterm::style::Color;
+
+use crate::highlight::Attrs;
+
+/// How many colors the terminal can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorDepth {
+    Mono,
+    Colors8,
+    Colors16,
+    Colors256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    fn from_count(colors: i32) -> Self {
+        match colors {
+            n if n >= 1 << 24 => Self::TrueColor,
+            n if n >= 256 => Self::Colors256,
+            n if n >= 16 => Self::Colors16,
+            n if n >= 8 => Self::Colors8,
+            _ => Self::Mono,
+        }
+    }
+}
+
+/// What the terminal can display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermCaps {
+    pub depth: ColorDepth,
+    pub italic: bool,
+    pub strikethrough: bool,
+    /// Curly, double, dotted and dashed underlines, and underline colors
+    pub undercurl: bool,
+}
+
+impl Default for TermCaps {
+    fn default() -> Self {
+        Self {
+            depth: ColorDepth::Colors256,
+            italic: true,
+            strikethrough: true,
+            undercurl: false,
+        }
+    }
+}
+
+/// Terminals known to support extended underlines
+const UNDERCURL_TERMS: [&str; 6] = [
+    "kitty",
+    "wezterm",
+    "foot",
+    "alacritty",
+    "ghostty",
+    "contour",
+];
+
+impl TermCaps {
+    /// Detects the capabilities of the terminal from `COLORTERM`, `TERM` and terminfo
+    pub fn detect() -> Self {
+        Self::from_env(|var| std::env::var(var).ok(), terminfo_colors)
+    }
+
+    fn from_env(
+        var: impl Fn(&str) -> Option<String>,
+        colors: impl Fn(&str) -> Option<i32>,
+    ) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let truecolor = var("COLORTERM").is_some_and(|c| c == "truecolor" || c == "24bit");
+        let depth = if truecolor || term.ends_with("-direct") {
+            ColorDepth::TrueColor
+        } else if let Some(colors) = colors(&term) {
+            ColorDepth::from_count(colors)
+        } else if term.contains("256color") {
+            ColorDepth::Colors256
+        } else if term.is_empty() || term == "dumb" || term.starts_with("vt") {
+            ColorDepth::Mono
+        } else {
+            ColorDepth::Colors16
+        };
+        // The console and old versions of screen show italics as reverse
+        let basic = term == "linux" || term == "screen" || depth == ColorDepth::Mono;
+        Self {
+            depth,
+            italic: !basic,
+            strikethrough: !basic,
+            undercurl: UNDERCURL_TERMS.iter().any(|t| term.contains(t)),
+        }
+    }
+}
+
+/// The `colors` capability of `term` from its terminfo entry
+fn terminfo_colors(term: &str) -> Option<i32> {
+    let first = term.chars().next()?;
+    let mut dirs: Vec<PathBuf> = vec![];
+    if let Ok(dir) = std::env::var("TERMINFO") {
+        dirs.push(dir.into());
+    }
+    if let Ok(home) = std::env::var("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Ok(list) = std::env::var("TERMINFO_DIRS") {
+        dirs.extend(list.split(':').filter(|d| !d.is_empty()).map(PathBuf::from));
+    }
+    dirs.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].map(PathBuf::from));
+    dirs.iter().find_map(|dir| {
+        // Entries are grouped by their first letter, or its hex code on some systems
+        [first.to_string(), format!("{:x}", first as u32)]
+            .iter()
+            .find_map(|sub| std::fs::read(dir.join(sub).join(term)).ok())
+            .and_then(|data| parse_terminfo_colors(&data))
+    })
+}
+
+/// Reads the `colors` number from a compiled terminfo entry
+fn parse_terminfo_colors(data: &[u8]) -> Option<i32> {
+    let word = |i: usize| Some(i16::from_le_bytes([*data.get(i)?, *data.get(i + 1)?]));
+    // The extended format uses 32 bit numbers
+    let size = match word(0)? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+    let names = word(2)? as usize;
+    let bools = word(4)? as usize;
+    let numbers = word(6)? as usize;
+    const COLORS: usize = 13;
+    if numbers <= COLORS {
+        return None;
+    }
+    let mut start = 12 + names + bools;
+    // Numbers start on an even byte
+    start += start % 2;
+    let at = start + COLORS * size;
+    let value = match size {
+        2 => word(at)? as i32,
+        _ => {
+            let b = data.get(at..at + 4)?;
+            i32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        }
+    };
+    (value >= 0).then_some(value)
+}
+
+/// The colors xterm uses for the first 16 colors
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+/// The levels of each component in the 6x6x6 color cube
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn ansi_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => PALETTE[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            (
+                CUBE[n as usize / 36],
+                CUBE[n as usize / 6 % 6],
+                CUBE[n as usize % 6],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (n - 232);
+            (level, level, level)
+        }
+    }
+}
+
+/// The closest of the 240 colors of the 256 color palette that don't depend on the terminal
+fn nearest_256(rgb: (u8, u8, u8)) -> u8 {
+    let level = |c: u8| {
+        (0..6)
+            .min_by_key(|i| (CUBE[*i] as i32 - c as i32).abs())
+            .unwrap_or(0)
+    };
+    let cube = 16 + 36 * level(rgb.0) + 6 * level(rgb.1) + level(rgb.2);
+    let avg = (rgb.0 as i32 + rgb.1 as i32 + rgb.2 as i32) / 3;
+    // The grays are 8, 18, ..., 238
+    let gray = 232 + ((avg - 3) / 10).clamp(0, 23) as u8;
+    if distance(ansi_rgb(gray), rgb) < distance(ansi_rgb(cube as u8), rgb) {
+        gray
+    } else {
+        cube as u8
+    }
+}
+
+fn nearest_16(rgb: (u8, u8, u8)) -> u8 {
+    (0..16)
+        .min_by_key(|n| distance(PALETTE[*n as usize], rgb))
+        .unwrap_or(0)
+}
+
+/// Approximates `color` with the colors the terminal has
+pub fn reduce(color: Color, depth: ColorDepth) -> Option<Color> {
+    let rgb = match color {
+        Color::Rgb { r, g, b } => (r, g, b),
+        Color::AnsiValue(n) => ansi_rgb(n),
+        // Named colors are always one of the first 16
+        color => return (depth != ColorDepth::Mono).then_some(color),
+    };
+    Some(match (depth, color) {
+        (ColorDepth::Mono, _) => return None,
+        (ColorDepth::TrueColor, color) => color,
+        (ColorDepth::Colors256, Color::AnsiValue(n)) => Color::AnsiValue(n),
+        (ColorDepth::Colors256, _) => Color::AnsiValue(nearest_256(rgb)),
+        (_, Color::AnsiValue(n)) if n < 16 => Color::AnsiValue(n),
+        _ => Color::AnsiValue(nearest_16(rgb)),
+    })
+}
+
+/// The colors and attributes text is drawn with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    /// The color of underlines
+    pub sp: Option<Color>,
+    pub attrs: Attrs,
+}
+
+impl Style {
+    pub fn apply<D: Display>(self, content: D) -> Styled<D> {
+        Styled {
+            style: self,
+            content,
+        }
+    }
+
+    /// This style with the colors and attributes set in `over` on top
+    pub fn combine(self, over: Style) -> Style {
+        Style {
+            fg: over.fg.or(self.fg),
+            bg: over.bg.or(self.bg),
+            sp: over.sp.or(self.sp),
+            attrs: self.attrs.union(over.attrs),
+        }
+    }
+
+    /// The parameters of the SGR sequence that sets this style
+    fn codes(&self) -> String {
+        let mut codes = String::new();
+        for name in self.attrs.names() {
+            let code = match name {
+                "bold" => "1",
+                "italic" => "3",
+                "underline" => "4",
+                "undercurl" => "4:3",
+                "underdouble" => "4:2",
+                "underdotted" => "4:4",
+                "underdashed" => "4:5",
+                "reverse" | "standout" => "7",
+                "strikethrough" => "9",
+                _ => continue,
+            };
+            let _ = write!(codes, ";{code}");
+        }
+        let colors = [
+            (self.fg, 30, 90, 38),
+            (self.bg, 40, 100, 48),
+            (self.sp, 0, 0, 58),
+        ];
+        for (color, normal, bright, extended) in colors {
+            let _ = match color {
+                Some(Color::AnsiValue(n)) if n < 8 && normal > 0 => {
+                    write!(codes, ";{}", normal + n as u32)
+                }
+                Some(Color::AnsiValue(n)) if n < 16 && bright > 0 => {
+                    write!(codes, ";{}", bright + n as u32 - 8)
+                }
+                Some(Color::AnsiValue(n)) => write!(codes, ";{extended};5;{n}"),
+                Some(Color::Rgb { r, g, b }) => write!(codes, ";{extended};2;{r};{g};{b}"),
+                _ => Ok(()),
+            };
+        }
+        codes
+    }
+}
+
+/// Text drawn with a `Style`, which is reset afterwards
+pub struct Styled<D> {
+    style: Style,
+    content: D,
+}
+
+impl<D: Display> Display for Styled<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let codes = self.style.codes();
+        if codes.is_empty() {
+            write!(f, "{}", self.content)
+        } else {
+            write!(f, "\x1b[0{codes}m{}\x1b[0m", self.content)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        let caps = |vars: &[(&str, &str)], colors: Option<i32>| {
+            let vars: Vec<(String, String)> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            TermCaps::from_env(
+                |var| vars.iter().find(|(k, _)| k == var).map(|(_, v)| v.clone()),
+                |_| colors,
+            )
+        };
+        let truecolor = caps(&[("TERM", "xterm"), ("COLORTERM", "truecolor")], Some(8));
+        assert_eq!(truecolor.depth, ColorDepth::TrueColor);
+        assert_eq!(
+            caps(&[("TERM", "xterm")], Some(8)).depth,
+            ColorDepth::Colors8
+        );
+        let guessed = caps(&[("TERM", "screen-256color")], None);
+        assert_eq!(guessed.depth, ColorDepth::Colors256);
+        assert_eq!(caps(&[("TERM", "dumb")], None).depth, ColorDepth::Mono);
+        assert!(!caps(&[("TERM", "linux")], Some(8)).italic);
+        assert!(caps(&[("TERM", "xterm-kitty")], Some(256)).undercurl);
+    }
+
+    #[test]
+    fn terminfo() {
+        // Header, names "x\0", no booleans, then 14 numbers with colors last
+        let mut data: Vec<u8> = [0o432u16, 2, 0, 14, 0, 0]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect();
+        data.extend(b"x\0");
+        data.extend((0..13).flat_map(|_| (-1i16).to_le_bytes()));
+        data.extend(256i16.to_le_bytes());
+        assert_eq!(parse_terminfo_colors(&data), Some(256));
+        data[0] = 0;
+        assert_eq!(parse_terminfo_colors(&data), None);
+    }
+
+    #[test]
+    fn approximate() {
+        let orange = Color::Rgb {
+            r: 0xff,
+            g: 0xa5,
+            b: 0x00,
+        };
+        assert_eq!(reduce(orange, ColorDepth::TrueColor), Some(orange));
+        assert_eq!(
+            reduce(orange, ColorDepth::Colors256),
+            Some(Color::AnsiValue(214))
+        );
+        assert_eq!(
+            reduce(orange, ColorDepth::Colors16),
+            Some(Color::AnsiValue(3))
+        );
+        let gray = Color::Rgb {
+            r: 0x80,
+            g: 0x80,
+            b: 0x80,
+        };
+        assert_eq!(
+            reduce(gray, ColorDepth::Colors256),
+            Some(Color::AnsiValue(244))
+        );
+        assert_eq!(
+            reduce(Color::AnsiValue(234), ColorDepth::Colors16),
+            Some(Color::AnsiValue(0))
+        );
+        assert_eq!(reduce(Color::AnsiValue(9), ColorDepth::Mono), None);
+    }
+
+    #[test]
+    fn escapes() {
+        let mut out = Vec::new();
+        let style = Style {
+            fg: Some(Color::AnsiValue(9)),
+            bg: Some(Color::AnsiValue(4)),
+            ..Style::default()
+        };
+        std::io::Write::write_fmt(&mut out, format_args!("{}", style.apply("a"))).unwrap();
+        assert_eq!(out, b"\x1b[0;91;44ma\x1b[0m");
+        let style = Style {
+            fg: Some(Color::Rgb { r: 1, g: 2, b: 3 }),
+            bg: Some(Color::AnsiValue(236)),
+            sp: Some(Color::AnsiValue(1)),
+            attrs: Attrs::parse("bold,undercurl").unwrap(),
+        };
+        assert_eq!(
+            style.apply("b").to_string(),
+            "\x1b[0;1;4:3;38;2;1;2;3;48;5;236;58;5;1mb\x1b[0m"
+        );
+        assert_eq!(Style::default().apply("c").to_string(), "c");
+    }
+}
@@ -0,0 +1,1047 @@
+//
+// complete.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::env;
+use std::fs;
+use std::io::Write;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use vimscript::regex::CharSet;
+use vimscript::{State, Value, VimError, VimScriptCtx};
+
+use crate::buffer::BufferRef;
+use crate::cursor::Motion;
+use crate::keymap::KeyState;
+use crate::util::Pos;
+use crate::width::WidthOpts;
+use crate::{autocmd, glob, tags, Renderable, Result, VimInner};
+
+/// The kinds of Insert mode completion, which CTRL-N, CTRL-P and CTRL-X start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Words from the places in 'complete'
+    Keyword,
+    /// Words from the current buffer, with CTRL-X CTRL-N
+    Local,
+    Line,
+    File,
+    Dictionary,
+    Thesaurus,
+    Tags,
+    /// Matches from 'completefunc', with CTRL-X CTRL-U
+    Function,
+    /// Matches from 'omnifunc', with CTRL-X CTRL-O
+    Omni,
+    /// Matches given to `complete()`
+    Eval,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Keyword => "Keyword completion (^N^P)",
+            Self::Local => "Keyword Local completion (^N^P)",
+            Self::Line => "Whole line completion (^L^N^P)",
+            Self::File => "File name completion (^F^N^P)",
+            Self::Dictionary => "Dictionary completion (^K^N^P)",
+            Self::Thesaurus => "Thesaurus completion (^T^N^P)",
+            Self::Tags => "Tag completion (^]^N^P)",
+            Self::Function => "User defined completion (^U^N^P)",
+            Self::Omni => "Omni completion (^O^N^P)",
+            Self::Eval => "Completion (^N^P)",
+        }
+    }
+
+    /// The mode `complete_info()` gives
+    fn info_name(self) -> &'static str {
+        match self {
+            Self::Keyword | Self::Local => "keyword",
+            Self::Line => "whole_line",
+            Self::File => "files",
+            Self::Dictionary => "dictionary",
+            Self::Thesaurus => "thesaurus",
+            Self::Tags => "tags",
+            Self::Function => "function",
+            Self::Omni => "omni",
+            Self::Eval => "eval",
+        }
+    }
+}
+
+/// A match, with the text the popup menu shows for it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Item {
+    pub word: String,
+    pub abbr: String,
+    pub menu: String,
+    pub info: String,
+    pub kind: String,
+    /// Any value a script wants to get back from `v:completed_item`
+    pub user_data: Option<Value>,
+}
+
+impl Item {
+    fn new(word: String) -> Self {
+        Self {
+            word,
+            ..Self::default()
+        }
+    }
+
+    /// Reads a match that a script gave, which is a string or a dict with at least a `word`
+    pub fn from_value(ctx: &VimScriptCtx<VimInner>, value: &Value) -> Option<Self> {
+        let Value::Object(dict) = value else {
+            return Some(Self::new(value.to_string(ctx)));
+        };
+        let dict = dict.lock().unwrap();
+        let text = |key| dict.get(key).map_or(String::new(), |v| v.to_string(ctx));
+        Some(Self {
+            word: dict.get("word")?.to_string(ctx),
+            abbr: text("abbr"),
+            menu: text("menu"),
+            info: text("info"),
+            kind: text("kind"),
+            user_data: dict.get("user_data").cloned(),
+        })
+    }
+
+    /// The dict of the match, as in `v:completed_item`
+    pub fn to_value(&self) -> Value {
+        Value::object([
+            ("word", Value::str(&self.word)),
+            ("abbr", Value::str(&self.abbr)),
+            ("menu", Value::str(&self.menu)),
+            ("info", Value::str(&self.info)),
+            ("kind", Value::str(&self.kind)),
+            (
+                "user_data",
+                self.user_data.clone().unwrap_or(Value::str("")),
+            ),
+        ])
+    }
+}
+
+/// The flags of 'completeopt'
+struct Opts {
+    menu: bool,
+    menuone: bool,
+    longest: bool,
+    noinsert: bool,
+    noselect: bool,
+    /// Shows the info of the selected item next to the menu, with `preview` or `popup`
+    info: bool,
+}
+
+impl Opts {
+    fn new(cot: &str) -> Self {
+        let has = |flag: &str| cot.split(',').any(|f| f == flag);
+        Self {
+            menu: has("menu"),
+            menuone: has("menuone"),
+            longest: has("longest"),
+            noinsert: has("noinsert"),
+            noselect: has("noselect"),
+            info: has("preview") || has("popup"),
+        }
+    }
+}
+
+pub struct Completion {
+    mode: Mode,
+    row: usize,
+    /// The column the completed text starts at
+    col: usize,
+    /// The text typed before the completed text, which the matches start with
+    leader: String,
+    /// Every match, for when the leader gets shorter again
+    all: Vec<Item>,
+    items: Vec<Item>,
+    /// `None` when the leader is shown instead of a match
+    selected: Option<usize>,
+    /// Whether the selected match is in the text, which isn't the case after <Up> or <Down>
+    inserted: bool,
+    menu: bool,
+}
+
+/// What a key typed while completing does
+pub enum Key {
+    /// The key was used by completion
+    Done,
+    /// The key is typed as usual, and the matches are filtered by the new text after it
+    Filter,
+    /// Completion ended, and the key is typed as usual
+    End,
+}
+
+/// Whether `word` starts with `leader`
+pub(crate) fn has_prefix(word: &str, leader: &str, ignorecase: bool) -> bool {
+    match ignorecase {
+        true => word.to_lowercase().starts_with(&leader.to_lowercase()),
+        false => word.starts_with(leader),
+    }
+}
+
+/// The words of `text`, which are made of the characters in `iskeyword`
+fn words<'a>(text: &'a str, iskeyword: &'a CharSet) -> impl Iterator<Item = &'a str> {
+    text.split(move |c| !iskeyword.contains(c))
+        .filter(|w| !w.is_empty())
+}
+
+/// The text all of `items` start with
+fn common_prefix(items: &[Item]) -> String {
+    let Some(first) = items.first() else {
+        return String::new();
+    };
+    let mut len = first.word.len();
+    for item in items[1..].iter() {
+        len = first.word[..len]
+            .char_indices()
+            .zip(item.word.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(len.min(item.word.len()), |((i, _), _)| i);
+    }
+    first.word[..first.word.floor_char_boundary(len)].to_string()
+}
+
+/// Collects the matches for a leader, without duplicates
+struct Matches<'a> {
+    leader: &'a str,
+    ignorecase: bool,
+    /// Changes the case of a match to that of the leader, with 'infercase'
+    infercase: bool,
+    items: Vec<Item>,
+}
+
+impl Matches<'_> {
+    fn add(&mut self, item: Item) {
+        let mut item = item;
+        if !has_prefix(&item.word, self.leader, self.ignorecase) || item.word == self.leader {
+            return;
+        }
+        if self.ignorecase && self.infercase {
+            let rest = item.word.chars().skip(self.leader.chars().count());
+            item.word = self.leader.chars().chain(rest).collect();
+        }
+        self.push(item);
+    }
+
+    /// Adds a match that doesn't have to start with the leader
+    fn push(&mut self, item: Item) {
+        if item.word != self.leader && !self.items.iter().any(|i| i.word == item.word) {
+            self.items.push(item);
+        }
+    }
+
+    fn add_words(&mut self, text: &str, iskeyword: &CharSet) {
+        for word in words(text, iskeyword) {
+            self.add(Item::new(word.to_string()));
+        }
+    }
+}
+
+/// The lines of `buffer`, starting at the cursor and going around the end when it is the
+/// current buffer, so the closest matches come first
+fn buffer_lines(v: &VimInner, buffer: &BufferRef) -> Vec<String> {
+    let win = v.get_focus();
+    let b = buffer.read();
+    let lines = (0..b.len()).map(|i| b[i].text().to_string());
+    if buffer.id() != win.buffer().id() {
+        return lines.collect();
+    }
+    let (row, col) = (win.cursor().row(), win.cursor().col());
+    let line = b[row].text();
+    let mut ret = vec![line[col..].to_string()];
+    ret.extend(lines.clone().skip(row + 1));
+    ret.extend(lines.take(row));
+    ret.push(line[..col].to_string());
+    ret
+}
+
+/// The buffers 'complete' searches with `.`, `w` and `b`, in the order they are searched
+fn buffers(v: &VimInner, mode: Mode) -> Vec<BufferRef> {
+    let mut ret = vec![v.get_focus().buffer().clone()];
+    if mode == Mode::Local {
+        return ret;
+    }
+    let complete = v.get_focus().buffer().read().options().complete.clone();
+    let sources: Vec<_> = complete.split(',').collect();
+    if !sources.contains(&".") {
+        ret.clear();
+    }
+    let mut add = |b: &BufferRef| {
+        if !ret.iter().any(|r| r.id() == b.id()) {
+            ret.push(b.clone());
+        }
+    };
+    if sources.contains(&"w") {
+        v.windows.for_each(&mut |w| add(w.buffer()));
+        v.floating.iter().for_each(|w| add(w.buffer()));
+    }
+    // Every buffer is loaded, so `u` has nothing to add
+    if sources.contains(&"b") {
+        v.buffers.iter().for_each(add);
+    }
+    ret
+}
+
+/// The files of 'dictionary' or 'thesaurus', or of the `k` or `s` flag of 'complete'
+fn files(spec: &str) -> Vec<String> {
+    spec.split(',')
+        .filter(|f| !f.is_empty())
+        .flat_map(|f| glob::glob(f, false))
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .collect()
+}
+
+/// Finds the matches for the text before the cursor. Returns the column the completed text
+/// starts at, and the matches.
+fn gather(v: &VimInner, mode: Mode) -> (usize, Vec<Item>) {
+    let opts = v.regex_options();
+    let win = v.get_focus();
+    let col = win.cursor().col();
+    let line = win.buffer().read()[win.cursor().row()].text()[..col].to_string();
+    let start = match mode {
+        Mode::Line => line.len() - line.trim_start().len(),
+        Mode::File => line
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| opts.isfname.contains(*c))
+            .last()
+            .map_or(col, |(i, _)| i),
+        _ => line
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| opts.iskeyword.contains(*c))
+            .last()
+            .map_or(col, |(i, _)| i),
+    };
+    let leader = &line[start..];
+    let mut m = Matches {
+        leader,
+        ignorecase: v.options().ignorecase && mode != Mode::File,
+        infercase: v.options().infercase,
+        items: vec![],
+    };
+    let dictionary = || {
+        let local = win.buffer().read().options().dictionary.clone();
+        match local.is_empty() {
+            true => v.options().dictionary.clone(),
+            false => local,
+        }
+    };
+    let thesaurus = |m: &mut Matches, spec: &str| {
+        for text in files(spec) {
+            for line in text.lines() {
+                // Every word on a line with a match is offered, since they are synonyms
+                if words(line, &opts.iskeyword).any(|w| has_prefix(w, leader, m.ignorecase)) {
+                    for word in words(line, &opts.iskeyword) {
+                        m.push(Item::new(word.to_string()));
+                    }
+                }
+            }
+        }
+    };
+    let tag_names = |m: &mut Matches| {
+        for tag in tags::find(v, &format!("/^{leader}")).unwrap_or_default() {
+            m.add(Item {
+                kind: tag.kind,
+                ..Item::new(tag.name)
+            });
+        }
+    };
+    match mode {
+        Mode::Keyword | Mode::Local => {
+            for buffer in buffers(v, mode) {
+                for line in buffer_lines(v, &buffer) {
+                    m.add_words(&line, &opts.iskeyword);
+                }
+            }
+            let complete = win.buffer().read().options().complete.clone();
+            for source in complete.split(',').filter(|_| mode == Mode::Keyword) {
+                match source.split_at(source.len().min(1)) {
+                    ("k", "") => files(&dictionary())
+                        .iter()
+                        .for_each(|text| m.add_words(text, &opts.iskeyword)),
+                    ("k", spec) => files(spec)
+                        .iter()
+                        .for_each(|text| m.add_words(text, &opts.iskeyword)),
+                    ("s", "") => thesaurus(&mut m, &v.options().thesaurus),
+                    ("s", spec) => thesaurus(&mut m, spec),
+                    ("t" | "]", _) => tag_names(&mut m),
+                    // Included files aren't searched, with `i` and `d`
+                    _ => (),
+                }
+            }
+        }
+        Mode::Line => {
+            let row = win.cursor().row();
+            for buffer in buffers(v, mode) {
+                let b = buffer.read();
+                for i in 0..b.len() {
+                    if buffer.id() != win.buffer().id() || i != row {
+                        m.add(Item::new(b[i].text().trim_start().to_string()));
+                    }
+                }
+            }
+        }
+        Mode::File => {
+            let home = env::var("HOME").unwrap_or_default();
+            for path in glob::glob(&format!("{leader}*"), false) {
+                let mut name = path.display().to_string();
+                if leader.starts_with('~') && !home.is_empty() {
+                    name = name.replacen(&home, "~", 1);
+                }
+                if path.is_dir() {
+                    name.push('/');
+                }
+                m.add(Item::new(name));
+            }
+        }
+        Mode::Dictionary => {
+            for text in files(&dictionary()) {
+                m.add_words(&text, &opts.iskeyword);
+            }
+        }
+        Mode::Thesaurus => thesaurus(&mut m, &v.options().thesaurus),
+        Mode::Tags => tag_names(&mut m),
+        // Scripts give these matches, with `user()` and `set()`
+        Mode::Function | Mode::Omni | Mode::Eval => (),
+    }
+    (start, m.items)
+}
+
+/// Replaces the completed text with `text`
+fn set_text(v: &mut VimInner, text: &str) {
+    let Some(c) = &v.completion else {
+        return;
+    };
+    let (row, col) = (c.row, c.col);
+    let win = v.get_focus_mut();
+    let cursor = win.cursor().col();
+    let line = win.buffer().read()[row].text().to_string();
+    let new = format!("{}{text}{}", &line[..col], &line[cursor..]);
+    win.buffer().write().replace_lines(row..row + 1, vec![new]);
+    win.cursor_apply(Motion::SetCol(col + text.len()));
+}
+
+/// Shows which match is selected, and runs the `CompleteChanged` autocommands when the menu is
+/// visible
+fn show(v: &mut VimInner, ctx: &mut VimScriptCtx<VimInner>) {
+    let Some(c) = &v.completion else {
+        return;
+    };
+    let msg = match c.selected {
+        Some(_) if c.items.len() == 1 => format!("-- {} The only match", c.mode.name()),
+        Some(i) => format!("-- {} match {} of {}", c.mode.name(), i + 1, c.items.len()),
+        None => format!("-- {} Back at original", c.mode.name()),
+    };
+    let item = c
+        .selected
+        .map_or_else(empty_dict, |i| c.items[i].to_value());
+    let size = c.items.len();
+    v.message(msg);
+    v.windows.redraw_all();
+    if let Some(pum) = layout(v) {
+        let event = Value::object([
+            ("completed_item", item),
+            ("height", Value::Integer(pum.height as isize)),
+            ("width", Value::Integer(pum.inner as isize + 2)),
+            ("row", Value::Integer(pum.top as isize)),
+            ("col", Value::Integer(pum.x as isize)),
+            ("size", Value::Integer(size as isize)),
+            ("scrollbar", Value::Bool(pum.scrollbar)),
+        ]);
+        let _ = ctx.insert_var("v:event", event);
+        autocmd::apply(v, ctx, "CompleteChanged", "");
+    }
+}
+
+fn empty_dict() -> Value {
+    Value::object(Vec::<(String, Value)>::new())
+}
+
+/// Starts completing the text from `col` to the cursor with `items`, and selects the first or
+/// last one
+fn begin(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    mode: Mode,
+    col: usize,
+    items: Vec<Item>,
+    forward: bool,
+) {
+    if items.is_empty() {
+        v.message(format!("-- {} Pattern not found", mode.name()));
+        return;
+    }
+    let win = v.get_focus();
+    let row = win.cursor().row();
+    let leader = win.buffer().read()[row].text()[col..win.cursor().col()].to_string();
+    let opts = Opts::new(&v.options().completeopt);
+    let menu = (opts.menu && items.len() > 1) || opts.menuone;
+    v.completion = Some(Completion {
+        mode,
+        row,
+        col,
+        leader,
+        all: items.clone(),
+        items,
+        selected: None,
+        inserted: false,
+        menu,
+    });
+    if opts.longest {
+        let longest = common_prefix(&v.completion.as_ref().unwrap().items);
+        if longest.len() > v.completion.as_ref().unwrap().leader.len() {
+            set_text(v, &longest);
+        }
+        show(v, ctx);
+    } else if opts.noselect {
+        show(v, ctx);
+    } else {
+        select(v, ctx, if forward { 1 } else { -1 }, !opts.noinsert);
+    }
+}
+
+/// Starts completion with CTRL-N, CTRL-P or CTRL-X
+pub fn start(v: &mut VimInner, ctx: &mut VimScriptCtx<VimInner>, mode: Mode, forward: bool) {
+    end(v, ctx);
+    let (col, items) = gather(v, mode);
+    begin(v, ctx, mode, col, items, forward);
+}
+
+/// Starts completion with the matches of 'completefunc' or 'omnifunc'
+pub fn user(v: &mut VimInner, ctx: &mut VimScriptCtx<VimInner>, omni: bool) {
+    end(v, ctx);
+    let (name, mode) = match omni {
+        true => ("omnifunc", Mode::Omni),
+        false => ("completefunc", Mode::Function),
+    };
+    let func = v.get_focus().buffer().with_read(|b| match omni {
+        true => b.options().omnifunc.clone(),
+        false => b.options().completefunc.clone(),
+    });
+    if func.is_empty() {
+        v.message(format!("E764: Option '{name}' is not set"));
+    } else if let Err(e) = call_user(v, ctx, &func, mode) {
+        v.message(format!("{e}"));
+    }
+}
+
+/// Calls a completion function first to find where the completed text starts, and then to
+/// find the matches for that text
+fn call_user(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    func: &str,
+    mode: Mode,
+) -> std::result::Result<(), VimError> {
+    let findstart = vec![Value::Integer(1), Value::str("")];
+    let start = ctx.run_function(func, findstart, v)?.to_int(ctx)?;
+    let win = v.get_focus();
+    let col = win.cursor().col();
+    let line = win.buffer().read()[win.cursor().row()].text().to_string();
+    // -2 and -3 cancel completion, other negative columns complete the text at the cursor
+    let start = match start {
+        -3 | -2 => return Ok(()),
+        s if s < 0 => col,
+        s => line.floor_char_boundary((s as usize).min(col)),
+    };
+    let base = vec![Value::Integer(0), Value::str(&line[start..col])];
+    let ret = ctx.run_function(func, base, v)?;
+    // The matches can also be the `words` of a dict
+    let list = match &ret {
+        Value::Object(dict) => dict.lock().unwrap().get("words").cloned(),
+        _ => Some(ret),
+    };
+    let items = list
+        .into_iter()
+        .flatten()
+        .filter_map(|i| Item::from_value(ctx, &i))
+        .collect();
+    begin(v, ctx, mode, start, items, true);
+    Ok(())
+}
+
+/// `complete({startcol}, {matches})`, which completes the text from `col` with `items`
+pub fn set(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    col: usize,
+    items: Vec<Item>,
+) -> std::result::Result<(), String> {
+    if v.get_state() != KeyState::Insert {
+        return Err("E785: complete() can only be used in Insert mode".into());
+    }
+    end(v, ctx);
+    let win = v.get_focus();
+    let cursor = win.cursor().col();
+    let col = win.buffer().read()[win.cursor().row()]
+        .text()
+        .floor_char_boundary(col.min(cursor));
+    begin(v, ctx, Mode::Eval, col, items, true);
+    Ok(())
+}
+
+/// The items of `complete_info()`
+pub fn info(v: &VimInner) -> Vec<(&'static str, Value)> {
+    let Some(c) = &v.completion else {
+        return vec![
+            ("mode", Value::str("")),
+            ("pum_visible", Value::Integer(0)),
+            ("items", Value::list(Vec::<Value>::new())),
+            ("selected", Value::Integer(-1)),
+        ];
+    };
+    let items: Vec<_> = c.items.iter().map(Item::to_value).collect();
+    vec![
+        ("mode", Value::str(c.mode.info_name())),
+        ("pum_visible", Value::Integer(pum_visible(v) as isize)),
+        ("items", Value::list(items)),
+        (
+            "selected",
+            Value::Integer(c.selected.map_or(-1, |i| i as isize)),
+        ),
+    ]
+}
+
+/// Whether the popup menu is shown, for `pumvisible()`
+pub fn pum_visible(v: &VimInner) -> bool {
+    layout(v).is_some()
+}
+
+/// Moves the selection by `by` matches, where the leader comes after the last match. The
+/// match is put in the text when `insert` is set.
+fn select(v: &mut VimInner, ctx: &mut VimScriptCtx<VimInner>, by: isize, insert: bool) {
+    let Some(c) = &mut v.completion else {
+        return;
+    };
+    // The leader is the last position
+    let len = c.items.len() as isize + 1;
+    let pos = c.selected.map_or(len - 1, |i| i as isize);
+    let pos = (pos + by).rem_euclid(len) as usize;
+    c.selected = (pos < c.items.len()).then_some(pos);
+    c.inserted = insert;
+    if insert {
+        let text = match c.selected {
+            Some(i) => c.items[i].word.clone(),
+            None => c.leader.clone(),
+        };
+        set_text(v, &text);
+    }
+    show(v, ctx);
+}
+
+/// Ends completion, leaving the text that was inserted, and runs the `CompleteDone`
+/// autocommands with the match in `v:completed_item`
+pub fn end(v: &mut VimInner, ctx: &mut VimScriptCtx<VimInner>) {
+    let Some(c) = &v.completion else {
+        return;
+    };
+    let item = match c.selected {
+        Some(i) if c.inserted => c.items[i].to_value(),
+        _ => empty_dict(),
+    };
+    let _ = ctx.insert_var("v:completed_item", item);
+    // `complete_info()` still works while `CompleteDonePre` runs
+    autocmd::apply(v, ctx, "CompleteDonePre", "");
+    v.completion = None;
+    v.message(String::new());
+    v.windows.redraw_all();
+    autocmd::apply(v, ctx, "CompleteDone", "");
+}
+
+/// Handles a key typed while completing
+pub fn on_key(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    k: KeyEvent,
+    state: KeyState,
+) -> Key {
+    let page = match v.options().pumheight {
+        h if h > 1 => h,
+        _ => 8,
+    };
+    let opts = v.regex_options();
+    let Some(c) = &mut v.completion else {
+        return Key::End;
+    };
+    if state != KeyState::Insert {
+        end(v, ctx);
+        return Key::End;
+    }
+    match (k.code, k.modifiers) {
+        (KeyCode::Char('n'), KeyModifiers::CONTROL) => select(v, ctx, 1, true),
+        (KeyCode::Char('p'), KeyModifiers::CONTROL) => select(v, ctx, -1, true),
+        (KeyCode::Down, _) => select(v, ctx, 1, false),
+        (KeyCode::Up, _) => select(v, ctx, -1, false),
+        (KeyCode::PageDown, _) => select(v, ctx, page, false),
+        (KeyCode::PageUp, _) => select(v, ctx, -page, false),
+        (KeyCode::Char('y'), KeyModifiers::CONTROL) => accept(v, ctx),
+        (KeyCode::Char('e'), KeyModifiers::CONTROL) => {
+            let leader = c.leader.clone();
+            c.selected = None;
+            set_text(v, &leader);
+            end(v, ctx);
+        }
+        // <CR> only inserts a match that was selected without putting it in the text
+        (KeyCode::Enter, _) if c.selected.is_some() && !c.inserted => accept(v, ctx),
+        (KeyCode::Backspace, _) => return Key::Filter,
+        (KeyCode::Char(ch), m) if m & !KeyModifiers::SHIFT == KeyModifiers::NONE => {
+            let filters = match c.mode {
+                Mode::Line => true,
+                Mode::File => opts.isfname.contains(ch),
+                _ => opts.iskeyword.contains(ch),
+            };
+            if filters {
+                return Key::Filter;
+            }
+            end(v, ctx);
+            return Key::End;
+        }
+        _ => {
+            end(v, ctx);
+            return Key::End;
+        }
+    }
+    Key::Done
+}
+
+/// Puts the selected match in the text, and ends completion
+fn accept(v: &mut VimInner, ctx: &mut VimScriptCtx<VimInner>) {
+    if let Some(c) = &mut v.completion {
+        if let (Some(i), false) = (c.selected, c.inserted) {
+            let word = c.items[i].word.clone();
+            c.inserted = true;
+            set_text(v, &word);
+        }
+    }
+    end(v, ctx);
+}
+
+/// Filters the matches by the text after a key was typed, and ends completion when the cursor
+/// left the completed text or nothing matches
+pub fn filter(v: &mut VimInner, ctx: &mut VimScriptCtx<VimInner>) {
+    let Some(c) = &v.completion else {
+        return;
+    };
+    let win = v.get_focus();
+    let (row, col) = (win.cursor().row(), win.cursor().col());
+    if row != c.row || col < c.col {
+        end(v, ctx);
+        return;
+    }
+    let leader = win.buffer().read()[row].text()[c.col..col].to_string();
+    // File names are found again, since the leader can now be in another directory
+    let all = match c.mode {
+        Mode::File => gather(v, Mode::File).1,
+        _ => c.all.clone(),
+    };
+    let ignorecase = v.options().ignorecase && c.mode != Mode::File;
+    let items: Vec<_> = all
+        .iter()
+        .filter(|i| has_prefix(&i.word, &leader, ignorecase) && i.word != leader)
+        .cloned()
+        .collect();
+    if items.is_empty() {
+        end(v, ctx);
+        return;
+    }
+    let c = v.completion.as_mut().unwrap();
+    c.all = all;
+    c.items = items;
+    c.leader = leader;
+    c.selected = None;
+    c.inserted = false;
+    show(v, ctx);
+}
+
+/// Truncates or pads `text` to `w` columns
+pub(crate) fn fit(text: &str, w: usize, width: &WidthOpts) -> String {
+    let mut ret = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let cw = width.char_width(c);
+        if used + cw > w {
+            break;
+        }
+        used += cw;
+        ret.push(c);
+    }
+    ret.extend(std::iter::repeat_n(' ', w - used));
+    ret
+}
+
+/// Where the popup menu is on the screen
+struct Layout {
+    x: usize,
+    top: usize,
+    height: usize,
+    /// The width of the text of an item, without the padding and the scrollbar
+    inner: usize,
+    scrollbar: bool,
+    /// The first item that is shown
+    first: usize,
+    /// The widths of the columns of the words, kinds and menus
+    columns: [usize; 3],
+}
+
+/// Places the popup menu below or above the completed text, when it is shown
+fn layout(v: &VimInner) -> Option<Layout> {
+    let c = v
+        .completion
+        .as_ref()
+        .filter(|c| c.menu && !c.items.is_empty())?;
+    let width = WidthOpts::new(v.options());
+    let win = v.get_focus();
+    let cursor = win.cursor_pos().pos();
+    let typed = {
+        let b = win.buffer().read();
+        let line = b[c.row].text();
+        width.str_width(&line[c.col..win.cursor().col().max(c.col).min(line.len())])
+    };
+    let (screen_w, screen_h) = (v.size.0 as usize, v.size.1 as usize - 1);
+    // The menu is below the text when it fits, or when there is more room there
+    let below = screen_h.saturating_sub(cursor.1 + 1);
+    let mut height = c.items.len();
+    if v.options().pumheight > 0 {
+        height = height.min(v.options().pumheight as usize);
+    }
+    let (top, height) = if height <= below || below >= cursor.1 {
+        (cursor.1 + 1, height.min(below))
+    } else {
+        let h = height.min(cursor.1);
+        (cursor.1 - h, h)
+    };
+    if height == 0 {
+        return None;
+    }
+    let col_w = |f: fn(&Item) -> &str| {
+        c.items
+            .iter()
+            .map(|i| width.str_width(f(i)))
+            .max()
+            .unwrap_or(0)
+    };
+    let min_w = match v.options().pumwidth {
+        w if w > 0 => w as usize,
+        _ => 15,
+    };
+    let word_w = col_w(|i| if i.abbr.is_empty() { &i.word } else { &i.abbr }).max(min_w - 2);
+    let columns = [word_w, col_w(|i| &i.kind), col_w(|i| &i.menu)];
+    let inner = columns[1..]
+        .iter()
+        .filter(|&&w| w > 0)
+        .fold(word_w, |inner, w| inner + w + 1);
+    let scrollbar = c.items.len() > height;
+    let inner = inner.min(screen_w.saturating_sub(2 + scrollbar as usize));
+    let x = cursor.0.saturating_sub(typed).saturating_sub(1);
+    let x = x.min(screen_w.saturating_sub(inner + 2 + scrollbar as usize));
+    let first = match c.selected {
+        Some(i) if i >= height => i + 1 - height,
+        _ => 0,
+    };
+    Some(Layout {
+        x,
+        top,
+        height,
+        inner,
+        scrollbar,
+        first,
+        columns,
+    })
+}
+
+/// Draws the popup menu, with the info of the selected match next to it
+pub fn draw<W: Write>(v: &VimInner, term: &mut W) -> Result<()> {
+    let (Some(c), Some(pum)) = (&v.completion, layout(v)) else {
+        return Ok(());
+    };
+    let width = WidthOpts::new(v.options());
+    let (screen_w, screen_h) = (v.size.0 as usize, v.size.1 as usize - 1);
+    let Layout {
+        x,
+        top,
+        height,
+        inner,
+        scrollbar,
+        first,
+        columns: [word_w, kind_w, menu_w],
+    } = pum;
+    let thumb_len = (height * height / c.items.len()).max(1);
+    let thumb = first * height / c.items.len();
+    for (row, item) in c.items[first..].iter().take(height).enumerate() {
+        let mut text = if item.abbr.is_empty() {
+            fit(&item.word, word_w, &width)
+        } else {
+            fit(&item.abbr, word_w, &width)
+        };
+        for (field, w) in [(&item.kind, kind_w), (&item.menu, menu_w)] {
+            if w > 0 {
+                text.push(' ');
+                text.push_str(&fit(field, w, &width));
+            }
+        }
+        let group = match c.selected == Some(first + row) {
+            true => "PmenuSel",
+            false => "Pmenu",
+        };
+        Pos(x, top + row).move_cursor(term)?;
+        let text = format!(" {} ", fit(&text, inner, &width));
+        write!(term, "{}", v.highlights.style_of(group).apply(text))?;
+        if scrollbar {
+            let group = match (thumb..thumb + thumb_len).contains(&row) {
+                true => "PmenuThumb",
+                false => "PmenuSbar",
+            };
+            write!(term, "{}", v.highlights.style_of(group).apply(" "))?;
+        }
+    }
+    let info = c.selected.map(|i| c.items[i].info.as_str()).unwrap_or("");
+    if Opts::new(&v.options().completeopt).info && !info.is_empty() {
+        let menu_end = x + inner + 2 + scrollbar as usize;
+        let info_w = info.lines().map(|l| width.str_width(l)).max().unwrap_or(0) + 2;
+        let info_x = match menu_end + info_w <= screen_w {
+            true => menu_end,
+            false => x.saturating_sub(info_w),
+        };
+        let info_w = info_w.min(screen_w - info_x);
+        for (row, line) in info.lines().take(screen_h - top).enumerate() {
+            Pos(info_x, top + row).move_cursor(term)?;
+            let text = format!(" {}", fit(line, info_w - 1, &width));
+            write!(term, "{}", v.highlights.style_of("Pmenu").apply(text))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use vimscript::BuiltinFunction;
+
+    use super::*;
+    use crate::window::WinMode;
+
+    /// A completion function that starts at `self.0`, and completes the base with `1` and `2`
+    struct Complete(isize);
+
+    impl BuiltinFunction<VimInner> for Complete {
+        fn execute(
+            &self,
+            args: Vec<Value>,
+            ctx: &mut VimScriptCtx<VimInner>,
+            _v: &mut VimInner,
+        ) -> std::result::Result<Value, VimError> {
+            match args[0] {
+                Value::Integer(1) => Ok(Value::Integer(self.0)),
+                _ => {
+                    let base = args[1].to_string(ctx);
+                    Ok(Value::list([
+                        Value::str(format!("{base}1")),
+                        Value::str(format!("{base}2")),
+                    ]))
+                }
+            }
+        }
+    }
+
+    /// Vim in Insert mode on `line`, with the cursor at its end
+    fn insert(line: &str) -> (VimInner, VimScriptCtx<VimInner>) {
+        let (mut v, ctx) = VimInner::with_lines(&[line]);
+        let win = v.get_focus_mut();
+        win.set_mode(WinMode::Insert);
+        win.cursor_apply(Motion::SetCol(line.len()));
+        (v, ctx)
+    }
+
+    fn started(v: &VimInner) -> Option<(usize, &str)> {
+        v.completion.as_ref().map(|c| (c.col, c.leader.as_str()))
+    }
+
+    #[test]
+    fn items() {
+        let ctx = VimScriptCtx::init();
+        let item = Item {
+            word: "foo".into(),
+            abbr: "f".into(),
+            menu: "[m]".into(),
+            info: "about foo".into(),
+            kind: "v".into(),
+            user_data: Some(Value::Integer(3)),
+        };
+        assert_eq!(Item::from_value(&ctx, &item.to_value()), Some(item));
+        assert_eq!(
+            Item::from_value(&ctx, &Value::str("bar")),
+            Some(Item::new("bar".into()))
+        );
+        let no_word = Value::object([("abbr", Value::str("b"))]);
+        assert_eq!(Item::from_value(&ctx, &no_word), None);
+    }
+
+    #[test]
+    fn findstart() {
+        let start = |line: &str, findstart| {
+            let (mut v, mut ctx) = insert(line);
+            ctx.builtin("test_complete", Arc::new(Complete(findstart)));
+            call_user(&mut v, &mut ctx, "test_complete", Mode::Function).unwrap();
+            started(&v).map(|(col, leader)| (col, leader.to_string()))
+        };
+        assert_eq!(start("foo.bar", -2), None);
+        assert_eq!(start("foo.bar", -3), None);
+        // Other negative columns complete at the cursor
+        assert_eq!(start("foo.bar", -1), Some((7, "".into())));
+        assert_eq!(start("foo.bar", 4), Some((4, "bar".into())));
+        // Columns past the cursor, or inside a character, are moved back
+        assert_eq!(start("foo.bar", 100), Some((7, "".into())));
+        assert_eq!(start("aéb", 2), Some((1, "éb".into())));
+    }
+
+    #[test]
+    fn complete_func() {
+        let (mut v, mut ctx) = insert("a foo");
+        // {startcol} is counted from 1
+        let words = Value::list([Value::str("foobar"), Value::str("food")]);
+        ctx.run_function("complete", vec![Value::Integer(3), words], &mut v)
+            .unwrap();
+        assert_eq!(started(&v), Some((2, "foo")));
+        let what = vec![Value::list([Value::str("mode")])];
+        let info = ctx.run_function("complete_info", what, &mut v).unwrap();
+        let Value::Object(info) = info else {
+            panic!("complete_info() didn't give a dict");
+        };
+        let info = info.lock().unwrap();
+        assert_eq!(info.keys().collect::<Vec<_>>(), ["mode"]);
+        assert_eq!(info["mode"], Value::str("eval"));
+    }
+
+    #[test]
+    fn prefixes() {
+        let items = |words: &[&str]| -> Vec<Item> {
+            words.iter().map(|w| Item::new(w.to_string())).collect()
+        };
+        assert_eq!(common_prefix(&items(&["foobar", "foobaz", "foo"])), "foo");
+        assert_eq!(common_prefix(&items(&["éa", "éb"])), "é");
+        assert_eq!(common_prefix(&items(&[])), "");
+        assert!(has_prefix("FooBar", "foo", true));
+        assert!(!has_prefix("FooBar", "foo", false));
+    }
+
+    #[test]
+    fn matches() {
+        let iskeyword = CharSet::parse("@,48-57,_", false).unwrap();
+        let mut m = Matches {
+            leader: "fo",
+            ignorecase: true,
+            infercase: true,
+            items: vec![],
+        };
+        m.add_words("Foo.bar fo fob(foo) Fork", &iskeyword);
+        let words: Vec<_> = m.items.iter().map(|i| i.word.as_str()).collect();
+        assert_eq!(words, ["foo", "fob", "fork"]);
+    }
+}
@@ -4,7 +4,7 @@
 // Distributed under terms of the MIT license.
 //
 
-use crate::{buffer::BufferRead, Result, util::Pos};
+use crate::{buffer::BufferRead, Result, util::Pos, width::WidthOpts};
 use crossterm::{
     cursor::{MoveTo, SetCursorShape},
     QueueableCommand,
@@ -29,6 +29,8 @@ pub enum Motion {
 pub struct Cursor {
     x: usize,
     y: usize,
+    /// Screen column the cursor should return to when moving between lines
+    want: usize,
     ty: CursorShape,
 }
 
@@ -43,18 +45,20 @@ impl Cursor {
         Self {
             x: 0,
             y: 0,
+            want: 0,
             ty: CursorShape::Block,
         }
     }
 
     pub fn from_params(x: usize, y: usize, ty: CursorShape) -> Self {
-        Self { x, y, ty }
+        Self { x, y, want: x, ty }
     }
 
     pub(crate) fn invalid() -> Self {
         Self {
             x: !0,
             y: !0,
+            want: !0,
             ty: CursorShape::Block,
         }
     }
@@ -67,14 +71,13 @@ impl Cursor {
         self.x
     }
 
-    pub fn apply(&mut self, motion: Motion, buffer: &BufferRead, insert: bool) {
+    pub fn apply(&mut self, motion: Motion, buffer: &BufferRead, insert: bool, width: &WidthOpts) {
         match motion {
             Motion::SetRow(r) => self.y = r.min(buffer.len() - 1),
             Motion::SetCol(c) => {
-                self.x = c.min(
-                    buffer[self.y]
-                        .len()
-                        .saturating_sub(if insert { 0 } else { 1 }),
+                let line = &buffer[self.y];
+                self.x = line.floor_grapheme(
+                    c.min(line.len().saturating_sub(if insert { 0 } else { 1 })),
                 )
             }
             Motion::Up => self.y = self.y.saturating_sub(1),
@@ -84,6 +87,19 @@ impl Cursor {
             Motion::End => self.x = buffer[self.y].len(),
             Motion::Start => self.x = buffer[self.y].first_char(),
         }
+        match motion {
+            Motion::SetRow(_) | Motion::Up | Motion::Down => {
+                let line = &buffer[self.y];
+                let x = width.byte_at(line.text(), self.want);
+                self.x = if x < line.len() || insert {
+                    x
+                } else {
+                    line.prev(line.len())
+                };
+            }
+            Motion::End => self.want = usize::MAX,
+            _ => self.want = width.col_of(buffer[self.y].text(), self.x),
+        }
     }
 
     pub fn shape(&self) -> CursorShape {
@@ -0,0 +1,457 @@
+//
+// edit.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::fs;
+
+use vimscript::{resolve_address, CmdRange, Value, VimScriptCtx};
+
+use crate::cli::commands::{count_lines, range_lines};
+use crate::cursor::Motion;
+use crate::registers::Register;
+use crate::shell;
+use crate::util::plural;
+use crate::VimInner;
+
+/// Splits off the register name that `:delete`, `:yank` and `:put` take before a count
+fn split_register(args: &str) -> (Option<char>, &str) {
+    let args = args.trim_start();
+    match args.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || "\"_-+*=".contains(c) => {
+            (Some(c), &args[c.len_utf8()..])
+        }
+        _ => (None, args),
+    }
+}
+
+/// The line that `:put`, `:read`, `:move` and `:copy` insert after, counted from 1, where 0 is
+/// before the first line
+fn insert_line(range: CmdRange, v: &VimInner) -> usize {
+    match range {
+        CmdRange::CurrentLine => v.get_focus().cursor().row() + 1,
+        CmdRange::Range { end, .. } => end,
+    }
+}
+
+/// Moves the cursor to the first non-blank of `line`
+pub(crate) fn set_cursor(v: &mut VimInner, line: usize) {
+    let buffer = v.get_focus().buffer().clone();
+    let (line, col) = buffer.with_read(|b| {
+        let line = line.min(b.len() - 1);
+        (line, b[line].first_char())
+    });
+    let win = v.get_focus_mut();
+    win.cursor_apply(Motion::SetRow(line));
+    win.cursor_apply(Motion::SetCol(col));
+}
+
+/// Reports a change to `n` lines, when there are more than 'report'
+pub(crate) fn report(v: &mut VimInner, n: usize, message: String) {
+    if n > v.options().report.max(0) as usize {
+        v.message(message);
+    }
+}
+
+fn lines(v: &VimInner, first: usize, last: usize) -> Vec<String> {
+    let buffer = v.get_focus().buffer();
+    let b = buffer.read();
+    (first..=last).map(|l| b[l].text().to_string()).collect()
+}
+
+/// Inserts `text` below `line`, counted from 1
+fn insert(v: &mut VimInner, line: usize, text: Vec<String>) {
+    v.get_focus()
+        .buffer()
+        .write()
+        .replace_lines(line..line, text);
+}
+
+/// `:[range]d[elete] [x] [count]`
+pub fn delete(v: &mut VimInner, range: CmdRange, args: &str) -> Result<(), String> {
+    let (name, args) = split_register(args);
+    let ((first, last), _) = count_lines(range, args, v)?;
+    let text = lines(v, first, last);
+    let n = text.len();
+    v.registers_mut().delete(name, Register::lines(text));
+    v.get_focus()
+        .buffer()
+        .write()
+        .replace_lines(first..last + 1, vec![]);
+    set_cursor(v, first);
+    report(v, n, plural(n, "fewer line", "fewer lines"));
+    Ok(())
+}
+
+/// `:[range]y[ank] [x] [count]`
+pub fn yank(v: &mut VimInner, range: CmdRange, args: &str) -> Result<(), String> {
+    let (name, args) = split_register(args);
+    let ((first, last), _) = count_lines(range, args, v)?;
+    let text = lines(v, first, last);
+    let n = text.len();
+    v.registers_mut().yank(name, Register::lines(text));
+    report(v, n, format!("{} yanked", plural(n, "line", "lines")));
+    Ok(())
+}
+
+/// `:[range]m[ove] {address}`
+pub fn move_lines(v: &mut VimInner, range: CmdRange, args: &str) -> Result<(), String> {
+    let (first, last) = range_lines(range, v);
+    let (dest, _) = resolve_address(args, v).map_err(|e| format!("{e}"))?;
+    if (first + 1..=last).contains(&dest) {
+        return Err("E134: Cannot move a range of lines into itself".into());
+    }
+    let n = last - first + 1;
+    // Moving the lines right after themselves or the line before them changes nothing
+    if dest != first && dest != last + 1 {
+        let at = if dest > last { dest - n } else { dest };
+        v.get_focus()
+            .buffer()
+            .write()
+            .move_lines(first..last + 1, at);
+        set_cursor(v, at + n - 1);
+    } else {
+        set_cursor(v, last);
+    }
+    report(v, n, format!("{} moved", plural(n, "line", "lines")));
+    Ok(())
+}
+
+/// `:[range]co[py] {address}` and `:t`
+pub fn copy(v: &mut VimInner, range: CmdRange, args: &str) -> Result<(), String> {
+    let (first, last) = range_lines(range, v);
+    let (dest, _) = resolve_address(args, v).map_err(|e| format!("{e}"))?;
+    let text = lines(v, first, last);
+    let n = text.len();
+    insert(v, dest, text);
+    set_cursor(v, dest + n - 1);
+    report(v, n, plural(n, "more line", "more lines"));
+    Ok(())
+}
+
+/// Joins `lines` like `J`, or without changing any white space (`gJ` and `:join!`) when
+/// `keep_space` is set. Returns the text and the column the last line was joined at.
+fn join_text(lines: &[String], keep_space: bool, joinspaces: bool) -> (String, usize) {
+    let mut text = lines[0].clone();
+    let mut col = 0;
+    for next in &lines[1..] {
+        if keep_space {
+            col = text.len();
+            text.push_str(next);
+            continue;
+        }
+        let next = next.trim_start_matches([' ', '\t']);
+        let end = text.chars().last();
+        if !next.is_empty() && !next.starts_with(')') && end.is_some_and(|c| c != ' ' && c != '\t')
+        {
+            text.push(' ');
+            if joinspaces && matches!(end, Some('.' | '!' | '?')) {
+                text.push(' ');
+            }
+        }
+        col = text.len();
+        text.push_str(next);
+    }
+    (text, col)
+}
+
+/// `:[range]j[oin][!] [count]`
+pub fn join(v: &mut VimInner, range: CmdRange, bang: bool, args: &str) -> Result<(), String> {
+    let len = v.get_focus().buffer().read().len();
+    let ((first, mut last), rest) = count_lines(range, args, v)?;
+    if first == last {
+        // `:2,2join` does nothing, but a line on its own is joined with the next one
+        let count = rest != args.trim_start();
+        if matches!(range, CmdRange::Range { start, end } if start == end && !count) {
+            return Ok(());
+        }
+        if last + 1 >= len {
+            return Ok(());
+        }
+        last += 1;
+    }
+    let (text, col) = join_text(&lines(v, first, last), bang, v.options().joinspaces != 0);
+    v.get_focus()
+        .buffer()
+        .write()
+        .replace_lines(first..last + 1, vec![text]);
+    let win = v.get_focus_mut();
+    win.cursor_apply(Motion::SetRow(first));
+    win.cursor_apply(Motion::SetCol(col));
+    Ok(())
+}
+
+/// The width of the indent of `text`, with tabs `tabstop` wide
+fn indent_width(text: &str, tabstop: usize) -> usize {
+    text.chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .fold(0, |w, c| {
+            if c == '\t' {
+                w + tabstop - w % tabstop
+            } else {
+                w + 1
+            }
+        })
+}
+
+/// Shifts `text` by `amount` times 'shiftwidth', rounding to a multiple of it with `round`
+fn shift_line(
+    text: &str,
+    amount: isize,
+    shiftwidth: usize,
+    tabstop: usize,
+    expandtab: bool,
+    round: bool,
+) -> String {
+    let body = text.trim_start_matches([' ', '\t']);
+    if body.is_empty() && amount > 0 {
+        return text.to_string();
+    }
+    let old = indent_width(text, tabstop) as isize;
+    let sw = shiftwidth as isize;
+    let new = if round {
+        let rounded = if amount > 0 {
+            old / sw * sw
+        } else {
+            (old + sw - 1) / sw * sw
+        };
+        rounded + sw * amount
+    } else {
+        old + sw * amount
+    }
+    .max(0) as usize;
+    let indent = if expandtab {
+        " ".repeat(new)
+    } else {
+        "\t".repeat(new / tabstop) + &" ".repeat(new % tabstop)
+    };
+    indent + body
+}
+
+/// `:[range]> [count]` and `:<`, where `dir` is the command, and repeating it in `args` shifts
+/// more
+pub fn shift(v: &mut VimInner, range: CmdRange, dir: char, args: &str) -> Result<(), String> {
+    let rest = args.trim_start();
+    let times = rest.len() - rest.trim_start_matches(dir).len() + 1;
+    let ((first, last), _) = count_lines(range, rest.trim_start_matches(dir), v)?;
+    let opts = v.options();
+    let tabstop = opts.tabstop.max(1) as usize;
+    let shiftwidth = if opts.shiftwidth > 0 {
+        opts.shiftwidth as usize
+    } else {
+        tabstop
+    };
+    let round = opts.shiftround != 0;
+    let expandtab = v.get_focus().buffer().read().options().expandtab;
+    let amount = if dir == '>' {
+        times as isize
+    } else {
+        -(times as isize)
+    };
+    let text = lines(v, first, last)
+        .iter()
+        .map(|l| shift_line(l, amount, shiftwidth, tabstop, expandtab, round))
+        .collect();
+    v.get_focus()
+        .buffer()
+        .write()
+        .replace_lines(first..last + 1, text);
+    set_cursor(v, last);
+    let n = last - first + 1;
+    report(
+        v,
+        n,
+        format!(
+            "{} {dir}ed {}",
+            plural(n, "line", "lines"),
+            plural(times, "time", "times")
+        ),
+    );
+    Ok(())
+}
+
+/// `:[range]p[rint] [count] [flags]`, and `:number` and `:#` when `number` is set
+pub fn print(v: &mut VimInner, range: CmdRange, number: bool, args: &str) -> Result<(), String> {
+    let ((first, last), flags) = count_lines(range, args, v)?;
+    let number = number || flags.contains('#') || v.options().number != 0;
+    let list = flags.contains('l');
+    let text: Vec<_> = lines(v, first, last)
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = if list {
+                line.replace('\t', "^I") + "$"
+            } else {
+                line
+            };
+            if number {
+                format!("{:>3} {line}", first + i + 1)
+            } else {
+                line
+            }
+        })
+        .collect();
+    set_cursor(v, last);
+    v.show_lines(text);
+    Ok(())
+}
+
+/// `:[line]pu[t][!] [x]`, which puts the register as lines below the line, or above it with `!`
+pub fn put(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    range: CmdRange,
+    bang: bool,
+    args: &str,
+) -> Result<(), String> {
+    let line = insert_line(range, v);
+    let text = match split_register(args) {
+        (Some('='), expr) => match ctx.eval(expr, v).map_err(|e| format!("{e}"))? {
+            Value::List(items) => items
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|i| i.to_string(ctx))
+                .collect(),
+            value => {
+                let text = value.to_string(ctx);
+                text.strip_suffix('\n')
+                    .unwrap_or(&text)
+                    .split('\n')
+                    .map(String::from)
+                    .collect()
+            }
+        },
+        (name, _) => {
+            let name = name.unwrap_or('"');
+            match v.registers().get(name) {
+                Some(reg) => reg.lines.clone(),
+                None => return Err(format!("E353: Nothing in register {name}")),
+            }
+        }
+    };
+    if text.is_empty() {
+        return Ok(());
+    }
+    let at = if bang { line.saturating_sub(1) } else { line };
+    let n = text.len();
+    insert(v, at, text);
+    set_cursor(v, at + n - 1);
+    report(v, n, plural(n, "more line", "more lines"));
+    Ok(())
+}
+
+/// `:[line]r[ead] [file]` and `:r !{cmd}`, which insert the file or the output of the command
+/// below the line
+pub fn read(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    range: CmdRange,
+    bang: bool,
+    args: &str,
+) -> Result<(), String> {
+    let line = insert_line(range, v);
+    let args = args.trim();
+    let cmd = args.strip_prefix('!').or(bang.then_some(args));
+    let (text, name) = match cmd {
+        Some(cmd) => {
+            let cmd = shell::expand(v, cmd)?;
+            (shell::run(v, ctx, &cmd, None)?, None)
+        }
+        None => {
+            let name = match args {
+                "" => v
+                    .get_focus()
+                    .buffer()
+                    .read()
+                    .filename()
+                    .map(|p| p.display().to_string()),
+                name => Some(name.to_string()),
+            };
+            let name = name.ok_or("E32: No file name")?;
+            let text =
+                fs::read_to_string(&name).map_err(|_| format!("E484: Can't open file {name}"))?;
+            (text, Some(name))
+        }
+    };
+    let lines: Vec<String> = text.lines().map(String::from).collect();
+    let n = lines.len();
+    if n > 0 {
+        insert(v, line, lines);
+        set_cursor(v, line);
+    }
+    if let Some(name) = name {
+        v.message(format!("\"{name}\" {n}L, {}B", text.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Pos;
+
+    #[test]
+    fn joins() {
+        let lines = |l: &[&str]| l.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let text = lines(&["a.", "  b", "\t)c", "", "d "]);
+        assert_eq!(join_text(&text, false, false), ("a. b)c d ".into(), 7));
+        assert_eq!(join_text(&text[..2], false, true), ("a.  b".into(), 4));
+        assert_eq!(join_text(&text[..3], true, false), ("a.  b\t)c".into(), 5));
+        assert_eq!(join_text(&lines(&["", "x"]), false, false), ("x".into(), 0));
+    }
+
+    #[test]
+    fn shifts() {
+        assert_eq!(shift_line("a", 1, 4, 8, true, false), "    a");
+        assert_eq!(shift_line("      a", 1, 4, 8, false, false), "\t  a");
+        assert_eq!(shift_line("   a", 1, 4, 8, true, true), "    a");
+        assert_eq!(shift_line("   a", -1, 4, 8, true, true), "a");
+        assert_eq!(shift_line("\ta", -3, 4, 8, false, false), "a");
+        assert_eq!(shift_line("", 1, 4, 8, false, false), "");
+    }
+
+    #[test]
+    fn put_lines() {
+        let (mut v, mut ctx) = VimInner::with_lines(&["a", "b"]);
+        let text = |v: &VimInner| lines(v, 0, v.get_focus().buffer().read().len() - 1);
+        let line = |l| CmdRange::Range { start: l, end: l };
+        put(&mut v, &mut ctx, line(0), false, "=['x', 'y']").unwrap();
+        assert_eq!(text(&v), ["x", "y", "a", "b"]);
+        assert_eq!(v.get_focus().cursor().row(), 1);
+        // An empty list puts nothing, and leaves the cursor
+        put(&mut v, &mut ctx, line(0), false, "=[]").unwrap();
+        put(&mut v, &mut ctx, line(1), true, "=[]").unwrap();
+        assert_eq!(text(&v), ["x", "y", "a", "b"]);
+        assert_eq!(v.get_focus().cursor().row(), 1);
+    }
+
+    #[test]
+    fn moves_marks() {
+        let (mut v, mut ctx) = VimInner::with_lines(&["a", "b", "c", "d"]);
+        let text = |v: &VimInner| lines(v, 0, v.get_focus().buffer().read().len() - 1);
+        let mark = |v: &VimInner, c| v.get_focus().buffer().read().mark(c).map(|p| p.1);
+        v.get_focus().buffer().write().set_mark('a', Pos(0, 2));
+        v.get_focus().buffer().write().set_mark('b', Pos(0, 3));
+        ctx.run("3,4m0", &mut v).unwrap();
+        assert_eq!(text(&v), ["c", "d", "a", "b"]);
+        assert_eq!((mark(&v, 'a'), mark(&v, 'b')), (Some(0), Some(1)));
+        ctx.run("1m$", &mut v).unwrap();
+        assert_eq!(text(&v), ["d", "a", "b", "c"]);
+        assert_eq!((mark(&v, 'a'), mark(&v, 'b')), (Some(3), Some(0)));
+        assert_eq!(v.get_focus().cursor().row(), 3);
+    }
+
+    #[test]
+    fn prints() {
+        let (mut v, mut ctx) = VimInner::with_lines(&["a", "\tb", "c"]);
+        ctx.run("1,2#", &mut v).unwrap();
+        assert_eq!(v.cli.take_lines(), ["  1 a", "  2 \tb"]);
+        ctx.run("2,3p l", &mut v).unwrap();
+        assert_eq!(v.cli.take_lines(), ["^Ib$", "c$"]);
+        ctx.run("2p", &mut v).unwrap();
+        assert!(!v.cli.listing());
+        assert_eq!(v.get_message(), "\tb");
+    }
+}
@@ -0,0 +1,376 @@
+//
+// errorformat.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::path::Path;
+
+use vimscript::regex::{self, Regex};
+
+use crate::quickfix::Entry;
+
+/// One part of 'errorformat', compiled to a pattern
+struct Format {
+    regex: Regex,
+    /// The item of each group of the pattern, like `f` for `%f`
+    fields: Vec<char>,
+    /// The prefix that makes it a multi-line or directory format, like `A` for `%A`
+    prefix: Option<char>,
+    /// `+` or `-` before the prefix, which includes the whole line or ignores it
+    flag: Option<char>,
+}
+
+/// Splits 'errorformat' at the commas that aren't escaped with a backslash
+fn split(efm: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut chars = efm.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            ',' => {
+                parts.push(&efm[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    parts.push(&efm[start..]);
+    parts.into_iter().filter(|p| !p.is_empty()).collect()
+}
+
+/// The pattern of a format, the items of its groups, its prefix and its flag
+type Parts = (String, Vec<char>, Option<char>, Option<char>);
+
+/// Converts a part of 'errorformat' to a pattern
+fn to_pattern(fmt: &str) -> Result<Parts, String> {
+    let mut pat = String::from("^");
+    let (mut fields, mut prefix, mut flag) = (vec![], None, None);
+    let mut chars = fmt.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '%' {
+            match c {
+                '\\' => pat.push(chars.next().map_or('\\', |(_, c)| c)),
+                '.' | '*' | '^' | '$' | '~' | '[' => {
+                    pat.push('\\');
+                    pat.push(c);
+                }
+                c => pat.push(c),
+            }
+            continue;
+        }
+        let Some((_, item)) = chars.next() else {
+            return Err("E377: Invalid %% in format string".into());
+        };
+        match item {
+            'f' | 'n' | 'l' | 'e' | 'c' | 'k' | 't' | 'm' | 'r' | 'p' | 'v' | 's' | 'o' => {
+                if fields.contains(&item) {
+                    return Err(format!("E372: Too many %{item} in format string"));
+                }
+                let item_pat = match item {
+                    // A file name followed by a character takes as little as it can, so
+                    // `%f:%l` works for names with a `:`
+                    'f' => match chars.peek() {
+                        Some((_, '\\' | '%')) => "\\f\\+",
+                        Some(_) => ".\\{-1,}",
+                        None => ".\\+",
+                    },
+                    'n' | 'l' | 'e' | 'c' | 'k' | 'v' => "\\d\\+",
+                    't' => ".",
+                    'r' => ".*",
+                    'p' => "[-\t .]*",
+                    _ => ".\\+",
+                };
+                fields.push(item);
+                pat.push_str(&format!("\\({item_pat}\\)"));
+            }
+            '*' => match chars.next() {
+                Some((_, '[')) => {
+                    pat.push('[');
+                    if let Some((_, '^')) = chars.peek() {
+                        chars.next();
+                        pat.push('^');
+                    }
+                    // The first character can be a `]`
+                    let mut first = true;
+                    loop {
+                        match chars.next() {
+                            Some((_, ']')) if !first => break,
+                            Some((_, c)) => pat.push(c),
+                            None => return Err("E374: Missing ] in format string".into()),
+                        }
+                        first = false;
+                    }
+                    pat.push_str("]\\+");
+                }
+                Some((_, '\\')) => {
+                    pat.push('\\');
+                    if let Some((_, c)) = chars.next() {
+                        pat.push(c);
+                    }
+                    pat.push_str("\\+");
+                }
+                _ => return Err("E375: Unsupported %* in format string".into()),
+            },
+            '%' | '\\' | '.' | '^' | '$' | '~' | '[' => pat.push(item),
+            '#' => pat.push('*'),
+            '>' => (),
+            '+' | '-' if i == 0 => {
+                flag = Some(item);
+                match chars.next() {
+                    Some((_, p)) if "DXAEWINCZGOPQ".contains(p) => prefix = Some(p),
+                    _ => return Err(format!("E376: Invalid %{item} in format string prefix")),
+                }
+            }
+            p if i == 0 && "DXAEWINCZGOPQ".contains(p) => prefix = Some(p),
+            c => return Err(format!("E377: Invalid %{c} in format string")),
+        }
+    }
+    if fields.len() > 9 {
+        return Err("E377: Too many items in format string".into());
+    }
+    pat.push('$');
+    Ok((pat, fields, prefix, flag))
+}
+
+impl Format {
+    fn new(fmt: &str) -> Result<Self, String> {
+        let (pattern, fields, prefix, flag) = to_pattern(fmt)?;
+        // Error messages are always matched ignoring case
+        let opts = regex::Options {
+            ignorecase: true,
+            ..regex::Options::default()
+        };
+        Ok(Self {
+            regex: Regex::new(&pattern, &opts)?,
+            fields,
+            prefix,
+            flag,
+        })
+    }
+}
+
+/// 'errorformat', 'grepformat' or the format given to `setqflist()`
+pub struct ErrorFormat {
+    formats: Vec<Format>,
+}
+
+/// Where a list is read from: the directories of `%D` and `%X`, the files of `%P` and `%Q`,
+/// and whether a multi-line message is being continued
+#[derive(Default)]
+struct Scan {
+    dirs: Vec<String>,
+    files: Vec<String>,
+    multiline: bool,
+    /// Set when the multi-line message was ignored with `%-`, so its continuations are too
+    ignore: bool,
+}
+
+impl ErrorFormat {
+    pub fn new(efm: &str) -> Result<Self, String> {
+        let formats = split(efm)
+            .into_iter()
+            .map(Format::new)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { formats })
+    }
+
+    /// Reads the entries from the lines of a compiler's output
+    pub fn parse<'a>(&self, lines: impl IntoIterator<Item = &'a str>) -> Vec<Entry> {
+        let mut scan = Scan::default();
+        let mut entries = vec![];
+        for line in lines {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if let Some(entry) = self.parse_line(line, &mut scan, &mut entries) {
+                entries.push(entry);
+            }
+        }
+        entries
+    }
+
+    fn parse_line(&self, line: &str, scan: &mut Scan, entries: &mut [Entry]) -> Option<Entry> {
+        let found = self.formats.iter().find_map(|fmt| {
+            if matches!(fmt.prefix, Some('C' | 'Z')) && !scan.multiline {
+                return None;
+            }
+            fmt.regex.find_str(line, 0).map(|m| (fmt, m))
+        });
+        let Some((fmt, m)) = found else {
+            // A line that isn't recognized is shown, but can't be jumped to
+            scan.multiline = false;
+            return Some(Entry {
+                text: line.to_string(),
+                ..Entry::default()
+            });
+        };
+        let mut entry = Entry {
+            kind: fmt.prefix.filter(|p| "EWIN".contains(*p)),
+            ..Entry::default()
+        };
+        let mut file = None;
+        for (i, item) in fmt.fields.iter().enumerate() {
+            let text = m.group_text(line, i + 1).unwrap_or_default();
+            let number = || text.parse().unwrap_or(0);
+            match item {
+                'f' => file = Some(text.clone()),
+                'n' => entry.nr = text.parse().unwrap_or(0),
+                'l' => entry.lnum = number(),
+                'e' => entry.end_lnum = number(),
+                'c' => entry.col = number(),
+                'k' => entry.end_col = number(),
+                'v' => {
+                    entry.col = number();
+                    entry.vcol = true;
+                }
+                'p' => {
+                    entry.col = text.chars().count() + 1;
+                    entry.vcol = true;
+                }
+                't' => entry.kind = text.chars().next(),
+                'm' => entry.text = text,
+                's' => entry.pattern = format!("^\\V{}\\$", text.replace('\\', "\\\\")),
+                _ => (),
+            }
+        }
+        if fmt.flag == Some('+') {
+            entry.text = line.to_string();
+        }
+        match fmt.prefix {
+            Some('D') => {
+                let dir = file.unwrap_or_default();
+                let dir = match scan.dirs.last() {
+                    Some(top) if Path::new(&dir).is_relative() => {
+                        Path::new(top).join(dir).display().to_string()
+                    }
+                    _ => dir,
+                };
+                scan.dirs.push(dir);
+                return None;
+            }
+            Some('X') => {
+                scan.dirs.pop();
+                return None;
+            }
+            Some('P') => {
+                scan.files.extend(file);
+                return None;
+            }
+            Some('Q') => {
+                scan.files.pop();
+                return None;
+            }
+            Some('O') => return None,
+            Some(p @ ('C' | 'Z')) => {
+                if p == 'Z' {
+                    scan.multiline = false;
+                }
+                if let Some(prev) = entries.last_mut().filter(|_| !scan.ignore) {
+                    if !entry.text.is_empty() {
+                        prev.text.push('\n');
+                        prev.text.push_str(&entry.text);
+                    }
+                    prev.kind = prev.kind.or(entry.kind);
+                    if prev.nr == 0 {
+                        prev.nr = entry.nr;
+                    }
+                    if prev.lnum == 0 {
+                        prev.lnum = entry.lnum;
+                    }
+                    if prev.col == 0 {
+                        prev.col = entry.col;
+                        prev.vcol = entry.vcol;
+                    }
+                    if prev.filename.is_none() {
+                        prev.filename = file.map(|f| scan.find(f));
+                        prev.valid |= prev.filename.is_some();
+                    }
+                }
+                return None;
+            }
+            Some('A' | 'E' | 'W' | 'I' | 'N') => {
+                scan.multiline = true;
+                scan.ignore = fmt.flag == Some('-');
+            }
+            _ => scan.multiline = false,
+        }
+        if fmt.flag == Some('-') {
+            return None;
+        }
+        entry.filename = file
+            .map(|f| scan.find(f))
+            .or_else(|| scan.files.last().cloned());
+        entry.valid = entry.filename.is_some() || entry.lnum > 0 || !entry.pattern.is_empty();
+        Some(entry)
+    }
+}
+
+impl Scan {
+    /// The path of `file` in the directories of `%D`, when it is relative and is in one of them
+    fn find(&self, file: String) -> String {
+        if Path::new(&file).is_absolute() {
+            return file;
+        }
+        self.dirs
+            .iter()
+            .rev()
+            .map(|dir| Path::new(dir).join(&file))
+            .find(|path| path.exists())
+            .map_or(file, |path| path.display().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert_eq!(split("%f:%l:%m,%f\\,%l"), ["%f:%l:%m", "%f\\,%l"]);
+        let (pat, fields, ..) = to_pattern("%f:%l:%c: %m").unwrap();
+        assert_eq!(
+            pat,
+            "^\\(.\\{-1,}\\):\\(\\d\\+\\):\\(\\d\\+\\): \\(.\\+\\)$"
+        );
+        assert_eq!(fields, ['f', 'l', 'c', 'm']);
+        let (pat, _, prefix, flag) = to_pattern("%-G%*[^\"]in %f.").unwrap();
+        assert_eq!(pat, "^[^\"]\\+in \\(.\\{-1,}\\)\\.$");
+        assert_eq!((prefix, flag), (Some('G'), Some('-')));
+        assert!(to_pattern("%l%l").is_err());
+        assert!(to_pattern("%f%A").is_err());
+    }
+
+    #[test]
+    fn entries() {
+        let efm = ErrorFormat::new(concat!(
+            "%D%*\\a: Entering directory '%f',%X%*\\a: Leaving directory '%f',",
+            "%E%f:%l:%c: error: %m,%-C%p^,%Z%m,%f:%l: %m,%-Gnote%.%#"
+        ))
+        .unwrap();
+        let lines = [
+            "make: Entering directory 'src'",
+            "a.c:3: warning",
+            "b.c:4:5: error: bad",
+            "   ^",
+            "end of it",
+            "note that",
+            "make: Leaving directory 'src'",
+            "c.c:1: ok",
+            "something else",
+        ];
+        let entries = efm.parse(lines);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].filename.as_deref(), Some("a.c"));
+        assert_eq!((entries[0].lnum, entries[0].text.as_str()), (3, "warning"));
+        assert_eq!(entries[1].text, "bad\nend of it");
+        assert_eq!(
+            (entries[1].lnum, entries[1].col, entries[1].kind),
+            (4, 5, Some('E'))
+        );
+        assert!(entries[2].valid);
+        assert_eq!(entries[3].text, "something else");
+        assert!(!entries[3].valid);
+    }
+}
@@ -0,0 +1,647 @@
+//
+// fold.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use crate::width::WidthOpts;
+
+/// The fold level of a single line, as returned by 'foldexpr'
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldLevel {
+    /// The line is in this many folds
+    Level(usize),
+    /// "=": the same level as the line before
+    Same,
+    /// "a1": the level of the line before plus this
+    Add(usize),
+    /// "s1": the level of the line before minus this
+    Sub(usize),
+    /// ">1": a fold with this level starts at this line
+    Start(usize),
+    /// "<1": a fold with this level ends at this line
+    End(usize),
+    /// "-1": the lower of the levels of the lines around it
+    Undefined,
+}
+
+impl FoldLevel {
+    pub fn parse(s: &str) -> Self {
+        let s = s.trim();
+        let num = |n: &str| n.parse().unwrap_or(1);
+        if s == "=" {
+            Self::Same
+        } else if let Some(n) = s.strip_prefix('a') {
+            Self::Add(num(n))
+        } else if let Some(n) = s.strip_prefix('s') {
+            Self::Sub(num(n))
+        } else if let Some(n) = s.strip_prefix('>') {
+            Self::Start(num(n))
+        } else if let Some(n) = s.strip_prefix('<') {
+            Self::End(num(n))
+        } else {
+            match s.parse::<isize>() {
+                Ok(n) if n < 0 => Self::Undefined,
+                Ok(n) => Self::Level(n as usize),
+                Err(_) => Self::Level(0),
+            }
+        }
+    }
+}
+
+/// Fold levels for 'foldmethod' "indent"
+pub fn indent_levels<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    ignore: &str,
+    shiftwidth: usize,
+    width: &WidthOpts,
+) -> Vec<FoldLevel> {
+    let shiftwidth = shiftwidth.max(1);
+    lines
+        .map(|text| {
+            let trimmed = text.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with(|c| ignore.contains(c)) {
+                FoldLevel::Undefined
+            } else {
+                let indent = &text[..text.len() - trimmed.len()];
+                FoldLevel::Level(width.display_width(indent, 0) / shiftwidth)
+            }
+        })
+        .collect()
+}
+
+/// Fold levels for 'foldmethod' "marker"
+pub fn marker_levels<'a>(lines: impl Iterator<Item = &'a str>, marker: &str) -> Vec<FoldLevel> {
+    let (open, close) = match marker.split_once(',') {
+        Some((open, close)) if !open.is_empty() && !close.is_empty() => (open, close),
+        _ => return lines.map(|_| FoldLevel::Level(0)).collect(),
+    };
+    let mut level = 0;
+    lines
+        .map(|text| {
+            let before = level;
+            let (mut started, mut ended) = (false, false);
+            let mut rest = text;
+            loop {
+                let (pos, len, is_open) = match (rest.find(open), rest.find(close)) {
+                    (Some(o), Some(c)) if o <= c => (o, open.len(), true),
+                    (Some(o), None) => (o, open.len(), true),
+                    (_, Some(c)) => (c, close.len(), false),
+                    (None, None) => break,
+                };
+                rest = &rest[pos + len..];
+                let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let n = rest[..digits].parse::<usize>().ok();
+                rest = &rest[digits..];
+                if is_open {
+                    level = n.unwrap_or(level + 1);
+                    started = true;
+                } else {
+                    level = n.unwrap_or(level).saturating_sub(1);
+                    ended = true;
+                }
+            }
+            match (started, ended) {
+                (true, false) if level > 0 => FoldLevel::Start(level),
+                (true, true) if level > before => FoldLevel::Start(level),
+                (_, true) if level < before => FoldLevel::End(level + 1),
+                _ => FoldLevel::Level(level),
+            }
+        })
+        .collect()
+}
+
+/// The text shown for a closed fold by `foldtext()`
+pub fn fold_text(text: &str, lines: usize, level: usize, marker: &str, cms: &str) -> String {
+    let mut text = text.replace('\t', " ");
+    for m in marker.split(',').filter(|m| !m.is_empty()) {
+        while let Some(i) = text.find(m) {
+            let end = text[i + m.len()..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(text.len(), |e| i + m.len() + e);
+            text.replace_range(i..end, "");
+        }
+    }
+    // Remove the comment the markers were in, if nothing else is left in it
+    let (pre, post) = cms.split_once("%s").unwrap_or((cms, ""));
+    let mut t = text.trim();
+    if let Some(s) = t.strip_suffix(post.trim()).filter(|_| !post.trim().is_empty()) {
+        t = s.trim_end();
+    }
+    if let Some(s) = t.strip_suffix(pre.trim()).filter(|_| !pre.trim().is_empty()) {
+        t = s.trim_end();
+    }
+    format!("+-{}{:3} lines: {}", "-".repeat(level), lines, t.trim_start())
+}
+
+/// The fold characters of 'fillchars'
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldChars {
+    pub fold: char,
+    pub open: char,
+    pub close: char,
+    pub sep: char,
+}
+
+impl FoldChars {
+    pub fn parse(fcs: &str) -> Self {
+        let mut ret = Self {
+            fold: '-',
+            open: '-',
+            close: '+',
+            sep: '|',
+        };
+        for item in fcs.split(',') {
+            let (name, c) = match item.split_once(':') {
+                Some((name, c)) => (name, c.chars().next().unwrap_or(' ')),
+                None => continue,
+            };
+            match name {
+                "fold" => ret.fold = c,
+                "foldopen" => ret.open = c,
+                "foldclose" => ret.close = c,
+                "foldsep" => ret.sep = c,
+                _ => (),
+            }
+        }
+        ret
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+    pub start: usize,
+    /// Last line in the fold
+    pub end: usize,
+    pub closed: bool,
+    pub nested: Vec<Fold>,
+}
+
+impl Fold {
+    fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            closed: false,
+            nested: vec![],
+        }
+    }
+
+    fn contains(&self, line: usize) -> bool {
+        self.start <= line && line <= self.end
+    }
+
+    fn intersects(&self, range: &RangeInclusive<usize>) -> bool {
+        self.start <= *range.end() && *range.start() <= self.end
+    }
+
+    fn lines(&self) -> usize {
+        self.end - self.start + 1
+    }
+}
+
+/// The folds of a window
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FoldTree {
+    folds: Vec<Fold>,
+}
+
+impl FoldTree {
+    /// Builds the folds described by the level of each line, with at most `nestmax` levels
+    pub fn from_levels(levels: &[FoldLevel], nestmax: usize) -> Self {
+        fn close(stack: &mut Vec<Fold>, ret: &mut Vec<Fold>, end: usize) {
+            let mut fold = stack.pop().unwrap();
+            fold.end = end;
+            match stack.last_mut() {
+                Some(parent) => parent.nested.push(fold),
+                None => ret.push(fold),
+            }
+        }
+        let mut ret = vec![];
+        let mut stack = vec![];
+        let mut prev = 0;
+        for (i, level) in levels.iter().enumerate() {
+            let (n, start, end) = match *level {
+                FoldLevel::Level(n) => (n, false, false),
+                FoldLevel::Same => (prev, false, false),
+                FoldLevel::Add(n) => (prev + n, false, false),
+                FoldLevel::Sub(n) => (prev.saturating_sub(n), false, false),
+                FoldLevel::Start(n) => (n, true, false),
+                FoldLevel::End(n) => (n, false, true),
+                FoldLevel::Undefined => {
+                    let next = levels[i + 1..].iter().find_map(|l| match *l {
+                        FoldLevel::Level(n) | FoldLevel::Start(n) | FoldLevel::End(n) => Some(n),
+                        FoldLevel::Undefined => None,
+                        _ => Some(prev),
+                    });
+                    (prev.min(next.unwrap_or(0)), false, false)
+                }
+            };
+            let n = n.min(nestmax);
+            let keep = if start { n.saturating_sub(1) } else { n };
+            while stack.len() > keep {
+                close(&mut stack, &mut ret, i - 1);
+            }
+            while stack.len() < n {
+                stack.push(Fold::new(i, i));
+            }
+            if end && n > 0 {
+                while stack.len() >= n {
+                    close(&mut stack, &mut ret, i);
+                }
+            }
+            prev = stack.len();
+        }
+        while !stack.is_empty() {
+            close(&mut stack, &mut ret, levels.len() - 1);
+        }
+        Self { folds: ret }
+    }
+
+    /// Indices of the folds that contain `line`, outermost first
+    fn path(&self, line: usize) -> Vec<usize> {
+        let mut ret = vec![];
+        let mut list = &self.folds;
+        while let Some(i) = list.iter().position(|f| f.contains(line)) {
+            ret.push(i);
+            list = &list[i].nested;
+        }
+        ret
+    }
+
+    fn chain(&self, line: usize) -> Vec<&Fold> {
+        let mut ret: Vec<&Fold> = vec![];
+        for i in self.path(line) {
+            let list = ret.last().map_or(&self.folds, |f| &f.nested);
+            ret.push(&list[i]);
+        }
+        ret
+    }
+
+    fn list_mut(&mut self, path: &[usize]) -> &mut Vec<Fold> {
+        let mut list = &mut self.folds;
+        for &i in path {
+            list = &mut list[i].nested;
+        }
+        list
+    }
+
+    fn get_mut(&mut self, path: &[usize]) -> &mut Fold {
+        let (last, parent) = path.split_last().expect("Empty fold path");
+        &mut self.list_mut(parent)[*last]
+    }
+
+    fn walk_mut(list: &mut [Fold], depth: usize, f: &mut impl FnMut(&mut Fold, usize)) {
+        for fold in list {
+            f(fold, depth);
+            Self::walk_mut(&mut fold.nested, depth + 1, f);
+        }
+    }
+
+    fn walk(list: &[Fold], depth: usize, f: &mut impl FnMut(&Fold, usize)) {
+        for fold in list {
+            f(fold, depth);
+            Self::walk(&fold.nested, depth + 1, f);
+        }
+    }
+
+    /// Index in the path to `line` of the outermost fold that is shown closed
+    fn closed_index(&self, line: usize, minlines: usize) -> Option<usize> {
+        self.chain(line)
+            .iter()
+            .position(|f| f.closed && f.lines() > minlines)
+    }
+
+    /// The lines of the outermost closed fold that contains `line`. Folds with `minlines` lines or
+    /// less are never shown closed.
+    pub fn closed_at(&self, line: usize, minlines: usize) -> Option<RangeInclusive<usize>> {
+        self.chain(line)
+            .into_iter()
+            .find(|f| f.closed && f.lines() > minlines)
+            .map(|f| f.start..=f.end)
+    }
+
+    /// Level of the outermost closed fold that contains `line`
+    pub fn closed_level(&self, line: usize, minlines: usize) -> Option<usize> {
+        self.closed_index(line, minlines).map(|i| i + 1)
+    }
+
+    /// Number of folds that contain `line`
+    pub fn level(&self, line: usize) -> usize {
+        self.path(line).len()
+    }
+
+    /// The deepest level of any fold
+    pub fn depth(&self) -> usize {
+        let mut ret = 0;
+        Self::walk(&self.folds, 1, &mut |_, d| ret = ret.max(d));
+        ret
+    }
+
+    /// Closes the folds deeper than `level` and opens the rest
+    pub fn set_level(&mut self, level: usize) {
+        Self::walk_mut(&mut self.folds, 1, &mut |f, d| f.closed = d > level);
+    }
+
+    /// Copies whether each fold is closed from the fold with the same start and depth in `old`
+    pub fn keep_closed(&mut self, old: &FoldTree) {
+        let mut closed = HashMap::new();
+        Self::walk(&old.folds, 1, &mut |f, d| {
+            closed.insert((f.start, d), f.closed);
+        });
+        Self::walk_mut(&mut self.folds, 1, &mut |f, d| {
+            if let Some(&c) = closed.get(&(f.start, d)) {
+                f.closed = c;
+            }
+        });
+    }
+
+    /// Creates a new closed fold. Folds inside of it become nested in the new fold.
+    pub fn create(&mut self, start: usize, end: usize) {
+        let mut list = &mut self.folds;
+        while let Some(i) = list.iter().position(|f| f.start <= start && end <= f.end) {
+            list = &mut list[i].nested;
+        }
+        let mut fold = Fold::new(start, end);
+        fold.closed = true;
+        let (inner, outer) = std::mem::take(list)
+            .into_iter()
+            .partition(|f| start <= f.start && f.end <= end);
+        fold.nested = inner;
+        *list = outer;
+        let pos = list.partition_point(|f| f.start < start);
+        list.insert(pos, fold);
+    }
+
+    /// Deletes the closed fold at `line`, or the innermost fold that contains it. Unless
+    /// `recursive` is set, the nested folds are moved up a level.
+    pub fn delete(&mut self, line: usize, recursive: bool, minlines: usize) -> bool {
+        let mut path = self.path(line);
+        if let Some(i) = self.closed_index(line, minlines) {
+            path.truncate(i + 1);
+        }
+        let (last, parent) = match path.split_last() {
+            Some((last, parent)) => (*last, parent.to_vec()),
+            None => return false,
+        };
+        let list = self.list_mut(&parent);
+        let fold = list.remove(last);
+        if !recursive {
+            list.splice(last..last, fold.nested);
+        }
+        true
+    }
+
+    /// Deletes every fold
+    pub fn clear(&mut self) {
+        self.folds.clear();
+    }
+
+    /// Opens `count` levels of closed folds at `line`
+    pub fn open(&mut self, line: usize, count: usize) -> bool {
+        let path = self.path(line);
+        let mut left = count;
+        for depth in 1..=path.len() {
+            let fold = self.get_mut(&path[..depth]);
+            if fold.closed && left > 0 {
+                fold.closed = false;
+                left -= 1;
+            }
+        }
+        !path.is_empty()
+    }
+
+    /// Closes `count` levels of open folds at `line`, starting with the innermost one that is not
+    /// inside a closed fold
+    pub fn close(&mut self, line: usize, count: usize) -> bool {
+        let path = self.path(line);
+        for _ in 0..count {
+            let chain = self.chain(line);
+            let open = chain.iter().position(|f| f.closed).unwrap_or(chain.len());
+            if open == 0 {
+                break;
+            }
+            self.get_mut(&path[..open]).closed = true;
+        }
+        !path.is_empty()
+    }
+
+    /// Opens the closed fold at `line`, or closes the fold at `line` if it is open
+    pub fn toggle(&mut self, line: usize, count: usize, minlines: usize) -> bool {
+        if self.closed_index(line, minlines).is_some() {
+            self.open(line, count)
+        } else {
+            self.close(line, count)
+        }
+    }
+
+    /// Opens one level of folds in `range`, or every fold if `all` is set
+    pub fn open_range(&mut self, range: RangeInclusive<usize>, all: bool) {
+        fn open(list: &mut [Fold], range: &RangeInclusive<usize>, all: bool) {
+            for fold in list.iter_mut().filter(|f| f.intersects(range)) {
+                let was_closed = fold.closed;
+                fold.closed = false;
+                if all || !was_closed {
+                    open(&mut fold.nested, range, all);
+                }
+            }
+        }
+        open(&mut self.folds, &range, all);
+    }
+
+    /// Closes one level of folds in `range`, or every fold if `all` is set
+    pub fn close_range(&mut self, range: RangeInclusive<usize>, all: bool) {
+        fn close(list: &mut [Fold], range: &RangeInclusive<usize>, all: bool) -> bool {
+            let mut any = false;
+            for fold in list.iter_mut().filter(|f| f.intersects(range)) {
+                if all {
+                    fold.closed = true;
+                    close(&mut fold.nested, range, all);
+                } else if !fold.closed {
+                    if !close(&mut fold.nested, range, all) {
+                        fold.closed = true;
+                    }
+                    any = true;
+                }
+            }
+            any
+        }
+        close(&mut self.folds, &range, all);
+    }
+
+    /// The first line of the next fold that starts after `line` (`zj`)
+    pub fn next_start(&self, line: usize) -> Option<usize> {
+        let mut ret = None;
+        Self::walk(&self.folds, 1, &mut |f, _| {
+            if f.start > line && ret.is_none_or(|r| f.start < r) {
+                ret = Some(f.start);
+            }
+        });
+        ret
+    }
+
+    /// The last line of the previous fold that ends before `line` (`zk`)
+    pub fn prev_end(&self, line: usize) -> Option<usize> {
+        let mut ret = None;
+        Self::walk(&self.folds, 1, &mut |f, _| {
+            if f.end < line && ret.is_none_or(|r| f.end > r) {
+                ret = Some(f.end);
+            }
+        });
+        ret
+    }
+
+    /// Moves the folds after `line` down by `delta` lines, or deletes `-delta` lines starting at
+    /// `line`
+    pub fn shift(&mut self, line: usize, delta: isize) {
+        fn shift(list: &mut Vec<Fold>, line: usize, delta: isize) {
+            if delta >= 0 {
+                let delta = delta as usize;
+                for fold in list.iter_mut() {
+                    if fold.start >= line {
+                        fold.start += delta;
+                    }
+                    if fold.end >= line {
+                        fold.end += delta;
+                    }
+                    shift(&mut fold.nested, line, delta as isize);
+                }
+            } else {
+                let removed = line..line + delta.unsigned_abs();
+                let map = |l: usize| {
+                    if l >= removed.end {
+                        Some(l - removed.len())
+                    } else if l >= removed.start {
+                        None
+                    } else {
+                        Some(l)
+                    }
+                };
+                list.retain_mut(|fold| {
+                    let start = map(fold.start).unwrap_or(removed.start);
+                    let end = match map(fold.end) {
+                        Some(end) => end,
+                        None if removed.start > 0 => removed.start - 1,
+                        None => return false,
+                    };
+                    if end < start {
+                        return false;
+                    }
+                    fold.start = start;
+                    fold.end = end;
+                    shift(&mut fold.nested, line, delta);
+                    true
+                });
+            }
+        }
+        shift(&mut self.folds, line, delta);
+    }
+
+    /// The fold column for `line`, `width` columns wide. Only the first row of a line shows where
+    /// folds start. Without `minlines`, every fold is shown open.
+    pub fn column(
+        &self,
+        line: usize,
+        width: usize,
+        first: bool,
+        minlines: Option<usize>,
+        chars: &FoldChars,
+    ) -> String {
+        let mut ret: Vec<char> = vec![];
+        for fold in self.chain(line) {
+            if fold.closed && minlines.is_some_and(|m| fold.lines() > m) {
+                ret.push(chars.close);
+                break;
+            } else if fold.start == line && first {
+                ret.push(chars.open);
+            } else {
+                ret.push(chars.sep);
+            }
+        }
+        if ret.len() > width && width > 0 {
+            let level = ret.len();
+            ret.truncate(width - 1);
+            ret.push(std::char::from_digit(level as u32, 10).unwrap_or('>'));
+        }
+        let mut ret: String = ret.into_iter().collect();
+        while ret.chars().count() < width {
+            ret.push(' ');
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(tree: &FoldTree) -> Vec<(usize, usize, usize)> {
+        let mut ret = vec![];
+        FoldTree::walk(&tree.folds, 1, &mut |f, d| ret.push((f.start, f.end, d)));
+        ret
+    }
+
+    #[test]
+    fn levels() {
+        use FoldLevel::*;
+        let tree = FoldTree::from_levels(&[Level(0), Level(1), Level(2), Level(1), Level(0)], 20);
+        assert_eq!(ranges(&tree), vec![(1, 3, 1), (2, 2, 2)]);
+        let tree = FoldTree::from_levels(&[Start(1), Same, Start(1), End(1), Level(0)], 20);
+        assert_eq!(ranges(&tree), vec![(0, 1, 1), (2, 3, 1)]);
+        let tree = FoldTree::from_levels(&[Level(1), Undefined, Level(1), Undefined, Level(0)], 20);
+        assert_eq!(ranges(&tree), vec![(0, 2, 1)]);
+        let tree = FoldTree::from_levels(&[Level(1), Level(3), Level(1)], 2);
+        assert_eq!(ranges(&tree), vec![(0, 2, 1), (1, 1, 2)]);
+        assert_eq!(FoldLevel::parse(">2"), Start(2));
+        assert_eq!(FoldLevel::parse("-1"), Undefined);
+        assert_eq!(FoldLevel::parse("a1"), Add(1));
+    }
+
+    #[test]
+    fn indent_and_marker() {
+        let w = WidthOpts::default();
+        let text = ["a", "  b", "    c", "", "  d", "e"];
+        let levels = indent_levels(text.iter().copied(), "#", 2, &w);
+        let tree = FoldTree::from_levels(&levels, 20);
+        assert_eq!(ranges(&tree), vec![(1, 4, 1), (2, 2, 2)]);
+        let text = ["a {{{", "b", "c {{{", "d }}}", "e }}}", "f {{{1", "g"];
+        let tree = FoldTree::from_levels(&marker_levels(text.iter().copied(), "{{{,}}}"), 20);
+        assert_eq!(ranges(&tree), vec![(0, 4, 1), (2, 3, 2), (5, 6, 1)]);
+    }
+
+    #[test]
+    fn manual() {
+        let mut tree = FoldTree::default();
+        tree.create(2, 4);
+        tree.create(0, 6);
+        assert_eq!(ranges(&tree), vec![(0, 6, 1), (2, 4, 2)]);
+        assert_eq!(tree.closed_at(3, 1), Some(0..=6));
+        tree.open(3, 1);
+        assert_eq!(tree.closed_at(3, 1), Some(2..=4));
+        tree.toggle(3, 1, 1);
+        assert_eq!(tree.closed_at(3, 1), None);
+        tree.close(3, 1);
+        assert_eq!(tree.closed_at(3, 1), Some(2..=4));
+        tree.close(3, 1);
+        assert_eq!(tree.closed_at(3, 1), Some(0..=6));
+        assert_eq!(tree.next_start(0), Some(2));
+        assert_eq!(tree.prev_end(6), Some(4));
+        tree.shift(1, 2);
+        assert_eq!(ranges(&tree), vec![(0, 8, 1), (4, 6, 2)]);
+        tree.shift(3, -3);
+        assert_eq!(ranges(&tree), vec![(0, 5, 1), (3, 3, 2)]);
+        assert!(tree.delete(0, false, 1));
+        assert_eq!(ranges(&tree), vec![(3, 3, 1)]);
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            fold_text("\tfn main() { // {{{1", 5, 1, "{{{,}}}", "//%s"),
+            "+--  5 lines: fn main() {"
+        );
+    }
+}
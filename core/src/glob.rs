@@ -0,0 +1,156 @@
+//
+// glob.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use vimscript::regex::{self, Regex};
+
+/// Converts a file pattern to a regex, where `*` is any text, `?` is any character, `[abc]` is
+/// one of the characters, and `{a,b}` is either `a` or `b`
+pub fn pattern(pat: &str) -> String {
+    let mut re = String::from("^");
+    let mut braces = 0;
+    let mut chars = pat.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '.' | '~' | '^' | '$' => {
+                re.push('\\');
+                re.push(c);
+            }
+            '{' => {
+                braces += 1;
+                re.push_str("\\(");
+            }
+            '}' if braces > 0 => {
+                braces -= 1;
+                re.push_str("\\)");
+            }
+            ',' if braces > 0 => re.push_str("\\|"),
+            '\\' => {
+                re.push('\\');
+                re.push(chars.next().unwrap_or('\\'));
+            }
+            c => re.push(c),
+        }
+    }
+    re.push('$');
+    re
+}
+
+fn has_wildcards(part: &str) -> bool {
+    part.contains(['*', '?', '[', '{'])
+}
+
+/// The entries of a directory whose name matches `part`, sorted by name. Hidden files only
+/// match a part that starts with a `.`.
+fn matching(dir: &Path, part: &str, ignorecase: bool) -> Vec<PathBuf> {
+    let opts = regex::Options {
+        ignorecase,
+        ..regex::Options::default()
+    };
+    let Ok(re) = Regex::new(&pattern(part), &opts) else {
+        return vec![];
+    };
+    // The empty path is the current directory, which isn't added to the names
+    let read = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let Ok(entries) = fs::read_dir(read) else {
+        return vec![];
+    };
+    let mut names: Vec<_> = entries
+        .filter_map(|e| e.ok()?.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') || part.starts_with('.'))
+        .filter(|name| re.is_match(name))
+        .collect();
+    names.sort();
+    names.into_iter().map(|name| dir.join(name)).collect()
+}
+
+/// `dir` and every directory below it, or every entry below it when `files` is set
+fn descendants(dir: &Path, files: bool, ret: &mut Vec<PathBuf>) {
+    for path in matching(dir, "*", false) {
+        let is_dir = path.is_dir();
+        if is_dir || files {
+            ret.push(path.clone());
+        }
+        // Links to directories are not followed, since they can make a loop
+        if is_dir && !path.is_symlink() {
+            descendants(&path, files, ret);
+        }
+    }
+}
+
+/// The paths that match `pat`, where `**` in a part of its own matches any number of
+/// directories. A path without wildcards is only returned when it exists.
+pub fn glob(pat: &str, ignorecase: bool) -> Vec<PathBuf> {
+    let pat = match pat.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{rest}", env::var("HOME").unwrap_or_default())
+        }
+        _ => pat.to_string(),
+    };
+    let mut paths = vec![PathBuf::new()];
+    let parts: Vec<_> = pat.split('/').collect();
+    for (i, part) in parts.iter().enumerate() {
+        let last = i + 1 == parts.len();
+        paths = match *part {
+            "" if i == 0 => vec![PathBuf::from("/")],
+            "" => paths,
+            "**" => paths
+                .iter()
+                .flat_map(|dir| {
+                    let mut ret = if last { vec![] } else { vec![dir.clone()] };
+                    descendants(dir, last, &mut ret);
+                    ret
+                })
+                .collect(),
+            part if has_wildcards(part) => paths
+                .iter()
+                .flat_map(|dir| matching(dir, part, ignorecase))
+                .collect(),
+            part => paths.iter().map(|dir| dir.join(part)).collect(),
+        };
+    }
+    paths.retain(|p| !p.as_os_str().is_empty() && p.exists());
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert_eq!(pattern("*.{c,h}"), "^.*\\.\\(c\\|h\\)$");
+        assert_eq!(pattern("[ab]?"), "^[ab].$");
+    }
+
+    #[test]
+    fn globs() {
+        let dir = env::temp_dir().join(format!("vim-glob-{}", std::process::id()));
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        for file in ["x.rs", "a/y.rs", "a/b/z.rs", "a/b/w.txt", ".h.rs"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let dir = dir.display();
+        let names = |pat: &str| -> Vec<String> {
+            glob(&format!("{dir}/{pat}"), false)
+                .iter()
+                .map(|p| p.display().to_string()[dir.to_string().len() + 1..].to_string())
+                .collect()
+        };
+        assert_eq!(names("*.rs"), ["x.rs"]);
+        assert_eq!(names("**/*.rs"), ["x.rs", "a/y.rs", "a/b/z.rs"]);
+        assert_eq!(names("a/**"), ["a/b", "a/b/w.txt", "a/b/z.rs", "a/y.rs"]);
+        assert_eq!(names("a/*/?.TXT"), Vec::<String>::new());
+        assert_eq!(names("a/y.rs"), ["a/y.rs"]);
+        assert_eq!(names("nope"), Vec::<String>::new());
+        fs::remove_dir_all(dir.to_string()).unwrap();
+    }
+}
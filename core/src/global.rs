@@ -0,0 +1,197 @@
+//
+// global.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use vimscript::regex::Regex;
+use vimscript::{State, VimScriptCtx};
+
+use crate::buffer::LineChange;
+use crate::cursor::Motion;
+use crate::search;
+use crate::substitute;
+use crate::VimInner;
+
+/// Splits `/pat/cmd` into the pattern and the command, which is `:p` when it is left out
+fn split_args(args: &str) -> Result<(&str, &str), String> {
+    let delim = match args.chars().next() {
+        Some(c) => c,
+        None => return Err("E476: Invalid command".into()),
+    };
+    if delim.is_alphanumeric() || delim == '\\' || delim == '"' || delim == '|' {
+        return Err("E146: Regular expressions can't be delimited by letters".into());
+    }
+    let (pattern, cmd) = search::split_delim(&args[delim.len_utf8()..], delim);
+    let cmd = cmd.map(str::trim).filter(|c| !c.is_empty()).unwrap_or("p");
+    Ok((pattern, cmd))
+}
+
+/// Moves the marks with a change to the lines, removing the ones on lines that were deleted.
+/// Moved lines can change the order of the marks, which are kept in reverse.
+fn follow(marks: &mut Vec<usize>, change: LineChange) {
+    marks.retain_mut(|m| match change.follow(*m) {
+        Some(line) => {
+            *m = line;
+            true
+        }
+        None => false,
+    });
+    marks.sort_unstable_by(|a, b| b.cmp(a));
+}
+
+/// `:g/pat/cmd`, or `:v/pat/cmd` when `invert` is set, on the lines `first` to `last`
+pub fn command(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    (first, last): (usize, usize),
+    args: &str,
+    invert: bool,
+) {
+    if v.global_busy {
+        v.message("E147: Cannot do :global recursive".into());
+        return;
+    }
+    v.global_busy = true;
+    let res = global(v, ctx, (first, last), args, invert);
+    v.global_busy = false;
+    if let Err(e) = res {
+        v.message(e);
+    }
+}
+
+fn global(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    (first, last): (usize, usize),
+    args: &str,
+    invert: bool,
+) -> Result<(), String> {
+    let (pattern, cmd) = split_args(args.trim_start())?;
+    let pattern = match pattern {
+        "" => v.registers().get('/').map(|r| r.text()),
+        pattern => Some(pattern.to_string()),
+    };
+    let pattern = pattern.ok_or("E35: No previous regular expression")?;
+    search::set_last_pattern(v, &pattern);
+    let mut opts = v.regex_options();
+    opts.ignorecase = search::ignore_case(&pattern, opts.ignorecase, v.options().smartcase);
+    let regex = Regex::new(&pattern, &opts)?;
+    let re_ctx = search::context(v);
+
+    let buffer = v.get_focus().buffer().clone();
+    // Marks are in reverse, so the next one is popped off the end
+    let mut marks: Vec<usize> = buffer.with_read(|b| {
+        (first..=last.min(b.len() - 1))
+            .rev()
+            .filter(|&l| regex.find_on_line(&**b, l, 0, &re_ctx).is_some() != invert)
+            .collect()
+    });
+    if marks.is_empty() {
+        return Err(if invert {
+            format!("Pattern found in every line: {pattern}")
+        } else {
+            format!("Pattern not found: {pattern}")
+        });
+    }
+
+    let mut output = vec![];
+    substitute::start_global(v);
+    while let Some(line) = marks.pop() {
+        if v.get_focus().buffer().id() != buffer.id() {
+            break;
+        }
+        let win = v.get_focus_mut();
+        win.cursor_apply(Motion::SetRow(line));
+        win.cursor_apply(Motion::SetCol(0));
+        let tick = buffer.read().tick();
+        v.message(String::new());
+        let res = ctx.run(cmd, v);
+        output.extend(v.cli.take_lines());
+        if !v.cli.get_message().is_empty() {
+            output.push(v.cli.get_message().to_string());
+        }
+        // Like Vim, the first error ends the command
+        if let Err(e) = res {
+            output.push(format!("{e}"));
+            break;
+        }
+        for change in buffer.read().line_changes_since(tick) {
+            follow(&mut marks, change);
+        }
+    }
+    output.extend(substitute::end_global(v));
+    // The message of the last command is in the output already
+    v.message(String::new());
+    v.show_lines(output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args() {
+        assert_eq!(split_args("/a/d"), Ok(("a", "d")));
+        assert_eq!(split_args("#a\\#b#s/x/y/"), Ok(("a\\#b", "s/x/y/")));
+        assert_eq!(split_args("/a"), Ok(("a", "p")));
+        assert_eq!(split_args("/a/  "), Ok(("a", "p")));
+        assert!(split_args("xax").is_err());
+    }
+
+    #[test]
+    fn marks() {
+        // Marks are kept in reverse
+        let mut marks = vec![9, 6, 5, 3];
+        follow(&mut marks, LineChange::Shift(4, 2));
+        assert_eq!(marks, vec![11, 8, 7, 3]);
+        follow(&mut marks, LineChange::Shift(7, -2));
+        assert_eq!(marks, vec![9, 3]);
+        follow(&mut marks, LineChange::Shift(0, -1));
+        assert_eq!(marks, vec![8, 2]);
+        let change = LineChange::Move {
+            from: 8,
+            n: 1,
+            to: 0,
+        };
+        follow(&mut marks, change);
+        assert_eq!(marks, vec![3, 0]);
+    }
+
+    fn global(lines: &[&str], cmd: &str) -> (Vec<String>, String) {
+        let (mut v, mut ctx) = VimInner::with_lines(lines);
+        ctx.run(cmd, &mut v).unwrap();
+        let b = v.get_focus().buffer().read();
+        let text = (0..b.len()).map(|l| b[l].text().to_string()).collect();
+        (text, v.get_message().to_string())
+    }
+
+    #[test]
+    fn commands() {
+        let lines = ["a x", "b", "c x", "d"];
+        assert_eq!(global(&lines, "g/x/d").0, ["b", "d"]);
+        assert_eq!(global(&lines, "v/x/d").0, ["a x", "c x"]);
+        assert_eq!(
+            global(&lines, "g/x/normal Ay").0,
+            ["a xy", "b", "c xy", "d"]
+        );
+        assert_eq!(global(&["1", "2", "3"], "g/^/m0").0, ["3", "2", "1"]);
+        // The second line is moved by the first command, and still runs its own
+        assert_eq!(
+            global(&["z", "a1", "a2", "b"], "g/a/.,+1m0").0,
+            ["a2", "z", "a1", "b"]
+        );
+        let (text, message) = global(&["x", "y"], "g/x/g/y/d");
+        assert_eq!(text, ["x", "y"]);
+        assert_eq!(message, "E147: Cannot do :global recursive");
+    }
+
+    #[test]
+    fn output() {
+        let (mut v, mut ctx) = VimInner::with_lines(&["a x", "b", "c x"]);
+        ctx.run("g/x/p", &mut v).unwrap();
+        assert!(v.cli.listing());
+        assert_eq!(v.cli.take_lines(), ["a x", "c x"]);
+    }
+}
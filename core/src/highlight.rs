@@ -0,0 +1,998 @@
+//
+// highlight.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crossterm::style::Color;
+
+use crate::color::{self, ColorDepth, Style, TermCaps};
+
+/// Attribute names for `term=`, `cterm=` and `gui=`, each with its own bit
+const ATTRS: [&str; 11] = [
+    "bold",
+    "underline",
+    "undercurl",
+    "underdouble",
+    "underdotted",
+    "underdashed",
+    "strikethrough",
+    "reverse",
+    "italic",
+    "standout",
+    "nocombine",
+];
+
+/// A set of the attributes in `ATTRS`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Attrs(u16);
+
+impl Attrs {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut ret = Self(0);
+        for name in s.split(',').map(str::to_lowercase) {
+            let name = match name.as_str() {
+                "none" => continue,
+                "inverse" => "reverse",
+                name => name,
+            };
+            match ATTRS.iter().position(|a| *a == name) {
+                Some(bit) => ret.0 |= 1 << bit,
+                None => return Err(format!("E418: Illegal value: {name}")),
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        let name = if name == "inverse" { "reverse" } else { name };
+        ATTRS
+            .iter()
+            .position(|a| *a == name)
+            .is_some_and(|bit| self.0 & 1 << bit != 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn set(&mut self, name: &str, on: bool) {
+        if let Some(bit) = ATTRS.iter().position(|a| *a == name) {
+            if on {
+                self.0 |= 1 << bit;
+            } else {
+                self.0 &= !(1 << bit);
+            }
+        }
+    }
+
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        ATTRS
+            .iter()
+            .enumerate()
+            .filter(move |(bit, _)| self.0 & 1 << bit != 0)
+            .map(|(_, name)| *name)
+    }
+
+    /// Leaves out the attributes the terminal can't show, using a plain underline for the
+    /// other kinds of underlines
+    fn degrade(mut self, caps: &TermCaps) -> Self {
+        if !caps.undercurl {
+            for name in ["undercurl", "underdouble", "underdotted", "underdashed"] {
+                if self.has(name) {
+                    self.set(name, false);
+                    self.set("underline", true);
+                }
+            }
+        }
+        self.set("italic", self.has("italic") && caps.italic);
+        self.set(
+            "strikethrough",
+            self.has("strikethrough") && caps.strikethrough,
+        );
+        self
+    }
+}
+
+impl std::fmt::Display for Attrs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "NONE");
+        }
+        for (i, name) in self.names().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{name}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The names of the first 16 colors, as used by `ctermfg` and `ctermbg`
+const CTERM_COLORS: [(&str, u8); 27] = [
+    ("black", 0),
+    ("darkblue", 4),
+    ("darkgreen", 2),
+    ("darkcyan", 6),
+    ("darkred", 1),
+    ("darkmagenta", 5),
+    ("brown", 3),
+    ("darkyellow", 3),
+    ("lightgray", 7),
+    ("lightgrey", 7),
+    ("gray", 7),
+    ("grey", 7),
+    ("darkgray", 8),
+    ("darkgrey", 8),
+    ("blue", 12),
+    ("lightblue", 12),
+    ("green", 10),
+    ("lightgreen", 10),
+    ("cyan", 14),
+    ("lightcyan", 14),
+    ("red", 9),
+    ("lightred", 9),
+    ("magenta", 13),
+    ("lightmagenta", 13),
+    ("yellow", 11),
+    ("lightyellow", 11),
+    ("white", 15),
+];
+
+/// Color names for `guifg`, `guibg` and `guisp`
+const GUI_COLORS: [(&str, u32); 30] = [
+    ("black", 0x000000),
+    ("darkblue", 0x00008b),
+    ("darkgreen", 0x006400),
+    ("darkcyan", 0x008b8b),
+    ("darkred", 0x8b0000),
+    ("darkmagenta", 0x8b008b),
+    ("brown", 0xa52a2a),
+    ("darkyellow", 0x8b8b00),
+    ("lightgray", 0xd3d3d3),
+    ("lightgrey", 0xd3d3d3),
+    ("gray", 0xbebebe),
+    ("grey", 0xbebebe),
+    ("darkgray", 0xa9a9a9),
+    ("darkgrey", 0xa9a9a9),
+    ("blue", 0x0000ff),
+    ("lightblue", 0xadd8e6),
+    ("green", 0x00ff00),
+    ("lightgreen", 0x90ee90),
+    ("cyan", 0x00ffff),
+    ("lightcyan", 0xe0ffff),
+    ("red", 0xff0000),
+    ("lightred", 0xff8b8b),
+    ("magenta", 0xff00ff),
+    ("lightmagenta", 0xff8bff),
+    ("yellow", 0xffff00),
+    ("lightyellow", 0xffffe0),
+    ("white", 0xffffff),
+    ("orange", 0xffa500),
+    ("purple", 0xa020f0),
+    ("seagreen", 0x2e8b57),
+];
+
+fn cterm_color(name: &str) -> Option<Color> {
+    let lower = name.to_lowercase();
+    if let Some((_, n)) = CTERM_COLORS.iter().find(|(c, _)| *c == lower) {
+        return Some(Color::AnsiValue(*n));
+    }
+    name.parse().ok().map(Color::AnsiValue)
+}
+
+fn rgb(n: u32) -> Color {
+    Color::Rgb {
+        r: (n >> 16) as u8,
+        g: (n >> 8) as u8,
+        b: n as u8,
+    }
+}
+
+fn gui_color(name: &str) -> Option<Color> {
+    if let Some(hex) = name.strip_prefix('#') {
+        return (hex.len() == 6)
+            .then(|| u32::from_str_radix(hex, 16).ok())
+            .flatten()
+            .map(rgb);
+    }
+    let lower = name.to_lowercase();
+    if let Some((_, n)) = GUI_COLORS.iter().find(|(c, _)| *c == lower) {
+        return Some(rgb(*n));
+    }
+    // grey0 to grey100
+    let level = lower
+        .strip_prefix("grey")
+        .or_else(|| lower.strip_prefix("gray"))?
+        .parse::<u32>()
+        .ok()
+        .filter(|l| *l <= 100)?;
+    let v = (level * 255 + 50) / 100;
+    Some(rgb(v << 16 | v << 8 | v))
+}
+
+/// The colors and attributes of a group for one kind of terminal. Colors are kept as they were
+/// given, since `fg` and `bg` refer to the colors of the Normal group.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Colors {
+    pub attrs: Attrs,
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+    pub sp: Option<String>,
+}
+
+/// A highlight group, either with its own settings or linked to another group
+#[derive(Debug, Clone)]
+struct Group {
+    name: String,
+    term: Attrs,
+    cterm: Colors,
+    gui: Colors,
+    link: Option<usize>,
+    /// The link from `:hi default link`, which is restored when the group is cleared
+    deflink: Option<usize>,
+}
+
+impl Group {
+    fn has_settings(&self) -> bool {
+        !self.term.is_empty() || self.cterm != Colors::default() || self.gui != Colors::default()
+    }
+
+    fn clear(&mut self) {
+        self.term = Attrs::default();
+        self.cterm = Colors::default();
+        self.gui = Colors::default();
+        self.link = self.deflink;
+    }
+}
+
+/// The default syntax groups, with their settings for a dark and a light background
+const SYNTAX_DEFAULTS: [(&str, &str, &str); 11] = [
+    (
+        "Comment",
+        "term=bold ctermfg=Cyan guifg=#80a0ff",
+        "term=bold ctermfg=DarkBlue guifg=Blue",
+    ),
+    (
+        "Constant",
+        "term=underline ctermfg=Magenta guifg=#ffa0a0",
+        "term=underline ctermfg=DarkRed guifg=Magenta",
+    ),
+    (
+        "Special",
+        "term=bold ctermfg=LightRed guifg=Orange",
+        "term=bold ctermfg=DarkMagenta guifg=#6a5acd",
+    ),
+    (
+        "Identifier",
+        "term=underline cterm=bold ctermfg=Cyan guifg=#40ffff",
+        "term=underline ctermfg=DarkCyan guifg=DarkCyan",
+    ),
+    (
+        "Statement",
+        "term=bold ctermfg=Yellow gui=bold guifg=#ffff60",
+        "term=bold ctermfg=Brown gui=bold guifg=Brown",
+    ),
+    (
+        "PreProc",
+        "term=underline ctermfg=LightBlue guifg=#ff80ff",
+        "term=underline ctermfg=DarkMagenta guifg=Purple",
+    ),
+    (
+        "Type",
+        "term=underline ctermfg=LightGreen gui=bold guifg=#60ff60",
+        "term=underline ctermfg=DarkGreen gui=bold guifg=SeaGreen",
+    ),
+    (
+        "Underlined",
+        "term=underline cterm=underline ctermfg=LightBlue gui=underline guifg=#80a0ff",
+        "term=underline cterm=underline ctermfg=DarkMagenta gui=underline guifg=#6a5acd",
+    ),
+    ("Ignore", "ctermfg=Black guifg=bg", "ctermfg=White guifg=bg"),
+    (
+        "Error",
+        "term=reverse ctermfg=White ctermbg=Red guifg=White guibg=Red",
+        "term=reverse ctermfg=White ctermbg=Red guifg=White guibg=Red",
+    ),
+    (
+        "Todo",
+        "term=standout ctermfg=Black ctermbg=Yellow guifg=Blue guibg=Yellow",
+        "term=standout ctermfg=Black ctermbg=Yellow guifg=Blue guibg=Yellow",
+    ),
+];
+
+const SYNTAX_LINKS: [(&str, &str); 24] = [
+    ("String", "Constant"),
+    ("Character", "Constant"),
+    ("Number", "Constant"),
+    ("Boolean", "Constant"),
+    ("Float", "Number"),
+    ("Function", "Identifier"),
+    ("Conditional", "Statement"),
+    ("Repeat", "Statement"),
+    ("Label", "Statement"),
+    ("Operator", "Statement"),
+    ("Keyword", "Statement"),
+    ("Exception", "Statement"),
+    ("Include", "PreProc"),
+    ("Define", "PreProc"),
+    ("Macro", "PreProc"),
+    ("PreCondit", "PreProc"),
+    ("StorageClass", "Type"),
+    ("Structure", "Type"),
+    ("Typedef", "Type"),
+    ("Tag", "Special"),
+    ("SpecialChar", "Special"),
+    ("Delimiter", "Special"),
+    ("SpecialComment", "Special"),
+    ("Debug", "Special"),
+];
+
+/// The builtin groups used to draw the editor itself
+const UI_DEFAULTS: [(&str, &str, &str); 34] = [
+    ("Normal", "", ""),
+    (
+        "ErrorMsg",
+        "term=standout ctermfg=White ctermbg=DarkRed guifg=White guibg=Red",
+        "term=standout ctermfg=White ctermbg=DarkRed guifg=White guibg=Red",
+    ),
+    (
+        "IncSearch",
+        "term=reverse cterm=reverse gui=reverse",
+        "term=reverse cterm=reverse gui=reverse",
+    ),
+    (
+        "ModeMsg",
+        "term=bold cterm=bold gui=bold",
+        "term=bold cterm=bold gui=bold",
+    ),
+    (
+        "NonText",
+        "term=bold ctermfg=Blue gui=bold guifg=Blue",
+        "term=bold ctermfg=Blue gui=bold guifg=Blue",
+    ),
+    (
+        "StatusLine",
+        "term=reverse,bold cterm=reverse,bold gui=reverse,bold",
+        "term=reverse,bold cterm=reverse,bold gui=reverse,bold",
+    ),
+    (
+        "StatusLineNC",
+        "term=reverse cterm=reverse gui=reverse",
+        "term=reverse cterm=reverse gui=reverse",
+    ),
+    (
+        "VertSplit",
+        "term=reverse cterm=reverse gui=reverse",
+        "term=reverse cterm=reverse gui=reverse",
+    ),
+    (
+        "Visual",
+        "term=reverse ctermbg=DarkGrey guibg=DarkGrey",
+        "term=reverse ctermbg=LightGrey guibg=LightGrey",
+    ),
+    (
+        "VisualNOS",
+        "term=bold,underline cterm=bold,underline gui=bold,underline",
+        "term=bold,underline cterm=bold,underline gui=bold,underline",
+    ),
+    (
+        "TabLine",
+        "term=underline cterm=underline ctermfg=White ctermbg=DarkGrey gui=underline guibg=DarkGrey",
+        "term=underline cterm=underline ctermfg=Black ctermbg=LightGrey gui=underline guibg=LightGrey",
+    ),
+    (
+        "TabLineFill",
+        "term=reverse cterm=reverse gui=reverse",
+        "term=reverse cterm=reverse gui=reverse",
+    ),
+    (
+        "TabLineSel",
+        "term=bold cterm=bold gui=bold",
+        "term=bold cterm=bold gui=bold",
+    ),
+    ("Cursor", "guifg=bg guibg=fg", "guifg=bg guibg=fg"),
+    (
+        "MatchParen",
+        "term=reverse ctermbg=DarkCyan guibg=DarkCyan",
+        "term=reverse ctermbg=Cyan guibg=Cyan",
+    ),
+    (
+        "Directory",
+        "term=bold ctermfg=LightCyan guifg=Cyan",
+        "term=bold ctermfg=DarkBlue guifg=Blue",
+    ),
+    (
+        "LineNr",
+        "term=underline ctermfg=Yellow guifg=Yellow",
+        "term=underline ctermfg=Brown guifg=Brown",
+    ),
+    (
+        "CursorLineNr",
+        "term=bold cterm=underline ctermfg=Yellow gui=bold guifg=Yellow",
+        "term=bold cterm=underline ctermfg=Brown gui=bold guifg=Brown",
+    ),
+    (
+        "MoreMsg",
+        "term=bold ctermfg=LightGreen gui=bold guifg=SeaGreen",
+        "term=bold ctermfg=DarkGreen gui=bold guifg=SeaGreen",
+    ),
+    (
+        "Question",
+        "term=standout ctermfg=LightGreen gui=bold guifg=Green",
+        "term=standout ctermfg=DarkGreen gui=bold guifg=SeaGreen",
+    ),
+    (
+        "Search",
+        "term=reverse ctermfg=Black ctermbg=Yellow guifg=Black guibg=Yellow",
+        "term=reverse ctermbg=Yellow guibg=Yellow",
+    ),
+    (
+        "SpecialKey",
+        "term=bold ctermfg=LightBlue guifg=Cyan",
+        "term=bold ctermfg=DarkBlue guifg=Blue",
+    ),
+    (
+        "Title",
+        "term=bold ctermfg=LightMagenta gui=bold guifg=Magenta",
+        "term=bold ctermfg=DarkMagenta gui=bold guifg=Magenta",
+    ),
+    (
+        "WarningMsg",
+        "term=standout ctermfg=LightRed guifg=Red",
+        "term=standout ctermfg=DarkRed guifg=Red",
+    ),
+    (
+        "WildMenu",
+        "term=standout ctermfg=Black ctermbg=Yellow guifg=Black guibg=Yellow",
+        "term=standout ctermfg=Black ctermbg=Yellow guifg=Black guibg=Yellow",
+    ),
+    (
+        "Folded",
+        "term=standout ctermfg=Cyan ctermbg=DarkGrey guifg=Cyan guibg=DarkGrey",
+        "term=standout ctermfg=DarkBlue ctermbg=Grey guifg=DarkBlue guibg=LightGrey",
+    ),
+    (
+        "FoldColumn",
+        "term=standout ctermfg=Cyan ctermbg=DarkGrey guifg=Cyan guibg=Grey",
+        "term=standout ctermfg=DarkBlue ctermbg=Grey guifg=DarkBlue guibg=Grey",
+    ),
+    (
+        "SignColumn",
+        "term=standout ctermfg=Cyan ctermbg=DarkGrey guifg=Cyan guibg=Grey",
+        "term=standout ctermfg=DarkBlue ctermbg=Grey guifg=DarkBlue guibg=Grey",
+    ),
+    (
+        "Conceal",
+        "ctermfg=LightGrey ctermbg=DarkGrey guifg=LightGrey guibg=DarkGrey",
+        "ctermfg=LightGrey ctermbg=DarkGrey guifg=LightGrey guibg=DarkGrey",
+    ),
+    (
+        "CursorColumn",
+        "term=reverse ctermbg=DarkGrey guibg=Grey40",
+        "term=reverse ctermbg=LightGrey guibg=Grey90",
+    ),
+    (
+        "CursorLine",
+        "term=underline cterm=underline guibg=Grey40",
+        "term=underline cterm=underline guibg=Grey90",
+    ),
+    (
+        "ColorColumn",
+        "term=reverse ctermbg=DarkRed guibg=DarkRed",
+        "term=reverse ctermbg=LightRed guibg=LightRed",
+    ),
+    (
+        "Pmenu",
+        "ctermfg=Black ctermbg=Magenta guibg=Magenta",
+        "ctermfg=Black ctermbg=LightMagenta guibg=LightMagenta",
+    ),
+    (
+        "PmenuSel",
+        "ctermfg=DarkGrey ctermbg=Black guibg=DarkGrey",
+        "ctermfg=Black ctermbg=LightGrey guibg=Grey",
+    ),
+];
+
+const UI_LINKS: [(&str, &str); 4] = [
+    ("EndOfBuffer", "NonText"),
+    ("QuickFixLine", "Search"),
+    ("PmenuSbar", "Pmenu"),
+    ("PmenuThumb", "PmenuSel"),
+];
+
+/// The table of highlight groups
+#[derive(Debug)]
+pub struct Highlights {
+    groups: Vec<Group>,
+    ids: HashMap<String, usize>,
+    /// The 'background' the defaults were set for
+    background: String,
+    caps: TermCaps,
+    /// Whether the gui colors are used, from 'termguicolors'
+    termguicolors: bool,
+    /// Incremented on every change, so cached styles can be recomputed
+    tick: usize,
+}
+
+impl Default for Highlights {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Highlights {
+    pub fn new() -> Self {
+        let mut ret = Self {
+            groups: vec![],
+            ids: HashMap::new(),
+            background: "dark".into(),
+            caps: TermCaps::default(),
+            termguicolors: false,
+            tick: 0,
+        };
+        ret.set_defaults(true, true);
+        ret
+    }
+
+    fn set_defaults(&mut self, syntax: bool, ui: bool) {
+        let light = self.background == "light";
+        let defaults = syntax
+            .then_some(SYNTAX_DEFAULTS.iter())
+            .into_iter()
+            .flatten()
+            .chain(ui.then_some(UI_DEFAULTS.iter()).into_iter().flatten());
+        for (name, dark_args, light_args) in defaults {
+            let id = self.id(name);
+            self.groups[id].clear();
+            self.set(name, if light { light_args } else { dark_args }, false)
+                .expect("valid default highlight");
+        }
+        let links = syntax
+            .then_some(SYNTAX_LINKS.iter())
+            .into_iter()
+            .flatten()
+            .chain(ui.then_some(UI_LINKS.iter()).into_iter().flatten());
+        for (from, to) in links {
+            let id = self.id(from);
+            self.groups[id].clear();
+            self.groups[id].deflink = None;
+            self.link(from, to, true, false);
+        }
+    }
+
+    /// The id of the group `name`, which is created if it doesn't exist yet
+    pub fn id(&mut self, name: &str) -> usize {
+        if let Some(id) = self.get(name) {
+            return id;
+        }
+        self.tick += 1;
+        self.groups.push(Group {
+            name: name.to_string(),
+            term: Attrs::default(),
+            cterm: Colors::default(),
+            gui: Colors::default(),
+            link: None,
+            deflink: None,
+        });
+        self.ids.insert(name.to_lowercase(), self.groups.len() - 1);
+        self.groups.len() - 1
+    }
+
+    /// The id of the group `name`. Group names are not case sensitive.
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.ids.get(&name.to_lowercase()).copied()
+    }
+
+    pub fn name(&self, id: usize) -> &str {
+        &self.groups[id].name
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn link_of(&self, id: usize) -> Option<usize> {
+        self.groups[id].link
+    }
+
+    pub fn has_settings(&self, id: usize) -> bool {
+        self.groups[id].has_settings()
+    }
+
+    /// The settings of a group for `term=`, `cterm` and `gui`
+    pub fn settings(&self, id: usize) -> (Attrs, &Colors, &Colors) {
+        let group = &self.groups[id];
+        (group.term, &group.cterm, &group.gui)
+    }
+
+    /// Links `from` to `to`, or removes the link if `to` is "NONE". With `default`, the link is
+    /// remembered for when the group is cleared, and only made if `from` has no settings or link
+    /// of its own, unless it is forced.
+    pub fn link(&mut self, from: &str, to: &str, default: bool, force: bool) {
+        let from = self.id(from);
+        let to = (!to.eq_ignore_ascii_case("none")).then(|| self.id(to));
+        let group = &mut self.groups[from];
+        if default && (force || group.deflink.is_none()) {
+            group.deflink = to;
+        }
+        if default && !force && (group.link.is_some() || group.has_settings()) {
+            return;
+        }
+        group.clear();
+        group.link = to;
+        self.tick += 1;
+    }
+
+    /// Applies the `key=value` arguments of `:highlight` to the group `name`. With `default`,
+    /// the group is left alone if it was already changed.
+    pub fn set(&mut self, name: &str, args: &str, default: bool) -> Result<(), String> {
+        let id = self.id(name);
+        if default && (self.groups[id].link.is_some() || self.groups[id].has_settings()) {
+            return Ok(());
+        }
+        let mut group = self.groups[id].clone();
+        for arg in args.split_whitespace() {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("E416: Missing equal sign: {arg}"))?;
+            if value.is_empty() {
+                return Err(format!("E417: Missing argument: {arg}"));
+            }
+            let color = |valid: fn(&str) -> Option<Color>| match value.to_lowercase().as_str() {
+                "none" => Ok(None),
+                "fg" | "bg" | "foreground" | "background" => Ok(Some(value.to_lowercase())),
+                _ if valid(value).is_some() => Ok(Some(value.to_string())),
+                _ => Err(format!("E421: Color name or number not recognized: {arg}")),
+            };
+            match key.to_lowercase().as_str() {
+                "term" => group.term = Attrs::parse(value)?,
+                "cterm" => group.cterm.attrs = Attrs::parse(value)?,
+                "gui" => group.gui.attrs = Attrs::parse(value)?,
+                "ctermfg" => group.cterm.fg = color(cterm_color)?,
+                "ctermbg" => group.cterm.bg = color(cterm_color)?,
+                "ctermul" => group.cterm.sp = color(cterm_color)?,
+                "guifg" => group.gui.fg = color(gui_color)?,
+                "guibg" => group.gui.bg = color(gui_color)?,
+                "guisp" => group.gui.sp = color(gui_color)?,
+                // There are no fonts or terminal codes to set
+                "font" | "start" | "stop" => (),
+                _ => return Err(format!("E423: Illegal argument: {arg}")),
+            }
+        }
+        group.link = None;
+        self.groups[id] = group;
+        self.tick += 1;
+        Ok(())
+    }
+
+    /// `:highlight clear {group}`
+    pub fn clear_group(&mut self, name: &str) {
+        if let Some(id) = self.get(name) {
+            self.groups[id].clear();
+            self.tick += 1;
+        }
+    }
+
+    /// `:highlight clear`, which restores the default settings of every group
+    pub fn clear(&mut self) {
+        self.groups.iter_mut().for_each(Group::clear);
+        self.set_defaults(true, true);
+        self.tick += 1;
+    }
+
+    /// `:syntax reset`, which restores the default syntax groups
+    pub fn reset_syntax(&mut self) {
+        self.set_defaults(true, false);
+        self.tick += 1;
+    }
+
+    pub fn background(&self) -> &str {
+        &self.background
+    }
+
+    /// Changes the background the defaults are for, restoring every group to its default
+    pub fn set_background(&mut self, background: &str) {
+        self.background = background.to_string();
+        self.clear();
+    }
+
+    /// Sets what the terminal can show, and whether the gui colors are used
+    pub fn set_output(&mut self, caps: TermCaps, termguicolors: bool) {
+        if caps != self.caps || termguicolors != self.termguicolors {
+            self.caps = caps;
+            self.termguicolors = termguicolors;
+            self.tick += 1;
+        }
+    }
+
+    pub fn termguicolors(&self) -> bool {
+        self.termguicolors
+    }
+
+    /// The group `id` follows links to, or `id` if it isn't linked
+    pub fn resolve(&self, mut id: usize) -> usize {
+        // Links can form a loop, so give up after a while like vim does
+        for _ in 0..100 {
+            match self.groups[id].link {
+                Some(link) => id = link,
+                None => return id,
+            }
+        }
+        id
+    }
+
+    /// The color a group uses for a terminal, where `fg` and `bg` refer to the Normal group
+    fn color(&self, value: &Option<String>, gui: bool) -> Option<Color> {
+        let value = value.as_deref()?;
+        let normal = || {
+            let normal = &self.groups[self.get("Normal")?];
+            Some(if gui { &normal.gui } else { &normal.cterm })
+        };
+        let value = match value {
+            "fg" | "foreground" => normal()?.fg.as_deref()?,
+            "bg" | "background" => normal()?.bg.as_deref()?,
+            value => value,
+        };
+        let color = if gui {
+            gui_color(value)
+        } else {
+            cterm_color(value)
+        };
+        color::reduce(color?, self.caps.depth)
+    }
+
+    /// The style of a group, following links
+    pub fn style(&self, id: usize) -> Style {
+        let group = &self.groups[self.resolve(id)];
+        if self.caps.depth == ColorDepth::Mono {
+            // Terminals without colors use the `term=` attributes
+            return Style {
+                attrs: group.term.degrade(&self.caps),
+                ..Style::default()
+            };
+        }
+        let gui = self.termguicolors;
+        let colors = if gui { &group.gui } else { &group.cterm };
+        let mut style = Style {
+            fg: self.color(&colors.fg, gui),
+            bg: self.color(&colors.bg, gui),
+            sp: self.color(&colors.sp, gui).filter(|_| self.caps.undercurl),
+            attrs: colors.attrs.degrade(&self.caps),
+        };
+        if self.caps.depth == ColorDepth::Colors8 {
+            // Bright colors are shown with bold text instead
+            if let Some(Color::AnsiValue(n @ 8..=15)) = style.fg {
+                style.fg = Some(Color::AnsiValue(n - 8));
+                style.attrs.set("bold", true);
+            }
+            if let Some(Color::AnsiValue(n @ 8..=15)) = style.bg {
+                style.bg = Some(Color::AnsiValue(n - 8));
+            }
+        }
+        style
+    }
+
+    /// The style of the group `name`, or the default style if there is no such group
+    pub fn style_of(&self, name: &str) -> Style {
+        self.get(name)
+            .map_or_else(Style::default, |id| self.style(id))
+    }
+
+    /// The style of text in group `id`, or in no group, on top of the Normal group
+    pub fn text_style(&self, id: Option<usize>) -> Style {
+        let normal = self.style_of("Normal");
+        let mut style = match id {
+            Some(id) => self.style(id),
+            None => return normal,
+        };
+        style.fg = style.fg.or(normal.fg);
+        style.bg = style.bg.or(normal.bg);
+        style
+    }
+
+    /// The line `:highlight` shows for a group
+    pub fn describe(&self, id: usize) -> String {
+        let group = &self.groups[id];
+        let mut ret = format!("{:<20}xxx", group.name);
+        if let Some(link) = group.link {
+            let _ = write!(ret, " links to {}", self.groups[link].name);
+            return ret;
+        }
+        if !group.has_settings() {
+            ret += " cleared";
+            return ret;
+        }
+        if !group.term.is_empty() {
+            let _ = write!(ret, " term={}", group.term);
+        }
+        for (prefix, colors) in [("cterm", &group.cterm), ("gui", &group.gui)] {
+            if !colors.attrs.is_empty() {
+                let _ = write!(ret, " {prefix}={}", colors.attrs);
+            }
+            let sp = if prefix == "gui" { "sp" } else { "ul" };
+            for (key, color) in [("fg", &colors.fg), ("bg", &colors.bg), (sp, &colors.sp)] {
+                if let Some(color) = color {
+                    let _ = write!(ret, " {prefix}{key}={color}");
+                }
+            }
+        }
+        ret
+    }
+
+    /// The listing of `:highlight`, for one group or all of them
+    pub fn list(&self, name: Option<&str>) -> Result<String, String> {
+        match name {
+            Some(name) => self
+                .get(name)
+                .map(|id| self.describe(id))
+                .ok_or_else(|| format!("E411: Highlight group not found: {name}")),
+            None => Ok((0..self.groups.len())
+                .map(|id| self.describe(id))
+                .collect::<Vec<_>>()
+                .join("\n")),
+        }
+    }
+
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    /// Runs a `:highlight` command, returning a message to show
+    pub fn command(&mut self, bang: bool, args: &str) -> Result<Option<String>, String> {
+        let mut words: Vec<&str> = args.split_whitespace().collect();
+        let default = matches!(words.first(), Some(&"def" | &"default"));
+        if default {
+            words.remove(0);
+        }
+        match words.as_slice() {
+            [] => self.list(None).map(Some),
+            ["clear"] => {
+                self.clear();
+                Ok(None)
+            }
+            ["clear", groups @ ..] => {
+                groups.iter().for_each(|g| self.clear_group(g));
+                Ok(None)
+            }
+            ["link", from, to] => {
+                let id = self.id(from);
+                if !bang && !default && self.groups[id].has_settings() {
+                    return Err("E414: Group has settings, highlight link ignored".into());
+                }
+                self.link(from, to, default, bang);
+                Ok(None)
+            }
+            [name] => self.list(Some(name)).map(Some),
+            [name, ..] => {
+                // Everything after the group name
+                let skip = if default { 2 } else { 1 };
+                let rest = (0..skip).fold(args, |rest, _| {
+                    let rest = rest.trim_start();
+                    rest.find(char::is_whitespace).map_or("", |i| &rest[i..])
+                });
+                self.set(name, rest, default)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn colors() {
+        assert_eq!(cterm_color("Red"), Some(Color::AnsiValue(9)));
+        assert_eq!(cterm_color("darkblue"), Some(Color::AnsiValue(4)));
+        assert_eq!(cterm_color("231"), Some(Color::AnsiValue(231)));
+        assert_eq!(cterm_color("#ff0000"), None);
+        assert_eq!(
+            gui_color("#80a0ff"),
+            Some(Color::Rgb {
+                r: 0x80,
+                g: 0xa0,
+                b: 0xff
+            })
+        );
+        assert_eq!(gui_color("grey100"), Some(rgb(0xffffff)));
+        assert_eq!(gui_color("nocolor"), None);
+    }
+
+    #[test]
+    fn commands() {
+        let mut hl = Highlights::new();
+        hl.command(false, "Foo ctermfg=Red cterm=bold,italic guibg=#000000")
+            .unwrap();
+        let id = hl.get("foo").unwrap();
+        assert_eq!(
+            hl.describe(id),
+            "Foo                 xxx cterm=bold,italic ctermfg=Red guibg=#000000"
+        );
+        let style = hl.style(id);
+        assert_eq!(style.fg, Some(Color::AnsiValue(9)));
+        assert!(style.attrs.has("italic"));
+        assert!(hl.command(false, "link Foo Comment").is_err());
+        hl.command(false, "default link Bar Comment").unwrap();
+        hl.command(false, "default link Bar Type").unwrap();
+        assert_eq!(hl.style_of("Bar"), hl.style_of("Comment"));
+        hl.command(true, "link Bar Type").unwrap();
+        assert_eq!(hl.style_of("Bar"), hl.style_of("Type"));
+        assert!(hl.command(false, "Foo ctermfg=nocolor").is_err());
+        hl.command(false, "clear Foo").unwrap();
+        assert!(hl.describe(id).ends_with("cleared"));
+    }
+
+    #[test]
+    fn default_links() {
+        let mut hl = Highlights::new();
+        hl.command(false, "def link Foo Comment").unwrap();
+        hl.command(true, "link Foo Type").unwrap();
+        assert_eq!(hl.style_of("Foo"), hl.style_of("Type"));
+        hl.command(false, "clear").unwrap();
+        assert_eq!(hl.style_of("Foo"), hl.style_of("Comment"));
+    }
+
+    #[test]
+    fn background() {
+        let mut hl = Highlights::new();
+        let dark = hl.style_of("Comment");
+        hl.command(false, "Comment ctermfg=1").unwrap();
+        hl.set_background("light");
+        assert_ne!(hl.style_of("Comment"), dark);
+        assert_eq!(hl.style_of("Comment").fg, Some(Color::AnsiValue(4)));
+    }
+
+    #[test]
+    fn output() {
+        let mut hl = Highlights::new();
+        hl.set(
+            "Test",
+            "cterm=italic,undercurl ctermfg=12 guifg=#ffa500 guisp=Red",
+            false,
+        )
+        .unwrap();
+        let id = hl.get("Test").unwrap();
+        // Renders the group the way the terminal receives it
+        let mut draw = |caps: TermCaps, tgc: bool| {
+            hl.set_output(caps, tgc);
+            let mut out = Vec::new();
+            write!(out, "{}", hl.style(id).apply("x")).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let caps = TermCaps::default();
+        assert_eq!(draw(caps, false), "\x1b[0;4;3;94mx\x1b[0m");
+        let truecolor = TermCaps {
+            depth: ColorDepth::TrueColor,
+            undercurl: true,
+            ..caps
+        };
+        assert_eq!(draw(truecolor, false), "\x1b[0;4:3;3;94mx\x1b[0m");
+        assert_eq!(
+            draw(truecolor, true),
+            "\x1b[0;38;2;255;165;0;58;2;255;0;0mx\x1b[0m"
+        );
+        assert_eq!(draw(caps, true), "\x1b[0;38;5;214mx\x1b[0m");
+        let colors8 = TermCaps {
+            depth: ColorDepth::Colors8,
+            italic: false,
+            ..caps
+        };
+        assert_eq!(draw(colors8, false), "\x1b[0;1;4;34mx\x1b[0m");
+        let mono = TermCaps {
+            depth: ColorDepth::Mono,
+            ..caps
+        };
+        assert_eq!(draw(mono, false), "x");
+    }
+}
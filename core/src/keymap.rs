@@ -14,10 +14,15 @@ use enum_map::{Enum, EnumMap};
 
 use crate::{
     cli::Cli,
+    complete,
     cursor::Motion,
     util::KeyDisplay,
-    window::{op, Dist, Scroll, WinMode},
-    Vim,
+    options::FoldMethod,
+    quickfix,
+    search,
+    tags,
+    window::{op, Dist, Scroll, ViewPos, WinMode, Window},
+    Vim, VimInner,
 };
 
 pub trait Action {
@@ -36,6 +41,8 @@ where
 
 pub enum MapAction {
     Act(usize, Arc<dyn Action>),
+    /// An action that uses the count itself, rather than being repeated
+    Count(Option<usize>, Arc<dyn Action>),
     Wait,
     None,
 }
@@ -43,6 +50,7 @@ pub enum MapAction {
 #[derive(Clone)]
 enum KeyMapAction {
     Action(Arc<dyn Action>),
+    Counted(Arc<dyn Action>),
     Chord(HashMap<KeyEvent, KeyMapAction>, Option<Arc<dyn Action>>),
 }
 
@@ -50,6 +58,7 @@ impl Debug for KeyMapAction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Action(_) => write!(f, "Action(dyn Action)"),
+            Self::Counted(_) => write!(f, "Counted(dyn Action)"),
             Self::Chord(map, a) => write!(
                 f,
                 "Chord({:?}, {})",
@@ -70,7 +79,7 @@ impl KeyMapAction {
             Self::Chord(map, _) => {
                 map.insert(k, a);
             }
-            Self::Action(old) => {
+            Self::Action(old) | Self::Counted(old) => {
                 let tmp = Arc::clone(old);
                 *self = Self::Chord(HashMap::new(), Some(tmp));
                 self.insert(k, a);
@@ -81,7 +90,7 @@ impl KeyMapAction {
     fn get(&self, k: &KeyEvent) -> Option<&KeyMapAction> {
         match self {
             Self::Chord(map, _) => map.get(k),
-            Self::Action(_) => None,
+            Self::Action(_) | Self::Counted(_) => None,
         }
     }
 }
@@ -110,7 +119,8 @@ impl KeyMap {
     }
 
     pub fn on_key(&mut self, k: KeyEvent) -> MapAction {
-        if let KeyCode::Char(c) = k.code {
+        // A digit typed with CTRL isn't a count, since it can be a key like CTRL-]
+        if let (KeyCode::Char(c), KeyModifiers::NONE) = (k.code, k.modifiers) {
             if let Some(d) = c.to_digit(10).filter(|&d| d != 0 || self.rep != 0) {
                 self.rep = self.rep * 10 + d as usize;
                 return MapAction::Wait;
@@ -125,6 +135,11 @@ impl KeyMap {
                 self.clear();
                 ret
             }
+            Some(KeyMapAction::Counted(a)) => {
+                let ret = MapAction::Count(Some(self.rep).filter(|&r| r != 0), Arc::clone(a));
+                self.clear();
+                ret
+            }
             Some(KeyMapAction::Chord(_, _)) => MapAction::Wait,
             None => self.default_action(),
         }
@@ -134,7 +149,7 @@ impl KeyMap {
         let mut cur = &self.map;
         for event in path {
             match cur {
-                a @ KeyMapAction::Action(_) => return Some(a),
+                a @ (KeyMapAction::Action(_) | KeyMapAction::Counted(_)) => return Some(a),
                 map @ KeyMapAction::Chord(_, _) => cur = map.get(event)?,
             }
         }
@@ -162,6 +177,65 @@ impl KeyMap {
     }
 }
 
+/// CTRL-D and CTRL-U. A count sets 'scroll' first.
+fn scroll_half(v: &mut Vim, scroll: Scroll) {
+    let count = v.count();
+    let win = v.get_focus_mut();
+    if let Some(n) = count {
+        win.options_mut().scroll = n as isize;
+    }
+    win.scroll_with_cursor(scroll);
+}
+
+/// `zt`, `zz` and `zb`, and `z<CR>`, `z.` and `z-` which also move to the first non-blank. A
+/// count is the line to put the cursor on first.
+fn redraw_at(v: &mut Vim, pos: ViewPos, first_char: bool) {
+    let count = v.count();
+    let win = v.get_focus_mut();
+    if let Some(line) = count {
+        win.cursor_apply(Motion::SetRow(line.saturating_sub(1)));
+    }
+    if first_char {
+        let col = win.buffer().read()[win.cursor().row()].first_char();
+        win.cursor_apply(Motion::SetCol(col));
+    }
+    win.scroll_cursor_to(pos);
+}
+
+/// Runs a fold command on the focused window, and shows the error it returns
+fn fold_cmd(v: &mut Vim, f: impl FnOnce(&mut Window) -> std::result::Result<(), &'static str>) {
+    if let Err(e) = f(v.get_focus_mut()) {
+        v.message(e.to_string());
+    }
+}
+
+/// Starts Insert mode completion, with CTRL-N, CTRL-P or CTRL-X
+fn ins_complete(v: &mut Vim, mode: complete::Mode, forward: bool) {
+    complete::start(v.inner, v.ctx, mode, forward);
+}
+
+/// Runs a tag command for the keyword under the cursor, and shows the error it returns
+fn tag_cmd(v: &mut Vim, f: impl FnOnce(&mut VimInner, &str) -> std::result::Result<(), String>) {
+    if let Err(e) = tags::keyword(v).and_then(|word| f(v, &word)) {
+        v.message(e);
+    }
+}
+
+/// `zf`, which folds the visual selection, or waits for a motion in normal mode
+fn fold_operator(v: &mut Vim) {
+    let count = v.count().unwrap_or(1);
+    let win = v.get_focus_mut();
+    if win.options().foldmethod != FoldMethod::manual {
+        v.message("E350: Cannot create fold with current 'foldmethod'".into());
+    } else if win.get_state() == KeyState::Visual {
+        let (start, end) = (win.visual_start().1, win.cursor().row());
+        fold_cmd(v, |w| w.create_fold(start.min(end), start.max(end)));
+        v.set_mode(WinMode::Normal);
+    } else {
+        v.set_mode(WinMode::Operation(op::fold(count)));
+    }
+}
+
 #[derive(Debug, Enum, Clone, Copy, PartialEq, Eq)]
 pub enum KeyState {
     Normal,
@@ -191,7 +265,7 @@ macro_rules! keys {
         );
     };
     ([$($tt:tt)*]; $c:tt $($mod:ident)* => {$($inner:tt)*} $(, $($rem:tt)*)?) => {
-        [
+        keys!([
             $($tt)*
             (
                 keys!(@keycode $c $($mod)*),
@@ -199,7 +273,18 @@ macro_rules! keys {
                     keys!([]; $($inner)*).into_iter().collect()
                 }, None),
             ),
-        ]
+        ]; $($($rem)*)?)
+    };
+    ([$($tt:tt)*]; $($c:tt $($mod:ident)*)|* => count |$s:ident| $e:expr $(, $($rem:tt)*)?) => {
+        keys!([
+         $($tt)*
+         $(
+             (
+                 keys!(@keycode $c $($mod)*),
+                 KeyMapAction::Counted(Arc::new(|$s: &mut Vim| {$e}) as Arc<dyn Action>),
+            ),
+                 )*
+        ]; $($($rem)*)?)
     };
     ([$($tt:tt)*]; $($c:tt $($mod:ident)*)|* => |$s:ident| $e:expr $(, $($rem:tt)*)?) => {
         keys!([
@@ -262,6 +347,19 @@ impl MapSet {
             'k' | Up => |v| {
                 v.get_focus_mut().cursor_apply(Motion::Up);
             },
+            'g' => {
+                'j' | Down => |v| {
+                    v.get_focus_mut().cursor_screen_line(true);
+                },
+                'k' | Up => |v| {
+                    v.get_focus_mut().cursor_screen_line(false);
+                },
+                '*' => count |v| search::star(v, true, false),
+                '#' => count |v| search::star(v, false, false),
+                '&' => |v| v.execute("%s//~/&"),
+                ']' => |v| tag_cmd(v, |v, word| tags::select(v, word, true)),
+                ']' C | '5' C => |v| tag_cmd(v, |v, word| tags::select(v, word, false)),
+            },
             '$' | End => |v| {
                 v.get_focus_mut().cursor_apply(Motion::End);
             },
@@ -277,6 +375,10 @@ impl MapSet {
                             .first_char();
                 win.cursor_apply(Motion::SetCol(col));
             },
+            'x' | Delete => |v| {
+                let delcombine = v.options().delcombine;
+                v.get_focus_mut().delete_grapheme(delcombine);
+            },
             'd' => |v| {
                 v.set_mode(WinMode::Operation(op::delete()));
             },
@@ -289,15 +391,110 @@ impl MapSet {
             'R' => |v| {
                 v.set_mode(WinMode::Replace);
             },
+            'm' => |v| {
+                v.set_mode(WinMode::Operation(op::mark()));
+            },
+            '!' => count |v| {
+                let count = v.count().unwrap_or(1);
+                v.set_mode(WinMode::Operation(op::filter(count)));
+            },
             ':' => |v| {
                 v.start_cli(Cli::Command);
             },
+            // Enter jumps to the error under the cursor in a quickfix window
+            Enter => count |v| {
+                if quickfix::enter(v) {
+                    return;
+                }
+                let count = v.count().unwrap_or(1);
+                let win = v.get_focus_mut();
+                for _ in 0..count {
+                    win.cursor_apply(Motion::Down);
+                }
+                let row = win.cursor().row();
+                let col = win.buffer().read()[row].first_char();
+                win.cursor_apply(Motion::SetCol(col));
+            },
+            'u' => count |v| {
+                let n = v.count().unwrap_or(1);
+                v.undo(false, n);
+            },
+            'r' C => count |v| {
+                let n = v.count().unwrap_or(1);
+                v.undo(true, n);
+            },
+            // The terminal sends CTRL-] as CTRL-5
+            ']' C | '5' C => count |v| {
+                let count = v.count().unwrap_or(1);
+                tag_cmd(v, |v, word| tags::tag(v, count, word));
+            },
+            't' C => count |v| {
+                let count = v.count().unwrap_or(1);
+                if let Err(e) = tags::pop(v, count) {
+                    v.message(e);
+                }
+            },
+            '&' => |v| v.execute("s"),
+            '/' => count |v| search::start(v, true),
+            '?' => count |v| search::start(v, false),
+            'n' => count |v| search::repeat(v, false),
+            'N' => count |v| search::repeat(v, true),
+            '*' => count |v| search::star(v, true, true),
+            '#' => count |v| search::star(v, false, true),
             'e' C => |v| {
                 v.get_focus_mut().scroll(Scroll::Down, Dist::One);
             },
-            'Y' C => |v| {
+            'y' C => |v| {
                 v.get_focus_mut().scroll(Scroll::Up, Dist::One);
             },
+            'd' C => count |v| {
+                scroll_half(v, Scroll::Down);
+            },
+            'u' C => count |v| {
+                scroll_half(v, Scroll::Up);
+            },
+            'f' C | PageDown => |v| {
+                v.get_focus_mut().scroll(Scroll::Down, Dist::Screen);
+            },
+            'b' C | PageUp => |v| {
+                v.get_focus_mut().scroll(Scroll::Up, Dist::Screen);
+            },
+            'z' => {
+                't' => count |v| redraw_at(v, ViewPos::Top, false),
+                Enter => count |v| redraw_at(v, ViewPos::Top, true),
+                'z' => count |v| redraw_at(v, ViewPos::Center, false),
+                '.' => count |v| redraw_at(v, ViewPos::Center, true),
+                'b' => count |v| redraw_at(v, ViewPos::Bottom, false),
+                '-' => count |v| redraw_at(v, ViewPos::Bottom, true),
+                'h' | Left => |v| v.get_focus_mut().scroll(Scroll::Left, Dist::One),
+                'l' | Right => |v| v.get_focus_mut().scroll(Scroll::Right, Dist::One),
+                's' => |v| v.get_focus_mut().scroll_cursor_col_to(false),
+                'e' => |v| v.get_focus_mut().scroll_cursor_col_to(true),
+                'f' => count |v| fold_operator(v),
+                'F' => count |v| {
+                    let n = v.count().unwrap_or(1);
+                    let win = v.get_focus_mut();
+                    let row = win.cursor().row();
+                    let last = win.buffer().read().len() - 1;
+                    fold_cmd(v, |w| w.create_fold(row, (row + n - 1).min(last)));
+                },
+                'd' => |v| fold_cmd(v, |w| w.delete_fold(false)),
+                'D' => |v| fold_cmd(v, |w| w.delete_fold(true)),
+                'E' => |v| fold_cmd(v, |w| w.delete_all_folds()),
+                'o' => |v| fold_cmd(v, |w| w.open_fold(1)),
+                'O' => |v| fold_cmd(v, |w| w.open_fold(usize::MAX)),
+                'c' => |v| fold_cmd(v, |w| w.close_fold(1)),
+                'C' => |v| fold_cmd(v, |w| w.close_fold(usize::MAX)),
+                'a' => count |v| {
+                    let n = v.count().unwrap_or(1);
+                    fold_cmd(v, |w| w.toggle_fold(n));
+                },
+                'A' => |v| fold_cmd(v, |w| w.toggle_fold(usize::MAX)),
+                'R' => |v| v.get_focus_mut().set_all_folds(true),
+                'M' => |v| v.get_focus_mut().set_all_folds(false),
+                'j' => |v| v.get_focus_mut().move_to_fold(true),
+                'k' => |v| v.get_focus_mut().move_to_fold(false),
+            },
             'w' C => {
                 'h' => |v| v.move_focus(Scroll::Left),
                 'j' => |v| v.move_focus(Scroll::Down),
@@ -314,9 +511,37 @@ impl MapSet {
                 keys!(@keycode Right),
                 keys!(@keycode Home),
                 keys!(@keycode End),
+                keys!(@keycode PageUp),
+                keys!(@keycode PageDown),
             ],
         );
         s.register_bindings(KeyState::Insert, arrow_keys.iter().cloned());
+        s.register_bindings(
+            KeyState::Insert,
+            keys!([];
+                Backspace => |v| {
+                    let delcombine = v.options().delcombine;
+                    v.get_focus_mut().delete_backward(delcombine);
+                },
+                Delete => |v| {
+                    let delcombine = v.options().delcombine;
+                    v.get_focus_mut().delete_forward(delcombine);
+                },
+                'n' C => |v| ins_complete(v, complete::Mode::Keyword, true),
+                'p' C => |v| ins_complete(v, complete::Mode::Keyword, false),
+                'x' C => {
+                    'n' C => |v| ins_complete(v, complete::Mode::Local, true),
+                    'p' C => |v| ins_complete(v, complete::Mode::Local, false),
+                    'l' C => |v| ins_complete(v, complete::Mode::Line, true),
+                    'f' C => |v| ins_complete(v, complete::Mode::File, true),
+                    'k' C => |v| ins_complete(v, complete::Mode::Dictionary, true),
+                    't' C => |v| ins_complete(v, complete::Mode::Thesaurus, true),
+                    ']' C | '5' C => |v| ins_complete(v, complete::Mode::Tags, true),
+                    'u' C => |v| complete::user(v.inner, v.ctx, false),
+                    'o' C => |v| complete::user(v.inner, v.ctx, true),
+                },
+            ),
+        );
         s.register_bindings(KeyState::Visual, arrow_keys.iter().cloned());
         let hjkl_keys = s.clone_bindings(
             KeyState::Normal,
@@ -325,14 +550,41 @@ impl MapSet {
                 keys!(@keycode 'j'),
                 keys!(@keycode 'k'),
                 keys!(@keycode 'l'),
+                keys!(@keycode 'g'),
                 keys!(@keycode '$'),
                 keys!(@keycode '^'),
                 keys!(@keycode '0'),
                 keys!(@keycode 'e' C),
-                keys!(@keycode 'Y' C),
+                keys!(@keycode 'y' C),
+                keys!(@keycode 'd' C),
+                keys!(@keycode 'u' C),
+                keys!(@keycode 'f' C),
+                keys!(@keycode 'b' C),
+                keys!(@keycode 'z'),
+                keys!(@keycode '/'),
+                keys!(@keycode '?'),
+                keys!(@keycode 'n'),
+                keys!(@keycode 'N'),
+                keys!(@keycode '*'),
+                keys!(@keycode '#'),
             ],
         );
         s.register_bindings(KeyState::Visual, hjkl_keys.iter().cloned());
+        s.register_bindings(
+            KeyState::Visual,
+            keys!([];
+                ':' => |v| {
+                    v.set_mode(WinMode::Normal);
+                    v.start_cli(Cli::Command);
+                    v.cli.insert("'<,'>");
+                },
+                '!' => |v| {
+                    v.set_mode(WinMode::Normal);
+                    v.start_cli(Cli::Command);
+                    v.cli.insert("'<,'>!");
+                },
+            ),
+        );
         let win_keys = s.clone_bindings(KeyState::Normal, [keys!(@keycode 'w' C)]);
         s.register_bindings(KeyState::Insert, win_keys.iter().cloned());
         s.register_bindings(KeyState::Visual, win_keys.iter().cloned());
@@ -364,6 +616,13 @@ impl MapSet {
         ret
     }
 
+    /// Forgets the keys typed for a command that isn't complete
+    pub fn clear(&mut self) {
+        for map in self.map.values_mut() {
+            map.clear();
+        }
+    }
+
     pub fn on_key(&mut self, k: KeyEvent, state: KeyState) -> MapAction {
         if self.last != state {
             self.map[self.last].clear();
@@ -373,9 +632,11 @@ impl MapSet {
         }
         if state == KeyState::Operator {
             MapAction::Act(
-                self.map[self.last].rep.max(0),
+                self.map[self.last].rep.max(1),
                 Arc::new(move |v: &mut Vim| {
-                    v.get_focus_mut().run_operation(k);
+                    if let Some(op) = v.get_focus_mut().take_operation() {
+                        op.run(v, k);
+                    }
                 }),
             )
         } else {
@@ -1,14 +1,33 @@
-#![feature(round_char_boundary, concat_idents)]
+#![feature(round_char_boundary)]
 
 mod args;
+mod autocmd;
 mod buffer;
 mod builtin;
 mod cli;
+mod color;
+mod complete;
 mod cursor;
+mod edit;
+mod errorformat;
+mod fold;
+mod glob;
+mod global;
+mod highlight;
 mod keymap;
+mod matches;
 mod options;
+mod quickfix;
+mod registers;
+mod search;
+mod shell;
+mod substitute;
+mod syntax;
+mod tags;
 mod util;
+mod width;
 mod window;
+mod wrap;
 
 use crate::buffer::BufferSelect;
 use std::{
@@ -21,6 +40,7 @@ use std::{
 };
 
 use args::Args;
+use autocmd::AutoCmds;
 use backtrace::{Backtrace, BacktraceFmt, BacktraceFrame, BacktraceSymbol, BytesOrWideString};
 use buffer::BufferRef;
 use clap::Parser;
@@ -37,12 +57,22 @@ use crossterm::{
     },
     QueueableCommand,
 };
-use cursor::Cursor;
+use color::TermCaps;
+use complete::Completion;
+use cursor::{Cursor, Motion};
+use highlight::Highlights;
 use keymap::{Action, KeyState, MapAction, MapSet};
 use log::{error, info};
 use options::{Options, Opts};
+use quickfix::QuickFix;
+use registers::Registers;
+use search::Search;
+use substitute::Substitute;
+use tags::Tags;
 use util::{Area, Pos};
-use vimscript::{Id, IdProcuder, State, Value, VimError, VimScriptCtx};
+use vimscript::regex::{self, CharSet};
+use vimscript::{Address, Id, IdProcuder, State, Value, VimError, VimScriptCtx};
+use fold::FoldLevel;
 use window::{Scroll, WinMode, Window};
 
 pub use crossterm::Result;
@@ -69,7 +99,12 @@ pub trait Renderable {
     fn set_area(&mut self, new_area: Area);
     fn area(&self) -> Area;
     fn cursor_pos(&self) -> Cursor;
-    fn draw<W: Write>(&mut self, term: &mut W) -> Result<()>;
+    fn draw<W: Write>(
+        &mut self,
+        term: &mut W,
+        options: &Options,
+        highlights: &Highlights,
+    ) -> Result<()>;
 }
 
 pub enum WindowSet {
@@ -181,6 +216,40 @@ impl WindowSet {
         }
     }
 
+    /// Sets the current focus to the window with the given id
+    fn focus_id(&mut self, id: Id) -> bool {
+        match self {
+            Self::Window(w) => w.id() == id,
+            Self::Horizontal(set, focused, _) | Self::Vertical(set, focused, _) => {
+                for (i, s) in set.iter_mut().enumerate() {
+                    if s.focus_id(id) {
+                        *focused = i;
+                        return true;
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    fn for_each(&self, f: &mut impl FnMut(&Window)) {
+        match self {
+            Self::Window(w) => f(w),
+            Self::Horizontal(set, _, _) | Self::Vertical(set, _, _) => {
+                set.iter().for_each(|s| s.for_each(f))
+            }
+        }
+    }
+
+    fn for_each_mut(&mut self, f: &mut impl FnMut(&mut Window)) {
+        match self {
+            Self::Window(w) => f(w),
+            Self::Horizontal(set, _, _) | Self::Vertical(set, _, _) => {
+                set.iter_mut().for_each(|s| s.for_each_mut(f))
+            }
+        }
+    }
+
     /// Sets the current focus to the window contianing the buffer selected by the criteria
     fn jump_to(&mut self, criteria: &impl BufferSelect) -> bool {
         match self {
@@ -230,6 +299,27 @@ impl WindowSet {
         }
     }
 
+    /// Adds a window of `height` lines below all the others, like the quickfix window
+    fn split_bottom(&mut self, new: Window, height: usize) {
+        let area = self.area();
+        let height = height.min(area.height().saturating_sub(2)).max(1);
+        let mut old = std::mem::replace(self, Self::Vertical(vec![], 1, area));
+        old.set_area(Area {
+            h: area.height().saturating_sub(height),
+            ..area
+        });
+        let mut new = Self::Window(new);
+        new.set_area(Area {
+            y: area.y + area.height().saturating_sub(height),
+            h: height,
+            ..area
+        });
+        if let Self::Vertical(set, ..) = self {
+            set.push(old);
+            set.push(new);
+        }
+    }
+
     fn split_horizontal(&mut self, new: Window) {
         match self {
             Self::Window(w) => {
@@ -265,6 +355,7 @@ impl WindowSet {
                         *focused -= 1;
                     }
                     set.remove(idx);
+                    *focused = (*focused).min(set.len().saturating_sub(1));
                 }
                 if set.len() == 1 {
                     let mut w = set.remove(0);
@@ -289,7 +380,7 @@ impl Renderable for WindowSet {
                     let percent = win.area().width() as f64 / total as f64;
                     let new_width = percent * new_area.width() as f64;
                     win.set_area(Area {
-                        x: cur,
+                        x: new_area.x + cur,
                         y: new_area.y,
                         w: new_width as usize,
                         h: new_area.height(),
@@ -310,8 +401,8 @@ impl Renderable for WindowSet {
                     let percent = win.area().height() as f64 / total as f64;
                     let new_height = percent * new_area.height() as f64;
                     win.set_area(Area {
-                        x: cur,
-                        y: new_area.y,
+                        x: new_area.x,
+                        y: new_area.y + cur,
                         w: new_area.width(),
                         h: new_height as usize,
                     });
@@ -337,12 +428,17 @@ impl Renderable for WindowSet {
         self.get_focus().cursor_pos()
     }
 
-    fn draw<W: Write>(&mut self, term: &mut W) -> Result<()> {
+    fn draw<W: Write>(
+        &mut self,
+        term: &mut W,
+        options: &Options,
+        highlights: &Highlights,
+    ) -> Result<()> {
         match self {
-            Self::Window(w) => w.draw(term),
-            Self::Vertical(set, _, _) | Self::Horizontal(set, _, _) => {
-                set.iter_mut().try_for_each(|w| w.draw(term))
-            }
+            Self::Window(w) => w.draw(term, options, highlights),
+            Self::Vertical(set, _, _) | Self::Horizontal(set, _, _) => set
+                .iter_mut()
+                .try_for_each(|w| w.draw(term, options, highlights)),
         }
     }
 }
@@ -354,41 +450,38 @@ pub enum TerminalState {
     Exit,
 }
 
-pub struct Vim {
-    inner: VimInner,
-    ctx: VimScriptCtx<VimInner>,
+/// The editor state together with the script context, as seen by key actions and commands that
+/// need both
+pub struct Vim<'a> {
+    inner: &'a mut VimInner,
+    ctx: &'a mut VimScriptCtx<VimInner>,
 }
 
-impl std::ops::Deref for Vim {
+impl std::ops::Deref for Vim<'_> {
     type Target = VimInner;
 
     fn deref(&self) -> &Self::Target {
-        &self.inner
+        self.inner
     }
 }
 
-impl std::ops::DerefMut for Vim {
+impl std::ops::DerefMut for Vim<'_> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.inner
+        self.inner
     }
 }
 
-impl Vim {
-    pub fn new() -> Self {
-        let mut ctx = VimScriptCtx::init();
-        cli::commands::default(&mut ctx);
-        Self {
-            inner: VimInner::new(),
-            ctx,
-        }
+impl<'a> Vim<'a> {
+    pub fn new(inner: &'a mut VimInner, ctx: &'a mut VimScriptCtx<VimInner>) -> Self {
+        Self { inner, ctx }
     }
 
     fn init(&mut self) {
-        self.inner.init(&mut self.ctx);
+        self.inner.init(self.ctx);
     }
 
     pub fn execute(&mut self, script: &str) {
-        match self.ctx.run(script, &mut self.inner) {
+        match self.ctx.run(script, self.inner) {
             Ok(()) => (),
             Err(e) => self.inner.message(format!("{e:?}")),
         }
@@ -406,7 +499,206 @@ impl Vim {
     fn exec_file_inner(&mut self, file: impl AsRef<Path>) -> std::result::Result<(), VimError> {
         let mut s = String::new();
         File::open(file)?.read_to_string(&mut s)?;
-        self.ctx.run(s.as_str(), &mut self.inner)
+        self.ctx.run(s.as_str(), self.inner)
+    }
+
+    fn draw<W: Write>(&mut self, mut lock: W) -> Result<()> {
+        if self.inner.sync_background() {
+            // The colorscheme can have different colors for the new background
+            if let Ok(Value::Str(name)) = self.ctx.lookup("g:colors_name").cloned() {
+                self.execute(&format!("colorscheme {name}"));
+            }
+        }
+        self.load_syntax();
+        self.eval_folds();
+        self.inner.draw(&mut lock)?;
+        if self.eval_folds() {
+            self.inner.draw(&mut lock)?;
+        }
+        Ok(())
+    }
+
+    /// Loads `syntax/{name}.vim` from 'runtimepath' for the buffers whose 'syntax' changed, with
+    /// a window on the buffer focused
+    fn load_syntax(&mut self) {
+        let mut pending: Vec<(Id, Id, String)> = vec![];
+        let syntax_on = self.inner.syntax_on;
+        let mut check = |w: &Window| {
+            let buf = w.buffer().read();
+            let opts = buf.options();
+            let name = match opts.syntax.as_str() {
+                "OFF" => "",
+                "" if !syntax_on => "",
+                "" | "ON" => opts.filetype.as_str(),
+                name => name,
+            };
+            if name != buf.syntax().name() && !pending.iter().any(|(_, b, _)| *b == w.buffer().id())
+            {
+                pending.push((w.id(), w.buffer().id(), name.to_string()));
+            }
+        };
+        self.inner.windows.for_each(&mut check);
+        self.inner.floating.iter().for_each(check);
+        if pending.is_empty() {
+            return;
+        }
+        let focus = self.inner.get_focus().id();
+        for (id, buffer, name) in pending {
+            self.inner.focus_window(id);
+            self.inner
+                .get_focus()
+                .buffer()
+                .with_write(|b| b.syntax_mut().reset(&name));
+            self.ctx.set_buffer(buffer);
+            // A syntax like "c.doxygen" loads both files
+            for part in name.split('.').filter(|p| !p.is_empty()) {
+                if let Err(e) = self
+                    .inner
+                    .source_rtp(self.ctx, format!("syntax/{part}.vim"))
+                {
+                    self.inner.message(format!("{e:?}"));
+                }
+            }
+            self.ctx.set_buffer(None);
+        }
+        self.inner.focus_window(focus);
+    }
+
+    /// Evaluates 'foldexpr' and 'foldtext' for the windows that need them, with each window
+    /// focused in turn. Returns whether anything was evaluated.
+    fn eval_folds(&mut self) -> bool {
+        let mut pending = vec![];
+        let mut check = |w: &Window| {
+            if w.fold_expr_pending().is_some() || !w.fold_text_pending().is_empty() {
+                pending.push(w.id());
+            }
+        };
+        self.inner.windows.for_each(&mut check);
+        self.inner.floating.iter().for_each(check);
+        if pending.is_empty() {
+            return false;
+        }
+        let focus = self.inner.get_focus().id();
+        for id in pending {
+            self.inner.focus_window(id);
+            if let Some(expr) = self.inner.get_focus().fold_expr_pending().map(String::from) {
+                let len = self.inner.get_focus().buffer().read().len();
+                let mut levels = Vec::with_capacity(len);
+                for lnum in 1..=len {
+                    let _ = self.ctx.insert_var("v:lnum", Value::Integer(lnum as isize));
+                    levels.push(match self.ctx.eval(&expr, self.inner) {
+                        Ok(v) => FoldLevel::parse(&v.to_string(self.ctx)),
+                        Err(_) => FoldLevel::Level(0),
+                    });
+                }
+                self.inner.get_focus_mut().set_fold_levels(levels);
+            }
+            let win = self.inner.get_focus();
+            let expr = win.buffer().read().options().foldtext.clone();
+            for (start, end, level) in win.fold_text_pending().to_vec() {
+                let vars = [
+                    ("v:foldstart", Value::Integer(start as isize + 1)),
+                    ("v:foldend", Value::Integer(end as isize + 1)),
+                    ("v:foldlevel", Value::Integer(level as isize)),
+                    ("v:folddashes", Value::str("-".repeat(level))),
+                ];
+                for (name, val) in vars {
+                    let _ = self.ctx.insert_var(name, val);
+                }
+                let text = match self.ctx.eval(&expr, self.inner) {
+                    Ok(v) => v.to_string(self.ctx),
+                    Err(e) => format!("{e}"),
+                };
+                self.inner.get_focus_mut().set_fold_text(start, text);
+            }
+        }
+        for name in ["v:lnum", "v:foldstart", "v:foldend", "v:foldlevel", "v:folddashes"] {
+            let _ = self.ctx.remove_var(name);
+        }
+        self.inner.focus_window(focus);
+        true
+    }
+
+    /// Runs the action bound to a key, as if it was typed
+    pub fn on_key(&mut self, k: KeyEvent) {
+        if self.inner.cli.listing() {
+            let used = self.inner.cli.more_key(k);
+            if !self.inner.cli.listing() {
+                self.inner.windows.redraw_all();
+            }
+            if used {
+                return;
+            }
+        }
+        let state = self.inner.get_state();
+        match self.state {
+            TerminalState::Window if self.inner.completion.is_some() => {
+                match complete::on_key(self.inner, self.ctx, k, state) {
+                    complete::Key::Done => (),
+                    complete::Key::Filter => {
+                        self.window_key(k, state);
+                        complete::filter(self.inner, self.ctx);
+                    }
+                    complete::Key::End => self.window_key(k, state),
+                }
+            }
+            TerminalState::Window => self.window_key(k, state),
+            TerminalState::Cli => {
+                cli::wild::hide(self.inner);
+                self.inner.cli.on_key(k).run(self);
+            }
+            TerminalState::Exit => (),
+        }
+    }
+
+    /// Runs the action of a key typed in a window
+    fn window_key(&mut self, k: KeyEvent, state: KeyState) {
+        match self.inner.map_set.on_key(k, state) {
+            MapAction::Act(rep, a) => {
+                for _ in 0..rep {
+                    a.run(self);
+                }
+            }
+            MapAction::Count(count, a) => {
+                self.inner.count = count;
+                a.run(self);
+                self.inner.count = None;
+            }
+            MapAction::Wait => info!("{:?}", self.inner.map_set),
+            MapAction::None => self.inner.get_focus_mut().on_key(k).run(self),
+        }
+    }
+
+    /// Runs `keys` as Normal mode commands, like `:normal`. A command that isn't finished at the
+    /// end is ended like with <Esc>.
+    pub fn normal(&mut self, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\r' | '\n' => KeyCode::Enter,
+                '\x1b' => KeyCode::Esc,
+                '\t' => KeyCode::Tab,
+                '\x08' | '\x7f' => KeyCode::Backspace,
+                c => KeyCode::Char(c),
+            };
+            let key = match code {
+                KeyCode::Char(c) if c < ' ' => {
+                    let c = (c as u8 + b'a' - 1) as char;
+                    KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+                }
+                code => KeyEvent::new(code, KeyModifiers::NONE),
+            };
+            self.on_key(key);
+        }
+        if self.state == TerminalState::Cli {
+            self.inner.end_cli();
+        }
+        for _ in 0..2 {
+            if matches!(self.get_focus().mode(), WinMode::Normal) {
+                break;
+            }
+            self.on_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        }
+        self.inner.map_set.clear();
     }
 
     fn on_event(&mut self, event: Event) {
@@ -416,20 +708,7 @@ impl Vim {
                 if k.code == KeyCode::Char('c') && k.modifiers == KeyModifiers::CONTROL {
                     self.inner.state = TerminalState::Exit;
                 } else {
-                    let state = self.inner.get_state();
-                    match self.state {
-                        TerminalState::Window => match self.inner.map_set.on_key(k, state) {
-                            MapAction::Act(rep, a) => {
-                                for _ in 0..rep {
-                                    a.run(self);
-                                }
-                            }
-                            MapAction::Wait => info!("{:?}", self.inner.map_set),
-                            MapAction::None => self.inner.get_focus_mut().on_key(k).run(self),
-                        },
-                        TerminalState::Cli => self.inner.cli.on_key(k).run(self),
-                        TerminalState::Exit => (),
-                    }
+                    self.on_key(k);
                 }
             }
             Event::Mouse(m) => match self.state {
@@ -438,12 +717,13 @@ impl Vim {
                 TerminalState::Exit => (),
             },
         }
-    }
-}
-
-impl Default for Vim {
-    fn default() -> Self {
-        Self::new()
+        // Everything typed outside of insert mode is its own undo step
+        if !self.inner.get_focus().mode().insert() {
+            for buffer in self.inner.buffers.iter() {
+                buffer.write().commit_undo();
+            }
+        }
+        self.inner.forget_line_changes();
     }
 }
 
@@ -456,10 +736,29 @@ pub struct VimInner {
     floating: Vec<Window>,
     size: (u16, u16),
     state: TerminalState,
+    /// Count typed before the command that is running, for commands that use it directly
+    count: Option<usize>,
     cursor: Cursor,
     map_set: MapSet,
     cli: CliState,
     silent: bool,
+    highlights: Highlights,
+    /// What the terminal can display
+    caps: TermCaps,
+    /// Whether `:syntax on` was used, so the syntax is loaded from 'filetype'
+    syntax_on: bool,
+    registers: Registers,
+    search: Search,
+    substitute: Substitute,
+    /// Whether `:global` is running, which can't be nested
+    global_busy: bool,
+    /// The last `:!` command, which a `!` in the next one repeats
+    shell_cmd: Option<String>,
+    quickfix: QuickFix,
+    autocmds: AutoCmds,
+    tags: Tags,
+    /// Insert mode completion, while it is active
+    completion: Option<Completion>,
     buffer_id: IdProcuder,
     window_id: IdProcuder,
     script_id: IdProcuder,
@@ -479,6 +778,54 @@ impl State for VimInner {
     fn get_option(&self, name: &str) -> std::result::Result<Value, VimError> {
         self.options.get(name).map(|v| v.into())
     }
+
+    fn regex_options(&self) -> regex::Options {
+        let opts = &self.options;
+        let default = regex::Options::default();
+        // Invalid values are rejected by Vim when they are set, here they are ignored
+        let set = |spec: &str, wide, default| CharSet::parse(spec, wide).unwrap_or(default);
+        let iskeyword = self.get_focus().buffer().read().options().iskeyword.clone();
+        regex::Options {
+            ignorecase: opts.ignorecase,
+            magic: opts.magic,
+            engine: regex::Engine::from_option(opts.regexpengine),
+            iskeyword: set(&iskeyword, false, default.iskeyword),
+            isident: set(&opts.isident, false, default.isident),
+            isfname: set(&opts.isfname, true, default.isfname),
+            isprint: set(&opts.isprint, true, default.isprint),
+            substitute: self.substitute.last_replacement().to_string(),
+        }
+    }
+
+    fn address(
+        &mut self,
+        address: Address<'_>,
+        line: usize,
+    ) -> std::result::Result<usize, VimError> {
+        let buffer = self.get_focus().buffer().clone();
+        match address {
+            Address::Current => Ok(self.get_focus().cursor().row() + 1),
+            Address::Last => Ok(buffer.read().len()),
+            Address::Mark(c) if c.is_ascii_lowercase() || c == '<' || c == '>' => {
+                match buffer.read().mark(c) {
+                    Some(pos) => Ok(pos.1 + 1),
+                    None => Err(VimError::IllegalArgument("E20: Mark not set")),
+                }
+            }
+            Address::Mark(_) => Err(VimError::IllegalArgument("E78: Unknown mark")),
+            Address::Search(pattern, forward) => {
+                search::line_address(self, Some(pattern), forward, line).map_err(VimError::Pattern)
+            }
+            Address::LastSearch(forward) => {
+                search::line_address(self, None, forward, line).map_err(VimError::Pattern)
+            }
+            Address::LastSubstitute => match self.substitute.last_pattern().map(String::from) {
+                Some(pattern) => search::line_address(self, Some(&pattern), true, line)
+                    .map_err(VimError::Pattern),
+                None => Err(VimError::Pattern("E35: No previous regular expression".into())),
+            },
+        }
+    }
 }
 
 impl Default for VimInner {
@@ -487,11 +834,31 @@ impl Default for VimInner {
     }
 }
 
+#[cfg(test)]
+impl VimInner {
+    /// An 80x24 vim editing `lines`, with the builtin commands and functions
+    pub(crate) fn with_lines(lines: &[&str]) -> (Self, VimScriptCtx<Self>) {
+        let mut v = Self::new();
+        let mut ctx = VimScriptCtx::init();
+        cli::commands::default(&mut ctx);
+        builtin::builtin_functions(&mut ctx);
+        v.update_area((80, 24));
+        let lines = lines.iter().map(|l| l.to_string()).collect();
+        v.get_focus().buffer().write().replace_lines(0..1, lines);
+        v.get_focus().buffer().write().commit_undo();
+        (v, ctx)
+    }
+}
+
 impl VimInner {
     pub fn new() -> Self {
         let mut buffer_id = IdProcuder::default();
         let mut window_id = IdProcuder::default();
         let script_id = IdProcuder::default();
+        // The arguments of the test harness aren't files to open
+        #[cfg(test)]
+        let args = Args::parse_from(["vim"]);
+        #[cfg(not(test))]
         let args = Args::parse();
         let mut buffers: Vec<_> = args
             .files
@@ -509,11 +876,24 @@ impl VimInner {
             floating: vec![],
             size: (0, 0),
             state: TerminalState::Window,
+            count: None,
             cursor: Cursor::invalid(),
             focus: 0,
             map_set: MapSet::global(),
             cli: CliState::new(),
             silent: false,
+            highlights: Highlights::new(),
+            caps: TermCaps::detect(),
+            syntax_on: false,
+            registers: Registers::default(),
+            search: Search::default(),
+            substitute: Substitute::default(),
+            global_busy: false,
+            shell_cmd: None,
+            quickfix: QuickFix::default(),
+            autocmds: AutoCmds::default(),
+            tags: Tags::default(),
+            completion: None,
             buffer_id,
             window_id,
             script_id,
@@ -591,6 +971,27 @@ impl VimInner {
         &self.options
     }
 
+    pub fn highlights(&self) -> &Highlights {
+        &self.highlights
+    }
+
+    pub fn highlights_mut(&mut self) -> &mut Highlights {
+        &mut self.highlights
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /// Turns loading the syntax for the 'filetype' of buffers on or off
+    pub fn set_syntax_on(&mut self, on: bool) {
+        self.syntax_on = on;
+    }
+
     pub fn options_mut(&mut self) -> &mut Options {
         &mut self.options
     }
@@ -601,10 +1002,23 @@ impl VimInner {
     }
 
     pub fn end_cli(&mut self) {
+        search::end_preview(self);
         self.cli.end();
         self.state = TerminalState::Window;
     }
 
+    /// Adds a line typed on the command line to its history, which keeps 'history' lines
+    pub fn add_history(&mut self, ty: Cli, line: &str) {
+        if let Some(kind) = ty.history() {
+            let max = self.options.history.max(0) as usize;
+            self.cli.history_mut().add(kind, line, max);
+        }
+    }
+
+    pub fn count(&self) -> Option<usize> {
+        self.count
+    }
+
     pub fn exit(&mut self) {
         self.state = TerminalState::Exit;
     }
@@ -624,6 +1038,79 @@ impl VimInner {
         }
     }
 
+    /// Focuses the window with the given id
+    pub fn focus_window(&mut self, id: Id) -> bool {
+        if self.windows.focus_id(id) {
+            self.focus = self.floating.len();
+            true
+        } else if let Some(i) = self.floating.iter().position(|w| w.id() == id) {
+            self.focus = i;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drops the line changes of each buffer that every window showing it has already moved its
+    /// folds by
+    fn forget_line_changes(&mut self) {
+        for buffer in self.buffers.iter() {
+            let mut tick = buffer.read().tick();
+            let mut oldest = |w: &Window| {
+                if w.buffer().id() == buffer.id() {
+                    tick = tick.min(w.fold_tick());
+                }
+            };
+            self.windows.for_each(&mut oldest);
+            self.floating.iter().for_each(oldest);
+            buffer.write().forget_changes_before(tick);
+        }
+    }
+
+    /// Runs `f` on every window, including floating windows
+    pub fn for_each_window_mut(&mut self, mut f: impl FnMut(&mut Window)) {
+        self.windows.for_each_mut(&mut f);
+        self.floating.iter_mut().for_each(f);
+    }
+
+    /// Undoes (or redoes) `count` undo steps in the focused buffer (`u` and `CTRL-R`)
+    pub fn undo(&mut self, redo: bool, count: usize) {
+        let buffer = self.get_focus().buffer().clone();
+        let (mut first, mut delta, mut changes) = (None, 0, 0);
+        for _ in 0..count.max(1) {
+            match buffer.with_write(|b| if redo { b.redo() } else { b.undo() }) {
+                Some((line, d, c)) => {
+                    first = Some(first.map_or(line, |f: usize| f.min(line)));
+                    delta += d;
+                    changes += c;
+                }
+                None => break,
+            }
+        }
+        let Some(line) = first else {
+            let newest = if redo { "newest" } else { "oldest" };
+            return self.message(format!("Already at {newest} change"));
+        };
+        let col = buffer.with_read(|b| b[line.min(b.len() - 1)].first_char());
+        let win = self.get_focus_mut();
+        win.cursor_apply(Motion::SetRow(line));
+        win.cursor_apply(Motion::SetCol(col));
+        let what = match delta {
+            1 => "1 more line".to_string(),
+            -1 => "1 line less".to_string(),
+            d if d > 1 => format!("{d} more lines"),
+            d if d < -1 => format!("{} fewer lines", -d),
+            _ if changes == 1 => "1 change".to_string(),
+            _ => format!("{changes} changes"),
+        };
+        let seq = buffer.read().undo_seq();
+        self.message(if redo {
+            format!("{what}; after #{seq}")
+        } else {
+            format!("{what}; before #{}", seq + 1)
+        });
+    }
+
     pub fn select_focus(&mut self, criteria: impl BufferSelect) {
         if self.windows.jump_to(&criteria) {
             self.focus = self.floating.len();
@@ -668,11 +1155,16 @@ impl VimInner {
     }
 
     fn draw<W: Write>(&mut self, mut lock: W) -> Result<()> {
-        self.windows.draw(&mut lock)?;
-        self.cli.draw(&mut lock)?;
+        self.highlights.set_output(self.caps, self.options.termguicolors);
+        search::sync_highlight(self);
+        cli::wild::clear(self, &mut lock)?;
+        self.windows.draw(&mut lock, &self.options, &self.highlights)?;
+        self.cli.draw(&mut lock, &self.options, &self.highlights)?;
         match self.state {
+            TerminalState::Window if self.cli.listing() => self.cursor = self.cli.cursor_pos(),
             TerminalState::Window => {
                 self.cursor = self.get_focus().cursor_pos();
+                complete::draw(self, &mut lock)?;
                 Pos(
                     self.size.0.saturating_sub(20) as usize,
                     self.size.1.saturating_sub(1) as usize,
@@ -680,13 +1172,56 @@ impl VimInner {
                 .move_cursor(&mut lock)?;
                 self.map_set.draw(&mut lock, self.get_state())?;
             }
-            TerminalState::Cli => self.cursor = self.cli.cursor_pos(),
+            TerminalState::Cli => {
+                cli::wild::draw(self, &mut lock)?;
+                self.cursor = self.cli.cursor_pos();
+            }
             TerminalState::Exit => (),
         }
         self.cursor.draw(&mut lock)?;
         Ok(())
     }
 
+    /// Redraws the screen and waits for a key, for a command that asks something while it runs
+    pub fn wait_key(&mut self) -> Result<KeyEvent> {
+        loop {
+            let mut lock = io::stdout().lock();
+            self.draw(&mut lock)?;
+            lock.flush()?;
+            drop(lock);
+            match event::read()? {
+                Event::Key(key) => return Ok(key),
+                Event::Resize(c, r) => self.update_area((c, r)),
+                Event::Mouse(_) => (),
+            }
+        }
+    }
+
+    /// Leaves the alternate screen while `f` runs a program on the terminal, like `:!cmd`, and
+    /// waits for a key before going back when `wait` is set
+    pub fn suspend<T>(&mut self, wait: bool, f: impl FnOnce() -> T) -> Result<T> {
+        let mut out = io::stdout();
+        out.queue(LeaveAlternateScreen)?
+            .queue(DisableMouseCapture)?
+            .queue(EnableLineWrap)?;
+        out.flush()?;
+        disable_raw_mode()?;
+        let ret = f();
+        if wait {
+            write!(out, "\nPress ENTER or type command to continue")?;
+            out.flush()?;
+        }
+        enable_raw_mode()?;
+        if wait {
+            while !matches!(event::read()?, Event::Key(_)) {}
+        }
+        out.queue(DisableLineWrap)?
+            .queue(EnterAlternateScreen)?
+            .queue(EnableMouseCapture)?;
+        self.windows.redraw_all();
+        Ok(ret)
+    }
+
     pub fn exiting(&self) -> bool {
         self.state == TerminalState::Exit
     }
@@ -715,6 +1250,11 @@ impl VimInner {
         self.cli.message(message);
     }
 
+    /// Shows the lines of a listing above the command line, which waits for a key
+    pub fn show_lines(&mut self, lines: Vec<String>) {
+        self.cli.show_lines(lines);
+    }
+
     pub fn err(&mut self, error: Result<()>) {
         if let Err(e) = error {
             self.message(format!("{e}"));
@@ -743,13 +1283,83 @@ impl VimInner {
             .split_horizontal(Window::new(self.window_id.get(), buffer));
     }
 
+    /// Opens a window of `height` lines at the bottom of the screen, and focuses it
+    pub fn split_bottom(&mut self, buffer: BufferRef, height: usize) -> Id {
+        let id = self.window_id.get();
+        self.windows.split_bottom(Window::new(id, buffer), height);
+        self.focus_window(id);
+        id
+    }
+
+    pub fn close_window(&mut self, id: Id) -> std::result::Result<(), String> {
+        if matches!(self.windows, WindowSet::Window(_)) {
+            return Err("E444: Cannot close last window".into());
+        }
+        self.windows.remove_window(id);
+        self.focus = self.floating.len();
+        Ok(())
+    }
+
+    /// The buffer of the file at `path`, if it is open
+    pub fn find_buffer(&self, path: impl AsRef<Path>) -> Option<&BufferRef> {
+        let path = path.as_ref();
+        let same = |file: &Path| {
+            file == path
+                || std::fs::canonicalize(file)
+                    .is_ok_and(|file| std::fs::canonicalize(path).is_ok_and(|p| p == file))
+        };
+        self.buffers
+            .iter()
+            .find(|b| b.read().filename().is_some_and(same))
+    }
+
+    /// The buffer of the file at `path`, which is opened when no buffer has it yet
+    pub fn edit(&mut self, path: impl AsRef<Path>) -> Result<BufferRef> {
+        match self.find_buffer(&path) {
+            Some(buffer) => Ok(buffer.clone()),
+            None => self.open_file(path.as_ref()),
+        }
+    }
+
+    /// Finds the buffer with the number `n`, as `bufnr()` gives it
+    pub fn buffer_nr(&self, n: usize) -> Option<&BufferRef> {
+        self.buffers.iter().find(|b| b.id().number() + 1 == n)
+    }
+
     fn get_next_script_id(&mut self) -> Id {
         self.script_id.get()
     }
+
+    /// Runs the script `name` from 'runtimepath'. Returns whether it was found.
+    pub fn source_rtp(
+        &mut self,
+        ctx: &mut VimScriptCtx<Self>,
+        name: impl AsRef<str>,
+    ) -> std::result::Result<bool, VimError> {
+        let mut script = String::new();
+        match self.find_on_rtp(name) {
+            Ok((_, mut file)) => file.read_to_string(&mut script)?,
+            Err(_) => return Ok(false),
+        };
+        ctx.set_script(Some(self.get_next_script_id()));
+        let res = ctx.run(&script, self);
+        ctx.set_script(None);
+        res.map(|()| true)
+    }
+
+    /// Makes the default highlight groups match 'background'. Returns whether it changed.
+    pub fn sync_background(&mut self) -> bool {
+        if self.options.background == self.highlights.background() {
+            return false;
+        }
+        self.highlights.set_background(&self.options.background);
+        true
+    }
 }
 
 pub struct Curse<W: Lockable> {
-    vim: Vim,
+    inner: VimInner,
+    ctx: VimScriptCtx<VimInner>,
     terminal: W,
 }
 
@@ -761,12 +1371,19 @@ impl Curse<Stdout> {
 
 impl<W: Lockable> Curse<W> {
     pub fn new(terminal: W) -> Self {
+        let mut ctx = VimScriptCtx::init();
+        cli::commands::default(&mut ctx);
         Self {
+            inner: VimInner::new(),
+            ctx,
             terminal,
-            vim: Vim::new(),
         }
     }
 
+    fn vim(&mut self) -> Vim<'_> {
+        Vim::new(&mut self.inner, &mut self.ctx)
+    }
+
     pub fn run(mut self) -> Result<()> {
         std::panic::set_hook(Box::new(panic_cleanup));
         enable_raw_mode()?;
@@ -776,7 +1393,7 @@ impl<W: Lockable> Curse<W> {
             lock.queue(EnterAlternateScreen)?;
             lock.queue(EnableMouseCapture)?;
         }
-        self.vim.init();
+        self.vim().init();
         self.event_loop()?;
         disable_raw_mode()?;
         {
@@ -789,12 +1406,12 @@ impl<W: Lockable> Curse<W> {
     }
 
     fn event_loop(&mut self) -> Result<()> {
-        self.vim.update_area(terminal::size()?);
+        self.inner.update_area(terminal::size()?);
         self.draw()?;
-        while !self.vim.exiting() {
+        while !self.inner.exiting() {
             if event::poll(Duration::from_millis(20))? {
                 let e = event::read()?;
-                self.vim.on_event(e);
+                self.vim().on_event(e);
             }
             self.draw()?;
         }
@@ -803,7 +1420,7 @@ impl<W: Lockable> Curse<W> {
 
     fn draw(&mut self) -> Result<()> {
         let mut lock = self.terminal.lock();
-        self.vim.draw(&mut lock)?;
+        Vim::new(&mut self.inner, &mut self.ctx).draw(&mut lock)?;
         lock.flush()?;
         Ok(())
     }
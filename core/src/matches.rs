@@ -0,0 +1,264 @@
+//
+// matches.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::ops::Range;
+
+use crate::syntax::Pattern;
+
+/// A position given to `matchaddpos()`. Without a column the whole line is highlighted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchPos {
+    pub line: usize,
+    pub col: Option<usize>,
+    pub len: usize,
+}
+
+/// What a match highlights
+#[derive(Debug, Clone)]
+pub enum Target {
+    Pattern {
+        source: String,
+        pattern: Box<Pattern>,
+    },
+    Pos(Vec<MatchPos>),
+}
+
+/// A window-local highlight added by `:match` or `matchadd()`
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub id: usize,
+    pub group: String,
+    pub target: Target,
+    pub priority: isize,
+    /// Character shown instead of the match when it is concealed
+    pub conceal: Option<char>,
+}
+
+/// The matches of a window, ordered by priority
+#[derive(Debug, Clone)]
+pub struct Matches {
+    list: Vec<Match>,
+    next_id: usize,
+}
+
+impl Default for Matches {
+    fn default() -> Self {
+        Self {
+            list: vec![],
+            next_id: 1000,
+        }
+    }
+}
+
+impl Matches {
+    pub fn list(&self) -> &[Match] {
+        &self.list
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Match> {
+        self.list.iter().find(|m| m.id == id)
+    }
+
+    /// Adds a match with the given id, or a free id when `id` is -1. Returns the id used.
+    pub fn add(
+        &mut self,
+        group: &str,
+        target: Target,
+        priority: isize,
+        id: isize,
+        conceal: Option<char>,
+    ) -> Result<usize, String> {
+        let id = match id {
+            -1 => {
+                while self.get(self.next_id).is_some() {
+                    self.next_id += 1;
+                }
+                self.next_id += 1;
+                self.next_id - 1
+            }
+            id if id < 1 => {
+                return Err(format!(
+                    "E799: Invalid ID: {id} (must be greater than or equal to 1)"
+                ))
+            }
+            1..=3 => return Err(format!("E798: ID is reserved for \":match\": {id}")),
+            id if self.get(id as usize).is_some() => {
+                return Err(format!("E801: ID already taken: {id}"))
+            }
+            id => id as usize,
+        };
+        self.insert(Match {
+            id,
+            group: group.to_string(),
+            target,
+            priority,
+            conceal,
+        });
+        Ok(id)
+    }
+
+    /// Keeps the list ordered by priority, with later matches after earlier ones
+    fn insert(&mut self, m: Match) {
+        let at = self.list.partition_point(|o| o.priority <= m.priority);
+        self.list.insert(at, m);
+    }
+
+    /// Sets or clears (with `None`) the match of `:match`, `:2match` or `:3match`
+    pub fn set_match(&mut self, n: usize, pat: Option<(&str, &str)>) -> Result<(), String> {
+        self.list.retain(|m| m.id != n);
+        if let Some((group, source)) = pat {
+            self.insert(Match {
+                id: n,
+                group: group.to_string(),
+                target: Target::Pattern {
+                    source: source.to_string(),
+                    pattern: Box::new(Pattern::search(source, false)?),
+                },
+                priority: 10,
+                conceal: None,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn delete(&mut self, id: isize) -> Result<(), String> {
+        if id < 1 {
+            return Err(format!(
+                "E802: Invalid ID: {id} (must be greater than or equal to 1)"
+            ));
+        }
+        let len = self.list.len();
+        self.list.retain(|m| m.id != id as usize);
+        if self.list.len() == len {
+            return Err(format!("E803: ID not found: {id}"));
+        }
+        Ok(())
+    }
+
+    /// Replaces the matches, like `setmatches()`
+    pub fn set_list(&mut self, list: Vec<Match>) {
+        self.list.clear();
+        for m in list {
+            self.insert(m);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    /// The parts of `text`, which is at `line`, highlighted by each match, from the lowest
+    /// priority to the highest
+    pub fn line_spans(&self, line: usize, text: &str) -> Vec<(Range<usize>, &Match)> {
+        let mut spans = vec![];
+        for m in &self.list {
+            match &m.target {
+                Target::Pattern { pattern, .. } => {
+                    let mut from = 0;
+                    while from <= text.len() {
+                        let found = match pattern.find_at(text, from) {
+                            Some(found) => found,
+                            None => break,
+                        };
+                        from = if found.is_empty() {
+                            text.ceil_char_boundary(found.end + 1)
+                        } else {
+                            spans.push((found.clone(), m));
+                            found.end
+                        };
+                        if found.end >= text.len() {
+                            break;
+                        }
+                    }
+                }
+                Target::Pos(pos) => {
+                    for p in pos.iter().filter(|p| p.line == line) {
+                        let range = match p.col {
+                            Some(col) => col.min(text.len())..(col + p.len).min(text.len()),
+                            None => 0..text.len(),
+                        };
+                        spans.push((range, m));
+                    }
+                }
+            }
+        }
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> Target {
+        Target::Pattern {
+            source: s.to_string(),
+            pattern: Box::new(Pattern::search(s, false).unwrap()),
+        }
+    }
+
+    #[test]
+    fn ids() {
+        let mut matches = Matches::default();
+        assert_eq!(matches.add("Error", pattern("x"), 10, -1, None), Ok(1000));
+        assert_eq!(matches.add("Error", pattern("y"), 10, 4, None), Ok(4));
+        assert_eq!(
+            matches.add("Error", pattern("y"), 10, 4, None),
+            Err("E801: ID already taken: 4".to_string())
+        );
+        assert!(matches.add("Error", pattern("y"), 10, 2, None).is_err());
+        assert!(matches.add("Error", pattern("y"), 10, 0, None).is_err());
+        matches.set_match(2, Some(("Todo", "z"))).unwrap();
+        assert_eq!(matches.delete(1000), Ok(()));
+        assert_eq!(
+            matches.delete(1000),
+            Err("E803: ID not found: 1000".to_string())
+        );
+        let ids: Vec<usize> = matches.list().iter().map(|m| m.id).collect();
+        assert_eq!(ids, [4, 2]);
+    }
+
+    #[test]
+    fn spans() {
+        let mut matches = Matches::default();
+        matches.add("Todo", pattern("TODO"), 20, -1, None).unwrap();
+        matches
+            .add("Error", pattern("\\s\\+$"), 10, -1, None)
+            .unwrap();
+        let pos = vec![
+            MatchPos {
+                line: 1,
+                col: Some(2),
+                len: 3,
+            },
+            MatchPos {
+                line: 2,
+                col: None,
+                len: 0,
+            },
+        ];
+        matches
+            .add("Search", Target::Pos(pos), 10, -1, None)
+            .unwrap();
+        let spans = |line: usize, text: &str| -> Vec<(Range<usize>, String)> {
+            matches
+                .line_spans(line, text)
+                .into_iter()
+                .map(|(r, m)| (r, m.group.clone()))
+                .collect()
+        };
+        assert_eq!(
+            spans(0, "a TODO TODO  "),
+            [
+                (11..13, "Error".to_string()),
+                (2..6, "Todo".to_string()),
+                (7..11, "Todo".to_string())
+            ]
+        );
+        assert_eq!(spans(1, "abcdef"), [(2..5, "Search".to_string())]);
+        assert_eq!(spans(2, "abc"), [(0..3, "Search".to_string())]);
+    }
+}
@@ -99,6 +99,24 @@ str_enum!(
     }
 );
 
+str_enum!(
+    enum AmbiWidth {
+        single,
+        double,
+    }
+);
+
+str_enum!(
+    enum FoldMethod {
+        manual,
+        indent,
+        expr,
+        marker,
+        syntax,
+        diff,
+    }
+);
+
 str_enum!(struct BellOff {
     all; set_all: 0,
     backspace; set_backspace: 1,
@@ -150,6 +168,22 @@ fn list_options<O: Opts>(opts: &O) -> String {
     ret
 }
 
+/// Removes the backslash before a space or a backslash in the value of `:set`
+fn unescape(value: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next @ (' ' | '\\'))) => {
+                ret.push(next);
+                chars.next();
+            }
+            (c, _) => ret.push(c),
+        }
+    }
+    ret
+}
+
 fn set_option_part(args: &str, opts: &mut impl Opts) -> Result<Option<String>, String> {
     if args.trim() == "all" {
         Ok(Some(list_options(opts)))
@@ -178,7 +212,7 @@ fn set_option_part(args: &str, opts: &mut impl Opts) -> Result<Option<String>, S
             Err(e) => Err(format!("{name} is not defined")),
         }
     } else if let Some((name, value)) = args.split_once('=') {
-        match opts.set(name, value) {
+        match opts.set(name, &unescape(value)) {
             Ok(()) => Ok(None),
             Err(e) => Err(format!("{name} is not defined")),
         }
@@ -198,7 +232,7 @@ fn set_option_part(args: &str, opts: &mut impl Opts) -> Result<Option<String>, S
 }
 
 pub(crate) fn set_option(
-    _range: CmdRange<'_>,
+    _range: CmdRange,
     _bang: bool,
     args: &str,
     _ctx: &mut VimScriptCtx<VimInner>,
@@ -243,7 +277,7 @@ pub(crate) fn set_option(
 }
 
 pub(crate) fn set_local(
-    _range: CmdRange<'_>,
+    _range: CmdRange,
     _bang: bool,
     args: &str,
     _ctx: &mut VimScriptCtx<VimInner>,
@@ -283,7 +317,7 @@ pub(crate) fn set_local(
 }
 
 pub(crate) fn set_global(
-    _range: CmdRange<'_>,
+    _range: CmdRange,
     _bang: bool,
     args: &str,
     _ctx: &mut VimScriptCtx<VimInner>,
@@ -406,7 +440,7 @@ options! {
     Options {
         aleph | al : isize => "224", // ASCII code of the letter Aleph (Hebrew)
         allowrevins | ari : bool => "false", // allow CTRL-_ in Insert and Command-line mode
-        ambiwidth | ambw : String => "single", // what to do with Unicode chars of ambiguous width
+        ambiwidth | ambw : AmbiWidth => "single", // what to do with Unicode chars of ambiguous width
         autochdir | acd : bool => "false", // change directory to the file in the current window
         arabicshape | arshape : bool => "true", // do shaping for Arabic characters
         autoread | ar : bool => "true", // autom. read file when changed outside of Vim
@@ -468,7 +502,7 @@ options! {
         fsync | fs : bool => "false", // whether to invoke fsync() after file write
         gdefault | gd : bool => "false", // the ":substitute" flag 'g' is default on
         grepformat | gfm : String => "%f:%l:%m,%f:%l%m,%f  %l%m", // format of 'grepprg' output
-        grepprg | gp : String => "grep -n ", // program to use for ":grep"
+        grepprg | gp : String => "grep -n $* /dev/null", // program to use for ":grep"
         guicursor | gcr : String => "n-v-c-sm:block,i-ci-ve:ver25,r-cr-o:hor20", // GUI: settings for cursor shape and blinking
         guifont | gfn : String => "", // GUI: Name(s) of font(s) to be used
         guifontwide | gfw : String => "", // list of font names for double-wide characters
@@ -479,28 +513,27 @@ options! {
         helpheight | hh : isize => "0", // minimum height of a new help window
         helplang | hlg : isize => "0", // preferred help languages
         hidden | hid : isize => "0", // don't unload buffer when it is |abandon|ed
-        hlsearch | hls : isize => "0", // highlight matches with last search pattern
-        history | hi : isize => "0", // number of command-lines that are remembered
+        hlsearch | hls : bool => "false", // highlight matches with last search pattern
+        history | hi : isize => "10000", // number of command-lines that are remembered
         hkmap | hk : isize => "0", // Hebrew keyboard mapping
         hkmapp | hkp : isize => "0", // phonetic Hebrew keyboard mapping
         icon : isize => "0", // let Vim set the text of the window icon
         iconstring : isize => "0", // string to use for the Vim icon text
-        ignorecase | ic : isize => "0", // ignore case in search patterns
+        ignorecase | ic : bool => "false", // ignore case in search patterns
         imcmdline | imc : isize => "0", // use IM when starting to edit a command line
         imdisable | imd : isize => "0", // do not use the IM in any mode
         iminsert | imi : isize => "0", // use :lmap or IM in Insert mode
         imsearch | ims : isize => "0", // use :lmap or IM when typing a search pattern
         include | inc : isize => "0", // pattern to be used to find an include file
         includeexpr | inex : isize => "0", // expression used to process an include line
-        incsearch | is : isize => "0", // highlight match while typing search pattern
+        incsearch | is : bool => "false", // highlight match while typing search pattern
         indentexpr | inde : isize => "0", // expression used to obtain the indent of a line
         indentkeys | indk : isize => "0", // keys that trigger indenting with 'indentexpr'
-        infercase | inf : isize => "0", // adjust case of match for keyword completion
+        infercase | inf : bool => "false", // adjust case of match for keyword completion
         insertmode | im : isize => "0", // start the edit of a file in Insert mode
-        isfname | isf : isize => "0", // characters included in file names and pathnames
-        isident | isi : isize => "0", // characters included in identifiers
-        iskeyword | isk : isize => "0", // characters included in keywords
-        isprint | isp : isize => "0", // printable characters
+        isfname | isf : String => "@,48-57,/,.,-,_,+,,,#,$,%,~,=", // characters included in file names and pathnames
+        isident | isi : String => "@,48-57,_,192-255", // characters included in identifiers
+        isprint | isp : String => "@,161-255", // printable characters
         joinspaces | js : isize => "0", // two spaces after a period with a join command
         jumpoptions | jop : isize => "0", // specifies how jumping is done
         keymap | kmp : isize => "0", // name of a keyboard mapping
@@ -511,7 +544,6 @@ options! {
         langremap | lrm : isize => "0", // do apply 'langmap' to mapped characters
         laststatus | ls : isize => "0", // tells when last window has status lines
         lazyredraw | lz : isize => "0", // don't redraw while executing macros
-        linebreak | lbr : isize => "0", // wrap long lines at a blank
         lines : isize => "0", // number of lines in the display
         linespace | lsp : isize => "0", // number of pixel lines to use between characters
         lisp : isize => "0", // automatic indenting for Lisp
@@ -519,10 +551,10 @@ options! {
         list : isize => "0", // show <Tab> and <EOL>
         listchars | lcs : isize => "0", // characters for displaying in list mode
         loadplugins | lpl : isize => "0", // load plugin scripts when starting up
-        magic : isize => "0", // changes special characters in search patterns
-        makeef | mef : isize => "0", // name of the errorfile for ":make"
+        magic : bool => "true", // changes special characters in search patterns
+        makeef | mef : String => "", // name of the errorfile for ":make"
         makeencoding | menc : isize => "0", // encoding of external make/grep commands
-        makeprg | mp : isize => "0", // program to use for the ":make" command
+        makeprg | mp : String => "make", // program to use for the ":make" command
         matchpairs | mps : isize => "0", // pairs of characters that "%" can match
         matchtime | mat : isize => "0", // tenths of a second to show matching paren
         maxcombine | mco : isize => "0", // maximum nr of combining characters displayed
@@ -546,7 +578,6 @@ options! {
         nrformats | nf : isize => "0", // number formats recognized for CTRL-A command
         number | nu : isize => "0", // print the line number in front of each line
         numberwidth | nuw : isize => "0", // number of columns used for the line number
-        omnifunc | ofu : isize => "0", // function for filetype-specific completion
         opendevice | odev : isize => "0", // allow reading/writing devices on MS-Windows
         operatorfunc | opfunc : isize => "0", // function to be called for |g@| operator
         packpath | pp : isize => "0", // list of directories used for packages
@@ -588,7 +619,6 @@ options! {
         ruler | ru : bool => "false", // show cursor line and column in the status line
         rulerformat | ruf : isize => "0", // custom format for the ruler
         runtimepath | rtp : String => "$XDG_CONFIG_HOME/rvim/", // list of directories used for runtime files
-        scroll | scr : isize => "1", // lines to scroll with CTRL-U and CTRL-D
         scrollbind | scb : isize => "0", // scroll in window as other windows scroll
         scrolljump | sj : isize => "0", // minimum number of lines to scroll
         scrolloff | so : isize => "0", // minimum nr. of lines above and below cursor
@@ -599,28 +629,28 @@ options! {
         selectmode | slm : isize => "0", // when to use Select mode instead of Visual mode
         sessionoptions | ssop : isize => "0", // options for |:mksession|
         shada | sd : isize => "0", // use .shada file upon startup and exiting
-        shell | sh : isize => "0", // name of shell to use for external commands
-        shellcmdflag | shcf : isize => "0", // flag to shell to execute one command
-        shellpipe | sp : isize => "0", // string to put output of ":make" in error file
-        shellquote | shq : isize => "0", // quote character(s) for around shell command
-        shellredir | srr : isize => "0", // string to put output of filter in a temp file
+        shell | sh : String => "sh", // name of shell to use for external commands
+        shellcmdflag | shcf : String => "-c", // flag to shell to execute one command
+        shellpipe | sp : String => "2>&1| tee", // string to put output of ":make" in error file
+        shellquote | shq : String => "", // quote character(s) for around shell command
+        shellredir | srr : String => ">%s 2>&1", // string to put output of filter in a temp file
         shellslash | ssl : isize => "0", // use forward slash for shell file names
-        shelltemp | stmp : isize => "0", // whether to use a temp file for shell commands
-        shellxescape | sxe : isize => "0", // characters to escape when 'shellxquote' is (
-        shellxquote | sxq : isize => "0", // like 'shellquote', but include redirection
+        shelltemp | stmp : bool => "true", // whether to use a temp file for shell commands
+        shellxescape | sxe : String => "", // characters to escape when 'shellxquote' is (
+        shellxquote | sxq : String => "", // like 'shellquote', but include redirection
         shiftround | sr : isize => "0", // round indent to multiple of shiftwidth
         shiftwidth | sw : isize => "0", // number of spaces to use for (auto)indent step
-        shortmess | shm : isize => "0", // list of flags, reduce length of messages
-        showbreak | sbr : isize => "0", // string to use at the start of wrapped lines
+        shortmess | shm : String => "filnxtToOS", // list of flags, reduce length of messages
+        showbreak | sbr : String => "", // string to use at the start of wrapped lines
         showcmd | sc : isize => "0", // show (partial) command in status line
-        showfulltag | sft : isize => "0", // show full tag pattern when completing tag
+        showfulltag | sft : bool => "false", // show full tag pattern when completing tag
         showmatch | sm : isize => "0", // briefly jump to matching bracket if insert one
         showmode | smd : isize => "0", // message on status line to show current mode
         showtabline | stal : isize => "0", // tells when the tab pages line is displayed
         sidescroll | ss : isize => "0", // minimum number of columns to scroll horizontal
         sidescrolloff | siso : isize => "0", // min. nr. of columns to left and right of cursor
         signcolumn | scl : isize => "0", // when and how to display the sign column
-        smartcase | scs : isize => "0", // no ignore case when pattern has uppercase
+        smartcase | scs : bool => "false", // no ignore case when pattern has uppercase
         smartindent | si : isize => "0", // smart autoindenting for C programs
         smarttab | sta : isize => "0", // use 'shiftwidth' when inserting <Tab>
         softtabstop | sts : isize => "0", // number of spaces that <Tab> uses while editing
@@ -634,26 +664,25 @@ options! {
         splitright | spr : isize => "0", // new window is put right of the current one
         startofline | sol : isize => "0", // commands move cursor to first non-blank in line
         statusline | stl : isize => "0", // custom format for the status line
-        suffixes | su : isize => "0", // suffixes that are ignored with multiple match
+        suffixes | su : String => ".bak,~,.o,.h,.info,.swp,.obj", // suffixes that are ignored with multiple match
         suffixesadd | sua : isize => "0", // suffixes added when searching for a file
         swapfile | swf : isize => "0", // whether to use a swapfile for a buffer
         switchbuf | swb : isize => "0", // sets behavior when switching to another buffer
-        synmaxcol | smc : isize => "0", // maximum column to find syntax items
-        syntax | syn : isize => "0", // syntax to be loaded for current buffer
         tabline | tal : isize => "0", // custom format for the console tab pages line
         tabpagemax | tpm : isize => "0", // maximum number of tab pages for |-p| and "tab all"
-        tabstop | ts : isize => "0", // number of spaces that <Tab> in file uses
-        tagbsearch | tbs : isize => "0", // use binary searching in tags files
-        tagcase | tc : isize => "0", // how to handle case when searching in tags files
+        tabstop | ts : isize => "8", // number of spaces that <Tab> in file uses
+        tagbsearch | tbs : bool => "true", // use binary searching in tags files
+        tagcase | tc : String => "followic", // how to handle case when searching in tags files
         taglength | tl : isize => "0", // number of significant characters for a tag
-        tagrelative | tr : isize => "0", // file names in tag file are relative
-        tags | tag : isize => "0", // list of file names used by the tag command
-        tagstack | tgst : isize => "0", // push tags onto the tag stack
+        tagrelative | tr : bool => "true", // file names in tag file are relative
+        tags | tag : String => "./tags;,tags", // list of file names used by the tag command
+        tagstack | tgst : bool => "true", // push tags onto the tag stack
         term : isize => "0", // name of the terminal
         termbidi | tbidi : isize => "0", // terminal takes care of bi-directionality
+        termguicolors | tgc : bool => "false", // use GUI colors for the terminal
         terse : isize => "0", // shorten some messages
         textwidth | tw : isize => "0", // maximum width of text that is being inserted
-        thesaurus | tsr : isize => "0", // list of thesaurus files for keyword completion
+        thesaurus | tsr : String => "", // list of thesaurus files for keyword completion
         thesaurusfunc | tsrfu : isize => "0", // function to be used for thesaurus completion
         tildeop | top : isize => "0", // tilde command "~" behaves like an operator
         timeout | to : isize => "0", // time out on mappings and key codes
@@ -683,11 +712,11 @@ options! {
         whichwrap | ww : isize => "0", // allow specified keys to cross line boundaries
         wildchar | wc : isize => "0", // command-line character for wildcard expansion
         wildcharm | wcm : isize => "0", // like 'wildchar' but also works when mapped
-        wildignore | wig : isize => "0", // files matching these patterns are not completed
-        wildignorecase | wic : isize => "0", // ignore case when completing file names
-        wildmenu | wmnu : isize => "0", // use menu for command line completion
-        wildmode | wim : isize => "0", // mode for 'wildchar' command-line expansion
-        wildoptions | wop : isize => "0", // specifies how command line completion is done
+        wildignore | wig : String => "", // files matching these patterns are not completed
+        wildignorecase | wic : bool => "false", // ignore case when completing file names
+        wildmenu | wmnu : bool => "true", // use menu for command line completion
+        wildmode | wim : String => "full", // mode for 'wildchar' command-line expansion
+        wildoptions | wop : String => "pum,tagfile", // specifies how command line completion is done
         winaltkeys | wak : isize => "0", // when the windows system handles ALT keys
         window | wi : isize => "0", // nr of lines to scroll for CTRL-F and CTRL-B
         winheight | wh : isize => "0", // minimum number of lines for the current window
@@ -697,9 +726,8 @@ options! {
         winminheight | wmh : isize => "0", // minimum number of lines for any window
         winminwidth | wmw : isize => "0", // minimal number of columns for any window
         winwidth | wiw : isize => "0", // minimal number of columns for current window
-        wrap : isize => "0", // long lines wrap and continue on the next line
         wrapmargin | wm : isize => "0", // chars from the right where wrapping starts
-        wrapscan | ws : isize => "0", // searches wrap around the end of the file
+        wrapscan | ws : bool => "true", // searches wrap around the end of the file
         write : bool => "true", // writing to a file is allowed
         writeany | wa : bool => "true", // write to file with no need for "!" override
         writebackup | wb : isize => "0", // make a backup before overwriting a file
@@ -737,7 +765,7 @@ options! {
 
         endofline | eol : bool => "true", // write <EOL> for last line in file
         equalprg | ep : String => "", // external program to use for "=" command
-        errorformat | efm : String => "%*[^\"]\"%f\"%*\\D%l: %m,\"%f\"%*\\D%l: %m,%-G%f:%l: (Each undeclared identifier is reported only once,%-G%f:%l: for each function it appears in.),%-GIn file included from %f:%l:%c:,%-GIn file included from %f:%l:%c\\,,%-GIn file included from %f:%l:%c,%-GIn file included from %f:%l,%-G%*[ ]from %f:%l:%c,%-G%*[ ]from %f:%l:,%-G%*[ ]from %f:%l\\,,%-G%*[ ]from %f:%l,%f:%l:%c:%m,%f(%l):%m,%f:%l:%m,\"%f\"\\, line %l%*\\D%c%*[^ ] %m,%D%*\\a[%*\\d]: Entering directory %*[`']%f',%X%*\\a[%*\\d]: Leaving directory %*[`']%f',%D%*\\a: Entering directory %*[`']%f',%X%*\\a: Leaving directory %*[`']%f',%DMaking %*\\a in %f,%f|%l| %m", // description of the lines in the error file
+        errorformat | efm : String => "", // description of the lines in the error file
         expandtab | et : bool => "false", // use spaces when <Tab> is inserted
         // exrc | ex : isize => , // read .nvimrc and .exrc in the current directory
         fileencoding | fenc : String => "", // file encoding for multibyte text
@@ -751,7 +779,12 @@ options! {
         formatlistpat | flp : String => "^\\s*\\d\\+[\\]:.)}\\t ]\\s*", // pattern used to recognize a list header
         formatoptions | fo : String => "tcqj", // how automatic formatting is to be done
         formatprg | fp : String => "", // name of external program used with "gq" command
-        grepprg | gp : String => "grep -n ", // program to use for ":grep"
+        grepprg | gp : String => "", // program to use for ":grep"
+        iskeyword | isk : String => "@,48-57,_,192-255", // characters included in keywords
+        omnifunc | ofu : String => "", // function for filetype-specific completion
+
+        synmaxcol | smc : isize => "3000", // maximum column to find syntax items
+        syntax | syn : String => "", // syntax to be loaded for current buffer
     }
 }
 
@@ -759,7 +792,7 @@ options! {
     WinOptions {
         arabic | arab : bool => "false", // for Arabic as a default second language
         breakindent | bri : bool => "false", // wrapped line repeats indent
-        breakindentopt | briopt : bool => "false", // settings for 'breakindent'
+        breakindentopt | briopt : String => "", // settings for 'breakindent'
         colorcolumn | cc : String => "", // columns to highlight
 
         concealcursor | cocu : String => "", // whether concealable text is hidden in cursor line
@@ -779,9 +812,12 @@ options! {
         foldlevel | fdl : isize => "0", // close folds with a level higher than this
 
         foldmarker | fmr : String => "{{{,}}}", // markers used when 'foldmethod' is "marker"
-        foldmethod | fdm : String => "manual", // folding type
+        foldmethod | fdm : FoldMethod => "manual", // folding type
         foldminlines | fml : isize => "1", // minimum number of lines for a fold to be closed
         foldnestmax | fdn : isize => "20", // maximum fold depth
         foldopen | fdo : String => "block,hor,mark,percent,quickfix,search,tag,undo", // for which commands a fold will be opened
+        linebreak | lbr : bool => "false", // wrap long lines at a blank
+        scroll | scr : isize => "0", // lines to scroll with CTRL-U and CTRL-D, 0 for half the window
+        wrap : bool => "true", // long lines wrap and continue on the next line
     }
 }
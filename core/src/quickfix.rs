@@ -0,0 +1,960 @@
+//
+// quickfix.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use vimscript::regex::Regex;
+use vimscript::{CmdRange, Id, State, VimScriptCtx};
+
+use crate::autocmd;
+use crate::buffer::BufferRef;
+use crate::cursor::Motion;
+use crate::errorformat::ErrorFormat;
+use crate::glob;
+use crate::search;
+use crate::shell;
+use crate::syntax::split_pattern;
+use crate::VimInner;
+
+/// An error of a quickfix or location list
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub filename: Option<String>,
+    pub lnum: usize,
+    pub end_lnum: usize,
+    pub col: usize,
+    pub end_col: usize,
+    /// Whether `col` is a screen column rather than a byte index
+    pub vcol: bool,
+    pub nr: isize,
+    /// A search pattern for the line of the error, from `%s`
+    pub pattern: String,
+    pub text: String,
+    /// The type of the error, like `E` for an error or `W` for a warning
+    pub kind: Option<char>,
+    /// Whether the line was recognized as an error, so it can be jumped to
+    pub valid: bool,
+}
+
+/// The text of an entry on one line, where a line break and the white space after it is a space
+fn one_line(text: &str) -> String {
+    let mut lines = text.split('\n');
+    let first = lines.next().unwrap_or_default().to_string();
+    lines.fold(first, |acc, line| acc + " " + line.trim_start())
+}
+
+impl Entry {
+    /// The type and number of the entry, like ` error  12`
+    fn kind(&self) -> String {
+        let name = match self.kind {
+            Some('W' | 'w') => " warning".to_string(),
+            Some('I' | 'i') => " info".to_string(),
+            Some('N' | 'n') => " note".to_string(),
+            Some('E' | 'e') => " error".to_string(),
+            None if self.nr > 0 => " error".to_string(),
+            Some(c) => format!(" {c}"),
+            None => String::new(),
+        };
+        if self.nr > 0 {
+            format!("{name} {:3}", self.nr)
+        } else {
+            name
+        }
+    }
+
+    /// The line of the entry in the quickfix window
+    fn line(&self) -> String {
+        if !self.valid {
+            return format!("|| {}", one_line(&self.text));
+        }
+        let mut pos = String::new();
+        if self.lnum > 0 {
+            pos.push_str(&self.lnum.to_string());
+            if self.col > 0 {
+                pos.push_str(&format!(" col {}", self.col));
+            }
+        }
+        pos.push_str(&self.kind());
+        let name = self.filename.as_deref().unwrap_or_default();
+        format!(
+            "{name}|{}| {}",
+            pos.trim_start(),
+            one_line(self.text.trim_start())
+        )
+    }
+}
+
+/// A quickfix or location list
+#[derive(Debug, Default)]
+pub struct List {
+    pub title: String,
+    pub entries: Vec<Entry>,
+    /// The current entry
+    pub idx: usize,
+}
+
+/// How the entries of a command are added: `:cfile` makes a new list, `:caddfile` adds to the
+/// current one, and `setqflist()` can also replace its entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    New,
+    Add,
+    Replace,
+}
+
+/// The lists that `:colder` and `:cnewer` go through, and the window that shows the current one
+#[derive(Default)]
+pub struct Stack {
+    lists: Vec<List>,
+    cur: usize,
+    window: Option<Id>,
+    buffer: Option<BufferRef>,
+}
+
+/// The number of lists that are kept
+const STACK_SIZE: usize = 10;
+
+impl Stack {
+    pub fn list(&self) -> Option<&List> {
+        self.lists.get(self.cur)
+    }
+
+    pub fn list_mut(&mut self) -> Option<&mut List> {
+        self.lists.get_mut(self.cur)
+    }
+
+    /// The number of the current list, which is 0 when there are none
+    pub fn nr(&self) -> usize {
+        if self.lists.is_empty() {
+            0
+        } else {
+            self.cur + 1
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lists.len()
+    }
+
+    pub fn get(&self, nr: usize) -> Option<&List> {
+        self.lists.get(nr.checked_sub(1)?)
+    }
+
+    pub fn window(&self) -> Option<Id> {
+        self.window
+    }
+
+    pub fn set(&mut self, action: Action, title: String, entries: Vec<Entry>) {
+        match (action, self.lists.get_mut(self.cur)) {
+            (Action::Add, Some(list)) => list.entries.extend(entries),
+            (Action::Replace, Some(list)) => {
+                list.entries = entries;
+                list.idx = 0;
+            }
+            _ => {
+                // A new list goes after the current one, and the newer lists are dropped
+                self.lists.truncate(self.cur + 1);
+                self.lists.push(List {
+                    title,
+                    entries,
+                    idx: 0,
+                });
+                if self.lists.len() > STACK_SIZE {
+                    self.lists.remove(0);
+                }
+                self.cur = self.lists.len() - 1;
+            }
+        }
+    }
+
+    /// Removes every list, for `setqflist([], 'f')`
+    pub fn clear(&mut self) {
+        self.lists.clear();
+        self.cur = 0;
+    }
+}
+
+/// The quickfix list, and the location list of each window
+#[derive(Default)]
+pub struct QuickFix {
+    list: Stack,
+    /// Location lists, by the window they belong to
+    locations: HashMap<Id, Stack>,
+}
+
+/// Whether a command uses the quickfix list or a location list, with the window it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Which {
+    QuickFix,
+    Location(Id),
+}
+
+impl Which {
+    /// The quickfix list, or the location list of the focused window. In a location list window,
+    /// that is the list it shows.
+    pub fn new(v: &VimInner, loc: bool) -> Self {
+        if !loc {
+            return Self::QuickFix;
+        }
+        let id = v.get_focus().id();
+        let owner = v
+            .quickfix
+            .locations
+            .iter()
+            .find(|(_, stack)| stack.window == Some(id))
+            .map_or(id, |(owner, _)| *owner);
+        Self::Location(owner)
+    }
+}
+
+impl QuickFix {
+    pub fn stack(&self, which: Which) -> Option<&Stack> {
+        match which {
+            Which::QuickFix => Some(&self.list),
+            Which::Location(id) => self.locations.get(&id),
+        }
+    }
+
+    pub fn stack_mut(&mut self, which: Which) -> &mut Stack {
+        match which {
+            Which::QuickFix => &mut self.list,
+            Which::Location(id) => self.locations.entry(id).or_default(),
+        }
+    }
+}
+
+/// The current list, which has to have entries
+fn current(v: &VimInner, which: Which) -> Result<&List, String> {
+    v.quickfix
+        .stack(which)
+        .ok_or("E776: No location list")?
+        .list()
+        .filter(|l| !l.entries.is_empty())
+        .ok_or_else(|| "E42: No Errors".to_string())
+}
+
+/// The errorformat of the focused buffer, or the global one
+fn errorformat(v: &VimInner) -> String {
+    let local = v.get_focus().buffer().read().options().errorformat.clone();
+    if local.is_empty() {
+        v.options().errorformat.clone()
+    } else {
+        local
+    }
+}
+
+/// The title of a list that a command made, like `:cfile errors.err`
+fn title(which: Which, cmd: &str, args: &str) -> String {
+    let prefix = if which == Which::QuickFix { 'c' } else { 'l' };
+    format!(":{prefix}{cmd} {}", args.trim())
+}
+
+/// Reads `lines` with `efm`, or 'errorformat', into a list, and jumps to its first error when
+/// `jump` is set
+#[allow(clippy::too_many_arguments)]
+pub fn load(
+    v: &mut VimInner,
+    which: Which,
+    action: Action,
+    jump: bool,
+    title: String,
+    lines: &[String],
+    efm: Option<&str>,
+) -> Result<(), String> {
+    let efm = efm.map_or_else(|| errorformat(v), String::from);
+    let entries = ErrorFormat::new(&efm)?.parse(lines.iter().map(String::as_str));
+    set_entries(v, which, action, jump, title, entries)
+}
+
+/// Makes `entries` a list, and jumps to its first error when `jump` is set
+fn set_entries(
+    v: &mut VimInner,
+    which: Which,
+    action: Action,
+    jump: bool,
+    title: String,
+    entries: Vec<Entry>,
+) -> Result<(), String> {
+    v.quickfix.stack_mut(which).set(action, title, entries);
+    refresh(v, which);
+    let first = current(v, which)
+        .ok()
+        .and_then(|list| list.entries.iter().position(|e| e.valid));
+    match first {
+        Some(idx) if jump => go_to(v, which, idx),
+        _ => Ok(()),
+    }
+}
+
+/// `:cfile [file]`, which reads the list from a file, or 'errorfile'
+pub fn file(
+    v: &mut VimInner,
+    which: Which,
+    action: Action,
+    jump: bool,
+    name: &str,
+) -> Result<(), String> {
+    let name = match name.trim() {
+        "" => v.options().errorfile.clone(),
+        name => name.to_string(),
+    };
+    let text = std::fs::read(&name).map_err(|_| format!("E40: Can't open errorfile {name}"))?;
+    let lines: Vec<_> = String::from_utf8_lossy(&text)
+        .lines()
+        .map(String::from)
+        .collect();
+    load(
+        v,
+        which,
+        action,
+        jump,
+        title(which, "file", &name),
+        &lines,
+        None,
+    )
+}
+
+/// `:[range]cbuffer [bufnr]`, which reads the list from the lines of a buffer
+pub fn buffer(
+    v: &mut VimInner,
+    which: Which,
+    action: Action,
+    jump: bool,
+    range: CmdRange,
+    nr: &str,
+) -> Result<(), String> {
+    let buffer = match nr.trim() {
+        "" => v.get_focus().buffer().clone(),
+        nr => {
+            let nr = nr
+                .parse()
+                .map_err(|_| format!("E474: Invalid argument: {nr}"))?;
+            v.buffer_nr(nr)
+                .cloned()
+                .ok_or("E681: Buffer is not loaded")?
+        }
+    };
+    let lines: Vec<_> = buffer.with_read(|b| {
+        let (first, last) = match range {
+            CmdRange::CurrentLine => (0, b.len() - 1),
+            CmdRange::Range { start, end } => (start.saturating_sub(1), end.saturating_sub(1)),
+        };
+        (first..=last.min(b.len() - 1))
+            .map(|l| b[l].text().to_string())
+            .collect()
+    });
+    let title = title(which, "buffer", &format!("({})", buffer.read().title()));
+    load(v, which, action, jump, title, &lines, None)
+}
+
+/// `:cexpr {expr}`, which reads the list from a string or a list of lines
+pub fn expr(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    which: Which,
+    action: Action,
+    jump: bool,
+    expr: &str,
+) -> Result<(), String> {
+    let lines: Vec<_> = match ctx.eval(expr, v).map_err(|e| format!("{e}"))? {
+        vimscript::Value::Str(s) => s.lines().map(String::from).collect(),
+        list @ vimscript::Value::List(_) => list.into_iter().map(|l| l.to_string(ctx)).collect(),
+        _ => return Err("E777: String or List expected".into()),
+    };
+    load(
+        v,
+        which,
+        action,
+        jump,
+        title(which, "expr", expr),
+        &lines,
+        None,
+    )
+}
+
+/// A program that `:make` or `:grep` runs
+struct Program {
+    /// The name of the command, for the title of the list and the autocommands
+    name: &'static str,
+    args: String,
+    cmd: String,
+    /// The format of its output
+    efm: String,
+    /// The file its output is written to
+    file: String,
+    /// Whether the file is removed after it is read
+    temp: bool,
+}
+
+/// Replaces `$*` in `prg` with `args`, or adds them at the end
+fn program_cmd(prg: &str, args: &str) -> String {
+    match prg.contains("$*") {
+        true => prg.replace("$*", args),
+        false if args.is_empty() => prg.trim_end().to_string(),
+        false => format!("{} {args}", prg.trim_end()),
+    }
+}
+
+/// Runs a program with its output in a file with 'shellpipe', and reads the file into a list
+fn run_program(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    which: Which,
+    action: Action,
+    jump: bool,
+    prg: Program,
+) -> Result<(), String> {
+    autocmd::apply(v, ctx, "QuickFixCmdPre", prg.name);
+    let redir = shell::redirect(&v.options().shellpipe, Path::new(&prg.file));
+    shell::terminal(v, ctx, &prg.cmd, &redir)?;
+    let text = std::fs::read(&prg.file)
+        .map_err(|_| format!("E40: Can't open errorfile {}", prg.file))?;
+    if prg.temp {
+        let _ = std::fs::remove_file(&prg.file);
+    }
+    let lines: Vec<_> = String::from_utf8_lossy(&text)
+        .lines()
+        .map(String::from)
+        .collect();
+    let title = format!(":{} {}", prg.name, prg.args).trim_end().to_string();
+    load(v, which, action, jump, title, &lines, Some(&prg.efm))?;
+    autocmd::apply(v, ctx, "QuickFixCmdPost", prg.name);
+    Ok(())
+}
+
+/// `:make [args]`, which runs 'makeprg' and reads its output with 'errorformat'
+pub fn make(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    which: Which,
+    jump: bool,
+    args: &str,
+) -> Result<(), String> {
+    let args = shell::expand_file(v, args.trim())?;
+    let (file, temp) = match v.options().makeef.as_str() {
+        "" => (v.options().errorfile.clone(), false),
+        // `##` makes the name unique
+        mef => (mef.replace("##", &std::process::id().to_string()), true),
+    };
+    let prg = Program {
+        name: if which == Which::QuickFix { "make" } else { "lmake" },
+        cmd: program_cmd(&v.options().makeprg, &args),
+        args,
+        efm: errorformat(v),
+        file,
+        temp,
+    };
+    run_program(v, ctx, which, Action::New, jump, prg)
+}
+
+/// `:grep [args]`, which runs 'grepprg' and reads its output with 'grepformat', or searches
+/// with `:vimgrep` when 'grepprg' is "internal"
+pub fn grep(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    which: Which,
+    action: Action,
+    jump: bool,
+    args: &str,
+) -> Result<(), String> {
+    let local = v.get_focus().buffer().read().options().grepprg.clone();
+    let grepprg = if local.is_empty() { v.options().grepprg.clone() } else { local };
+    if grepprg == "internal" {
+        return vimgrep(v, ctx, which, action, jump, args);
+    }
+    let args = shell::expand_file(v, args.trim())?;
+    let prg = Program {
+        name: match (which, action) {
+            (Which::QuickFix, Action::Add) => "grepadd",
+            (Which::QuickFix, _) => "grep",
+            (_, Action::Add) => "lgrepadd",
+            _ => "lgrep",
+        },
+        cmd: program_cmd(&grepprg, &args),
+        args,
+        efm: v.options().grepformat.clone(),
+        file: shell::temp_file().display().to_string(),
+        temp: true,
+    };
+    run_program(v, ctx, which, action, jump, prg)
+}
+
+/// Splits the arguments of `:vimgrep` into the pattern, its flags and the files. The pattern
+/// is delimited like `/pat/`, or is the first word when it starts with a keyword character.
+fn vimgrep_args(args: &str) -> Result<(&str, &str, &str), String> {
+    let args = args.trim_start();
+    let first = args.chars().next().ok_or("E683: File name missing or invalid pattern")?;
+    if first.is_alphanumeric() || first == '_' {
+        let (pat, files) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        return Ok((pat, "", files));
+    }
+    let (pat, rest) = split_pattern(args);
+    let (flags, files) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if !flags.chars().all(|c| "gjf".contains(c)) {
+        return Err("E682: Invalid search pattern or delimiter".into());
+    }
+    Ok((pat, flags, files))
+}
+
+/// The file names of the arguments of `:vimgrep`, which are separated by white space that isn't
+/// escaped with a backslash
+fn file_args(files: &str) -> Vec<String> {
+    let mut ret = vec![];
+    let mut word = String::new();
+    let mut chars = files.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ (' ' | '\t')) => word.push(c),
+                Some(c) => {
+                    word.push('\\');
+                    word.push(c);
+                }
+                None => word.push('\\'),
+            },
+            c if c.is_whitespace() => ret.extend(Some(std::mem::take(&mut word))),
+            c => word.push(c),
+        }
+    }
+    ret.push(word);
+    ret.retain(|w| !w.is_empty());
+    ret
+}
+
+/// `:vimgrep /{pattern}/[g][j] {file} ...`, which searches the files with the regex engine. A
+/// file that is loaded is searched in its buffer, and the others are read without loading them.
+pub fn vimgrep(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    which: Which,
+    action: Action,
+    jump: bool,
+    args: &str,
+) -> Result<(), String> {
+    let name = match (which, action) {
+        (Which::QuickFix, Action::Add) => "vimgrepadd",
+        (Which::QuickFix, _) => "vimgrep",
+        (_, Action::Add) => "lvimgrepadd",
+        _ => "lvimgrep",
+    };
+    let (pat, flags, files) = vimgrep_args(args)?;
+    let pat = match pat {
+        "" => search::last_pattern(v).ok_or("E35: No previous regular expression")?,
+        pat => pat.to_string(),
+    };
+    let regex = search::compile(v, &pat, true)?;
+    let mut paths = vec![];
+    for file in file_args(files) {
+        let file = shell::expand_file(v, &file)?;
+        paths.extend(glob::glob(&file, false).into_iter().filter(|p| !p.is_dir()));
+    }
+    if paths.is_empty() {
+        return Err("E683: File name missing or invalid pattern".into());
+    }
+    autocmd::apply(v, ctx, "QuickFixCmdPre", name);
+    let mut entries = vec![];
+    for path in paths {
+        let lines: Vec<String> = match v.find_buffer(&path) {
+            Some(buffer) => buffer.with_read(|b| {
+                (0..b.len())
+                    .map(|l| b[l].text().to_string())
+                    .collect()
+            }),
+            None => match std::fs::read(&path) {
+                Ok(text) => String::from_utf8_lossy(&text).lines().map(String::from).collect(),
+                Err(_) => continue,
+            },
+        };
+        let filename = path.strip_prefix(".").unwrap_or(&path).display().to_string();
+        for (l, line) in lines.iter().enumerate() {
+            let mut from = 0;
+            while let Some(m) = regex.find_str(line, from) {
+                entries.push(Entry {
+                    filename: Some(filename.clone()),
+                    lnum: l + 1,
+                    col: m.range().start + 1,
+                    text: line.clone(),
+                    valid: true,
+                    ..Entry::default()
+                });
+                // Without `g` only the first match in a line is added
+                if !flags.contains('g') || m.range().end >= line.len() {
+                    break;
+                }
+                from = match m.is_empty() {
+                    true => {
+                        let len = line[m.range().start..].chars().next().map_or(1, char::len_utf8);
+                        m.range().start + len
+                    }
+                    false => m.range().end,
+                };
+            }
+        }
+    }
+    search::set_last_pattern(v, &pat);
+    if entries.is_empty() {
+        return Err(format!("E480: No match: {pat}"));
+    }
+    let title = format!(":{name} {}", args.trim());
+    set_entries(v, which, action, jump && !flags.contains('j'), title, entries)?;
+    autocmd::apply(v, ctx, "QuickFixCmdPost", name);
+    Ok(())
+}
+
+/// Where `:cc`, `:cnext` and the others go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// The entry with a number, or the current one for 0
+    Nr(usize),
+    Last,
+    /// A number of valid entries after the current one
+    Next(usize),
+    Prev(usize),
+}
+
+/// Jumps to an entry of the current list
+pub fn go(v: &mut VimInner, which: Which, target: Target) -> Result<(), String> {
+    let list = current(v, which)?;
+    let len = list.entries.len();
+    let valid = |i: &usize| list.entries[*i].valid;
+    let idx = match target {
+        Target::Nr(0) => list.idx,
+        Target::Nr(n) => n.min(len) - 1,
+        Target::Last => len - 1,
+        Target::Next(count) | Target::Prev(count) => {
+            let mut idx = list.idx;
+            for n in 0..count.max(1) {
+                let next = match target {
+                    Target::Next(_) => (idx + 1..len).find(valid),
+                    _ => (0..idx).rev().find(valid),
+                };
+                match next {
+                    Some(next) => idx = next,
+                    // A count that goes past the end stops at the last entry
+                    None if n > 0 => break,
+                    None => return Err("E553: No more items".into()),
+                }
+            }
+            idx
+        }
+    };
+    go_to(v, which, idx)
+}
+
+/// Focuses a window that isn't a quickfix window, which is where the entries are shown
+fn leave_quickfix(v: &mut VimInner) {
+    let is_quickfix = |b: &BufferRef| b.read().options().buftype == "quickfix";
+    if !is_quickfix(v.get_focus().buffer()) {
+        return;
+    }
+    let mut other = None;
+    v.for_each_window_mut(|w| {
+        if other.is_none() && !is_quickfix(w.buffer()) {
+            other = Some(w.id());
+        }
+    });
+    if let Some(id) = other {
+        v.focus_window(id);
+    }
+}
+
+/// Makes the entry `idx` the current one, and shows it
+fn go_to(v: &mut VimInner, which: Which, idx: usize) -> Result<(), String> {
+    let (entry, len) = {
+        let list = v
+            .quickfix
+            .stack_mut(which)
+            .list_mut()
+            .ok_or("E42: No Errors")?;
+        list.idx = idx;
+        (list.entries[idx].clone(), list.entries.len())
+    };
+    refresh(v, which);
+    if entry.valid {
+        leave_quickfix(v);
+        if let Some(name) = &entry.filename {
+            let buffer = v
+                .edit(name)
+                .map_err(|_| format!("E484: Can't open file {name}"))?;
+            if buffer.id() != v.get_focus().buffer().id() {
+                v.get_focus_mut().set_buffer(buffer);
+            }
+        }
+        show(v, &entry);
+    }
+    let text = one_line(&entry.text);
+    v.message(format!(
+        "({} of {len}){}: {}",
+        idx + 1,
+        entry.kind(),
+        text.trim_start()
+    ));
+    Ok(())
+}
+
+/// Moves the cursor to the line and column of an entry
+fn show(v: &mut VimInner, entry: &Entry) {
+    let buffer = v.get_focus().buffer().clone();
+    let pattern = match entry.pattern.as_str() {
+        "" => None,
+        pattern => Regex::new(pattern, &v.regex_options()).ok(),
+    };
+    let (row, col) = buffer.with_read(|b| {
+        let row = match pattern {
+            Some(pattern) if entry.lnum == 0 => (0..b.len())
+                .find(|&l| pattern.is_match(b[l].text()))
+                .unwrap_or(0),
+            _ => entry.lnum.saturating_sub(1).min(b.len() - 1),
+        };
+        let line = &b[row];
+        let col = match entry.col {
+            0 => line.first_char(),
+            col if entry.vcol => line
+                .text()
+                .char_indices()
+                .nth(col - 1)
+                .map_or(line.len(), |(i, _)| i),
+            col => line.floor_grapheme((col - 1).min(line.len())),
+        };
+        (row, col)
+    });
+    let win = v.get_focus_mut();
+    win.cursor_apply(Motion::SetRow(row));
+    win.cursor_apply(Motion::SetCol(col));
+}
+
+/// Shows the current list in its window, with the cursor on the current entry
+pub fn refresh(v: &mut VimInner, which: Which) {
+    let Some(stack) = v.quickfix.stack(which) else {
+        return;
+    };
+    let (Some(buffer), Some(id)) = (stack.buffer.clone(), stack.window) else {
+        return;
+    };
+    let (lines, idx) = match stack.list() {
+        Some(list) => (list.entries.iter().map(Entry::line).collect(), list.idx),
+        None => (vec![], 0),
+    };
+    buffer.with_write(|b| {
+        let len = b.len();
+        b.replace_lines(0..len, lines);
+        b.commit_undo();
+    });
+    v.for_each_window_mut(|w| {
+        if w.id() == id {
+            w.cursor_apply(Motion::SetRow(idx));
+            w.cursor_apply(Motion::SetCol(0));
+        }
+    });
+}
+
+/// `:copen [height]`, which opens the window of the list, or goes to it
+pub fn open(v: &mut VimInner, which: Which, height: &str) -> Result<(), String> {
+    let height = match height.trim() {
+        "" => 10,
+        h => h
+            .parse()
+            .map_err(|_| format!("E474: Invalid argument: {h}"))?,
+    };
+    if v.quickfix.stack(which).is_none() {
+        return Err("E776: No location list".into());
+    }
+    if let Some(id) = v.quickfix.stack_mut(which).window {
+        if v.focus_window(id) {
+            return Ok(());
+        }
+    }
+    let buffer = match v.quickfix.stack_mut(which).buffer.clone() {
+        Some(buffer) => buffer,
+        None => {
+            let buffer = v.create_empty_buffer();
+            buffer.write().options_mut().buftype = "quickfix".into();
+            buffer
+        }
+    };
+    let id = v.split_bottom(buffer.clone(), height);
+    let stack = v.quickfix.stack_mut(which);
+    stack.window = Some(id);
+    stack.buffer = Some(buffer);
+    refresh(v, which);
+    Ok(())
+}
+
+/// `:cclose`, which closes the window of the list
+pub fn close(v: &mut VimInner, which: Which) -> Result<(), String> {
+    let Some(id) = v.quickfix.stack(which).and_then(|s| s.window) else {
+        return Ok(());
+    };
+    v.close_window(id)?;
+    v.quickfix.stack_mut(which).window = None;
+    Ok(())
+}
+
+/// `:cwindow [height]`, which opens the window when the list has errors, and closes it otherwise
+pub fn window(v: &mut VimInner, which: Which, height: &str) -> Result<(), String> {
+    let valid = current(v, which).is_ok_and(|l| l.entries.iter().any(|e| e.valid));
+    let open = v.quickfix.stack(which).and_then(|s| s.window).is_some();
+    match (valid, open) {
+        (true, false) => self::open(v, which, height),
+        (false, true) => close(v, which),
+        _ => Ok(()),
+    }
+}
+
+/// Jumps to the entry under the cursor, when Enter is pressed in a quickfix window. Returns
+/// whether the focused window is one.
+pub fn enter(v: &mut VimInner) -> bool {
+    let id = v.get_focus().buffer().id();
+    let shows = |s: &Stack| s.buffer.as_ref().is_some_and(|b| b.id() == id);
+    let which = if shows(&v.quickfix.list) {
+        Which::QuickFix
+    } else {
+        match v.quickfix.locations.iter().find(|(_, s)| shows(s)) {
+            Some((owner, _)) => Which::Location(*owner),
+            None => return false,
+        }
+    };
+    let row = v.get_focus().cursor().row();
+    let res = match current(v, which) {
+        Ok(list) if row < list.entries.len() => go_to(v, which, row),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        v.message(e);
+    }
+    true
+}
+
+/// `:colder [count]` and `:cnewer [count]`, which go to an older or newer list
+pub fn history(v: &mut VimInner, which: Which, older: bool, count: usize) -> Result<(), String> {
+    let stack = v.quickfix.stack_mut(which);
+    if older {
+        if stack.cur == 0 {
+            return Err("E380: At bottom of quickfix stack".into());
+        }
+        stack.cur = stack.cur.saturating_sub(count);
+    } else {
+        if stack.cur + 1 >= stack.lists.len() {
+            return Err("E381: At top of quickfix stack".into());
+        }
+        stack.cur = (stack.cur + count).min(stack.lists.len() - 1);
+    }
+    let message = match stack.list() {
+        Some(list) => format!(
+            "error list {} of {}; {} errors {}",
+            stack.cur + 1,
+            stack.lists.len(),
+            list.entries.len(),
+            list.title
+        ),
+        None => String::new(),
+    };
+    refresh(v, which);
+    v.message(message);
+    Ok(())
+}
+
+/// `:clist[!]`, which shows the valid entries, or all of them with `!`
+pub fn list(v: &mut VimInner, which: Which, all: bool) -> Result<(), String> {
+    let list = current(v, which)?;
+    let lines: Vec<_> = list
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| all || e.valid)
+        .map(|(i, e)| {
+            if !e.valid {
+                return format!("{:2} {}", i + 1, one_line(&e.text));
+            }
+            let mut pos = e.filename.clone().unwrap_or_default();
+            if e.lnum > 0 {
+                pos.push_str(&format!(":{}", e.lnum));
+                if e.col > 0 {
+                    pos.push_str(&format!(" col {}", e.col));
+                }
+            }
+            format!(
+                "{:2} {pos}{}: {}",
+                i + 1,
+                e.kind(),
+                one_line(e.text.trim_start())
+            )
+        })
+        .collect();
+    v.show_lines(lines);
+    Ok(())
+}
+
+/// `:[range]cdo {cmd}` and `:[range]cfdo {cmd}`, which run a command at each valid entry, or
+/// at the first one in each file
+pub fn each(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    which: Which,
+    files: bool,
+    range: CmdRange,
+    cmd: &str,
+) -> Result<(), String> {
+    let list = current(v, which)?;
+    let mut last_file = None;
+    let targets: Vec<_> = list
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.valid)
+        .filter(|(_, e)| {
+            let file = Some(&e.filename);
+            !files || std::mem::replace(&mut last_file, file) != file
+        })
+        .map(|(i, _)| i)
+        .collect();
+    let (first, last) = match range {
+        CmdRange::CurrentLine => (1, targets.len()),
+        CmdRange::Range { start, end } => (start, end),
+    };
+    for (n, idx) in targets.into_iter().enumerate() {
+        if (first..=last).contains(&(n + 1)) {
+            go_to(v, which, idx)?;
+            ctx.run(cmd, v).map_err(|e| format!("{e}"))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let entry = Entry {
+            filename: Some("a.c".into()),
+            lnum: 3,
+            col: 5,
+            kind: Some('E'),
+            text: "bad\n   thing".into(),
+            valid: true,
+            ..Entry::default()
+        };
+        assert_eq!(entry.line(), "a.c|3 col 5 error| bad thing");
+        let entry = Entry {
+            text: "other".into(),
+            ..Entry::default()
+        };
+        assert_eq!(entry.line(), "|| other");
+        let mut stack = Stack::default();
+        for n in 0..12 {
+            stack.set(Action::New, n.to_string(), vec![]);
+        }
+        assert_eq!((stack.len(), stack.nr()), (10, 10));
+        assert_eq!(stack.get(1).unwrap().title, "2");
+        stack.cur = 3;
+        stack.set(Action::New, "new".into(), vec![]);
+        assert_eq!((stack.len(), stack.nr()), (5, 5));
+    }
+}
@@ -0,0 +1,127 @@
+//
+// registers.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::collections::HashMap;
+
+/// The text of a register
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Register {
+    pub lines: Vec<String>,
+    /// Whether the text is made of whole lines, rather than characters
+    pub linewise: bool,
+}
+
+impl Register {
+    pub fn lines(lines: Vec<String>) -> Self {
+        Self {
+            lines,
+            linewise: true,
+        }
+    }
+
+    pub fn chars(text: &str) -> Self {
+        Self {
+            lines: text.split('\n').map(String::from).collect(),
+            linewise: false,
+        }
+    }
+
+    /// The contents as returned by `getreg()`, which end with a newline for whole lines
+    pub fn text(&self) -> String {
+        let mut ret = self.lines.join("\n");
+        if self.linewise {
+            ret.push('\n');
+        }
+        ret
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Registers {
+    map: HashMap<char, Register>,
+}
+
+impl Registers {
+    /// The register `name`, where uppercase names are the same as lowercase ones
+    pub fn get(&self, name: char) -> Option<&Register> {
+        self.map.get(&name.to_ascii_lowercase())
+    }
+
+    pub fn set(&mut self, name: char, reg: Register) {
+        self.map.insert(name.to_ascii_lowercase(), reg);
+    }
+
+    /// Stores `reg` in register `name`, where an uppercase name appends to it and `_` drops it.
+    /// The unnamed register gets the text as well.
+    fn store(&mut self, name: char, reg: Register) {
+        let reg = match (name, self.get(name)) {
+            ('_', _) => return,
+            ('A'..='Z', Some(old)) => {
+                let mut new = old.clone();
+                if old.linewise || reg.linewise {
+                    new.lines.extend(reg.lines);
+                    new.linewise = true;
+                } else {
+                    let mut lines = reg.lines.into_iter();
+                    if let (Some(last), Some(first)) = (new.lines.last_mut(), lines.next()) {
+                        last.push_str(&first);
+                    }
+                    new.lines.extend(lines);
+                }
+                new
+            }
+            _ => reg,
+        };
+        self.set('"', reg.clone());
+        self.set(name, reg);
+    }
+
+    /// Stores yanked text in `name`, or in `"0` when no register is given
+    pub fn yank(&mut self, name: Option<char>, reg: Register) {
+        self.store(name.unwrap_or('0'), reg);
+    }
+
+    /// Stores deleted text in `name`. Without a register, whole lines (or text that spans lines)
+    /// go to `"1` after shifting the numbered registers, and smaller deletes to `"-`.
+    pub fn delete(&mut self, name: Option<char>, reg: Register) {
+        match name {
+            Some(name) => self.store(name, reg),
+            None if reg.linewise || reg.lines.len() > 1 => {
+                for n in (1..9).rev() {
+                    let from = char::from_digit(n, 10).unwrap();
+                    if let Some(r) = self.map.remove(&from) {
+                        self.map.insert(char::from_digit(n + 1, 10).unwrap(), r);
+                    }
+                }
+                self.store('1', reg);
+            }
+            None => self.store('-', reg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbered() {
+        let mut regs = Registers::default();
+        regs.delete(None, Register::lines(vec!["a".into()]));
+        regs.delete(None, Register::lines(vec!["b".into()]));
+        regs.yank(None, Register::chars("c"));
+        regs.delete(Some('_'), Register::chars("d"));
+        assert_eq!(regs.get('1').unwrap().text(), "b\n");
+        assert_eq!(regs.get('2').unwrap().text(), "a\n");
+        assert_eq!(regs.get('0').unwrap().text(), "c");
+        assert_eq!(regs.get('"').unwrap().text(), "c");
+        regs.yank(Some('x'), Register::chars("e"));
+        regs.yank(Some('X'), Register::chars("f\ng"));
+        assert_eq!(regs.get('x').unwrap().text(), "ef\ng");
+        regs.yank(Some('X'), Register::lines(vec!["h".into()]));
+        assert_eq!(regs.get('x').unwrap().text(), "ef\ng\nh\n");
+    }
+}
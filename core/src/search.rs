@@ -0,0 +1,821 @@
+//
+// search.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::fmt::Display;
+use std::ops::Range;
+use std::sync::Arc;
+
+use vimscript::regex::{self, CharSet, Haystack, Match, Pos, Regex};
+use vimscript::State;
+
+use crate::cli::Cli;
+use crate::cursor::Motion;
+use crate::registers::Register;
+use crate::width::WidthOpts;
+use crate::window::View;
+use crate::VimInner;
+
+/// Where the cursor is put relative to a match, from the offset after a search pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offset {
+    /// `[+-]N`: lines below the match, in the first column
+    Line(isize),
+    /// `s[+-]N` or `b[+-]N`: characters after the start of the match
+    Start(isize),
+    /// `e[+-]N`: characters after the last character of the match
+    End(isize),
+}
+
+impl Default for Offset {
+    fn default() -> Self {
+        Self::Start(0)
+    }
+}
+
+impl Offset {
+    fn parse(s: &str) -> Result<Self, String> {
+        let num = |n: &str| match n {
+            "" => Ok(0),
+            "+" => Ok(1),
+            "-" => Ok(-1),
+            n => n
+                .strip_prefix('+')
+                .unwrap_or(n)
+                .parse()
+                .map_err(|_| format!("E488: Trailing characters: {s}")),
+        };
+        match s.as_bytes().first() {
+            None => Ok(Self::default()),
+            Some(b'e') => num(&s[1..]).map(Self::End),
+            Some(b's' | b'b') => num(&s[1..]).map(Self::Start),
+            Some(_) => num(s).map(Self::Line),
+        }
+    }
+}
+
+impl Display for Offset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::Start(0) => Ok(()),
+            Self::Start(n) => write!(f, "s{n:+}"),
+            Self::End(0) => write!(f, "e"),
+            Self::End(n) => write!(f, "e{n:+}"),
+            Self::Line(n) => write!(f, "{n:+}"),
+        }
+    }
+}
+
+/// One search of a search command, where `/foo/;?bar` searches twice
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub forward: bool,
+    /// The pattern, which is empty to use the last one
+    pub pattern: String,
+    /// `None` when the pattern is not closed by a delimiter, which keeps the last offset for an
+    /// empty pattern
+    pub offset: Option<Offset>,
+}
+
+/// The end of the collection `[...]` that starts at `start`, if it is closed
+fn collection_end(s: &str, start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    if s[i..].starts_with('^') {
+        i += 1;
+    }
+    // A `]` right after the `[` is part of the collection
+    if s[i..].starts_with(']') {
+        i += 1;
+    }
+    let mut chars = s[i..].char_indices();
+    while let Some((j, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            ']' => return Some(i + j),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Splits `s` at the first `delim` that is not escaped or in a collection
+pub fn split_delim(s: &str, delim: char) -> (&str, Option<&str>) {
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == delim {
+            return (&s[..i], Some(&s[i + c.len_utf8()..]));
+        } else if c == '[' {
+            if let Some(end) = collection_end(s, i) {
+                while chars.next().is_some_and(|(j, _)| j < end) {}
+            }
+        }
+    }
+    (s, None)
+}
+
+/// Parses the text typed after `/` (`forward`) or `?`
+pub fn parse(text: &str, forward: bool) -> Result<Vec<Step>, String> {
+    let mut steps = vec![];
+    let (mut rest, mut forward) = (text, forward);
+    loop {
+        let (pattern, after) = split_delim(rest, if forward { '/' } else { '?' });
+        let (offset, next) = match after.map(|a| a.split_once(';').ok_or(a)) {
+            None => (None, None),
+            Some(Ok((offset, next))) => (Some(Offset::parse(offset)?), Some(next)),
+            Some(Err(offset)) => (Some(Offset::parse(offset)?), None),
+        };
+        steps.push(Step {
+            forward,
+            pattern: pattern.to_string(),
+            offset,
+        });
+        let next = match next {
+            Some(next) => next,
+            None => return Ok(steps),
+        };
+        forward = match next.chars().next() {
+            Some('/') => true,
+            Some('?') => false,
+            _ => return Err("E386: Expected '?' or '/'  after ';'".into()),
+        };
+        rest = &next[1..];
+    }
+}
+
+/// Whether `pat` has an uppercase letter, other than in an item like `\S` or `\%V`
+fn has_upper(pat: &str) -> bool {
+    let mut chars = pat.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some('_' | '%') = chars.next() {
+                chars.next();
+            }
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether case is ignored when searching for `pat`. With 'smartcase', case is only ignored when
+/// the pattern has no uppercase letters.
+pub fn ignore_case(pat: &str, ignorecase: bool, smartcase: bool) -> bool {
+    ignorecase && !(smartcase && has_upper(pat))
+}
+
+/// Why a search did not find a match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotFound {
+    Pattern,
+    /// The search stopped at the end of the text, without 'wrapscan'
+    Bottom,
+    Top,
+}
+
+/// The byte `n` characters after `col` in `line`, or before it when `n` is negative
+fn move_chars(line: &str, col: usize, n: isize) -> usize {
+    let mut col = col.min(line.len());
+    for _ in 0..n.unsigned_abs() {
+        col = if n > 0 {
+            line.ceil_char_boundary(col + 1).min(line.len())
+        } else {
+            line.floor_char_boundary(col.saturating_sub(1))
+        };
+    }
+    col
+}
+
+/// The last character of a match, or its start when it is empty
+fn last_char<H: Haystack + ?Sized>(text: &H, m: &Match) -> Pos {
+    if m.is_empty() {
+        m.start
+    } else if m.end.col > 0 {
+        let line = text.line(m.end.line);
+        Pos::new(m.end.line, line.floor_char_boundary(m.end.col - 1))
+    } else {
+        // The match ends with the newline of the line before
+        Pos::new(m.end.line - 1, text.line(m.end.line - 1).len())
+    }
+}
+
+/// The position of a match that the search moves past, for an offset
+fn anchor<H: Haystack + ?Sized>(text: &H, m: &Match, offset: Offset) -> Pos {
+    match offset {
+        Offset::Line(_) => Pos::new(m.start.line, 0),
+        Offset::Start(_) => m.start,
+        Offset::End(_) => last_char(text, m),
+    }
+}
+
+/// Where the cursor goes for a match
+pub fn target<H: Haystack + ?Sized>(text: &H, m: &Match, offset: Offset) -> Pos {
+    match offset {
+        Offset::Line(n) => {
+            let line = (m.start.line as isize + n).clamp(0, text.line_count() as isize - 1);
+            Pos::new(line as usize, 0)
+        }
+        Offset::Start(n) => Pos::new(
+            m.start.line,
+            move_chars(text.line(m.start.line), m.start.col, n),
+        ),
+        Offset::End(n) => {
+            let last = last_char(text, m);
+            Pos::new(last.line, move_chars(text.line(last.line), last.col, n))
+        }
+    }
+}
+
+/// The anchor of the match that the cursor at `from` was put on by `offset`
+fn cursor_anchor<H: Haystack + ?Sized>(text: &H, from: Pos, offset: Offset) -> Pos {
+    match offset {
+        Offset::Line(n) => {
+            let line = (from.line as isize - n).clamp(0, text.line_count() as isize - 1);
+            Pos::new(line as usize, 0)
+        }
+        Offset::Start(n) | Offset::End(n) => {
+            Pos::new(from.line, move_chars(text.line(from.line), from.col, -n))
+        }
+    }
+}
+
+/// Every match that starts on `line`, including ones that overlap
+pub fn line_matches<H: Haystack + ?Sized>(
+    text: &H,
+    regex: &Regex,
+    line: usize,
+    ctx: &regex::Context,
+) -> Vec<Match> {
+    let len = text.line(line).len();
+    let mut ret = vec![];
+    let mut col = 0;
+    while col <= len {
+        match regex.find_on_line(text, line, col, ctx) {
+            Some(m) => {
+                col = move_chars(text.line(line), m.start.col, 1).max(m.start.col + 1);
+                ret.push(m);
+            }
+            None => break,
+        }
+    }
+    ret
+}
+
+/// The parts of `line` that are in a match of `regex`, for 'hlsearch'
+pub fn line_spans<H: Haystack + ?Sized>(
+    text: &H,
+    regex: &Regex,
+    line: usize,
+    ctx: &regex::Context,
+) -> Vec<Range<usize>> {
+    let len = text.line(line).len();
+    // Matches that start on the line before can continue on this one
+    let before = match line.checked_sub(1) {
+        Some(prev) => line_matches(text, regex, prev, ctx),
+        None => vec![],
+    };
+    before
+        .iter()
+        .chain(&line_matches(text, regex, line, ctx))
+        .filter(|m| m.end.line >= line)
+        .map(|m| {
+            let start = if m.start.line < line { 0 } else { m.start.col };
+            let end = if m.end.line > line { len } else { m.end.col };
+            start..end
+        })
+        .filter(|r| !r.is_empty())
+        .collect()
+}
+
+/// The first match after `after` (or the last before it), where matches are compared by their
+/// anchor. Returns the match and whether the search wrapped around the end of the text.
+fn find_one<H: Haystack + ?Sized>(
+    text: &H,
+    regex: &Regex,
+    ctx: &regex::Context,
+    after: Pos,
+    forward: bool,
+    offset: Offset,
+    wrapscan: bool,
+) -> Result<(Match, bool), NotFound> {
+    let lines = text.line_count();
+    let mut order: Vec<(usize, bool)> = if forward {
+        (after.line..lines).map(|l| (l, false)).collect()
+    } else {
+        (0..=after.line).rev().map(|l| (l, false)).collect()
+    };
+    if wrapscan {
+        if forward {
+            order.extend((0..=after.line).map(|l| (l, true)));
+        } else {
+            order.extend((after.line..lines).rev().map(|l| (l, true)));
+        }
+    }
+    for (line, wrapped) in order {
+        let matches = line_matches(text, regex, line, ctx);
+        let found = if forward {
+            matches
+                .into_iter()
+                .find(|m| wrapped || anchor(text, m, offset) > after)
+        } else {
+            matches
+                .into_iter()
+                .rev()
+                .find(|m| wrapped || anchor(text, m, offset) < after)
+        };
+        if let Some(m) = found {
+            return Ok((m, wrapped));
+        }
+    }
+    Err(match (wrapscan, forward) {
+        (true, _) => NotFound::Pattern,
+        (false, true) => NotFound::Bottom,
+        (false, false) => NotFound::Top,
+    })
+}
+
+/// Finds the `count`th match from the cursor at `from`, which `offset` put there for the match
+/// before. Returns the match and whether the search wrapped around the end of the text.
+#[allow(clippy::too_many_arguments)]
+pub fn find<H: Haystack + ?Sized>(
+    text: &H,
+    regex: &Regex,
+    ctx: &regex::Context,
+    from: Pos,
+    forward: bool,
+    offset: Offset,
+    count: usize,
+    wrapscan: bool,
+) -> Result<(Match, bool), NotFound> {
+    let mut after = cursor_anchor(text, from, offset);
+    let mut wrapped = false;
+    let mut found = None;
+    for _ in 0..count.max(1) {
+        let (m, w) = find_one(text, regex, ctx, after, forward, offset, wrapscan)?;
+        after = anchor(text, &m, offset);
+        wrapped |= w;
+        found = Some(m);
+    }
+    Ok((found.unwrap(), wrapped))
+}
+
+/// The keyword under or after `col`, or else the non-blank text there, for `*` and `#`. Returns
+/// where it starts, and whether it is a keyword.
+pub fn word_at<'a>(line: &'a str, col: usize, iskeyword: &CharSet) -> Option<(usize, &'a str, bool)> {
+    let col = col.min(line.len());
+    let word = |c: char| iskeyword.contains(c);
+    let text = |c: char| !c.is_whitespace();
+    for (class, keyword) in [(&word as &dyn Fn(char) -> bool, true), (&text, false)] {
+        let start = match line[col..].char_indices().find(|(_, c)| class(*c)) {
+            Some((0, _)) => line[..col]
+                .char_indices()
+                .rev()
+                .take_while(|(_, c)| class(*c))
+                .last()
+                .map_or(col, |(i, _)| i),
+            Some((i, _)) => col + i,
+            None => continue,
+        };
+        let end = line[start..]
+            .char_indices()
+            .find(|(_, c)| !class(*c))
+            .map_or(line.len(), |(i, _)| start + i);
+        return Some((start, &line[start..end], keyword));
+    }
+    None
+}
+
+/// A search command being typed with 'incsearch'
+#[derive(Debug)]
+struct Preview {
+    /// The view before the search, which is restored on every change
+    view: View,
+    /// The pattern typed so far
+    pattern: String,
+    /// The match the cursor was moved to
+    current: Option<(Pos, Pos)>,
+}
+
+/// The last search, which `n` and `N` repeat. Its pattern is in the `/` register.
+#[derive(Debug)]
+pub struct Search {
+    forward: bool,
+    offset: Offset,
+    /// Whether 'smartcase' is used for the pattern, which it is not after `*` and `#`
+    smartcase: bool,
+    /// Set by `:nohlsearch` until the next search
+    no_hlsearch: bool,
+    /// 'hlsearch' as of the last redraw, since setting it ends `:nohlsearch`
+    hlsearch: bool,
+    /// The highlighted pattern, with 'ignorecase' and 'magic' it was compiled with
+    highlight: Option<((String, bool, bool), Arc<Regex>)>,
+    preview: Option<Preview>,
+    /// The match `:s` is asking to replace
+    confirm: Option<(Pos, Pos)>,
+    /// The count typed before `/` or `?`
+    count: Option<usize>,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self {
+            forward: true,
+            offset: Offset::default(),
+            smartcase: true,
+            no_hlsearch: false,
+            hlsearch: false,
+            highlight: None,
+            preview: None,
+            confirm: None,
+            count: None,
+        }
+    }
+}
+
+pub fn last_pattern(v: &VimInner) -> Option<String> {
+    v.registers().get('/').map(Register::text)
+}
+
+/// Highlights the match `:s` is asking to replace, like the match of a search being typed
+pub fn set_confirm_match(v: &mut VimInner, current: Option<(Pos, Pos)>) {
+    v.search.confirm = current;
+}
+
+/// Compiles `pat` with the options of `v`, and 'smartcase' when `smartcase` is set
+pub fn compile(v: &VimInner, pat: &str, smartcase: bool) -> Result<Regex, String> {
+    let mut opts = v.regex_options();
+    opts.ignorecase = ignore_case(pat, opts.ignorecase, smartcase && v.options().smartcase);
+    Regex::new(pat, &opts)
+}
+
+/// What `\%#` and the like match in the focused window
+pub fn context(v: &VimInner) -> regex::Context {
+    let cursor = v.get_focus().cursor();
+    regex::Context {
+        cursor: Some(Pos::new(cursor.row(), cursor.col())),
+        tabstop: WidthOpts::new(v.options()).tabstop,
+        ..regex::Context::default()
+    }
+}
+
+/// Makes `pattern` the last search pattern, which is also highlighted again after `:nohlsearch`
+pub fn set_last_pattern(v: &mut VimInner, pattern: &str) {
+    v.registers_mut().set('/', Register::chars(pattern));
+    v.search.smartcase = true;
+    v.search.no_hlsearch = false;
+}
+
+/// Starts typing a search command, `/` when `forward` is set and `?` otherwise
+pub fn start(v: &mut VimInner, forward: bool) {
+    v.search.count = v.count();
+    v.start_cli(if forward { Cli::Search } else { Cli::SearchBack });
+}
+
+/// Searches for the last pattern and moves the cursor to the match. Returns whether there was
+/// a match.
+fn search_last(v: &mut VimInner, forward: bool, count: usize) -> bool {
+    let pattern = match last_pattern(v) {
+        Some(pattern) => pattern,
+        None => {
+            v.message("E35: No previous regular expression".into());
+            return false;
+        }
+    };
+    v.search.no_hlsearch = false;
+    let regex = match compile(v, &pattern, v.search.smartcase) {
+        Ok(regex) => regex,
+        Err(e) => {
+            v.message(e);
+            return false;
+        }
+    };
+    let offset = v.search.offset;
+    let ctx = context(v);
+    let win = v.get_focus();
+    let from = Pos::new(win.cursor().row(), win.cursor().col());
+    let found = win.buffer().with_read(|buf| {
+        find(&**buf, &regex, &ctx, from, forward, offset, count, v.options().wrapscan)
+            .map(|(m, wrapped)| (target(&**buf, &m, offset), wrapped))
+    });
+    let (pos, wrapped) = match found {
+        Ok(found) => found,
+        Err(e) => {
+            v.message(match e {
+                NotFound::Pattern => format!("E486: Pattern not found: {pattern}"),
+                NotFound::Bottom => format!("E385: Search hit BOTTOM without match for: {pattern}"),
+                NotFound::Top => format!("E384: Search hit TOP without match for: {pattern}"),
+            });
+            return false;
+        }
+    };
+    let win = v.get_focus_mut();
+    if win.options().foldopen.split(',').any(|o| o == "search" || o == "all") {
+        win.set_folds_in(pos.line..=pos.line, true, true);
+    }
+    win.cursor_apply(Motion::SetRow(pos.line));
+    win.cursor_apply(Motion::SetCol(pos.col));
+    let message = if wrapped && !v.options().shortmess.contains('s') {
+        if forward {
+            "search hit BOTTOM, continuing at TOP".to_string()
+        } else {
+            "search hit TOP, continuing at BOTTOM".to_string()
+        }
+    } else {
+        let delim = if forward { '/' } else { '?' };
+        match offset {
+            Offset::Start(0) => format!("{delim}{pattern}"),
+            offset => format!("{delim}{pattern}{delim}{offset}"),
+        }
+    };
+    v.message(message);
+    true
+}
+
+/// The line (counted from 1) of the first match of `pattern` after `line`, or before it when
+/// searching back, for a `/pat/` or `?pat?` address. The last search pattern is used when
+/// `pattern` is empty or missing.
+pub fn line_address(
+    v: &mut VimInner,
+    pattern: Option<&str>,
+    forward: bool,
+    line: usize,
+) -> Result<usize, String> {
+    let pattern = match pattern.filter(|p| !p.is_empty()) {
+        Some(pattern) => {
+            set_last_pattern(v, pattern);
+            pattern.to_string()
+        }
+        None => last_pattern(v).ok_or("E35: No previous regular expression")?,
+    };
+    let regex = compile(v, &pattern, true)?;
+    let ctx = context(v);
+    let wrapscan = v.options().wrapscan;
+    let found = v.get_focus().buffer().with_read(|b| {
+        let len = b.len();
+        // `line` counts from 1, so it is the index of the line after it
+        let mut lines: Box<dyn Iterator<Item = usize>> = if forward {
+            Box::new((line..len).chain((0..line.min(len)).filter(|_| wrapscan)))
+        } else {
+            let before = line.saturating_sub(1).min(len);
+            Box::new((0..before).rev().chain((before..len).rev().filter(|_| wrapscan)))
+        };
+        lines.find(|&l| regex.find_on_line(&**b, l, 0, &ctx).is_some())
+    });
+    found.map(|l| l + 1).ok_or_else(|| match (wrapscan, forward) {
+        (true, _) => format!("E486: Pattern not found: {pattern}"),
+        (false, true) => format!("E385: Search hit BOTTOM without match for: {pattern}"),
+        (false, false) => format!("E384: Search hit TOP without match for: {pattern}"),
+    })
+}
+
+/// Runs the search command typed after `/` (`forward`) or `?`
+pub fn command(v: &mut VimInner, forward: bool, text: &str) {
+    let count = v.search.count.take().unwrap_or(1);
+    let steps = match parse(text, forward) {
+        Ok(steps) => steps,
+        Err(e) => return v.message(e),
+    };
+    for (i, step) in steps.into_iter().enumerate() {
+        v.search.offset = match step.offset {
+            Some(offset) => offset,
+            None if step.pattern.is_empty() => v.search.offset,
+            None => Offset::default(),
+        };
+        if !step.pattern.is_empty() {
+            set_last_pattern(v, &step.pattern);
+        }
+        v.search.forward = step.forward;
+        // Only the first search uses the count, the rest start from its match
+        if !search_last(v, step.forward, if i == 0 { count } else { 1 }) {
+            return;
+        }
+    }
+}
+
+/// `n` and `N` (`reverse`), which repeat the last search
+pub fn repeat(v: &mut VimInner, reverse: bool) {
+    let count = v.count().unwrap_or(1);
+    search_last(v, v.search.forward != reverse, count);
+}
+
+/// `*` and `#`, which search for the keyword under the cursor, and `g*` and `g#`, which also find
+/// it inside other words
+pub fn star(v: &mut VimInner, forward: bool, whole: bool) {
+    let iskeyword = v.regex_options().iskeyword;
+    let win = v.get_focus();
+    let cursor = win.cursor();
+    let found = win.buffer().with_read(|buf| {
+        word_at(buf[cursor.row()].text(), cursor.col(), &iskeyword)
+            .map(|(start, word, keyword)| (start, word.to_string(), keyword))
+    });
+    let (start, word, keyword) = match found {
+        Some(found) => found,
+        None => return v.message("E348: No string under cursor".into()),
+    };
+    let mut pattern = String::new();
+    for c in word.chars() {
+        if "\\/.*$^~[".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    if whole && keyword {
+        pattern = format!("\\<{pattern}\\>");
+    }
+    v.registers_mut().set('/', Register::chars(&pattern));
+    v.search.forward = forward;
+    v.search.offset = Offset::default();
+    v.search.smartcase = false;
+    // The search starts at the start of the word, so `#` does not find the word itself
+    v.get_focus_mut().cursor_apply(Motion::SetCol(start));
+    let count = v.count().unwrap_or(1);
+    search_last(v, forward, count);
+}
+
+/// `:nohlsearch`, which stops highlighting matches until the next search
+pub fn nohlsearch(v: &mut VimInner) {
+    v.search.no_hlsearch = true;
+}
+
+/// Moves the cursor to the match of the search command being typed, with 'incsearch'
+pub fn preview(v: &mut VimInner) {
+    let forward = match v.cli.ty() {
+        Cli::Search => true,
+        Cli::SearchBack => false,
+        _ => return,
+    };
+    if !v.options().incsearch {
+        return;
+    }
+    let view = match &v.search.preview {
+        Some(preview) => preview.view,
+        None => v.get_focus().view(),
+    };
+    v.get_focus_mut().set_view(view);
+    let pattern = match parse(&v.cli.text(), forward) {
+        Ok(steps) => steps[0].pattern.clone(),
+        Err(_) => String::new(),
+    };
+    let mut current = None;
+    let regex = match pattern.as_str() {
+        "" => None,
+        pattern => compile(v, pattern, true).ok(),
+    };
+    if let Some(regex) = regex {
+        let count = v.search.count.unwrap_or(1);
+        let ctx = context(v);
+        let wrapscan = v.options().wrapscan;
+        let from = Pos::new(view.cursor.row(), view.cursor.col());
+        current = v.get_focus().buffer().with_read(|buf| {
+            find(&**buf, &regex, &ctx, from, forward, Offset::default(), count, wrapscan)
+                .ok()
+                .map(|(m, _)| (m.start, m.end))
+        });
+        if let Some((start, _)) = current {
+            let win = v.get_focus_mut();
+            win.set_folds_in(start.line..=start.line, true, true);
+            win.cursor_apply(Motion::SetRow(start.line));
+            win.cursor_apply(Motion::SetCol(start.col));
+        }
+    }
+    v.search.preview = Some(Preview {
+        view,
+        pattern,
+        current,
+    });
+}
+
+/// Ends the preview of a search command, and puts the cursor back
+pub fn end_preview(v: &mut VimInner) {
+    if let Some(preview) = v.search.preview.take() {
+        v.get_focus_mut().set_view(preview.view);
+    }
+}
+
+/// The compiled pattern that is highlighted: the one being typed, or the last one with
+/// 'hlsearch'
+fn highlight_regex(v: &mut VimInner) -> Option<Arc<Regex>> {
+    let (pattern, smartcase) = match &v.search.preview {
+        Some(preview) if !preview.pattern.is_empty() => (preview.pattern.clone(), true),
+        _ if v.search.no_hlsearch => return None,
+        _ => (last_pattern(v)?, v.search.smartcase),
+    };
+    let opts = v.options();
+    let ignorecase = ignore_case(&pattern, opts.ignorecase, smartcase && opts.smartcase);
+    let key = (pattern, ignorecase, opts.magic);
+    match &v.search.highlight {
+        Some((k, regex)) if *k == key => return Some(Arc::clone(regex)),
+        _ => (),
+    }
+    let regex = Arc::new(compile(v, &key.0, smartcase).ok()?);
+    v.search.highlight = Some((key, Arc::clone(&regex)));
+    Some(regex)
+}
+
+/// Highlights the matches of the search pattern in every window with 'hlsearch', and the match
+/// of the search being typed with 'incsearch'
+pub fn sync_highlight(v: &mut VimInner) {
+    let hlsearch = v.options().hlsearch;
+    if hlsearch && !v.search.hlsearch {
+        v.search.no_hlsearch = false;
+    }
+    v.search.hlsearch = hlsearch;
+    let regex = if hlsearch { highlight_regex(v) } else { None };
+    let current = v.search.preview.as_ref().and_then(|p| p.current).or(v.search.confirm);
+    let focus = v.get_focus().id();
+    v.for_each_window_mut(|w| {
+        let current = current.filter(|_| w.id() == focus);
+        w.set_search_highlight(regex.clone(), current);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_offsets() {
+        let step = |pattern: &str, forward, offset| Step {
+            forward,
+            pattern: pattern.to_string(),
+            offset,
+        };
+        assert_eq!(parse("foo", true), Ok(vec![step("foo", true, None)]));
+        assert_eq!(
+            parse("a\\/b/e+1", true),
+            Ok(vec![step("a\\/b", true, Some(Offset::End(1)))])
+        );
+        assert_eq!(
+            parse("[/]/-", true),
+            Ok(vec![step("[/]", true, Some(Offset::Line(-1)))])
+        );
+        assert_eq!(
+            parse("foo?b-2;/bar/", false),
+            Ok(vec![
+                step("foo", false, Some(Offset::Start(-2))),
+                step("bar", true, Some(Offset::Start(0))),
+            ])
+        );
+        assert!(parse("foo/;x", true).is_err());
+        assert_eq!(Offset::End(-1).to_string(), "e-1");
+        assert_eq!(Offset::Line(2).to_string(), "+2");
+    }
+
+    #[test]
+    fn smartcase() {
+        assert!(ignore_case("foo", true, true));
+        assert!(!ignore_case("Foo", true, true));
+        assert!(ignore_case("\\Sfoo\\%V", true, true));
+        assert!(ignore_case("Foo", true, false));
+        assert!(!ignore_case("foo", false, true));
+    }
+
+    #[test]
+    fn find_matches() {
+        let text = ["foo bar", "", "bar foo foo"];
+        let regex = Regex::new("foo", &regex::Options::default()).unwrap();
+        let ctx = regex::Context::default();
+        let find = |line, col, forward, offset, count, wrapscan| {
+            find(
+                &text[..],
+                &regex,
+                &ctx,
+                Pos::new(line, col),
+                forward,
+                offset,
+                count,
+                wrapscan,
+            )
+            .map(|(m, wrapped)| (target(&text[..], &m, offset), wrapped))
+        };
+        let start = Offset::default();
+        assert_eq!(find(0, 0, true, start, 1, true), Ok((Pos::new(2, 4), false)));
+        assert_eq!(find(0, 0, true, start, 2, true), Ok((Pos::new(2, 8), false)));
+        assert_eq!(find(2, 8, true, start, 1, true), Ok((Pos::new(0, 0), true)));
+        assert_eq!(find(2, 8, true, start, 1, false), Err(NotFound::Bottom));
+        assert_eq!(find(2, 4, false, start, 1, true), Ok((Pos::new(0, 0), false)));
+        assert_eq!(find(0, 0, false, start, 1, false), Err(NotFound::Top));
+        // The cursor is moved past the match it was put after
+        let end = Offset::End(1);
+        assert_eq!(find(0, 0, true, end, 1, true), Ok((Pos::new(0, 3), false)));
+        assert_eq!(find(0, 3, true, end, 1, true), Ok((Pos::new(2, 7), false)));
+        let line = Offset::Line(-1);
+        assert_eq!(find(0, 0, true, line, 1, true), Ok((Pos::new(1, 0), false)));
+        assert_eq!(find(1, 0, true, line, 1, true), Ok((Pos::new(0, 0), true)));
+    }
+
+    #[test]
+    fn words() {
+        let isk = CharSet::parse("@,48-57,_", false).unwrap();
+        assert_eq!(word_at("foo.bar", 1, &isk), Some((0, "foo", true)));
+        assert_eq!(word_at("foo.bar", 3, &isk), Some((4, "bar", true)));
+        assert_eq!(word_at("a += 1", 1, &isk), Some((5, "1", true)));
+        assert_eq!(word_at("x  +=  ", 2, &isk), Some((3, "+=", false)));
+        assert_eq!(word_at("x   ", 2, &isk), None);
+    }
+}
@@ -0,0 +1,334 @@
+//
+// shell.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use vimscript::{Value, VimScriptCtx};
+
+use crate::edit;
+use crate::options::Options;
+use crate::util::plural;
+use crate::VimInner;
+
+/// The line given to 'shell' for `cmd`, quoted with 'shellquote' and 'shellxquote', where
+/// `redir` is added after the command, inside 'shellxquote'
+fn command_line(opts: &Options, cmd: &str, redir: &str) -> String {
+    let (open, close) = match opts.shellxquote.as_str() {
+        "(" => ("(", ")"),
+        "\"(" => ("\"(", ")\""),
+        quote => (quote, quote),
+    };
+    let mut escaped = String::new();
+    for c in cmd.chars() {
+        // 'shellxescape' only applies when 'shellxquote' is a parenthesis
+        if open.ends_with('(') && opts.shellxescape.contains(c) {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    format!("{open}{q}{escaped}{q}{redir}{close}", q = opts.shellquote)
+}
+
+fn shell_command(opts: &Options, cmd: &str, redir: &str) -> process::Command {
+    let mut words = opts.shell.split_whitespace();
+    let mut command = process::Command::new(words.next().unwrap_or("sh"));
+    command
+        .args(words)
+        .args(opts.shellcmdflag.split_whitespace())
+        .arg(command_line(opts, cmd, redir));
+    command
+}
+
+/// Redirects the output to `file` with 'shellredir', or 'shellpipe' for `:make`, where `%s` is
+/// replaced by the file name, or it is added at the end
+pub fn redirect(redir: &str, file: &Path) -> String {
+    let file = file.display().to_string();
+    if redir.contains("%s") {
+        format!(" {}", redir.replace("%s", &file))
+    } else {
+        format!(" {redir} {file}")
+    }
+}
+
+/// A new file name in the temporary directory
+pub fn temp_file() -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNT.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("vim-{}-{n}", process::id()))
+}
+
+/// Runs `cmd` with its input and output in temporary files, which is used with 'shelltemp'
+fn with_temp(opts: &Options, cmd: &str, input: Option<&str>) -> io::Result<(String, i32)> {
+    let output = temp_file();
+    let mut redir = String::new();
+    let input = match input {
+        Some(text) => {
+            let file = temp_file();
+            fs::write(&file, text)?;
+            redir.push_str(&format!(" <{}", file.display()));
+            Some(file)
+        }
+        None => None,
+    };
+    redir.push_str(&redirect(&opts.shellredir, &output));
+    // The parentheses make the redirection apply to all of a command like `a; b`
+    let status = shell_command(opts, &format!("({cmd})"), &redir)
+        .stdin(Stdio::null())
+        .status();
+    let text = fs::read(&output).unwrap_or_default();
+    let _ = fs::remove_file(&output);
+    if let Some(input) = input {
+        let _ = fs::remove_file(input);
+    }
+    Ok((
+        String::from_utf8_lossy(&text).into_owned(),
+        status?.code().unwrap_or(-1),
+    ))
+}
+
+/// Runs `cmd` with pipes for its input and output
+fn with_pipes(opts: &Options, cmd: &str, input: Option<&str>) -> io::Result<(String, i32)> {
+    let mut child = shell_command(opts, cmd, "")
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Writing on another thread keeps a command that writes before it reads from blocking
+    if let (Some(text), Some(mut stdin)) = (input, child.stdin.take()) {
+        let text = text.to_string();
+        thread::spawn(move || stdin.write_all(text.as_bytes()));
+    }
+    let output = child.wait_with_output()?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok((text, output.status.code().unwrap_or(-1)))
+}
+
+fn set_shell_error(ctx: &mut VimScriptCtx<VimInner>, status: i32) {
+    let _ = ctx.insert_var("v:shell_error", Value::Integer(status as isize));
+}
+
+/// Runs `cmd` with `input` as its input, and returns what it wrote, setting `v:shell_error` to
+/// its exit status
+pub fn run(
+    v: &VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    cmd: &str,
+    input: Option<&str>,
+) -> Result<String, String> {
+    let opts = v.options();
+    let res = match opts.shelltemp {
+        true => with_temp(opts, cmd, input),
+        false => with_pipes(opts, cmd, input),
+    };
+    let (text, status) = res.map_err(|e| format!("E282: Cannot execute {}: {e}", opts.shell))?;
+    set_shell_error(ctx, status);
+    Ok(text)
+}
+
+/// Replaces `!` in the command of `:!` with the previous command, and `%` with the file name,
+/// unless they are escaped with a backslash. The result is the previous command for the next
+/// one.
+pub fn expand(v: &mut VimInner, cmd: &str) -> Result<String, String> {
+    let ret = expand_with(v, cmd, true)?;
+    v.shell_cmd = Some(ret.clone());
+    Ok(ret)
+}
+
+/// Replaces `%` with the file name, for the arguments of commands like `:make`
+pub fn expand_file(v: &VimInner, cmd: &str) -> Result<String, String> {
+    expand_with(v, cmd, false)
+}
+
+fn expand_with(v: &VimInner, cmd: &str, bang: bool) -> Result<String, String> {
+    let mut ret = String::new();
+    let mut chars = cmd.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('!') if bang => ret.push('!'),
+                Some('%') => ret.push('%'),
+                Some(c) => {
+                    ret.push('\\');
+                    ret.push(c);
+                }
+                None => ret.push('\\'),
+            },
+            '!' if bang => ret.push_str(v.shell_cmd.as_deref().ok_or("E34: No previous command")?),
+            '%' => {
+                let name = v
+                    .get_focus()
+                    .buffer()
+                    .read()
+                    .filename()
+                    .map(|p| p.display().to_string());
+                ret.push_str(&name.ok_or("E499: Empty file name for '%' or '#'")?);
+            }
+            c => ret.push(c),
+        }
+    }
+    Ok(ret)
+}
+
+/// `:!cmd`, which runs the command on the terminal, and waits for a key after it
+pub fn execute(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    cmd: &str,
+) -> Result<(), String> {
+    let cmd = expand(v, cmd)?;
+    terminal(v, ctx, &cmd, "")
+}
+
+/// Runs `cmd` on the terminal with `redir` after it, and waits for a key after it, setting
+/// `v:shell_error` to its exit status
+pub fn terminal(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    cmd: &str,
+    redir: &str,
+) -> Result<(), String> {
+    let mut command = shell_command(v.options(), cmd, redir);
+    let cmd = format!("{cmd}{redir}");
+    let status = v
+        .suspend(true, move || {
+            println!(":!{cmd}");
+            let status = command.status().map(|s| s.code().unwrap_or(-1));
+            match &status {
+                Ok(0) => (),
+                Ok(code) => println!("\nshell returned {code}"),
+                Err(e) => println!("\n{e}"),
+            }
+            status
+        })
+        .map_err(|e| e.to_string())?;
+    set_shell_error(ctx, status.unwrap_or(-1));
+    Ok(())
+}
+
+/// `:{range}!cmd`, which replaces the lines `first` to `last` with the output of the command,
+/// given them as its input
+pub fn filter(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    (first, last): (usize, usize),
+    cmd: &str,
+) -> Result<(), String> {
+    let cmd = expand(v, cmd)?;
+    let input: String = v.get_focus().buffer().with_read(|b| {
+        (first..=last)
+            .map(|l| format!("{}\n", b[l].text()))
+            .collect()
+    });
+    let output = run(v, ctx, &cmd, Some(&input))?;
+    let lines: Vec<_> = output.lines().map(String::from).collect();
+    let n = lines.len();
+    // Replacing every line with no output leaves an empty line
+    v.get_focus()
+        .buffer()
+        .write()
+        .replace_lines(first..last + 1, lines);
+    edit::set_cursor(v, first);
+    edit::report(v, n, format!("{} filtered", plural(n, "line", "lines")));
+    Ok(())
+}
+
+/// `:shell`, which starts 'shell' on the terminal
+pub fn shell(v: &mut VimInner) -> Result<(), String> {
+    let mut words = v.options().shell.split_whitespace();
+    let mut command = process::Command::new(words.next().unwrap_or("sh"));
+    command.args(words);
+    v.suspend(false, move || command.status())
+        .and_then(|status| status)
+        .map(|_| ())
+        .map_err(|e| format!("E282: Cannot execute {}: {e}", v.options().shell))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Opts;
+
+    #[test]
+    fn command_lines() {
+        let mut opts = Options::new();
+        assert_eq!(command_line(&opts, "ls", ""), "ls");
+        let redir = redirect(&opts.shellredir, Path::new("out"));
+        assert_eq!(command_line(&opts, "(ls)", &redir), "(ls) >out 2>&1");
+        assert_eq!(redirect("| tee", Path::new("out")), " | tee out");
+        opts.shellquote = "'".into();
+        opts.shellxquote = "\"(".into();
+        opts.shellxescape = "&".into();
+        assert_eq!(command_line(&opts, "a&b", " >x"), "\"('a^&b' >x)\"");
+    }
+
+    #[test]
+    fn expands() {
+        let (mut v, _ctx) = VimInner::with_lines(&[""]);
+        let err = |e: &str| Err(e.to_string());
+        assert_eq!(
+            expand_with(&v, "ls !", true),
+            err("E34: No previous command")
+        );
+        assert_eq!(
+            expand_with(&v, "wc %", true),
+            err("E499: Empty file name for '%' or '#'")
+        );
+        // `!` is only special for `:!`
+        assert_eq!(expand_with(&v, "a!b \\!", false), Ok("a!b \\!".into()));
+        v.shell_cmd = Some("ls".into());
+        assert_eq!(expand_with(&v, "! -l", true), Ok("ls -l".into()));
+        assert_eq!(
+            expand_with(&v, "echo \\! \\% \\n", true),
+            Ok("echo ! % \\n".into())
+        );
+        let buffer = v.edit("Cargo.toml").unwrap();
+        v.get_focus_mut().set_buffer(buffer);
+        assert_eq!(expand_with(&v, "wc %", true), Ok("wc Cargo.toml".into()));
+    }
+
+    #[test]
+    fn filters() {
+        let opts = Options::new();
+        let input = "a\nb c\n";
+        assert_eq!(
+            with_pipes(&opts, "cat", Some(input)).unwrap(),
+            (input.into(), 0)
+        );
+        assert_eq!(
+            with_temp(&opts, "cat", Some(input)).unwrap(),
+            (input.into(), 0)
+        );
+        assert_eq!(with_pipes(&opts, "exit 3", None).unwrap(), ("".into(), 3));
+        assert_eq!(with_temp(&opts, "exit 3", None).unwrap(), ("".into(), 3));
+    }
+
+    #[test]
+    fn filters_lines() {
+        let (mut v, mut ctx) = VimInner::with_lines(&["b", "a", "b", "c", "a"]);
+        let text = |v: &VimInner| {
+            let b = v.get_focus().buffer().read();
+            (0..b.len())
+                .map(|l| b[l].text().to_string())
+                .collect::<Vec<_>>()
+        };
+        ctx.run("%!sort -u", &mut v).unwrap();
+        assert_eq!(text(&v), ["a", "b", "c"]);
+        assert_eq!(v.get_message(), "3 lines filtered");
+        ctx.run("%!true", &mut v).unwrap();
+        assert_eq!(text(&v), [""]);
+    }
+}
@@ -0,0 +1,636 @@
+//
+// substitute.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use vimscript::regex::{Match, Regex};
+use vimscript::{State, Value, VimScriptCtx};
+
+use crate::cursor::Motion;
+use crate::search;
+use crate::util::plural;
+use crate::window::{Dist, Scroll};
+use crate::VimInner;
+
+/// The flags after `:s/pat/rep/`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    /// `g`: every match in a line, rather than the first
+    global: bool,
+    /// `c`: ask before each replacement
+    confirm: bool,
+    /// `e`: no error when nothing matches
+    no_error: bool,
+    /// `i` and `I`, which override 'ignorecase'
+    ignorecase: Option<bool>,
+    /// `n`: count the matches instead of replacing them
+    count: bool,
+    /// `r`: `:&` and `:s` without a pattern use the last search pattern
+    last_search: bool,
+}
+
+/// Which command is running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// `:s`, which can be given a new pattern and replacement
+    Substitute,
+    /// `:&`, which uses the last ones
+    Repeat,
+    /// `:~`, which uses the last replacement with the last search pattern
+    RepeatSearch,
+}
+
+/// The last substitution, which `:&` and `&` repeat
+#[derive(Debug, Default)]
+pub struct Substitute {
+    pattern: Option<String>,
+    /// The last replacement, with `~` already replaced by the one before it
+    replacement: Option<String>,
+    flags: Flags,
+    /// The text of `\0` to `\9` in the match being replaced, for `submatch()`
+    groups: Vec<Option<String>>,
+    /// Substitutions (or matches with `n`) and lines under `:global`, reported at the end
+    totals: Option<(usize, usize, bool)>,
+}
+
+impl Substitute {
+    /// The pattern of the last substitution, for `\&` and `:&`
+    pub fn last_pattern(&self) -> Option<&str> {
+        self.pattern.as_deref()
+    }
+
+    /// The replacement of the last substitution, which `~` stands for
+    pub fn last_replacement(&self) -> &str {
+        self.replacement.as_deref().unwrap_or_default()
+    }
+
+    /// The text of group `n` of the match being replaced by a `\=` expression
+    pub fn submatch(&self, n: usize) -> Option<&str> {
+        self.groups.get(n)?.as_deref()
+    }
+}
+
+/// The pattern and replacement given to `:s`
+type Given<'a> = Option<(&'a str, &'a str)>;
+
+/// Splits the arguments of `:s` into the pattern and replacement, if there are any, and the rest
+fn split_args(args: &str) -> Result<(Given<'_>, &str), String> {
+    let delim = match args.chars().next() {
+        Some(c) if !c.is_whitespace() && !"0123456789cegriIp|\"".contains(c) => c,
+        _ => return Ok((None, args)),
+    };
+    if delim.is_alphanumeric() || delim == '\\' {
+        return Err("E146: Regular expressions can't be delimited by letters".into());
+    }
+    let (pattern, rest) = search::split_delim(&args[delim.len_utf8()..], delim);
+    let rest = match rest {
+        Some(rest) => rest,
+        None => return Ok((Some((pattern, "")), "")),
+    };
+    // Only escaped characters are skipped in the replacement
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == delim {
+            return Ok((Some((pattern, &rest[..i])), &rest[i + c.len_utf8()..]));
+        }
+    }
+    Ok((Some((pattern, rest)), ""))
+}
+
+/// Parses the flags and count after the pattern, where `&` keeps the `last` flags
+fn parse_flags(s: &str, last: Flags, gdefault: bool) -> Result<(Flags, Option<usize>), String> {
+    let (mut flags, s) = match s.strip_prefix('&') {
+        Some(rest) => (last, rest),
+        None => (
+            Flags {
+                global: gdefault,
+                ..Flags::default()
+            },
+            s,
+        ),
+    };
+    let end = s
+        .find(|c: char| !"gceiInr#lp".contains(c))
+        .unwrap_or(s.len());
+    for c in s[..end].chars() {
+        match c {
+            'g' => flags.global = !flags.global,
+            'c' => flags.confirm = true,
+            'e' => flags.no_error = true,
+            'i' => flags.ignorecase = Some(true),
+            'I' => flags.ignorecase = Some(false),
+            'n' => flags.count = true,
+            'r' => flags.last_search = true,
+            // Printing the last line is left to `:p`
+            _ => (),
+        }
+    }
+    let s = s[end..].trim_start();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let count = match &s[..digits] {
+        "" => None,
+        n => match n.parse() {
+            Ok(0) | Err(_) => return Err("E939: Positive count required".into()),
+            Ok(n) => Some(n),
+        },
+    };
+    match s[digits..].trim() {
+        "" => Ok((flags, count)),
+        rest => Err(format!("E488: Trailing characters: {rest}")),
+    }
+}
+
+/// Replaces `~` in `rep` with the `last` replacement (`\~` without 'magic')
+fn tilde(rep: &str, last: &str, magic: bool) -> String {
+    let mut ret = String::new();
+    let mut chars = rep.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('~') if !magic => ret.push_str(last),
+                Some(c) => {
+                    ret.push('\\');
+                    ret.push(c);
+                }
+                None => ret.push('\\'),
+            },
+            '~' if magic => ret.push_str(last),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+impl Case {
+    fn apply(self, c: char, to: &mut String) {
+        match self {
+            Self::Upper => to.extend(c.to_uppercase()),
+            Self::Lower => to.extend(c.to_lowercase()),
+        }
+    }
+}
+
+/// A part of a replacement string
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Char(char),
+    /// `\0` to `\9`, where `&` is `\0`
+    Group(usize),
+    /// `\r`, which splits the line
+    Break,
+    /// `\u` and `\l`, which change the next character
+    One(Case),
+    /// `\U` and `\L`, which change the characters up to `\e` or `\E`
+    All(Case),
+    End,
+}
+
+fn parse_replacement(rep: &str, magic: bool) -> Vec<Piece> {
+    let mut ret = vec![];
+    let mut chars = rep.chars();
+    while let Some(c) = chars.next() {
+        ret.push(match c {
+            '\\' => match chars.next() {
+                Some(n @ '0'..='9') => Piece::Group(n as usize - '0' as usize),
+                Some('&') if !magic => Piece::Group(0),
+                Some('n') => Piece::Char('\0'),
+                Some('r') => Piece::Break,
+                Some('t') => Piece::Char('\t'),
+                Some('u') => Piece::One(Case::Upper),
+                Some('l') => Piece::One(Case::Lower),
+                Some('U') => Piece::All(Case::Upper),
+                Some('L') => Piece::All(Case::Lower),
+                Some('e' | 'E') => Piece::End,
+                Some(c) => Piece::Char(c),
+                None => Piece::Char('\\'),
+            },
+            '&' if magic => Piece::Group(0),
+            '\r' => Piece::Break,
+            c => Piece::Char(c),
+        });
+    }
+    ret
+}
+
+/// Adds `c` to `line`, changing its case for `\u` (`one`) or `\U` (`all`)
+fn push(line: &mut String, c: char, one: &mut Option<Case>, all: Option<Case>) {
+    match one.take().or(all) {
+        Some(case) => case.apply(c, line),
+        None => line.push(c),
+    }
+}
+
+/// The lines that replace a match with `groups`
+fn expand(pieces: &[Piece], groups: &[Option<String>]) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    let (mut one, mut all) = (None, None);
+    for piece in pieces {
+        match piece {
+            Piece::Char(c) => push(lines.last_mut().unwrap(), *c, &mut one, all),
+            Piece::Group(n) => {
+                let text = groups
+                    .get(*n)
+                    .and_then(Option::as_deref)
+                    .unwrap_or_default();
+                for c in text.chars() {
+                    if c == '\n' {
+                        lines.push(String::new());
+                    } else {
+                        push(lines.last_mut().unwrap(), c, &mut one, all);
+                    }
+                }
+            }
+            Piece::Break => lines.push(String::new()),
+            Piece::One(case) => one = Some(*case),
+            Piece::All(case) => all = Some(*case),
+            Piece::End => all = None,
+        }
+    }
+    lines
+}
+
+/// The lines that replace a match from the result of a `\=` expression, where a list is one item
+/// per line
+fn expr_lines(value: &Value, ctx: &VimScriptCtx<VimInner>) -> Vec<String> {
+    let text = match value {
+        Value::List(items) => {
+            let items = items.lock().unwrap();
+            items
+                .iter()
+                .map(|i| i.to_string(ctx))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        value => value.to_string(ctx),
+    };
+    text.split(['\n', '\r']).map(String::from).collect()
+}
+
+/// What to do with a match, when asked with the `c` flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    /// This match and every one after it
+    All,
+    Quit,
+    /// This match, and then stop
+    Last,
+}
+
+/// Asks whether to replace `m` with `replacement`, with the match highlighted
+fn confirm(v: &mut VimInner, m: &Match, replacement: &str) -> Result<Answer, String> {
+    let win = v.get_focus_mut();
+    win.set_folds_in(m.start.line..=m.start.line, true, true);
+    win.cursor_apply(Motion::SetRow(m.start.line));
+    win.cursor_apply(Motion::SetCol(m.start.col));
+    search::set_confirm_match(v, Some((m.start, m.end)));
+    let answer = loop {
+        v.message(format!("replace with {replacement} (y/n/a/q/l/^E/^Y)?"));
+        let key = v.wait_key().map_err(|e| e.to_string())?;
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('e') if ctrl => v.get_focus_mut().scroll(Scroll::Down, Dist::One),
+            KeyCode::Char('y') if ctrl => v.get_focus_mut().scroll(Scroll::Up, Dist::One),
+            KeyCode::Char('y') => break Answer::Yes,
+            KeyCode::Char('n') => break Answer::No,
+            KeyCode::Char('a') => break Answer::All,
+            KeyCode::Char('q') | KeyCode::Esc => break Answer::Quit,
+            KeyCode::Char('l') => break Answer::Last,
+            _ => (),
+        }
+    };
+    search::set_confirm_match(v, None);
+    v.message(String::new());
+    Ok(answer)
+}
+
+/// The start of the character after `col`, or past the end of the line
+fn next_char(line: &str, col: usize) -> usize {
+    line[col..]
+        .chars()
+        .next()
+        .map_or(col + 1, |c| col + c.len_utf8())
+}
+
+/// Runs `:s` (or `:&` and `:~`, given by `kind`) on the lines from `first` to `last`
+pub fn command(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    (first, last): (usize, usize),
+    args: &str,
+    kind: Kind,
+) {
+    if let Err(e) = substitute(v, ctx, (first, last), args, kind) {
+        v.message(e);
+    }
+}
+
+fn substitute(
+    v: &mut VimInner,
+    ctx: &mut VimScriptCtx<VimInner>,
+    (first, last): (usize, usize),
+    args: &str,
+    kind: Kind,
+) -> Result<(), String> {
+    let magic = v.options().magic;
+    let (given, rest) = match kind {
+        Kind::Substitute => split_args(args.trim_start())?,
+        _ => (None, args.trim_start()),
+    };
+    let (flags, count) = parse_flags(rest, v.substitute.flags, v.options().gdefault)?;
+    let last_search = || v.registers().get('/').map(|r| r.text());
+    let (pattern, replacement) = match given {
+        Some((pattern, rep)) => {
+            let rep = if rep.starts_with("\\=") {
+                rep.to_string()
+            } else {
+                tilde(rep, v.substitute.last_replacement(), magic)
+            };
+            match pattern {
+                "" => (last_search(), rep),
+                pattern => (Some(pattern.to_string()), rep),
+            }
+        }
+        None => {
+            let pattern = match kind {
+                Kind::Substitute | Kind::Repeat if !flags.last_search => {
+                    v.substitute.pattern.clone()
+                }
+                _ => last_search(),
+            };
+            let rep = v.substitute.replacement.clone();
+            (pattern, rep.ok_or("E35: No previous regular expression")?)
+        }
+    };
+    let pattern = pattern.ok_or("E35: No previous regular expression")?;
+    search::set_last_pattern(v, &pattern);
+    v.substitute.pattern = Some(pattern.clone());
+    v.substitute.replacement = Some(replacement.clone());
+    v.substitute.flags = flags;
+
+    let buffer = v.get_focus().buffer().clone();
+    let len = buffer.read().len();
+    let (first, last) = match count {
+        Some(n) => (last, (last + n - 1).min(len - 1)),
+        None => (first, last),
+    };
+    let mut opts = v.regex_options();
+    opts.ignorecase = flags
+        .ignorecase
+        .unwrap_or_else(|| search::ignore_case(&pattern, opts.ignorecase, v.options().smartcase));
+    let regex = Regex::new(&pattern, &opts)?;
+    let re_ctx = search::context(v);
+    let expr = replacement.strip_prefix("\\=");
+    let pieces = parse_replacement(&replacement, magic);
+
+    let mut ask = flags.confirm;
+    // Matches found, and the ones replaced (or counted with `n`) and the lines they are on
+    let (mut found, mut done, mut lines) = (0, 0, 0);
+    let mut last_changed = None;
+    let mut end = last as isize;
+    let mut line = first;
+    'lines: while line as isize <= end && line < buffer.read().len() {
+        let mut col = 0;
+        // An empty match where the last replacement ended is skipped
+        let mut after = None;
+        let mut counted = false;
+        loop {
+            let m = buffer.with_read(|b| regex.find_on_line(&**b, line, col, &re_ctx));
+            let m = match m {
+                Some(m) if m.end.line < buffer.read().len() => m,
+                _ => break,
+            };
+            if m.is_empty() && after == Some(m.start.col) {
+                col = buffer.with_read(|b| next_char(b[line].text(), m.start.col));
+                after = None;
+                continue;
+            }
+            found += 1;
+            let answer = match (flags.count, ask) {
+                (true, _) => Answer::No,
+                (false, true) => confirm(v, &m, &replacement)?,
+                (false, false) => Answer::Yes,
+            };
+            if answer == Answer::Quit {
+                break 'lines;
+            }
+            if answer != Answer::No || flags.count {
+                done += 1;
+                if !counted {
+                    counted = true;
+                    lines += 1;
+                }
+            }
+            if answer == Answer::No {
+                col = if m.is_empty() || m.end.line > line {
+                    buffer.with_read(|b| next_char(b[line].text(), m.start.col))
+                } else {
+                    m.end.col
+                };
+                after = Some(col);
+                if !flags.global {
+                    break;
+                }
+                continue;
+            }
+            ask &= answer != Answer::All;
+            let groups: Vec<_> =
+                buffer.with_read(|b| (0..10).map(|n| m.group_text(&**b, n)).collect());
+            let text = match expr {
+                Some(expr) => {
+                    // `line('.')` is the line with the match
+                    v.get_focus_mut().cursor_apply(Motion::SetRow(line));
+                    v.substitute.groups = groups;
+                    let value = ctx.eval(expr, v);
+                    v.substitute.groups.clear();
+                    expr_lines(&value.map_err(|e| format!("{e}"))?, ctx)
+                }
+                None => expand(&pieces, &groups),
+            };
+            let (end_line, end_col) = buffer.with_write(|b| {
+                let mut text = text;
+                let first = text.first_mut().unwrap();
+                first.insert_str(0, &b[line].text()[..m.start.col]);
+                let last = text.len() - 1;
+                let end_col = text[last].len();
+                text[last].push_str(&b[m.end.line].text()[m.end.col..]);
+                let end_line = line + last;
+                end += last as isize - (m.end.line - line) as isize;
+                b.replace_lines(line..m.end.line + 1, text);
+                (end_line, end_col)
+            });
+            last_changed = Some(end_line);
+            let spans = m.end.line > line;
+            line = end_line;
+            col = end_col;
+            after = Some(end_col);
+            if answer == Answer::Last {
+                break 'lines;
+            }
+            if m.is_empty() {
+                col = buffer.with_read(|b| next_char(b[line].text(), col));
+            }
+            // The text after a match that ends on another line is searched like the next line
+            if spans {
+                if line as isize > end {
+                    break 'lines;
+                }
+                counted = false;
+                continue;
+            }
+            if !flags.global {
+                break;
+            }
+        }
+        line += 1;
+    }
+
+    if let Some((subs, on, count)) = v.substitute.totals.as_mut() {
+        *subs += done;
+        *on += lines;
+        *count = flags.count;
+        if let Some(line) = last_changed {
+            let col = buffer.read()[line].first_char();
+            let win = v.get_focus_mut();
+            win.cursor_apply(Motion::SetRow(line));
+            win.cursor_apply(Motion::SetCol(col));
+        }
+        return Ok(());
+    }
+    if found == 0 {
+        if !flags.no_error {
+            return Err(format!("E486: Pattern not found: {pattern}"));
+        }
+    } else if flags.count {
+        v.message(report(done, lines, true));
+    } else if let Some(line) = last_changed {
+        let col = buffer.read()[line].first_char();
+        let win = v.get_focus_mut();
+        win.cursor_apply(Motion::SetRow(line));
+        win.cursor_apply(Motion::SetCol(col));
+        if done > v.options().report.max(0) as usize {
+            v.message(report(done, lines, false));
+        }
+    }
+    Ok(())
+}
+
+fn report(done: usize, lines: usize, count: bool) -> String {
+    let on_lines = plural(lines, "line", "lines");
+    if count {
+        format!("{} on {on_lines}", plural(done, "match", "matches"))
+    } else {
+        format!("{} on {on_lines}", plural(done, "substitution", "substitutions"))
+    }
+}
+
+/// Starts counting the substitutions of the `:s` commands that `:global` runs, which don't
+/// report them or a missing match
+pub fn start_global(v: &mut VimInner) {
+    v.substitute.totals = Some((0, 0, false));
+}
+
+/// The report for the substitutions since `start_global`, when there are more than 'report'
+pub fn end_global(v: &mut VimInner) -> Option<String> {
+    let (done, lines, count) = v.substitute.totals.take()?;
+    (done > v.options().report.max(0) as usize || count && done > 0)
+        .then(|| report(done, lines, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args() {
+        assert_eq!(split_args("/a/b/g 3"), Ok((Some(("a", "b")), "g 3")));
+        assert_eq!(split_args("#a/b#c\\#d#"), Ok((Some(("a/b", "c\\#d")), "")));
+        assert_eq!(split_args("/[/]/x"), Ok((Some(("[/]", "x")), "")));
+        assert_eq!(split_args("/a"), Ok((Some(("a", "")), "")));
+        assert_eq!(split_args("g 2"), Ok((None, "g 2")));
+        assert!(split_args("xaxbx").is_err());
+        let (flags, count) = parse_flags("gc 4", Flags::default(), false).unwrap();
+        assert!(flags.global && flags.confirm && !flags.count);
+        assert_eq!(count, Some(4));
+        assert!(!parse_flags("g", Flags::default(), true).unwrap().0.global);
+        let (same, _) = parse_flags("&", flags, false).unwrap();
+        assert_eq!(same, flags);
+        assert!(parse_flags("g x", flags, false).is_err());
+    }
+
+    #[test]
+    fn replacement() {
+        let groups = [Some("ab\ncd".to_string()), Some("xy".to_string()), None];
+        let rep = |r: &str, magic| expand(&parse_replacement(r, magic), &groups);
+        assert_eq!(rep("<&>", true), ["<ab", "cd>"]);
+        assert_eq!(rep("&\\&", false), ["&ab", "cd"]);
+        assert_eq!(rep("\\u\\1-\\2-\\U\\1z\\ez", true), ["Xy--XYZz"]);
+        assert_eq!(rep("\\L\\uXYZ", true), ["Xyz"]);
+        assert_eq!(rep("a\\rb\\tc\\nd\\\\", true), ["a", "b\tc\0d\\"]);
+        assert_eq!(tilde("x~\\~", "[&]", true), "x[&]\\~");
+        assert_eq!(tilde("~\\~", "y", false), "~y");
+    }
+
+    /// Runs `:s{args}` on the lines in `range`, and gives the lines of the buffer after it
+    fn sub(
+        v: &mut VimInner,
+        ctx: &mut VimScriptCtx<VimInner>,
+        range: (usize, usize),
+        args: &str,
+    ) -> Vec<String> {
+        substitute(v, ctx, range, args, Kind::Substitute).unwrap();
+        let b = v.get_focus().buffer().read();
+        (0..b.len()).map(|l| b[l].text().to_string()).collect()
+    }
+
+    #[test]
+    fn substitutes() {
+        let (mut v, mut ctx) = VimInner::with_lines(&["a a a", "a"]);
+        assert_eq!(sub(&mut v, &mut ctx, (0, 0), "/a/b/"), ["b a a", "a"]);
+        assert_eq!(sub(&mut v, &mut ctx, (0, 1), "/a/c/g"), ["b c c", "c"]);
+        // An empty match right after a replacement isn't replaced
+        let (mut v, mut ctx) = VimInner::with_lines(&["abc"]);
+        assert_eq!(sub(&mut v, &mut ctx, (0, 0), "/b*/-/g"), ["-a-c-"]);
+        // The range grows with the lines that `\r` adds
+        let (mut v, mut ctx) = VimInner::with_lines(&["a,b", "c,d", "e,f"]);
+        let lines = sub(&mut v, &mut ctx, (0, 1), "/,/\\r/");
+        assert_eq!(lines, ["a", "b", "c", "d", "e,f"]);
+        assert_eq!(v.get_focus().cursor().row(), 3);
+    }
+
+    #[test]
+    fn counts() {
+        // A count is the number of lines from the last line of the range
+        let (mut v, mut ctx) = VimInner::with_lines(&["x", "x", "x", "x"]);
+        assert_eq!(
+            sub(&mut v, &mut ctx, (0, 1), "/x/y/ 2"),
+            ["x", "y", "y", "x"]
+        );
+        // `n` only counts the matches
+        let (mut v, mut ctx) = VimInner::with_lines(&["x x", "x"]);
+        assert_eq!(sub(&mut v, &mut ctx, (0, 1), "/x/y/gn"), ["x x", "x"]);
+        assert_eq!(v.cli.get_message(), "3 matches on 2 lines");
+    }
+
+    #[test]
+    fn one_undo_step() {
+        let (mut v, mut ctx) = VimInner::with_lines(&["a a", "b", "a"]);
+        assert_eq!(sub(&mut v, &mut ctx, (0, 2), "/a/x\\ry/g").len(), 6);
+        let buffer = v.get_focus().buffer().clone();
+        let mut b = buffer.write();
+        assert!(b.undo().is_some());
+        let text: Vec<_> = (0..b.len()).map(|l| b[l].text().to_string()).collect();
+        assert_eq!(text, ["a a", "b", "a"]);
+    }
+}
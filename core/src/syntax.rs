@@ -0,0 +1,1526 @@
+//
+// syntax.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+use vimscript::regex::{self, Regex};
+
+use crate::buffer::Line;
+use crate::fold::FoldLevel;
+use crate::highlight::Highlights;
+
+/// Where an offset in a pattern is counted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Anchor {
+    Start,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Offset {
+    anchor: Anchor,
+    chars: isize,
+}
+
+impl Offset {
+    fn apply(off: Option<Self>, text: &str, (start, end): (usize, usize), default: usize) -> usize {
+        let off = match off {
+            Some(off) => off,
+            None => return default,
+        };
+        let mut pos = match off.anchor {
+            Anchor::Start => start,
+            Anchor::End => end,
+        };
+        if off.chars > 0 {
+            for _ in 0..off.chars {
+                pos = text.ceil_char_boundary((pos + 1).min(text.len()));
+            }
+        } else {
+            for _ in 0..-off.chars {
+                pos = text.floor_char_boundary(pos.saturating_sub(1));
+            }
+        }
+        pos
+    }
+}
+
+/// The offsets after a pattern, like `ms=s+1`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Offsets {
+    ms: Option<Offset>,
+    me: Option<Offset>,
+    hs: Option<Offset>,
+    he: Option<Offset>,
+    rs: Option<Offset>,
+    re: Option<Offset>,
+}
+
+impl Offsets {
+    fn parse(s: &str) -> Result<Self, String> {
+        let mut ret = Self::default();
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            let err = || format!("E402: Garbage after pattern: {s}");
+            let (name, val) = part.split_once('=').ok_or_else(err)?;
+            if name == "lc" {
+                let n = val.parse().map_err(|_| err())?;
+                ret.ms = ret.ms.or(Some(Offset {
+                    anchor: Anchor::Start,
+                    chars: n,
+                }));
+                continue;
+            }
+            let anchor = match val.chars().next() {
+                Some('s' | 'b') => Anchor::Start,
+                Some('e') => Anchor::End,
+                _ => return Err(err()),
+            };
+            let chars = match &val[1..] {
+                "" => 0,
+                n => n.trim_start_matches('+').parse().map_err(|_| err())?,
+            };
+            let off = Some(Offset { anchor, chars });
+            match name {
+                "ms" => ret.ms = off,
+                "me" => ret.me = off,
+                "hs" => ret.hs = off,
+                "he" => ret.he = off,
+                "rs" => ret.rs = off,
+                "re" => ret.re = off,
+                _ => return Err(err()),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// A compiled pattern of a syntax item
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+    offsets: Offsets,
+}
+
+/// A match of a pattern in a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Found {
+    /// Where the regex match started, which is before `start` with `\zs`
+    from: usize,
+    start: usize,
+    end: usize,
+    /// The part that is highlighted
+    hs: usize,
+    he: usize,
+    /// Where the contained items of a region start, or where they end for an end pattern
+    body: usize,
+    /// The 'matchgroup' of a region start or end
+    group: Option<usize>,
+}
+
+impl Found {
+    fn empty(pos: usize) -> Self {
+        Self {
+            from: pos,
+            start: pos,
+            end: pos,
+            hs: pos,
+            he: pos,
+            body: pos,
+            group: None,
+        }
+    }
+}
+
+impl Pattern {
+    fn new(pat: &str, offsets: &str, ignorecase: bool) -> Result<Self, String> {
+        // Syntax patterns ignore 'ignorecase' and 'magic'
+        let opts = regex::Options {
+            ignorecase,
+            ..regex::Options::default()
+        };
+        Ok(Self {
+            regex: Regex::new(pat, &opts)?,
+            offsets: Offsets::parse(offsets)?,
+        })
+    }
+
+    /// Compiles a pattern that is used outside of syntax items, without offsets
+    pub fn search(pat: &str, ignorecase: bool) -> Result<Self, String> {
+        Self::new(pat, "", ignorecase)
+    }
+
+    /// The first match that starts at or after `from`
+    pub fn find_at(&self, text: &str, from: usize) -> Option<Range<usize>> {
+        self.find(text, from, false).map(|f| f.start..f.end)
+    }
+
+    /// The first match that starts at or after `from`. `end` selects the offsets of an end
+    /// pattern, where contained items end at the start of the match.
+    fn find(&self, text: &str, from: usize, end: bool) -> Option<Found> {
+        let m = self.regex.find_str(text, from)?;
+        let (from_pos, start, stop) = (m.origin.col, m.start.col, m.end.col);
+        let o = &self.offsets;
+        let ms = Offset::apply(o.ms, text, (start, stop), start);
+        let me = Offset::apply(o.me, text, (start, stop), stop).max(ms);
+        let hs = Offset::apply(o.hs, text, (ms, me), ms);
+        let he = Offset::apply(o.he, text, (ms, me), me).max(hs);
+        let body = if end {
+            Offset::apply(o.re, text, (ms, me), ms)
+        } else {
+            Offset::apply(o.rs, text, (ms, me), me)
+        };
+        Some(Found {
+            from: from_pos,
+            start: ms,
+            end: me,
+            hs,
+            he,
+            body,
+            group: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Keyword,
+    Match(Pattern),
+    Region {
+        start: Vec<(Pattern, Option<usize>)>,
+        skip: Option<Pattern>,
+        end: Vec<(Pattern, Option<usize>)>,
+    },
+}
+
+/// The groups given to `contains=`. For everything but `List`, the names are excluded.
+#[derive(Debug, Clone)]
+enum Contains {
+    All(Vec<String>),
+    Top(Vec<String>),
+    Contained(Vec<String>),
+    List(Vec<String>),
+}
+
+impl Contains {
+    fn parse(s: &str) -> Self {
+        let mut names: Vec<String> = s
+            .split(',')
+            .filter(|n| !n.is_empty())
+            .map(String::from)
+            .collect();
+        let first = names.first().map(|n| n.as_str());
+        match first {
+            Some("ALL") | Some("ALLBUT") => Self::All(names.split_off(1)),
+            Some("TOP") => Self::Top(names.split_off(1)),
+            Some("CONTAINED") => Self::Contained(names.split_off(1)),
+            Some("NONE") => Self::List(vec![]),
+            _ => Self::List(names),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Item {
+    group: usize,
+    kind: Kind,
+    contained: bool,
+    contains: Option<Contains>,
+    containedin: Vec<String>,
+    nextgroup: Vec<String>,
+    skipwhite: bool,
+    skipnl: bool,
+    skipempty: bool,
+    transparent: bool,
+    keepend: bool,
+    oneline: bool,
+    fold: bool,
+}
+
+/// How to find a state to start highlighting from, when the lines before it aren't known
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Sync {
+    fromstart: bool,
+    minlines: usize,
+    maxlines: usize,
+    /// Look for C comments, which are highlighted with this group
+    ccomment: Option<String>,
+}
+
+impl Default for Sync {
+    fn default() -> Self {
+        Self {
+            fromstart: true,
+            minlines: 1,
+            maxlines: 0,
+            ccomment: None,
+        }
+    }
+}
+
+/// An item that is being matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Frame {
+    item: usize,
+    /// The item whose `contains=` applies, which is the parent for a transparent item
+    scope: Option<usize>,
+    /// The end of a match item, which is always on the same line
+    end: Option<usize>,
+}
+
+/// The items that are being matched at the start of a line
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    stack: Vec<Frame>,
+    /// An item whose `nextgroup=` continues on this line
+    next: Option<usize>,
+}
+
+/// The items each item may contain, resolved from the group names
+#[derive(Debug)]
+struct Compiled {
+    top: Vec<usize>,
+    children: Vec<Option<Vec<usize>>>,
+    next: Vec<Vec<usize>>,
+}
+
+/// Syntax items of a buffer, and the highlighting they give to its lines
+#[derive(Debug, Default)]
+pub struct Syntax {
+    /// The 'syntax' that was loaded
+    name: String,
+    groups: Vec<String>,
+    group_ids: HashMap<String, usize>,
+    items: Vec<Item>,
+    keywords: HashMap<String, Vec<usize>>,
+    /// Keywords defined after `:syntax case ignore`, in lower case
+    keywords_icase: HashMap<String, Vec<usize>>,
+    clusters: HashMap<String, Vec<String>>,
+    ignorecase: bool,
+    sync: Sync,
+    compiled: Option<Compiled>,
+    /// Incremented when the items change
+    tick: usize,
+    /// Highlight group of each group, and the tick of the highlights they came from
+    hl_ids: Vec<Option<usize>>,
+    hl_tick: usize,
+    /// The state at the start of each line, and one past the last line. A line has been
+    /// highlighted if the state after it is known.
+    starts: Vec<Option<State>>,
+    folds: Vec<FoldLevel>,
+    /// The first and last line changed since they were highlighted
+    check: Option<(usize, usize)>,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Splits `args` at whitespace, keeping patterns like `start=/a b/` together
+fn split_args(args: &str) -> Result<Vec<(Option<&str>, &str)>, String> {
+    let mut ret = vec![];
+    let mut rest = args.trim_start();
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let (key, value_start) = if rest[key_end..].starts_with('=') {
+            (Some(&rest[..key_end]), key_end + 1)
+        } else {
+            (None, 0)
+        };
+        let value = &rest[value_start..];
+        let is_pattern = match key {
+            Some(k) => ["start", "skip", "end"].contains(&k),
+            None => value.starts_with(|c: char| !is_word(c) && c != '@'),
+        };
+        let len = if is_pattern {
+            pattern_len(value)?
+        } else {
+            value.find(char::is_whitespace).unwrap_or(value.len())
+        };
+        ret.push((key, &value[..len]));
+        rest = value[len..].trim_start();
+    }
+    Ok(ret)
+}
+
+/// The length of a delimited pattern and the offsets after it
+fn pattern_len(s: &str) -> Result<usize, String> {
+    let delim = s.chars().next().ok_or("E398: Missing '='")?;
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == delim {
+            let rest = &s[i + c.len_utf8()..];
+            return Ok(s.len() - rest.len() + rest.find(char::is_whitespace).unwrap_or(rest.len()));
+        }
+    }
+    Err(format!("E401: Pattern delimiter not found: {s}"))
+}
+
+/// Splits a delimited pattern into the pattern and its offsets
+pub fn split_pattern(s: &str) -> (&str, &str) {
+    let delim = s.chars().next().unwrap_or('/');
+    let body = &s[delim.len_utf8()..];
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == delim {
+            return (&body[..i], &body[i + c.len_utf8()..]);
+        }
+    }
+    (body, "")
+}
+
+/// Expands a keyword like `fu[nction]` into every word it matches
+fn expand_keyword(word: &str) -> Vec<String> {
+    match word.split_once('[') {
+        Some((head, tail)) => {
+            let tail = tail.trim_end_matches(']');
+            (0..=tail.chars().count())
+                .map(|n| format!("{head}{}", tail.chars().take(n).collect::<String>()))
+                .collect()
+        }
+        None => vec![word.to_string()],
+    }
+}
+
+impl Syntax {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Removes every item, for loading the syntax `name`
+    pub fn reset(&mut self, name: &str) {
+        *self = Self {
+            name: name.to_string(),
+            starts: std::mem::take(&mut self.starts),
+            folds: std::mem::take(&mut self.folds),
+            tick: self.tick + 1,
+            ..Self::default()
+        };
+        self.invalidate();
+    }
+
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The name of every group used by an item
+    pub fn groups(&self) -> &[String] {
+        &self.groups
+    }
+
+    fn group(&mut self, name: &str) -> usize {
+        if let Some(id) = self.group_ids.get(name) {
+            return *id;
+        }
+        self.groups.push(name.to_string());
+        self.group_ids
+            .insert(name.to_string(), self.groups.len() - 1);
+        self.groups.len() - 1
+    }
+
+    /// Forgets the highlighting of every line
+    fn invalidate(&mut self) {
+        self.starts.iter_mut().skip(1).for_each(|s| *s = None);
+        if let Some(first) = self.starts.first_mut() {
+            *first = Some(State::default());
+        }
+        self.check = None;
+    }
+
+    fn changed_items(&mut self) {
+        self.compiled = None;
+        self.tick += 1;
+        self.invalidate();
+    }
+
+    /// Runs a `:syntax` command, returning a message to show
+    pub fn command(&mut self, args: &str) -> Result<Option<String>, String> {
+        let args = args.trim();
+        let (cmd, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        match cmd {
+            "" | "list" => Ok(Some(self.list())),
+            "keyword" | "match" | "region" => self.define(cmd, rest),
+            "cluster" => self.cluster(rest),
+            "case" => match rest.trim() {
+                "match" => {
+                    self.ignorecase = false;
+                    Ok(None)
+                }
+                "ignore" => {
+                    self.ignorecase = true;
+                    Ok(None)
+                }
+                "" => Ok(Some(format!(
+                    "syntax case {}",
+                    if self.ignorecase { "ignore" } else { "match" }
+                ))),
+                _ => Err(format!("E390: Illegal argument: {rest}")),
+            },
+            "sync" => self.sync(rest),
+            "clear" => {
+                if rest.trim().is_empty() {
+                    let name = std::mem::take(&mut self.name);
+                    self.reset(&name);
+                } else {
+                    let remove: Vec<usize> = rest
+                        .split_whitespace()
+                        .filter_map(|g| self.group_ids.get(g).copied())
+                        .collect();
+                    let clusters: Vec<_> = rest
+                        .split_whitespace()
+                        .filter_map(|g| g.strip_prefix('@'))
+                        .collect();
+                    self.clusters.retain(|c, _| !clusters.contains(&c.as_str()));
+                    self.remove_items(|item| remove.contains(&item.group));
+                }
+                Ok(None)
+            }
+            _ => Err(format!("E410: Invalid :syntax subcommand: {cmd}")),
+        }
+    }
+
+    fn remove_items(&mut self, f: impl Fn(&Item) -> bool) {
+        let items = std::mem::take(&mut self.items);
+        let mut map = vec![None; items.len()];
+        for (i, item) in items.into_iter().enumerate() {
+            if !f(&item) {
+                map[i] = Some(self.items.len());
+                self.items.push(item);
+            }
+        }
+        for words in self
+            .keywords
+            .values_mut()
+            .chain(self.keywords_icase.values_mut())
+        {
+            *words = words.iter().filter_map(|i| map[*i]).collect();
+        }
+        self.keywords.retain(|_, w| !w.is_empty());
+        self.keywords_icase.retain(|_, w| !w.is_empty());
+        self.changed_items();
+    }
+
+    fn list(&self) -> String {
+        let mut ret = String::from("--- Syntax items ---");
+        for (id, name) in self.groups.iter().enumerate() {
+            let kinds: Vec<_> = self
+                .items
+                .iter()
+                .filter(|i| i.group == id)
+                .map(|i| match i.kind {
+                    Kind::Keyword => "keyword",
+                    Kind::Match(_) => "match",
+                    Kind::Region { .. } => "region",
+                })
+                .collect();
+            if !kinds.is_empty() {
+                write!(ret, "\n{name:<16}{}", kinds.join(" ")).unwrap();
+            }
+        }
+        for (name, groups) in self.clusters.iter() {
+            write!(ret, "\n@{name:<15}cluster={}", groups.join(",")).unwrap();
+        }
+        ret
+    }
+
+    /// Defines a keyword, match or region item from the arguments of the command
+    fn define(&mut self, kind: &str, args: &str) -> Result<Option<String>, String> {
+        let args = args.trim();
+        let (group, rest) = args
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("E399: Not enough arguments: {args}"))?;
+        let keyword = kind == "keyword";
+        let mut item = Item {
+            group: 0,
+            kind: Kind::Keyword,
+            contained: false,
+            contains: None,
+            containedin: vec![],
+            nextgroup: vec![],
+            skipwhite: false,
+            skipnl: false,
+            skipempty: false,
+            transparent: false,
+            keepend: false,
+            oneline: false,
+            fold: false,
+        };
+        let mut words = vec![];
+        let mut pattern = None;
+        let mut matchgroup = None;
+        let (mut start, mut skip, mut end) = (vec![], None, vec![]);
+        for (key, value) in split_args(rest)? {
+            match (key, value) {
+                (None, "contained") => item.contained = true,
+                (None, "oneline") => item.oneline = true,
+                (None, "fold") => item.fold = true,
+                (None, "transparent") => item.transparent = true,
+                (None, "keepend") => item.keepend = true,
+                (None, "skipwhite") => item.skipwhite = true,
+                (None, "skipnl") => item.skipnl = true,
+                (None, "skipempty") => item.skipempty = true,
+                (None, "display" | "extend" | "excludenl" | "concealends" | "conceal") => (),
+                (Some("contains"), v) => item.contains = Some(Contains::parse(v)),
+                (Some("containedin"), v) => {
+                    item.containedin = v.split(',').map(String::from).collect()
+                }
+                (Some("nextgroup"), v) => item.nextgroup = v.split(',').map(String::from).collect(),
+                (Some("cchar"), _) => (),
+                (Some("matchgroup"), v) => {
+                    matchgroup = match v {
+                        "" | "NONE" => None,
+                        v => Some(self.group(v)),
+                    }
+                }
+                (Some("start" | "skip" | "end"), v) if kind == "region" => {
+                    let (pat, offsets) = split_pattern(v);
+                    let pat = Pattern::new(pat, offsets, self.ignorecase)?;
+                    match key {
+                        Some("start") => start.push((pat, matchgroup)),
+                        Some("end") => end.push((pat, matchgroup)),
+                        _ => skip = Some(pat),
+                    }
+                }
+                (Some(key), _) => return Err(format!("E395: Illegal argument: {key}")),
+                (None, word) if keyword => words.extend(expand_keyword(word)),
+                (None, pat) if kind == "match" && pattern.is_none() => {
+                    let (pat, offsets) = split_pattern(pat);
+                    pattern = Some(Pattern::new(pat, offsets, self.ignorecase)?);
+                }
+                (None, arg) => return Err(format!("E395: Illegal argument: {arg}")),
+            }
+        }
+        item.kind = if keyword {
+            if words.is_empty() {
+                return Err(format!("E399: Not enough arguments: {args}"));
+            }
+            Kind::Keyword
+        } else if kind == "match" {
+            Kind::Match(pattern.ok_or_else(|| format!("E399: Not enough arguments: {args}"))?)
+        } else if start.is_empty() {
+            return Err("E398: Missing '=': start".to_string());
+        } else if end.is_empty() {
+            return Err("E398: Missing '=': end".to_string());
+        } else {
+            Kind::Region { start, skip, end }
+        };
+        item.group = self.group(group);
+        let id = self.items.len();
+        self.items.push(item);
+        for word in words {
+            let (map, word) = if self.ignorecase {
+                (&mut self.keywords_icase, word.to_lowercase())
+            } else {
+                (&mut self.keywords, word)
+            };
+            map.entry(word).or_default().push(id);
+        }
+        self.changed_items();
+        Ok(None)
+    }
+
+    fn cluster(&mut self, args: &str) -> Result<Option<String>, String> {
+        let mut args = args.split_whitespace();
+        let name = args
+            .next()
+            .ok_or_else(|| "E400: No cluster specified".to_string())?
+            .to_string();
+        let list = self.clusters.entry(name).or_default();
+        for arg in args {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("E475: Invalid argument: {arg}"))?;
+            let names = value.split(',').filter(|n| !n.is_empty()).map(String::from);
+            match key {
+                "contains" => *list = names.collect(),
+                "add" => list.extend(names),
+                "remove" => {
+                    let names: Vec<_> = names.collect();
+                    list.retain(|n| !names.contains(n));
+                }
+                _ => return Err(format!("E475: Invalid argument: {arg}")),
+            }
+        }
+        self.changed_items();
+        Ok(None)
+    }
+
+    fn sync(&mut self, args: &str) -> Result<Option<String>, String> {
+        let mut words = args.split_whitespace().peekable();
+        if words.peek().is_none() {
+            return Ok(Some(if self.sync.fromstart {
+                "syncing starts at the first line".to_string()
+            } else {
+                format!(
+                    "syncing starts {} lines before top line",
+                    self.sync.minlines
+                )
+            }));
+        }
+        while let Some(word) = words.next() {
+            let num = |v: &str| {
+                v.parse::<usize>()
+                    .map_err(|_| format!("E475: Invalid argument: {word}"))
+            };
+            match word.split_once('=') {
+                None if word == "fromstart" => self.sync.fromstart = true,
+                None if word == "clear" => self.sync = Sync::default(),
+                None if word == "ccomment" => {
+                    let group = match words.peek() {
+                        Some(g) if !g.contains('=') => words.next().unwrap(),
+                        _ => "Comment",
+                    };
+                    self.sync.ccomment = Some(group.to_string());
+                    self.sync.fromstart = false;
+                }
+                Some(("minlines", v)) => {
+                    self.sync.minlines = num(v)?;
+                    self.sync.fromstart = false;
+                }
+                Some(("maxlines", v)) => {
+                    self.sync.maxlines = num(v)?;
+                    self.sync.fromstart = false;
+                }
+                // Only matters for multi-line patterns, which aren't matched across lines
+                Some(("linebreaks", v)) => {
+                    num(v)?;
+                }
+                _ => return Err(format!("E404: Illegal arguments: {word}")),
+            }
+        }
+        self.invalidate();
+        Ok(None)
+    }
+
+    /// Whether the group of `id` is one of `names`, which can be clusters or patterns
+    fn group_in(&self, id: usize, names: &[String], depth: usize) -> bool {
+        let group = &self.groups[self.items[id].group];
+        names.iter().any(|name| {
+            if let Some(cluster) = name.strip_prefix('@') {
+                depth < 20
+                    && self
+                        .clusters
+                        .get(cluster)
+                        .is_some_and(|c| self.group_in(id, c, depth + 1))
+            } else if name.contains(['*', '.', '[', '\\']) {
+                Regex::new(&format!("^\\%({name}\\)$"), &regex::Options::default())
+                    .is_ok_and(|r| r.is_match(group))
+            } else {
+                group == name
+            }
+        })
+    }
+
+    fn compile(&self) -> Compiled {
+        let n = self.items.len();
+        let top = (0..n).filter(|i| !self.items[*i].contained).collect();
+        let children = (0..n)
+            .map(|i| {
+                let item = &self.items[i];
+                let group = &self.groups[item.group];
+                let contained_in = |j: usize| self.items[j].containedin.iter().any(|c| c == group);
+                let listed = |j: usize| {
+                    let other = &self.items[j];
+                    match &item.contains {
+                        Some(Contains::All(not)) => !self.group_in(j, not, 0),
+                        Some(Contains::Top(not)) => !other.contained && !self.group_in(j, not, 0),
+                        Some(Contains::Contained(not)) => {
+                            other.contained && !self.group_in(j, not, 0)
+                        }
+                        Some(Contains::List(names)) => self.group_in(j, names, 0),
+                        None => false,
+                    }
+                };
+                if item.contains.is_none() && item.transparent {
+                    // Transparent items contain what their parent does
+                    None
+                } else {
+                    Some((0..n).filter(|&j| listed(j) || contained_in(j)).collect())
+                }
+            })
+            .collect();
+        let next = (0..n)
+            .map(|i| {
+                let names = &self.items[i].nextgroup;
+                (0..n).filter(|&j| self.group_in(j, names, 0)).collect()
+            })
+            .collect();
+        Compiled {
+            top,
+            children,
+            next,
+        }
+    }
+
+    /// The highlight group of every group, from `highlights`. Every line is highlighted again
+    /// if they changed.
+    fn update_hl_ids(&mut self, highlights: &Highlights) {
+        if self.hl_tick != highlights.tick() || self.hl_ids.len() != self.groups.len() {
+            let ids: Vec<_> = self.groups.iter().map(|g| highlights.get(g)).collect();
+            self.hl_tick = highlights.tick();
+            if ids != self.hl_ids {
+                self.hl_ids = ids;
+                self.invalidate();
+            }
+        }
+    }
+
+    /// Keeps the saved states in line with the buffer after lines are added or removed at `line`
+    pub fn lines_changed(&mut self, line: usize, delta: isize) {
+        if self.starts.len() <= line {
+            return;
+        }
+        if delta > 0 {
+            let at = (line + 1).min(self.starts.len());
+            self.starts
+                .splice(at..at, std::iter::repeat_n(None, delta as usize));
+            let at = line.min(self.folds.len());
+            self.folds.splice(
+                at..at,
+                std::iter::repeat_n(FoldLevel::Level(0), delta as usize),
+            );
+        } else {
+            let end = (line + 1 + (-delta) as usize).min(self.starts.len());
+            self.starts.drain((line + 1).min(end)..end);
+            let end = (line + (-delta) as usize).min(self.folds.len());
+            self.folds.drain(line.min(end)..end);
+        }
+        let last = if delta > 0 {
+            line + delta as usize - 1
+        } else {
+            line
+        };
+        self.changed(line.saturating_sub(1), last);
+    }
+
+    /// Marks the lines `first..=last` as changed
+    pub fn changed(&mut self, first: usize, last: usize) {
+        self.check = Some(match self.check {
+            Some((f, l)) => (f.min(first), l.max(last)),
+            None => (first, last),
+        });
+    }
+
+    /// Highlights `lines`, as well as any lines before them that are needed. Returns whether
+    /// the style of any line in `range` changed.
+    pub fn highlight(
+        &mut self,
+        lines: &mut [Line],
+        range: Range<usize>,
+        highlights: &Highlights,
+        synmaxcol: usize,
+    ) -> bool {
+        self.update_hl_ids(highlights);
+        self.update(lines, range, synmaxcol)
+    }
+
+    /// The fold level of every line, for 'foldmethod' "syntax"
+    pub fn fold_levels(&mut self, lines: &mut [Line], synmaxcol: usize) -> Vec<FoldLevel> {
+        self.update(lines, 0..lines.len(), synmaxcol);
+        self.folds.clone()
+    }
+
+    fn update(&mut self, lines: &mut [Line], range: Range<usize>, synmaxcol: usize) -> bool {
+        if self.compiled.is_none() {
+            self.compiled = Some(self.compile());
+        }
+        self.starts.resize(lines.len() + 1, None);
+        self.folds.resize(lines.len(), FoldLevel::Level(0));
+        if self.starts[0].is_none() {
+            self.starts[0] = Some(State::default());
+        }
+        let range = range.start.min(lines.len())..range.end.min(lines.len());
+        let mut changed = false;
+        if let Some((first, last)) = self.check.take() {
+            if first < lines.len() && self.starts[first].is_some() {
+                // Highlight from the first changed line, until the state after a line is the
+                // same as before the change
+                let mut line = first;
+                loop {
+                    let before = self.starts[line + 1].take();
+                    changed |= self.run(lines, line, synmaxcol) && range.contains(&line);
+                    line += 1;
+                    if line >= lines.len() {
+                        break;
+                    }
+                    if line > last && before.is_some() && before == self.starts[line] {
+                        break;
+                    }
+                    if line > last && line >= range.end {
+                        self.starts[line + 1..].iter_mut().for_each(|s| *s = None);
+                        break;
+                    }
+                }
+            } else {
+                let end = (last + 2).min(self.starts.len());
+                self.starts[(first + 1).min(end)..end]
+                    .iter_mut()
+                    .for_each(|s| *s = None);
+            }
+        }
+        for line in range.clone() {
+            changed |= self.ensure(lines, line, synmaxcol);
+        }
+        changed
+    }
+
+    /// Highlights `line` if it isn't yet, starting from the last line before it that is
+    fn ensure(&mut self, lines: &mut [Line], line: usize, synmaxcol: usize) -> bool {
+        if self.starts[line + 1].is_some() {
+            return false;
+        }
+        let lookback = if self.sync.fromstart {
+            line
+        } else {
+            self.sync.maxlines.max(self.sync.minlines)
+        };
+        let from = line.saturating_sub(lookback);
+        let start = match (from..=line).rev().find(|l| self.starts[*l].is_some()) {
+            Some(start) => start,
+            None => {
+                let start = line.saturating_sub(self.sync.minlines);
+                self.starts[start] = Some(self.sync_state(lines, start));
+                start
+            }
+        };
+        let mut changed = false;
+        for l in start..=line {
+            if self.starts[l + 1].is_none() {
+                changed |= self.run(lines, l, synmaxcol);
+            }
+        }
+        changed
+    }
+
+    /// A guess at the state at the start of `line`, when it can't be worked out from the lines
+    /// before it
+    fn sync_state(&self, lines: &[Line], line: usize) -> State {
+        let group = match &self.sync.ccomment {
+            Some(group) => group,
+            None => return State::default(),
+        };
+        let lookback = self.sync.maxlines.max(self.sync.minlines).max(1);
+        // Whether the last comment marker before the line starts a comment
+        let in_comment = lines[line.saturating_sub(lookback)..line]
+            .iter()
+            .rev()
+            .find_map(|l| {
+                let open = l.text().rfind("/*");
+                let close = l.text().rfind("*/");
+                match (open, close) {
+                    (Some(o), Some(c)) => Some(o > c),
+                    (Some(_), None) => Some(true),
+                    (None, Some(_)) => Some(false),
+                    (None, None) => None,
+                }
+            })
+            .unwrap_or(false);
+        let item = self
+            .items
+            .iter()
+            .position(|i| matches!(i.kind, Kind::Region { .. }) && &self.groups[i.group] == group);
+        match item {
+            Some(item) if in_comment => State {
+                stack: vec![Frame {
+                    item,
+                    scope: Some(item),
+                    end: None,
+                }],
+                next: None,
+            },
+            _ => State::default(),
+        }
+    }
+
+    /// Highlights `line`, from the state at its start. Returns whether its style changed.
+    fn run(&mut self, lines: &mut [Line], line: usize, synmaxcol: usize) -> bool {
+        let mut state = self.starts[line].clone().unwrap_or_default();
+        let text = lines[line].text();
+        let limit = if synmaxcol == 0 {
+            text.len()
+        } else {
+            text.char_indices()
+                .nth(synmaxcol)
+                .map_or(text.len(), |(i, _)| i)
+        };
+        let compiled = self
+            .compiled
+            .as_ref()
+            .expect("compiled before highlighting");
+        let mut run = LineRun {
+            syntax: self,
+            compiled,
+            text: &text[..limit],
+            spans: vec![],
+            cache: vec![None; self.items.len()],
+        };
+        let fold = run.run(&mut state);
+        let mut spans = run.spans;
+        match spans.last_mut() {
+            Some((end, None)) => *end = text.len(),
+            _ => spans.push((text.len(), None)),
+        }
+        let mut style: Vec<(usize, Option<usize>)> = vec![];
+        for (end, group) in spans {
+            let s = group.and_then(|g| self.hl_ids.get(g).copied().flatten());
+            match style.last_mut() {
+                Some((e, last)) if *last == s => *e = end,
+                _ => style.push((end, s)),
+            }
+        }
+        self.starts[line + 1] = Some(state);
+        self.folds[line] = fold;
+        lines[line].set_style(style)
+    }
+}
+
+/// Matching the items in a single line
+struct LineRun<'a> {
+    syntax: &'a Syntax,
+    compiled: &'a Compiled,
+    text: &'a str,
+    /// The end of each highlighted part of the line and its group
+    spans: Vec<(usize, Option<usize>)>,
+    /// The next match of each item, and where it was searched from
+    cache: Vec<Option<Option<Found>>>,
+}
+
+impl<'a> LineRun<'a> {
+    fn paint(&mut self, end: usize, group: Option<usize>) {
+        let painted = self.spans.last().map_or(0, |s| s.0);
+        let end = end.min(self.text.len());
+        if end <= painted {
+            return;
+        }
+        match self.spans.last_mut() {
+            Some((e, g)) if *g == group => *e = end,
+            _ => self.spans.push((end, group)),
+        }
+    }
+
+    fn item(&self, id: usize) -> &'a Item {
+        &self.syntax.items[id]
+    }
+
+    /// The group the text is highlighted with, from the innermost item that isn't transparent
+    fn current(&self, state: &State) -> Option<usize> {
+        state
+            .stack
+            .iter()
+            .rev()
+            .map(|f| self.item(f.item))
+            .find(|i| !i.transparent)
+            .map(|i| i.group)
+    }
+
+    /// The items that can start at this point
+    fn allowed(&self, state: &State) -> &'a [usize] {
+        match state.stack.last().and_then(|f| f.scope) {
+            Some(scope) => self.compiled.children[scope].as_deref().unwrap_or(&[]),
+            None => &self.compiled.top,
+        }
+    }
+
+    /// The scope of a new frame for `item`, started in `state`. `None` is the top level.
+    fn scope(&self, state: &State, item: usize) -> Option<usize> {
+        if self.compiled.children[item].is_some() {
+            Some(item)
+        } else {
+            state.stack.last().and_then(|f| f.scope)
+        }
+    }
+
+    /// Whether `item` is matched as a frame, which other items can be inside of
+    fn has_frame(&self, item: usize) -> bool {
+        matches!(self.item(item).kind, Kind::Region { .. })
+            || self.item(item).transparent
+            || self.compiled.children[item]
+                .as_ref()
+                .is_some_and(|c| !c.is_empty())
+    }
+
+    /// Where to continue matching after `item` started with `found`
+    fn after_start(&self, item: usize, found: Found) -> usize {
+        match self.item(item).kind {
+            Kind::Region { .. } => found.end.max(found.body),
+            _ if self.has_frame(item) => found.start,
+            _ => found.end,
+        }
+    }
+
+    fn keyword(&self, word: &str, allowed: &[usize]) -> Option<usize> {
+        let syn = self.syntax;
+        let exact = syn.keywords.get(word).into_iter().flatten();
+        let icase = if syn.keywords_icase.is_empty() {
+            None
+        } else {
+            syn.keywords_icase.get(&word.to_lowercase())
+        };
+        exact
+            .chain(icase.into_iter().flatten())
+            .copied()
+            .filter(|i| allowed.binary_search(i).is_ok())
+            .max()
+    }
+
+    /// The first keyword at or after `from` that is in `allowed`
+    fn find_keyword(&self, from: usize, allowed: &[usize]) -> Option<(usize, Found)> {
+        if self.syntax.keywords.is_empty() && self.syntax.keywords_icase.is_empty() {
+            return None;
+        }
+        let text = self.text;
+        let mut pos = from;
+        // Keywords only match whole words
+        if text[..pos].chars().next_back().is_some_and(is_word) {
+            pos = text[pos..]
+                .find(|c| !is_word(c))
+                .map_or(text.len(), |e| pos + e);
+        }
+        while pos < text.len() {
+            let start = pos + text[pos..].find(is_word)?;
+            let end = text[start..]
+                .find(|c| !is_word(c))
+                .map_or(text.len(), |e| start + e);
+            if let Some(item) = self.keyword(&text[start..end], allowed) {
+                let mut found = Found::empty(start);
+                found.end = end;
+                found.he = end;
+                found.body = end;
+                return Some((item, found));
+            }
+            pos = end;
+        }
+        None
+    }
+
+    fn find_start(&mut self, id: usize, from: usize) -> Option<Found> {
+        if let Some(cached) = self.cache[id] {
+            if cached.is_none_or(|f| f.from >= from) {
+                return cached;
+            }
+        }
+        let found = match &self.item(id).kind {
+            Kind::Keyword => None,
+            Kind::Match(pat) => {
+                let mut from = from;
+                loop {
+                    match pat.find(self.text, from, false) {
+                        // Empty matches are ignored
+                        Some(f) if f.end == f.start => {
+                            if f.from >= self.text.len() {
+                                break None;
+                            }
+                            from = self.text.ceil_char_boundary(f.from + 1);
+                        }
+                        f => break f,
+                    }
+                }
+            }
+            Kind::Region { start, .. } => {
+                let mut from = from;
+                loop {
+                    let found = start
+                        .iter()
+                        .filter_map(|(p, g)| {
+                            p.find(self.text, from, false)
+                                .map(|f| Found { group: *g, ..f })
+                        })
+                        .min_by_key(|f| f.start);
+                    match found {
+                        // A oneline region must end on the same line
+                        Some(f) if self.item(id).oneline && self.find_end(id, f.body).is_none() => {
+                            if f.from >= self.text.len() {
+                                break None;
+                            }
+                            from = self.text.ceil_char_boundary(f.from + 1);
+                        }
+                        f => break f,
+                    }
+                }
+            }
+        };
+        self.cache[id] = Some(found);
+        found
+    }
+
+    /// The end of the region `id`, searching from `from`
+    fn find_end(&self, id: usize, from: usize) -> Option<Found> {
+        let (end, skip) = match &self.item(id).kind {
+            Kind::Region { end, skip, .. } => (end, skip),
+            _ => return None,
+        };
+        let mut from = from;
+        loop {
+            let found = end
+                .iter()
+                .filter_map(|(p, g)| {
+                    p.find(self.text, from, true)
+                        .map(|f| Found { group: *g, ..f })
+                })
+                .min_by_key(|f| f.start)?;
+            match skip.as_ref().and_then(|s| s.find(self.text, from, false)) {
+                Some(s) if s.start <= found.start && s.end > from => from = s.end,
+                _ => return Some(found),
+            }
+        }
+    }
+
+    /// The first item in `allowed` that matches before `limit`
+    fn find_item(
+        &mut self,
+        allowed: &[usize],
+        from: usize,
+        limit: usize,
+    ) -> Option<(usize, Found)> {
+        let mut best = self
+            .find_keyword(from, allowed)
+            .filter(|(_, f)| f.start < limit);
+        let keyword = best.is_some();
+        for &id in allowed {
+            if matches!(self.item(id).kind, Kind::Keyword) {
+                continue;
+            }
+            let found = match self.find_start(id, from) {
+                Some(f) if f.start < limit => f,
+                _ => continue,
+            };
+            // Later items win over earlier ones, and keywords win over both
+            let better = match best {
+                None => true,
+                Some((_, b)) => found.start < b.start || found.start == b.start && !keyword,
+            };
+            if better {
+                best = Some((id, found));
+            }
+        }
+        best
+    }
+
+    /// An item in the `nextgroup=` of `prev` that starts at `pos`
+    fn find_next(&mut self, prev: usize, pos: usize) -> Option<(usize, Found)> {
+        let allowed = &self.compiled.next[prev];
+        if let Some((id, f)) = self.find_keyword(pos, allowed) {
+            if f.start == pos {
+                return Some((id, f));
+            }
+        }
+        let items = &self.syntax.items;
+        allowed
+            .iter()
+            .rev()
+            .filter(|id| !matches!(items[**id].kind, Kind::Keyword))
+            .find_map(|&id| {
+                // The cache is for the items allowed in the current frame
+                let saved = self.cache[id].take();
+                let found = self.find_start(id, pos);
+                self.cache[id] = saved;
+                found.filter(|f| f.start == pos).map(|f| (id, f))
+            })
+    }
+
+    /// The first end of a frame in the stack, which is the top frame or one that ends all of the
+    /// frames inside of it
+    fn find_frame_end(&self, state: &State, from: usize) -> Option<(usize, Found)> {
+        let mut best: Option<(usize, Found)> = None;
+        let top = state.stack.len().checked_sub(1)?;
+        for (level, frame) in state.stack.iter().enumerate().rev() {
+            if level != top && frame.end.is_none() && !self.item(frame.item).keepend {
+                continue;
+            }
+            let found = match frame.end {
+                Some(end) => Some(Found::empty(end)),
+                None => self.find_end(frame.item, from),
+            };
+            if let Some(found) = found {
+                if best.is_none_or(|(_, b)| found.start < b.start) {
+                    best = Some((level, found));
+                }
+            }
+        }
+        best
+    }
+
+    fn start_item(&mut self, state: &mut State, id: usize, found: Found) {
+        let cur = self.current(state);
+        self.paint(found.start, cur);
+        let item = self.item(id);
+        let group = if item.transparent {
+            cur
+        } else {
+            Some(item.group)
+        };
+        match item.kind {
+            Kind::Keyword | Kind::Match(_) if !self.has_frame(id) => {
+                self.paint(found.hs, cur);
+                self.paint(found.he, group);
+                self.paint(found.end, cur);
+                state.next = (!item.nextgroup.is_empty()).then_some(id);
+            }
+            Kind::Keyword | Kind::Match(_) => {
+                let scope = self.scope(state, id);
+                state.stack.push(Frame {
+                    item: id,
+                    scope,
+                    end: Some(found.end),
+                });
+                self.cache.iter_mut().for_each(|c| *c = None);
+            }
+            Kind::Region { .. } => {
+                let scope = self.scope(state, id);
+                self.paint(found.hs, group);
+                self.paint(found.he, found.group.or(group));
+                self.paint(found.end.max(found.body), group);
+                state.stack.push(Frame {
+                    item: id,
+                    scope,
+                    end: None,
+                });
+                self.cache.iter_mut().for_each(|c| *c = None);
+            }
+        }
+    }
+
+    fn end_frame(&mut self, state: &mut State, level: usize, found: Found) {
+        let cur = self.current(state);
+        self.paint(found.body, cur);
+        state.stack.truncate(level + 1);
+        let frame = state.stack.pop().expect("ended frame is on the stack");
+        let item = self.item(frame.item);
+        let group = if item.transparent {
+            self.current(state)
+        } else {
+            Some(item.group)
+        };
+        self.paint(found.hs, group);
+        self.paint(found.he, found.group.or(group));
+        self.paint(found.end, group);
+        state.next = (!item.nextgroup.is_empty()).then_some(frame.item);
+        self.cache.iter_mut().for_each(|c| *c = None);
+    }
+
+    /// Highlights the line, returning its fold level
+    fn run(&mut self, state: &mut State) -> FoldLevel {
+        let items = &self.syntax.items;
+        let folds = |stack: &[Frame]| stack.iter().filter(|f| items[f.item].fold).count();
+        let start_folds = folds(&state.stack);
+        let mut lowest = state.stack.len();
+        let mut pos = 0;
+        // Patterns can match without moving forward, so give up eventually
+        let mut steps = 0;
+        while steps < 4 * self.text.len() + 64 {
+            steps += 1;
+            if let Some(prev) = state.next.take() {
+                let item = self.item(prev);
+                let mut at = pos;
+                if item.skipwhite {
+                    at += self.text[at..].len()
+                        - self.text[at..].trim_start_matches([' ', '\t']).len();
+                }
+                if at >= self.text.len() {
+                    if item.skipnl || item.skipempty && self.text.is_empty() {
+                        state.next = Some(prev);
+                    }
+                    break;
+                }
+                if let Some((id, found)) = self.find_next(prev, at) {
+                    self.start_item(state, id, found);
+                    pos = self.after_start(id, found);
+                    continue;
+                }
+            }
+            let end = self.find_frame_end(state, pos);
+            let limit = end.map_or(self.text.len(), |(_, f)| f.start);
+            let allowed = self.allowed(state);
+            match (self.find_item(allowed, pos, limit), end) {
+                (Some((id, found)), _) => {
+                    self.start_item(state, id, found);
+                    pos = self.after_start(id, found);
+                }
+                (None, Some((level, found))) => {
+                    lowest = lowest.min(level);
+                    self.end_frame(state, level, found);
+                    pos = pos.max(found.end);
+                }
+                (None, None) => break,
+            }
+        }
+        let cur = self.current(state);
+        self.paint(self.text.len(), cur);
+        // Matches and oneline regions end with the line
+        if let Some(end) = state
+            .stack
+            .iter()
+            .position(|f| f.end.is_some() || self.item(f.item).oneline)
+        {
+            state.stack.truncate(end);
+        }
+        lowest = lowest.min(state.stack.len());
+        let end_folds = folds(&state.stack);
+        if folds(&state.stack[lowest..]) > 0 {
+            FoldLevel::Start(end_folds)
+        } else {
+            FoldLevel::Level(start_folds.max(end_folds))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The highlighted parts of each line and their groups, and the fold level of each line
+    fn groups(syn: &mut Syntax, text: &[&str]) -> (Vec<Vec<(String, String)>>, Vec<FoldLevel>) {
+        syn.compiled = Some(syn.compile());
+        let compiled = syn.compiled.as_ref().unwrap();
+        let mut state = State::default();
+        let mut folds = vec![];
+        let groups = text
+            .iter()
+            .map(|text| {
+                let mut run = LineRun {
+                    syntax: syn,
+                    compiled,
+                    text,
+                    spans: vec![],
+                    cache: vec![None; syn.items.len()],
+                };
+                folds.push(run.run(&mut state));
+                let mut start = 0;
+                run.spans
+                    .iter()
+                    .filter_map(|(end, group)| {
+                        let part = text[start..*end].to_string();
+                        start = *end;
+                        group.map(|g| (part, syn.groups[g].clone()))
+                    })
+                    .collect()
+            })
+            .collect();
+        (groups, folds)
+    }
+
+    fn pairs(p: &[(&str, &str)]) -> Vec<(String, String)> {
+        p.iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn patterns() {
+        let t = |p: &str, text: &str| {
+            Pattern::search(p, false)
+                .unwrap()
+                .find_at(text, 0)
+                .map(|r| text[r].to_string())
+        };
+        let s = |s: &str| Some(s.to_string());
+        assert_eq!(t("a\\+b*", "xaabbc"), s("aabb"));
+        assert_eq!(t("\\<if\\>", "elif if"), s("if"));
+        assert_eq!(t("\\(a\\|b\\)\\{2,}", "xabba"), s("abba"));
+        assert_eq!(t("\\v(a|b)+", "xabba"), s("abba"));
+        assert_eq!(t("\\Va*", "aa*"), s("a*"));
+        assert_eq!(t("foo\\zsbar", "foobar"), s("bar"));
+        assert_eq!(t("\\(x\\)\\zs\\1", "axx"), s("x"));
+        assert_eq!(t("a\\(b\\)\\@!", "abac"), s("a"));
+        assert_eq!(t("[^\"\\\\]\\+", "\"ab\\"), s("ab"));
+        assert_eq!(t("a$", "a a"), s("a"));
+        assert_eq!(t("a$b", "a$b"), s("a$b"));
+        let found = Pattern::search("foo\\zsbar", false)
+            .unwrap()
+            .find("xfoobar", 0, false);
+        assert_eq!(found.map(|f| (f.from, f.start)), Some((1, 4)));
+    }
+
+    #[test]
+    fn items() {
+        let mut syn = Syntax::default();
+        syn.command("keyword Keyword if else").unwrap();
+        syn.command("match Number /\\<\\d\\+\\>/").unwrap();
+        syn.command("region String start=/\"/ skip=/\\\\\"/ end=/\"/ contains=Escape")
+            .unwrap();
+        syn.command("match Escape /\\\\./ contained").unwrap();
+        syn.command("region Comment start=+/\\*+ end=+\\*/+ fold")
+            .unwrap();
+        let (g, folds) = groups(&mut syn, &["if 12 \"a\\\"b\" else", "x /* c", "d */ y"]);
+        assert_eq!(
+            g[0],
+            pairs(&[
+                ("if", "Keyword"),
+                ("12", "Number"),
+                ("\"a", "String"),
+                ("\\\"", "Escape"),
+                ("b\"", "String"),
+                ("else", "Keyword"),
+            ])
+        );
+        assert_eq!(g[1], pairs(&[("/* c", "Comment")]));
+        assert_eq!(g[2], pairs(&[("d */", "Comment")]));
+        assert_eq!(
+            folds,
+            vec![
+                FoldLevel::Level(0),
+                FoldLevel::Start(1),
+                FoldLevel::Level(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn nextgroup_and_keepend() {
+        let mut syn = Syntax::default();
+        syn.command("match Statement /\\<let\\>/ nextgroup=Identifier skipwhite")
+            .unwrap();
+        syn.command("match Identifier /\\w\\+/ contained").unwrap();
+        syn.command("region Paren start=/(/ end=/)/ keepend contains=Inner")
+            .unwrap();
+        syn.command("region Inner start=/\\[/ end=/]/ contained")
+            .unwrap();
+        syn.command("region Delim matchgroup=Special start=/</ end=/>/")
+            .unwrap();
+        let (g, _) = groups(&mut syn, &["let x = y", "([a) b <c>"]);
+        assert_eq!(g[0], pairs(&[("let", "Statement"), ("x", "Identifier")]));
+        assert_eq!(
+            g[1],
+            pairs(&[
+                ("(", "Paren"),
+                ("[a", "Inner"),
+                (")", "Paren"),
+                ("<", "Special"),
+                ("c", "Delim"),
+                (">", "Special"),
+            ])
+        );
+    }
+
+    #[test]
+    fn incremental() {
+        let mut syn = Syntax::default();
+        syn.command("region Comment start=+/\\*+ end=+\\*/+")
+            .unwrap();
+        let hl = Highlights::new();
+        let mut lines: Vec<Line> = ["a", "b", "c"]
+            .iter()
+            .map(|t| Line::new(t.to_string()))
+            .collect();
+        syn.highlight(&mut lines, 0..3, &hl, 0);
+        assert_eq!(syn.starts[3], Some(State::default()));
+        lines[0] = Line::new("/* a".into());
+        syn.changed(0, 0);
+        assert!(syn.highlight(&mut lines, 0..3, &hl, 0));
+        assert_eq!(syn.starts[3].as_ref().map(|s| s.stack.len()), Some(1));
+    }
+}
@@ -0,0 +1,677 @@
+//
+// tags.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use vimscript::regex::{self, Regex};
+use vimscript::{Id, State};
+
+use crate::buffer::BufferRef;
+use crate::cursor::Motion;
+use crate::glob;
+use crate::search;
+use crate::VimInner;
+
+/// The number of tags the tag stack keeps
+const STACK_SIZE: usize = 20;
+
+/// A tag from a tags file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    /// The file the tag is in, relative to the tags file with 'tagrelative'
+    pub filename: String,
+    /// The Ex command that finds the tag, which is a line number or a search pattern
+    pub cmd: String,
+    pub kind: String,
+    /// The extension fields after the kind, like `class:Name`
+    pub fields: Vec<(String, String)>,
+    /// Whether the tag is only visible in its file, which is given by the `file:` field
+    pub is_static: bool,
+}
+
+/// How a tags file is sorted, from its `!_TAG_FILE_SORTED` line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sorted {
+    No,
+    Yes,
+    /// Sorted ignoring case
+    FoldCase,
+}
+
+/// The tags a command looks for
+enum Pattern {
+    /// A tag name, where only the first `len` characters matter when it isn't 0
+    Name {
+        name: String,
+        ignorecase: bool,
+        len: usize,
+    },
+    /// The tags that match a pattern, like `:tag /pat`
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// The part of a tag name that is compared, with its case folded when case is ignored
+    fn key(&self, name: &str) -> String {
+        let Self::Name {
+            ignorecase, len, ..
+        } = self
+        else {
+            return name.to_string();
+        };
+        let name = match *len {
+            0 => name,
+            len => name
+                .char_indices()
+                .nth(len)
+                .map_or(name, |(i, _)| &name[..i]),
+        };
+        match ignorecase {
+            true => name.to_lowercase(),
+            false => name.to_string(),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Name { name: pat, .. } => self.key(name) == self.key(pat),
+            Self::Regex(re) => re.is_match(name),
+        }
+    }
+}
+
+/// Reads a line of a tags file, which is `{name}<Tab>{file}<Tab>{cmd};"<Tab>{fields}`. The file
+/// name is relative to `dir`.
+fn parse_line(line: &str, dir: Option<&Path>) -> Option<Tag> {
+    if line.starts_with("!_TAG_") {
+        return None;
+    }
+    let (name, rest) = line.split_once('\t')?;
+    let (filename, rest) = rest.split_once('\t')?;
+    // The fields come after `;"`, which older files don't have
+    let (cmd, fields) = match rest.find(";\"\t") {
+        Some(i) => (&rest[..i], &rest[i + 3..]),
+        None => (rest.strip_suffix(";\"").unwrap_or(rest), ""),
+    };
+    let filename = match dir {
+        Some(dir) if Path::new(filename).is_relative() && dir != Path::new(".") => {
+            dir.join(filename).display().to_string()
+        }
+        _ => filename.to_string(),
+    };
+    let mut tag = Tag {
+        name: name.to_string(),
+        filename,
+        cmd: cmd.to_string(),
+        ..Tag::default()
+    };
+    for field in fields.split('\t').filter(|f| !f.is_empty()) {
+        match field.split_once(':') {
+            Some(("kind", kind)) => tag.kind = kind.to_string(),
+            Some(("file", _)) => tag.is_static = true,
+            Some((key, value)) => tag.fields.push((key.to_string(), value.to_string())),
+            // A field without a name is the kind
+            None => tag.kind = field.to_string(),
+        }
+    }
+    Some(tag)
+}
+
+fn sorted(text: &str) -> Sorted {
+    let line = text
+        .lines()
+        .take_while(|l| l.starts_with('!'))
+        .find_map(|l| l.strip_prefix("!_TAG_FILE_SORTED\t"));
+    match line.and_then(|l| l.chars().next()) {
+        Some('1') => Sorted::Yes,
+        Some('2') => Sorted::FoldCase,
+        _ => Sorted::No,
+    }
+}
+
+/// The key of a tag name that a sorted file is ordered by, which is also lowercased when the
+/// file is sorted ignoring case
+fn sort_key(pat: &Pattern, name: &str, foldcase: bool) -> String {
+    match foldcase {
+        true => pat.key(name).to_lowercase(),
+        false => pat.key(name),
+    }
+}
+
+/// The start of the first line whose tag name isn't before the name of `pat`, found with a
+/// binary search
+fn bsearch(text: &str, pat: &Pattern, foldcase: bool) -> usize {
+    let Pattern::Name { name, .. } = pat else {
+        return 0;
+    };
+    let target = sort_key(pat, name, foldcase);
+    let bytes = text.as_bytes();
+    let (mut lo, mut hi) = (0, text.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        let start = bytes[..mid]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1)
+            .max(lo);
+        let end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+        let line = &text[start..end];
+        let tag = line.split('\t').next().unwrap_or(line);
+        if line.starts_with("!_TAG_") || sort_key(pat, tag, foldcase) < target {
+            lo = end + 1;
+        } else {
+            hi = start;
+        }
+    }
+    lo.min(text.len())
+}
+
+/// The tags in a tags file that match `pat`, using a binary search when the file is sorted
+/// the way the pattern compares names
+fn find_in(text: &str, dir: Option<&Path>, pat: &Pattern, binary: bool) -> Vec<Tag> {
+    let sorted = sorted(text);
+    let can_bsearch = match (pat, sorted) {
+        (Pattern::Name { ignorecase, .. }, Sorted::Yes) => !ignorecase,
+        (Pattern::Name { .. }, Sorted::FoldCase) => true,
+        _ => false,
+    };
+    match pat {
+        Pattern::Name { name, .. } if binary && can_bsearch => {
+            let foldcase = sorted == Sorted::FoldCase;
+            let target = sort_key(pat, name, foldcase);
+            // The tags with the same key are together, but only some of them can match when
+            // the file folds case and the pattern doesn't
+            text[bsearch(text, pat, foldcase)..]
+                .lines()
+                .map_while(|l| parse_line(l, dir))
+                .take_while(|t| sort_key(pat, &t.name, foldcase) == target)
+                .filter(|t| pat.matches(&t.name))
+                .collect()
+        }
+        _ => text
+            .lines()
+            .filter_map(|l| parse_line(l, dir))
+            .filter(|t| pat.matches(&t.name))
+            .collect(),
+    }
+}
+
+/// The directory of the focused buffer, or the current directory
+fn buffer_dir(v: &VimInner) -> PathBuf {
+    let file = v
+        .get_focus()
+        .buffer()
+        .read()
+        .filename()
+        .map(Path::to_path_buf);
+    match file.as_ref().and_then(|f| f.parent()) {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// The tags files of 'tags'. A name that starts with `./` is in the directory of the current
+/// file, and one that ends with `;` is also looked for in the directories above it, up to the
+/// directory after the `;` if there is one.
+pub fn files(v: &VimInner) -> Vec<PathBuf> {
+    let mut ret = vec![];
+    let tags = v.options().tags.clone();
+    for item in tags.split([',', ' ']).filter(|i| !i.is_empty()) {
+        let (item, upward) = match item.split_once(';') {
+            Some((item, stop)) => (item, Some(stop)),
+            None => (item, None),
+        };
+        let item = match item.strip_prefix("./") {
+            Some(rest) => buffer_dir(v).join(rest).display().to_string(),
+            None => item.to_string(),
+        };
+        let mut found = glob::glob(&item, false);
+        if let Some(stop) = upward {
+            let path = PathBuf::from(&item);
+            let name = path.file_name().unwrap_or_default();
+            let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+            let mut dir =
+                std::fs::canonicalize(parent.unwrap_or(Path::new("."))).unwrap_or_default();
+            while dir.pop() {
+                let file = dir.join(name);
+                if file.is_file() {
+                    found.push(file);
+                }
+                if !stop.is_empty() && dir.ends_with(stop) {
+                    break;
+                }
+            }
+        }
+        for file in found {
+            let file = file
+                .strip_prefix(".")
+                .map_or(file.clone(), Path::to_path_buf);
+            ret.push(file);
+        }
+    }
+    // The same file can be found by more than one name
+    let mut seen = vec![];
+    ret.retain(|f| {
+        let real = std::fs::canonicalize(f).unwrap_or_else(|_| f.clone());
+        let new = !seen.contains(&real);
+        seen.push(real);
+        new
+    });
+    ret
+}
+
+/// Whether `file` is the file of the focused buffer
+fn is_current(v: &VimInner, file: &str) -> bool {
+    let buffer = v.get_focus().buffer().read();
+    buffer.filename().is_some_and(|f| {
+        f == Path::new(file)
+            || std::fs::canonicalize(f)
+                .is_ok_and(|f| std::fs::canonicalize(file).is_ok_and(|file| f == file))
+    })
+}
+
+/// The tags that match `name`, which is a pattern when it starts with `/`. The static tags of
+/// the current file come first, then the other tags, and the static tags of other files last.
+pub fn find(v: &VimInner, name: &str) -> Result<Vec<Tag>, String> {
+    let opts = v.options();
+    let pat = match name.strip_prefix('/') {
+        Some(re) => {
+            let mut ropts = v.regex_options();
+            ropts.ignorecase = ignore_case(v, re);
+            Pattern::Regex(Regex::new(re, &ropts)?)
+        }
+        None => Pattern::Name {
+            name: name.to_string(),
+            ignorecase: ignore_case(v, name),
+            len: opts.taglength.max(0) as usize,
+        },
+    };
+    let mut tags = vec![];
+    for file in files(v) {
+        let Ok(text) = std::fs::read(&file) else {
+            continue;
+        };
+        let text = String::from_utf8_lossy(&text);
+        let dir = file.parent().filter(|_| opts.tagrelative);
+        tags.extend(find_in(&text, dir, &pat, opts.tagbsearch));
+    }
+    // A full match comes before one that only matches with case ignored
+    let exact = |t: &Tag| t.name == name;
+    tags.sort_by_key(|t| {
+        let rank = match (t.is_static, is_current(v, &t.filename)) {
+            (true, true) => 0,
+            (false, _) => 1,
+            (true, false) => 2,
+        };
+        (!exact(t), rank)
+    });
+    Ok(tags)
+}
+
+/// Whether case is ignored for a tag, from 'tagcase'
+fn ignore_case(v: &VimInner, name: &str) -> bool {
+    let opts = v.options();
+    match opts.tagcase.as_str() {
+        "followscs" => search::ignore_case(name, opts.ignorecase, opts.smartcase),
+        "ignore" => true,
+        "match" => false,
+        "smart" => search::ignore_case(name, true, true),
+        _ => opts.ignorecase,
+    }
+}
+
+/// The line of a tag's Ex command, which is a line number or a search pattern, like
+/// `/^int main()$/`. The pattern is always used with 'nomagic', and when it isn't found case is
+/// ignored.
+fn find_line(v: &VimInner, buffer: &BufferRef, cmd: &str) -> Result<(usize, usize), String> {
+    let digits = cmd.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        let line: usize = cmd[..digits].parse().unwrap_or(1);
+        return Ok((line.saturating_sub(1).min(buffer.read().len() - 1), 0));
+    }
+    let delim = cmd.chars().next().ok_or("E434: Can't find tag pattern")?;
+    let body = &cmd[delim.len_utf8()..];
+    let body = body.strip_suffix(delim).unwrap_or(body);
+    let pattern = body.replace(&format!("\\{delim}"), &delim.to_string());
+    let mut opts = regex::Options {
+        magic: false,
+        ignorecase: false,
+        ..v.regex_options()
+    };
+    for ignorecase in [false, true] {
+        opts.ignorecase = ignorecase;
+        let regex = Regex::new(&pattern, &opts)?;
+        let found = buffer.with_read(|b| {
+            let mut lines: Box<dyn Iterator<Item = usize>> = match delim {
+                '?' => Box::new((0..b.len()).rev()),
+                _ => Box::new(0..b.len()),
+            };
+            lines.find_map(|l| regex.find_str(b[l].text(), 0).map(|m| (l, m.range().start)))
+        });
+        if let Some(pos) = found {
+            return Ok(pos);
+        }
+    }
+    Err("E434: Can't find tag pattern".into())
+}
+
+/// Opens the file of a tag, and moves the cursor to it
+fn jump(v: &mut VimInner, tag: &Tag) -> Result<(), String> {
+    if !Path::new(&tag.filename).exists() && v.find_buffer(&tag.filename).is_none() {
+        return Err(format!("E429: File \"{}\" does not exist", tag.filename));
+    }
+    let buffer = v
+        .edit(&tag.filename)
+        .map_err(|_| format!("E429: File \"{}\" does not exist", tag.filename))?;
+    let (row, col) = find_line(v, &buffer, &tag.cmd)?;
+    if buffer.id() != v.get_focus().buffer().id() {
+        v.get_focus_mut().set_buffer(buffer);
+    }
+    let win = v.get_focus_mut();
+    win.cursor_apply(Motion::SetRow(row));
+    win.cursor_apply(Motion::SetCol(col));
+    Ok(())
+}
+
+/// A tag that was jumped to, and where it was jumped from
+struct Item {
+    name: String,
+    /// Which of the matching tags it is, from 0
+    idx: usize,
+    buffer: BufferRef,
+    from: (usize, usize),
+}
+
+/// The tags that were jumped to in a window, which `CTRL-T` goes back through
+#[derive(Default)]
+struct Stack {
+    items: Vec<Item>,
+    /// The item that the next tag is put at, which is after the current one
+    idx: usize,
+}
+
+/// The tag stacks of the windows
+#[derive(Default)]
+pub struct Tags {
+    stacks: HashMap<Id, Stack>,
+}
+
+impl Tags {
+    fn stack(&mut self, win: Id) -> &mut Stack {
+        self.stacks.entry(win).or_default()
+    }
+}
+
+/// Jumps to match `idx` of the tags, and puts it on the tag stack
+fn jump_new(v: &mut VimInner, name: &str, tags: &[Tag], idx: usize) -> Result<(), String> {
+    let tag = tags
+        .get(idx)
+        .ok_or("E428: Cannot go beyond last matching tag")?;
+    let item = Item {
+        name: name.to_string(),
+        idx,
+        buffer: v.get_focus().buffer().clone(),
+        from: (v.get_focus().cursor().row(), v.get_focus().cursor().col()),
+    };
+    jump(v, tag)?;
+    if v.options().tagstack {
+        let id = v.get_focus().id();
+        let stack = v.tags.stack(id);
+        stack.items.truncate(stack.idx);
+        stack.items.push(item);
+        if stack.items.len() > STACK_SIZE {
+            stack.items.remove(0);
+        }
+        stack.idx = stack.items.len();
+    }
+    if tags.len() > 1 {
+        v.message(format!("tag {} of {}", idx + 1, tags.len()));
+    }
+    Ok(())
+}
+
+/// `:[count]tag {name}`, which jumps to the `count`th tag that matches, and `:[count]tag`,
+/// which jumps to a newer tag on the tag stack
+pub fn tag(v: &mut VimInner, count: usize, name: &str) -> Result<(), String> {
+    if !name.is_empty() {
+        let tags = find(v, name)?;
+        if tags.is_empty() {
+            return Err(format!("E426: Tag not found: {name}"));
+        }
+        return jump_new(v, name, &tags, count.max(1) - 1);
+    }
+    let id = v.get_focus().id();
+    let stack = v.tags.stack(id);
+    if stack.idx + count.max(1) > stack.items.len() {
+        return Err("E556: At top of tag stack".into());
+    }
+    stack.idx += count.max(1);
+    let item = &stack.items[stack.idx - 1];
+    let (name, idx) = (item.name.clone(), item.idx);
+    let tags = find(v, &name)?;
+    let tag = tags
+        .get(idx)
+        .ok_or(format!("E426: Tag not found: {name}"))?;
+    jump(v, tag)
+}
+
+/// `CTRL-T` and `:[count]pop`, which go back to where an older tag was jumped from
+pub fn pop(v: &mut VimInner, count: usize) -> Result<(), String> {
+    let id = v.get_focus().id();
+    let stack = v.tags.stack(id);
+    if stack.idx == 0 {
+        return Err("E555: At bottom of tag stack".into());
+    }
+    stack.idx = stack.idx.saturating_sub(count.max(1));
+    let item = &stack.items[stack.idx];
+    let (buffer, (row, col)) = (item.buffer.clone(), item.from);
+    if buffer.id() != v.get_focus().buffer().id() {
+        v.get_focus_mut().set_buffer(buffer);
+    }
+    let win = v.get_focus_mut();
+    win.cursor_apply(Motion::SetRow(row));
+    win.cursor_apply(Motion::SetCol(col));
+    Ok(())
+}
+
+/// The keyword under the cursor, which `CTRL-]` and `g]` look for
+pub fn keyword(v: &VimInner) -> Result<String, String> {
+    let iskeyword = v.regex_options().iskeyword;
+    let win = v.get_focus();
+    let cursor = win.cursor();
+    win.buffer()
+        .with_read(|b| {
+            search::word_at(b[cursor.row()].text(), cursor.col(), &iskeyword)
+                .filter(|(_, _, keyword)| *keyword)
+                .map(|(_, word, _)| word.to_string())
+        })
+        .ok_or_else(|| "E349: No identifier under cursor".into())
+}
+
+/// The name of the current tag on the tag stack
+fn last_name(v: &mut VimInner) -> Result<String, String> {
+    let id = v.get_focus().id();
+    let stack = v.tags.stack(id);
+    stack
+        .idx
+        .checked_sub(1)
+        .and_then(|i| stack.items.get(i))
+        .map(|item| item.name.clone())
+        .ok_or_else(|| "E73: Tag stack empty".into())
+}
+
+/// The text of a tag's search pattern, without the delimiters and anchors
+fn pattern_text(cmd: &str) -> &str {
+    let Some(delim) = cmd.chars().next().filter(|c| *c == '/' || *c == '?') else {
+        return cmd;
+    };
+    let body = &cmd[1..];
+    let body = body.strip_suffix(delim).unwrap_or(body);
+    let body = body.strip_prefix('^').unwrap_or(body);
+    body.strip_suffix('$').unwrap_or(body).trim()
+}
+
+/// `:tselect [name]`, which lists the matching tags and jumps to the one that is chosen. With
+/// `:tjump`, a single match is jumped to without asking.
+pub fn select(v: &mut VimInner, name: &str, ask_one: bool) -> Result<(), String> {
+    let name = match name {
+        "" => last_name(v)?,
+        name => name.to_string(),
+    };
+    let tags = find(v, &name)?;
+    if tags.is_empty() {
+        return Err(format!("E426: Tag not found: {name}"));
+    }
+    if tags.len() == 1 && !ask_one {
+        return jump_new(v, &name, &tags, 0);
+    }
+    let mut text = String::from("  # pri kind tag               file\n");
+    for (i, tag) in tags.iter().enumerate() {
+        let pri = format!(
+            "F{}{}",
+            if tag.is_static { 'S' } else { ' ' },
+            if is_current(v, &tag.filename) {
+                'C'
+            } else {
+                ' '
+            }
+        );
+        text.push_str(&format!(
+            "{:>3} {pri} {:<4} {:<17} {}\n",
+            i + 1,
+            tag.kind,
+            tag.name,
+            tag.filename
+        ));
+        for (key, value) in tag.fields.iter() {
+            text.push_str(&format!("               {key}:{value}\n"));
+        }
+        text.push_str(&format!("               {}\n", pattern_text(&tag.cmd)));
+    }
+    let answer = v
+        .suspend(false, move || {
+            print!("{text}Type number and <Enter> (q or empty cancels): ");
+            let _ = io::stdout().flush();
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer).map(|_| answer)
+        })
+        .and_then(|answer| answer)
+        .map_err(|e| e.to_string())?;
+    match answer.trim().parse::<usize>() {
+        Ok(n) if n >= 1 && n <= tags.len() => jump_new(v, &name, &tags, n - 1),
+        _ => Ok(()),
+    }
+}
+
+/// `:tags`, which shows the tag stack, with a `>` at the current item
+pub fn list(v: &mut VimInner) -> Result<(), String> {
+    let id = v.get_focus().id();
+    let current = v.get_focus().buffer().id();
+    let stack = v.tags.stack(id);
+    let mut lines = vec!["  # TO tag         FROM line  in file/text".to_string()];
+    for (i, item) in stack.items.iter().enumerate() {
+        let buffer = item.buffer.read();
+        let from = match item.buffer.id() == current {
+            true => buffer
+                .get_line(item.from.0)
+                .map_or(String::new(), |l| l.text().trim().to_string()),
+            false => buffer.title().to_string(),
+        };
+        lines.push(format!(
+            "{}{:>2} {:>2} {:<15} {:>5}  {from}",
+            if i == stack.idx { '>' } else { ' ' },
+            i + 1,
+            item.idx + 1,
+            item.name,
+            item.from.0 + 1,
+        ));
+    }
+    if stack.idx == stack.items.len() {
+        lines.push(">".into());
+    }
+    v.show_lines(lines);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAGS: &str = concat!(
+        "!_TAG_FILE_FORMAT\t2\t/extended format/\n",
+        "!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/\n",
+        "Main\tsrc/b.c\t/^void Main()$/;\"\tf\n",
+        "helper\tsrc/a.c\t/^static int helper(void)$/;\"\tf\tfile:\n",
+        "main\tsrc/a.c\t/^int main(void)$/;\"\tkind:f\tsignature:(void)\n",
+        "main\tsrc/c.c\t12;\"\tf\n",
+        "zed\tz.c\t?^zed?\n",
+    );
+
+    const FOLDCASE_TAGS: &str = concat!(
+        "!_TAG_FILE_SORTED\t2\t/0=unsorted, 1=sorted, 2=foldcase/\n",
+        "abc\ta.c\t1\n",
+        "abd\ta.c\t2\n",
+        "Bcd\tb.c\t1\n",
+        "bcd\tb.c\t2\n",
+        "BCE\tb.c\t3\n",
+    );
+
+    fn name(name: &str, ignorecase: bool) -> Pattern {
+        Pattern::Name {
+            name: name.into(),
+            ignorecase,
+            len: 0,
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let tag = parse_line("main\ta.c\t/^int main(void)$/;\"\tf\tfile:\tclass:X", None).unwrap();
+        assert_eq!(tag.cmd, "/^int main(void)$/");
+        assert_eq!((tag.kind.as_str(), tag.is_static), ("f", true));
+        assert_eq!(tag.fields, [("class".to_string(), "X".to_string())]);
+        let tag = parse_line("x\ta.c\t12", Some(Path::new("dir"))).unwrap();
+        assert_eq!((tag.filename.as_str(), tag.cmd.as_str()), ("dir/a.c", "12"));
+        assert_eq!(parse_line("!_TAG_FILE_SORTED\t1\t", None), None);
+        assert_eq!(pattern_text("/^int main(void)$/"), "int main(void)");
+    }
+
+    #[test]
+    fn search() {
+        assert_eq!(sorted(TAGS), Sorted::Yes);
+        for binary in [false, true] {
+            let found = find_in(TAGS, None, &name("main", false), binary);
+            let files: Vec<_> = found.iter().map(|t| t.filename.as_str()).collect();
+            assert_eq!(files, ["src/a.c", "src/c.c"]);
+            assert_eq!(find_in(TAGS, None, &name("zed", false), binary).len(), 1);
+            assert_eq!(find_in(TAGS, None, &name("nope", false), binary).len(), 0);
+            assert_eq!(find_in(TAGS, None, &name("a", false), binary).len(), 0);
+        }
+        assert_eq!(find_in(TAGS, None, &name("MAIN", true), true).len(), 3);
+        let pat = Pattern::Name {
+            name: "helpme".into(),
+            ignorecase: false,
+            len: 4,
+        };
+        assert_eq!(find_in(TAGS, None, &pat, true).len(), 1);
+        // Names that only differ in case are together when the file folds case
+        assert_eq!(sorted(FOLDCASE_TAGS), Sorted::FoldCase);
+        let lines = |tags: Vec<Tag>| -> Vec<String> { tags.into_iter().map(|t| t.cmd).collect() };
+        for binary in [false, true] {
+            let found = find_in(FOLDCASE_TAGS, None, &name("Bcd", false), binary);
+            assert_eq!(lines(found), ["1"]);
+            let found = find_in(FOLDCASE_TAGS, None, &name("bcd", true), binary);
+            assert_eq!(lines(found), ["1", "2"]);
+            let found = find_in(FOLDCASE_TAGS, None, &name("bce", true), binary);
+            assert_eq!(lines(found), ["3"]);
+            assert!(find_in(FOLDCASE_TAGS, None, &name("bce", false), binary).is_empty());
+            assert!(find_in(FOLDCASE_TAGS, None, &name("abe", false), binary).is_empty());
+        }
+    }
+}
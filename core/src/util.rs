@@ -109,6 +109,15 @@ impl<'s> Iterator for LineIter<'s> {
     }
 }
 
+/// `n` followed by the singular or plural noun, like "1 line" or "3 lines"
+pub fn plural(n: usize, one: &str, many: &str) -> String {
+    if n == 1 {
+        format!("1 {one}")
+    } else {
+        format!("{n} {many}")
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Pos(pub usize, pub usize);
 
@@ -0,0 +1,302 @@
+//
+// width.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::borrow::Cow;
+
+use unicode_segmentation::{GraphemeIndices, UnicodeSegmentation};
+use unicode_width::UnicodeWidthChar;
+
+use crate::options::{AmbiWidth, Options};
+
+/// The options that control how many screen cells text takes up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WidthOpts {
+    pub tabstop: usize,
+    pub ambiwidth: AmbiWidth,
+    pub emoji: bool,
+}
+
+impl Default for WidthOpts {
+    fn default() -> Self {
+        Self {
+            tabstop: 8,
+            ambiwidth: AmbiWidth::single,
+            emoji: true,
+        }
+    }
+}
+
+impl WidthOpts {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            tabstop: if options.tabstop > 0 {
+                options.tabstop as usize
+            } else {
+                8
+            },
+            ambiwidth: options.ambiwidth,
+            emoji: options.emoji,
+        }
+    }
+
+    /// Width of a single character, ignoring any characters it may be combined with. Control
+    /// characters are displayed as `^X` or `<80>`, and take up the space that needs.
+    pub fn char_width(&self, c: char) -> usize {
+        if c < ' ' || c == '\x7f' {
+            2
+        } else if ('\u{80}'..'\u{a0}').contains(&c) {
+            4
+        } else if self.emoji && is_emoji(c) {
+            2
+        } else {
+            match self.ambiwidth {
+                AmbiWidth::single => c.width(),
+                AmbiWidth::double => c.width_cjk(),
+            }
+            .unwrap_or(1)
+        }
+    }
+
+    /// Width of an extended grapheme cluster. Combining characters take no space of their own,
+    /// but a cluster with nothing to combine with is drawn on top of a space.
+    pub fn grapheme_width(&self, g: &str) -> usize {
+        let mut chars = g.chars();
+        let base = match chars.next() {
+            Some(c) => c,
+            None => return 0,
+        };
+        let width = self.char_width(base);
+        if width == 0 {
+            1
+        } else if self.emoji
+            && width < 2
+            && (g.contains('\u{fe0f}') || (is_regional_indicator(base) && chars.next().is_some()))
+        {
+            2
+        } else {
+            width
+        }
+    }
+
+    pub fn tab_width(&self, col: usize) -> usize {
+        self.tabstop - col % self.tabstop
+    }
+
+    /// Width of the grapheme `g` when it is displayed starting at screen column `col`
+    pub fn cell_width(&self, g: &str, col: usize) -> usize {
+        if g == "\t" {
+            self.tab_width(col)
+        } else {
+            self.grapheme_width(g)
+        }
+    }
+
+    /// Width of a string, where a tab only takes up a single cell (see `strwidth()`)
+    pub fn str_width(&self, s: &str) -> usize {
+        s.graphemes(true)
+            .map(|g| if g == "\t" { 1 } else { self.grapheme_width(g) })
+            .sum()
+    }
+
+    /// Width of a string starting at screen column `start` (see `strdisplaywidth()`)
+    pub fn display_width(&self, s: &str, start: usize) -> usize {
+        self.cells_from(s, start)
+            .last()
+            .map_or(0, |c| c.col + c.width - start)
+    }
+
+    pub fn cells<'a>(&self, s: &'a str) -> Cells<'a> {
+        self.cells_from(s, 0)
+    }
+
+    pub fn cells_from<'a>(&self, s: &'a str, start: usize) -> Cells<'a> {
+        Cells {
+            opts: *self,
+            iter: s.grapheme_indices(true),
+            col: start,
+        }
+    }
+
+    /// Screen column that the character at byte index `byte` starts on
+    pub fn col_of(&self, s: &str, byte: usize) -> usize {
+        let mut col = 0;
+        for cell in self.cells(s) {
+            if cell.byte >= byte {
+                return cell.col;
+            }
+            col = cell.col + cell.width;
+        }
+        col
+    }
+
+    /// Byte index of the grapheme that covers screen column `col`, or the length of the string if
+    /// the column is past the end.
+    pub fn byte_at(&self, s: &str, col: usize) -> usize {
+        self.cells(s)
+            .find(|c| c.col + c.width > col)
+            .map_or(s.len(), |c| c.byte)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell<'a> {
+    pub byte: usize,
+    pub text: &'a str,
+    pub col: usize,
+    pub width: usize,
+}
+
+impl<'a> Cell<'a> {
+    /// The text that should be written to the terminal to display this cell
+    pub fn display(&self) -> Cow<'a, str> {
+        let mut chars = self.text.chars();
+        match chars.next() {
+            Some('\t') => Cow::Owned(" ".repeat(self.width)),
+            Some(c) if c < ' ' || c == '\x7f' => {
+                Cow::Owned(format!("^{}", ((c as u8) ^ 0x40) as char))
+            }
+            Some(c) if ('\u{80}'..'\u{a0}').contains(&c) => {
+                Cow::Owned(format!("<{:02x}>", c as u32))
+            }
+            Some(c) if c.width() == Some(0) => Cow::Owned(format!(" {}", self.text)),
+            _ => Cow::Borrowed(self.text),
+        }
+    }
+}
+
+pub struct Cells<'a> {
+    opts: WidthOpts,
+    iter: GraphemeIndices<'a>,
+    col: usize,
+}
+
+impl<'a> Iterator for Cells<'a> {
+    type Item = Cell<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let (byte, text) = self.iter.next()?;
+        let width = self.opts.cell_width(text, self.col);
+        let cell = Cell {
+            byte,
+            text,
+            col: self.col,
+            width,
+        };
+        self.col += width;
+        Some(cell)
+    }
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+/// Emoji that are drawn full width when 'emoji' is set. Text style emoji (such as U+263A) are
+/// not included, since terminals draw them as a single cell.
+const EMOJI_WIDE: &[(u32, u32)] = &[
+    (0x1f004, 0x1f004),
+    (0x1f0cf, 0x1f0cf),
+    (0x1f18e, 0x1f18e),
+    (0x1f191, 0x1f19a),
+    (0x1f1e6, 0x1f1ff),
+    (0x1f201, 0x1f202),
+    (0x1f21a, 0x1f21a),
+    (0x1f22f, 0x1f22f),
+    (0x1f232, 0x1f23a),
+    (0x1f250, 0x1f251),
+    (0x1f300, 0x1f64f),
+    (0x1f680, 0x1f6ff),
+    (0x1f7e0, 0x1f7eb),
+    (0x1f90c, 0x1f93a),
+    (0x1f93c, 0x1f945),
+    (0x1f947, 0x1f9ff),
+    (0x1fa70, 0x1faff),
+];
+
+fn is_emoji(c: char) -> bool {
+    let c = c as u32;
+    EMOJI_WIDE
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                std::cmp::Ordering::Less
+            } else if start > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii() {
+        let w = WidthOpts::default();
+        assert_eq!(w.str_width("hello"), 5);
+        assert_eq!(w.col_of("hello", 3), 3);
+        assert_eq!(w.byte_at("hello", 3), 3);
+    }
+
+    #[test]
+    fn wide() {
+        let w = WidthOpts::default();
+        assert_eq!(w.str_width("日本"), 4);
+        assert_eq!(w.col_of("日本語", 3), 2);
+        assert_eq!(w.byte_at("日本語", 3), 3);
+        assert_eq!(w.byte_at("日本語", 10), 9);
+    }
+
+    #[test]
+    fn combining() {
+        let w = WidthOpts::default();
+        assert_eq!(w.str_width("e\u{301}a"), 2);
+        assert_eq!(w.col_of("e\u{301}a", 3), 1);
+        assert_eq!(w.grapheme_width("\u{301}"), 1);
+    }
+
+    #[test]
+    fn emoji() {
+        let w = WidthOpts::default();
+        assert_eq!(w.grapheme_width("\u{1f1fa}\u{1f1f8}"), 2);
+        assert_eq!(w.grapheme_width("\u{2764}\u{fe0f}"), 2);
+        let no_emoji = WidthOpts {
+            emoji: false,
+            ..WidthOpts::default()
+        };
+        assert_eq!(no_emoji.grapheme_width("\u{2764}\u{fe0f}"), 1);
+    }
+
+    #[test]
+    fn ambiguous() {
+        let single = WidthOpts::default();
+        let double = WidthOpts {
+            ambiwidth: AmbiWidth::double,
+            ..WidthOpts::default()
+        };
+        assert_eq!(single.grapheme_width("\u{b1}"), 1);
+        assert_eq!(double.grapheme_width("\u{b1}"), 2);
+    }
+
+    #[test]
+    fn tabs() {
+        let w = WidthOpts::default();
+        assert_eq!(w.display_width("\tx", 0), 9);
+        assert_eq!(w.display_width("\tx", 3), 6);
+        assert_eq!(w.str_width("\tx"), 2);
+        assert_eq!(w.col_of("a\tb", 2), 8);
+        assert_eq!(w.byte_at("a\tb", 5), 1);
+    }
+
+    #[test]
+    fn control() {
+        let w = WidthOpts::default();
+        assert_eq!(w.str_width("\x01"), 2);
+        let cell = w.cells("\x01").next().unwrap();
+        assert_eq!(cell.display(), "^A");
+    }
+}
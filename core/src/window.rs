@@ -4,21 +4,29 @@
 // Distributed under terms of the MIT license.
 //
 
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::io::Write;
-use std::ops::Deref;
+use std::ops::{Deref, RangeInclusive};
 use std::sync::Arc;
 
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
 use crossterm::Result;
 use log::info;
+use vimscript::regex::{self, Regex};
 use vimscript::Id;
 
-use crate::buffer::{BufferRef, BufferSelect, Signs};
+use crate::buffer::{Buffer, BufferRef, BufferSelect, Overlay, Signs};
 use crate::cursor::CursorShape;
+use crate::fold::{self, FoldChars, FoldLevel, FoldTree};
+use crate::highlight::Highlights;
 use crate::keymap::{Action, KeyState};
-use crate::options::{WinOptions, Opts};
+use crate::matches::Matches;
+use crate::options::{FoldMethod, Options, Opts, WinOptions};
+use crate::search;
 use crate::util::Pos;
+use crate::width::WidthOpts;
+use crate::wrap::{LastLine, Row, WrapOpts};
 use crate::Vim;
 use crate::{cursor::Motion, Area, Cursor, EventReader, Renderable};
 
@@ -32,6 +40,9 @@ bitfield::bitfield! {
     pub relative, set_relative: 3;
     pub status, set_status: 4;
     pub buffer, set_buffer: 5;
+    pub foldcolumn, set_foldcolumn: 6;
+    /// Only the lines the cursor line highlighting moved between
+    pub cursorline, set_cursorline: 7;
 }
 
 impl WindowProps {
@@ -42,6 +53,7 @@ impl WindowProps {
         s.set_linenum(true);
         s.set_status(true);
         s.set_buffer(true);
+        s.set_foldcolumn(true);
         s
     }
 
@@ -57,15 +69,18 @@ impl Default for WindowProps {
         s.set_linenum(true);
         s.set_status(true);
         s.set_buffer(true);
+        s.set_foldcolumn(true);
         s
     }
 }
 
 pub(crate) mod op {
-    use crossterm::event::KeyEvent;
+    use crossterm::event::{KeyCode, KeyEvent};
     use std::sync::Arc;
 
-    use super::{Operation, Window};
+    use super::Operation;
+    use crate::cli::Cli;
+    use crate::Vim;
 
     pub fn delete() -> Arc<dyn Operation> {
         Arc::new(DeleteOp)
@@ -73,8 +88,8 @@ pub(crate) mod op {
 
     struct DeleteOp;
     impl Operation for DeleteOp {
-        fn run(&self, window: &mut Window, key: KeyEvent) {
-            let start = window.cursor().pos();
+        fn run(&self, v: &mut Vim, key: KeyEvent) {
+            let start = v.get_focus().cursor().pos();
 
             todo!()
         }
@@ -87,10 +102,76 @@ pub(crate) mod op {
     pub fn replace() -> Arc<dyn Operation> {
         Arc::new(DeleteOp)
     }
+
+    /// `m`, which sets a mark at the cursor
+    pub fn mark() -> Arc<dyn Operation> {
+        Arc::new(MarkOp)
+    }
+
+    struct MarkOp;
+    impl Operation for MarkOp {
+        fn run(&self, v: &mut Vim, key: KeyEvent) {
+            let window = v.get_focus_mut();
+            if let KeyCode::Char(c @ 'a'..='z') = key.code {
+                let pos = window.cursor().pos();
+                window.buffer().write().set_mark(c, pos);
+            }
+        }
+    }
+
+    /// `zf`, which creates a fold over the lines the motion moves over
+    pub fn fold(count: usize) -> Arc<dyn Operation> {
+        Arc::new(FoldOp(count))
+    }
+
+    struct FoldOp(usize);
+    impl Operation for FoldOp {
+        fn run(&self, v: &mut Vim, key: KeyEvent) {
+            let window = v.get_focus_mut();
+            let start = window.cursor().row();
+            let end = match key.code {
+                KeyCode::Char('j') | KeyCode::Down => start + self.0,
+                KeyCode::Char('k') | KeyCode::Up => start.saturating_sub(self.0),
+                KeyCode::Char('G') => window.buffer().read().len() - 1,
+                _ => return,
+            };
+            let end = end.min(window.buffer().read().len() - 1);
+            let _ = window.create_fold(start.min(end), start.max(end));
+        }
+    }
+
+    /// `!`, which starts a command line that filters the lines the motion moves over
+    pub fn filter(count: usize) -> Arc<dyn Operation> {
+        Arc::new(FilterOp(count))
+    }
+
+    struct FilterOp(usize);
+    impl Operation for FilterOp {
+        fn run(&self, v: &mut Vim, key: KeyEvent) {
+            let window = v.get_focus();
+            let start = window.cursor().row();
+            let last = window.buffer().read().len() - 1;
+            let end = match key.code {
+                KeyCode::Char('!') => start + self.0 - 1,
+                KeyCode::Char('j') | KeyCode::Down => start + self.0,
+                KeyCode::Char('k') | KeyCode::Up => start.saturating_sub(self.0),
+                KeyCode::Char('G') => last,
+                _ => return,
+            };
+            let end = end.min(last);
+            let range = match (start.min(end), start.max(end)) {
+                (first, last) if first == last => ".".to_string(),
+                (first, last) if first == start => format!(".,.+{}", last - first),
+                (first, last) => format!(".-{},.", last - first),
+            };
+            v.start_cli(Cli::Command);
+            v.cli.insert(&format!("{range}!"));
+        }
+    }
 }
 
 pub trait Operation {
-    fn run(&self, window: &mut Window, key: KeyEvent);
+    fn run(&self, v: &mut Vim, key: KeyEvent);
 }
 
 #[derive(Clone)]
@@ -155,6 +236,13 @@ pub enum Scroll {
     Right,
 }
 
+/// Where a line is put in the window
+pub enum ViewPos {
+    Top,
+    Center,
+    Bottom,
+}
+
 pub enum Dist {
     One,
     Step,
@@ -162,9 +250,18 @@ pub enum Dist {
     Screen,
 }
 
+/// The cursor and the scroll position of a window
+#[derive(Debug, Clone, Copy)]
+pub struct View {
+    pub cursor: Cursor,
+    row: usize,
+    col: usize,
+}
+
 pub struct VisibileArea {
     screen_pos: Area,
     buffer_row: usize,
+    /// First visible screen column (not byte) of each line
     buffer_col: usize,
 }
 
@@ -177,6 +274,109 @@ pub struct Window {
     cursor: Cursor,
     mode: WinMode,
     options: WinOptions,
+    /// Width settings as of the last redraw
+    width: WidthOpts,
+    /// Wrap settings as of the last redraw
+    wrap: WrapOpts,
+    /// Scroll settings as of the last redraw
+    scroll_opts: ScrollOpts,
+    /// Where visual mode was started
+    visual_start: Pos,
+    folds: FoldTree,
+    /// Buffer tick and options the folds were last computed for
+    fold_tick: usize,
+    fold_syntax_tick: usize,
+    /// Tick of the highlight groups the window was drawn with
+    highlight_tick: usize,
+    fold_opts: Option<FoldOpts>,
+    /// 'foldlevel' the folds were last opened and closed for
+    fold_level: isize,
+    /// Set when 'foldexpr' needs to be evaluated again
+    fold_expr_pending: bool,
+    /// Text shown for each closed fold, by the first line of the fold
+    fold_text: HashMap<usize, String>,
+    /// Closed folds that were drawn without evaluating 'foldtext'
+    fold_text_missing: Vec<(usize, usize, usize)>,
+    /// Fold column width, fold characters and effective 'shiftwidth' as of the last redraw
+    foldcolumn: usize,
+    fold_chars: FoldChars,
+    shiftwidth: usize,
+    matches: Matches,
+    /// Cursor line as of the last redraw, when it was drawn without concealing
+    conceal_row: Option<usize>,
+    /// Cursor line and column highlighting and 'colorcolumn' as of the last redraw
+    cursorline: Option<CursorLine>,
+    cursorcolumn: Option<usize>,
+    colorcolumn: Vec<usize>,
+    /// Pattern highlighted by 'hlsearch', and the match of the search being typed
+    search: Option<Arc<Regex>>,
+    incsearch: Option<(regex::Pos, regex::Pos)>,
+}
+
+/// How the cursor line is highlighted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CursorLine {
+    line: usize,
+    /// Start of the screen row with the cursor, when only that row is highlighted
+    row: Option<usize>,
+    text: bool,
+    number: bool,
+}
+
+/// The screen columns in 'colorcolumn', where `+N` and `-N` are relative to 'textwidth'
+fn color_columns(spec: &str, textwidth: isize) -> Vec<usize> {
+    spec.split(',')
+        .filter_map(|c| {
+            let col = match c.as_bytes().first()? {
+                b'+' | b'-' if textwidth > 0 => textwidth + c.parse::<isize>().ok()?,
+                b'+' | b'-' => return None,
+                _ => c.parse().ok()?,
+            };
+            (col > 0).then(|| col as usize - 1)
+        })
+        .collect()
+}
+
+/// The options that decide which folds there are
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FoldOpts {
+    method: FoldMethod,
+    marker: String,
+    ignore: String,
+    expr: String,
+    nestmax: usize,
+    shiftwidth: usize,
+}
+
+impl FoldOpts {
+    fn new(options: &WinOptions, shiftwidth: usize) -> Self {
+        Self {
+            method: options.foldmethod,
+            marker: options.foldmarker.clone(),
+            ignore: options.foldignore.clone(),
+            expr: options.foldexpr.clone(),
+            nestmax: options.foldnestmax.max(0) as usize,
+            shiftwidth,
+        }
+    }
+}
+
+/// The global options that control how the view follows the cursor
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct ScrollOpts {
+    scrolloff: usize,
+    sidescroll: usize,
+    sidescrolloff: usize,
+}
+
+impl ScrollOpts {
+    fn new(options: &Options) -> Self {
+        Self {
+            scrolloff: options.scrolloff.max(0) as usize,
+            sidescroll: options.sidescroll.max(0) as usize,
+            sidescrolloff: options.sidescrolloff.max(0) as usize,
+        }
+    }
 }
 
 impl Window {
@@ -194,6 +394,29 @@ impl Window {
             cursor: Cursor::new(),
             mode: WinMode::Normal,
             options: WinOptions::new(),
+            width: WidthOpts::default(),
+            wrap: WrapOpts::default(),
+            scroll_opts: ScrollOpts::default(),
+            visual_start: Pos(0, 0),
+            folds: FoldTree::default(),
+            fold_tick: 0,
+            fold_syntax_tick: 0,
+            highlight_tick: 0,
+            fold_opts: None,
+            fold_level: 0,
+            fold_expr_pending: false,
+            fold_text: HashMap::new(),
+            fold_text_missing: vec![],
+            foldcolumn: 0,
+            fold_chars: FoldChars::parse(""),
+            shiftwidth: 8,
+            matches: Matches::default(),
+            conceal_row: None,
+            cursorline: None,
+            cursorcolumn: None,
+            colorcolumn: vec![],
+            search: None,
+            incsearch: None,
         }
     }
 
@@ -205,10 +428,54 @@ impl Window {
         &self.options
     }
 
+    pub fn matches(&self) -> &Matches {
+        &self.matches
+    }
+
+    pub fn matches_mut(&mut self) -> &mut Matches {
+        self.window_updates.set_buffer(true);
+        &mut self.matches
+    }
+
     pub fn options_mut(&mut self) -> &mut WinOptions {
         &mut self.options
     }
 
+    /// Sets the pattern highlighted by 'hlsearch', and the match highlighted by 'incsearch'
+    pub fn set_search_highlight(
+        &mut self,
+        search: Option<Arc<Regex>>,
+        incsearch: Option<(regex::Pos, regex::Pos)>,
+    ) {
+        let same = match (&self.search, &search) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        if !same || incsearch != self.incsearch {
+            self.window_updates.set_buffer(true);
+        }
+        self.search = search;
+        self.incsearch = incsearch;
+    }
+
+    pub fn view(&self) -> View {
+        View {
+            cursor: self.cursor,
+            row: self.buffer_view.buffer_row,
+            col: self.buffer_view.buffer_col,
+        }
+    }
+
+    /// Goes back to a view from `view()`
+    pub fn set_view(&mut self, view: View) {
+        self.cursor = view.cursor;
+        if (view.row, view.col) != (self.buffer_view.buffer_row, self.buffer_view.buffer_col) {
+            self.buffer_view.buffer_row = view.row;
+            self.buffer_view.buffer_col = view.col;
+            self.on_scroll();
+        }
+    }
+
     pub fn buffer_select(&self, criteria: &impl BufferSelect) -> bool {
         criteria.select(self.buffer.read().deref())
     }
@@ -223,47 +490,411 @@ impl Window {
 
     pub fn cursor_apply(&mut self, motion: Motion) -> &mut Self {
         // let old_cursor = self.cursor;
-        self.cursor.apply(
-            motion,
-            &self.buffer.read(),
-            matches!(self.mode, WinMode::Insert),
-        );
-        if self.cursor.row() < self.buffer_view.buffer_row {
-            self.buffer_view.buffer_row = self.cursor.row();
-            self.on_scroll();
-        } else if self.cursor.row()
-            >= self.buffer_view.buffer_row + self.buffer_view.screen_pos.height()
-        {
-            self.buffer_view.buffer_row =
-                self.cursor.row() - self.buffer_view.screen_pos.height() + 1;
-            self.on_scroll();
+        self.sync_folds();
+        // Moving down from a closed fold moves past the whole fold
+        let motion = match (motion, self.closed_fold(self.cursor.row())) {
+            (Motion::Down, Some(fold)) => Motion::SetRow(fold.end() + 1),
+            _ => motion,
+        };
+        let insert = matches!(self.mode, WinMode::Insert);
+        self.cursor
+            .apply(motion, &self.buffer.read(), insert, &self.width);
+        // The cursor is always on the first line of a closed fold
+        let start = self.fold_start(self.cursor.row());
+        if start != self.cursor.row() {
+            self.cursor
+                .apply(Motion::SetRow(start), &self.buffer.read(), insert, &self.width);
+        }
+        self.scroll_to_cursor();
+        self
+    }
+
+    /// Moves the cursor one screen row down or up (`gj` and `gk`). Without 'wrap', this is the
+    /// same as moving by lines.
+    pub fn cursor_screen_line(&mut self, down: bool) -> &mut Self {
+        let row = self.cursor.row();
+        let next = self.next_line(row);
+        let folded = self.closed_fold(row).is_some()
+            || (down && self.closed_fold(next).is_some())
+            || (!down && row > 0 && self.closed_fold(row - 1).is_some());
+        if !self.wrap.wrap || folded {
+            return self.cursor_apply(if down { Motion::Down } else { Motion::Up });
+        }
+        let buf = self.buffer.read();
+        let rows = self.line_rows(&buf, self.cursor.row());
+        let r = row_of(&rows, self.cursor.col());
+        let col = self.cursor_cols().0.saturating_sub(rows[r].cols.start);
+        let (line, rows, r) = if down && r + 1 < rows.len() {
+            (self.cursor.row(), rows, r + 1)
+        } else if !down && r > 0 {
+            (self.cursor.row(), rows, r - 1)
+        } else if down && next < buf.len() {
+            (next, self.line_rows(&buf, next), 0)
+        } else if !down && self.cursor.row() > 0 {
+            let rows = self.line_rows(&buf, self.cursor.row() - 1);
+            let last = rows.len() - 1;
+            (self.cursor.row() - 1, rows, last)
+        } else {
+            drop(buf);
+            return self;
+        };
+        let target = &rows[r];
+        let byte = self
+            .width
+            .byte_at(buf[line].text(), target.cols.start + col)
+            .min(target.bytes.end);
+        let byte = if byte == target.bytes.end && r + 1 < rows.len() {
+            buf[line].prev(byte)
+        } else {
+            byte
+        };
+        drop(buf);
+        self.cursor_apply(Motion::SetRow(line));
+        self.cursor_apply(Motion::SetCol(byte))
+    }
+
+    /// The screen rows `line` is displayed on
+    fn line_rows(&self, buf: &Buffer, line: usize) -> Vec<Row> {
+        let text = buf[line].text();
+        if self.wrap.wrap {
+            self.wrap.rows(text, &self.width, self.buffer_area().width())
+        } else {
+            vec![Row {
+                bytes: 0..text.len(),
+                cols: self.buffer_view.buffer_col..usize::MAX,
+                indent: 0,
+            }]
+        }
+    }
+
+    fn line_height(&self, buf: &Buffer, line: usize) -> usize {
+        if self.wrap.wrap && self.closed_fold(line).is_none() {
+            self.line_rows(buf, line).len()
+        } else {
+            1
+        }
+    }
+
+    /// The first line to show so that `last` is the last line that fits in the window, without
+    /// going above `min`
+    fn top_for_bottom(&self, buf: &Buffer, last: usize, min: usize) -> usize {
+        let height = self.buffer_area().height();
+        let mut top = self.fold_start(last);
+        let mut used = self.line_height(buf, top);
+        while top > min {
+            let prev = self.fold_start(top - 1);
+            let h = self.line_height(buf, prev);
+            if used + h > height {
+                break;
+            }
+            used += h;
+            top = prev;
+        }
+        top
+    }
+
+    /// The last line that fits in the window completely when `top` is the first line shown
+    fn bottom_for_top(&self, buf: &Buffer, top: usize) -> usize {
+        let height = self.buffer_area().height();
+        let mut bottom = self.fold_start(top);
+        let mut used = self.line_height(buf, bottom);
+        loop {
+            let next = self.next_line(bottom);
+            if next >= buf.len() {
+                break;
+            }
+            let h = self.line_height(buf, next);
+            if used + h > height {
+                break;
+            }
+            used += h;
+            bottom = next;
         }
-        if self.cursor.col() < self.buffer_view.buffer_col {
-            self.buffer_view.buffer_col = self.cursor.col();
+        bottom
+    }
+
+    fn scrolloff(&self) -> usize {
+        self.scroll_opts
+            .scrolloff
+            .min(self.buffer_area().height().saturating_sub(1) / 2)
+    }
+
+    fn sidescrolloff(&self) -> usize {
+        self.scroll_opts
+            .sidescrolloff
+            .min(self.buffer_area().width().saturating_sub(1) / 2)
+    }
+
+    /// Adjusts the scroll so the cursor line, and the character under the cursor, are visible
+    fn scroll_to_cursor(&mut self) {
+        let so = self.scrolloff();
+        let row = self.fold_start(self.cursor.row());
+        let buf = self.buffer.read();
+        let above = self.lines_above(row, so);
+        let top = if above < self.buffer_view.buffer_row {
+            above
+        } else {
+            let last = self.lines_below(&buf, row, so);
+            self.top_for_bottom(&buf, last, self.buffer_view.buffer_row)
+                .min(row)
+        };
+        let top = self.fold_start(top);
+        drop(buf);
+        if top != self.buffer_view.buffer_row {
+            self.buffer_view.buffer_row = top;
             self.on_scroll();
-        } else if self.cursor.col()
-            >= self.buffer_view.buffer_col + self.buffer_view.screen_pos.width()
-        {
-            self.buffer_view.buffer_col =
-                self.cursor.col() - self.buffer_view.screen_pos.width() + 1;
+        }
+        if !self.wrap.wrap {
+            self.scroll_to_cursor_col();
+        } else if self.buffer_view.buffer_col != 0 {
+            self.buffer_view.buffer_col = 0;
             self.on_scroll();
         }
-        self
     }
 
-    pub fn run_operation(&mut self, key_event: KeyEvent) {
-        if let WinMode::Operation(op) = std::mem::replace(&mut self.mode, WinMode::Normal) {
-            op.run(self, key_event);
+    /// Moves the cursor onto the lines that are visible after scrolling, keeping 'scrolloff'
+    fn cursor_into_view(&mut self) {
+        let so = self.scrolloff();
+        let top = self.buffer_view.buffer_row;
+        let buf = self.buffer.read();
+        let bottom = self.bottom_for_top(&buf, top);
+        let last = if self.next_line(bottom) < buf.len() {
+            self.lines_above(bottom, so)
+        } else {
+            bottom
+        };
+        let first = if top == 0 {
+            0
+        } else {
+            self.lines_below(&buf, top, so).min(last)
+        };
+        drop(buf);
+        if self.cursor.row() < first {
+            self.cursor_apply(Motion::SetRow(first));
+        } else if self.cursor.row() > last {
+            self.cursor_apply(Motion::SetRow(last.max(first)));
+        }
+    }
+
+    /// Moves the cursor onto the columns that are visible after scrolling sideways, keeping
+    /// 'sidescrolloff'
+    fn cursor_into_view_col(&mut self) {
+        let (start, end) = self.cursor_cols();
+        let siso = self.sidescrolloff();
+        let left = self.buffer_view.buffer_col
+            + if self.buffer_view.buffer_col == 0 {
+                0
+            } else {
+                siso
+            };
+        let right = self.buffer_view.buffer_col + self.buffer_area().width() - siso;
+        let buf = self.buffer.read();
+        let line = &buf[self.cursor.row()];
+        let col = if start < left {
+            let byte = self.width.byte_at(line.text(), left);
+            if self.width.col_of(line.text(), byte) < left {
+                line.next(byte, false)
+            } else {
+                byte
+            }
+        } else if end > right {
+            line.prev(self.width.byte_at(line.text(), right))
+        } else {
+            return;
+        };
+        drop(buf);
+        self.cursor_apply(Motion::SetCol(col));
+    }
+
+    /// Screen columns taken up by the character under the cursor. The end of the line counts as a
+    /// single column.
+    fn cursor_cols(&self) -> (usize, usize) {
+        let buf = self.buffer.read();
+        let text = buf[self.cursor.row()].text();
+        self.width
+            .cells(text)
+            .find(|c| c.byte >= self.cursor.col())
+            .map_or_else(
+                || {
+                    let end = self.width.display_width(text, 0);
+                    (end, end + 1)
+                },
+                |c| (c.col, c.col + c.width),
+            )
+    }
+
+    /// Adjusts the horizontal scroll so the whole character under the cursor is visible, with
+    /// 'sidescrolloff' columns around it. Scrolls by at least 'sidescroll' columns, or puts the
+    /// cursor in the middle of the window if that is zero.
+    fn scroll_to_cursor_col(&mut self) {
+        let (start, end) = self.cursor_cols();
+        let width = self.buffer_area().width();
+        let siso = self.sidescrolloff();
+        let ss = self.scroll_opts.sidescroll;
+        let left = self.buffer_view.buffer_col;
+        let col = if start.saturating_sub(siso) < left {
+            if ss == 0 {
+                start.saturating_sub(width / 2)
+            } else {
+                start.saturating_sub(siso).min(left.saturating_sub(ss))
+            }
+        } else if end + siso > left + width {
+            if ss == 0 {
+                start.saturating_sub(width / 2)
+            } else {
+                (end + siso - width).max(left + ss)
+            }
+        } else {
+            return;
+        };
+        self.buffer_view.buffer_col = col;
+        self.on_scroll();
+    }
+
+    /// Scrolls the text and the cursor together by 'scroll' lines (CTRL-D and CTRL-U)
+    pub fn scroll_with_cursor(&mut self, scroll: Scroll) {
+        let n = match self.options.scroll {
+            n if n > 0 => n as usize,
+            _ => (self.buffer_area().height() / 2).max(1),
+        };
+        let buf = self.buffer.read();
+        let top = self.buffer_view.buffer_row;
+        let row = self.cursor.row();
+        let (top, row) = match scroll {
+            Scroll::Down => {
+                let last_top = self.top_for_bottom(&buf, buf.len() - 1, 0).max(top);
+                (
+                    self.lines_below(&buf, top, n).min(last_top),
+                    self.lines_below(&buf, row, n),
+                )
+            }
+            Scroll::Up => (self.lines_above(top, n), self.lines_above(row, n)),
+            Scroll::Left | Scroll::Right => return,
+        };
+        drop(buf);
+        self.buffer_view.buffer_row = top;
+        self.on_scroll();
+        self.cursor_apply(Motion::SetRow(row));
+    }
+
+    /// Scrolls so the cursor line is at the top, middle or bottom of the window (`zt`, `zz` and
+    /// `zb`)
+    pub fn scroll_cursor_to(&mut self, pos: ViewPos) {
+        let so = self.scrolloff();
+        let row = self.fold_start(self.cursor.row());
+        let buf = self.buffer.read();
+        let top = match pos {
+            ViewPos::Top => self.lines_above(row, so),
+            ViewPos::Center => {
+                let above = self
+                    .buffer_area()
+                    .height()
+                    .saturating_sub(self.line_height(&buf, row))
+                    / 2;
+                let mut top = row;
+                let mut used = 0;
+                while top > 0 {
+                    let prev = self.fold_start(top - 1);
+                    let h = self.line_height(&buf, prev);
+                    if used + h > above {
+                        break;
+                    }
+                    used += h;
+                    top = prev;
+                }
+                top
+            }
+            ViewPos::Bottom => self
+                .top_for_bottom(&buf, self.lines_below(&buf, row, so), 0)
+                .min(row),
+        };
+        drop(buf);
+        self.buffer_view.buffer_row = top;
+        self.on_scroll();
+    }
+
+    /// Scrolls sideways so the cursor is at the start (`zs`) or end (`ze`) of the window
+    pub fn scroll_cursor_col_to(&mut self, end: bool) {
+        if self.wrap.wrap {
+            return;
+        }
+        let (start, stop) = self.cursor_cols();
+        let siso = self.sidescrolloff();
+        self.buffer_view.buffer_col = if end {
+            (stop + siso).saturating_sub(self.buffer_area().width())
+        } else {
+            start.saturating_sub(siso)
+        };
+        self.on_scroll();
+    }
+
+    /// Deletes the grapheme under the cursor (`x`)
+    pub fn delete_grapheme(&mut self, delcombine: bool) {
+        let col = self.cursor.col();
+        if col < self.buffer.read()[self.cursor.row()].len() {
+            self.buffer
+                .write()
+                .remove_grapheme(self.cursor.row(), col, delcombine);
+            self.cursor_apply(Motion::SetCol(col));
+            self.window_updates.set_buffer(true);
+        }
+    }
+
+    /// Deletes the grapheme under the cursor, or joins the next line at the end of a line
+    /// (`<Del>` in insert mode)
+    pub fn delete_forward(&mut self, delcombine: bool) {
+        if self.cursor.col() < self.buffer.read()[self.cursor.row()].len() {
+            self.delete_grapheme(delcombine);
+        } else if self.cursor.row() + 1 < self.buffer.read().len() {
+            self.buffer().write().join_line(self.cursor.row());
+            self.window_updates.set_buffer(true);
+            self.window_updates.set_linenum(true);
+            self.window_updates.set_gutter(true);
+        }
+    }
+
+    /// Deletes the grapheme before the cursor, or joins with the previous line at the start of a
+    /// line (`<BS>` in insert mode)
+    pub fn delete_backward(&mut self, delcombine: bool) {
+        let col = self.cursor.col();
+        if col > 0 {
+            let start = self.buffer.read()[self.cursor.row()].prev(col);
+            let removed = self
+                .buffer
+                .write()
+                .remove_grapheme(self.cursor.row(), start, delcombine);
+            self.cursor_apply(Motion::SetCol(col - removed));
+            self.window_updates.set_buffer(true);
+        } else if self.cursor.row() > 0 {
+            self.cursor_apply(Motion::Up);
+            self.cursor_apply(Motion::End);
+            self.buffer().write().join_line(self.cursor.row());
+            self.window_updates.set_buffer(true);
+            self.window_updates.set_linenum(true);
+            self.window_updates.set_gutter(true);
+        }
+    }
+
+    /// Ends the operator that is waiting for a motion, and returns it
+    pub fn take_operation(&mut self) -> Option<Arc<dyn Operation>> {
+        match std::mem::replace(&mut self.mode, WinMode::Normal) {
+            WinMode::Operation(op) => Some(op),
+            mode => {
+                self.mode = mode;
+                None
+            }
         }
     }
 
     fn on_scroll(&mut self) {
+        self.window_updates.set_foldcolumn(true);
         self.window_updates.set_gutter(true);
         self.window_updates.set_buffer(true);
         self.window_updates.set_linenum(true);
     }
 
     pub fn redraw_all(&mut self) {
+        self.window_updates.set_foldcolumn(true);
         self.window_updates.set_gutter(true);
         self.window_updates.set_buffer(true);
         self.window_updates.set_linenum(true);
@@ -274,25 +905,44 @@ impl Window {
 
     pub fn scroll(&mut self, scroll: Scroll, dist: Dist) {
         match scroll {
-            Scroll::Down => {
-                self.buffer_view.buffer_row = self
-                    .buffer_view
-                    .buffer_row
-                    .saturating_add(self.row_dist(dist))
-                    .min(self.buffer.read().len().saturating_sub(1))
-            }
-            Scroll::Up => {
-                self.buffer_view.buffer_row = self
-                    .buffer_view
-                    .buffer_row
-                    .saturating_sub(self.row_dist(dist));
+            Scroll::Down | Scroll::Up => {
+                // Scroll by whole lines, as many as fit in the requested number of screen rows
+                let rows = self.row_dist(dist);
+                let down = matches!(scroll, Scroll::Down);
+                let buf = self.buffer.read();
+                let mut top = self.fold_start(self.buffer_view.buffer_row);
+                let mut used = 0;
+                loop {
+                    let next = match top.checked_sub(1) {
+                        _ if down => self.next_line(top),
+                        Some(prev) => self.fold_start(prev),
+                        None => break,
+                    };
+                    if next >= buf.len() {
+                        break;
+                    }
+                    let h = self.line_height(&buf, if down { top } else { next });
+                    if used > 0 && used + h > rows {
+                        break;
+                    }
+                    used += h;
+                    top = next;
+                }
+                drop(buf);
+                self.buffer_view.buffer_row = top;
+                self.cursor_into_view();
             }
+            Scroll::Left | Scroll::Right if self.wrap.wrap => return,
             Scroll::Right => {
                 self.buffer_view.buffer_col = self
                     .buffer_view
                     .buffer_col
                     .saturating_add(self.col_dist(dist))
-                    .min(self.buffer.read()[self.cursor.row()].len() - 1)
+                    .min(
+                        self.buffer.read()[self.cursor.row()]
+                            .width(&self.width)
+                            .saturating_sub(1),
+                    )
             }
             Scroll::Left => {
                 self.buffer_view.buffer_col = self
@@ -301,6 +951,9 @@ impl Window {
                     .saturating_sub(self.col_dist(dist));
             }
         }
+        if matches!(scroll, Scroll::Left | Scroll::Right) {
+            self.cursor_into_view_col();
+        }
         self.on_scroll();
     }
 
@@ -309,7 +962,8 @@ impl Window {
             Dist::One => 1,
             Dist::Step => 4,
             Dist::HalfScreen => self.buffer_area().height() / 2,
-            Dist::Screen => self.buffer_area().height(),
+            // Keep two lines of context when paging
+            Dist::Screen => self.buffer_area().height().saturating_sub(2).max(1),
         }
     }
 
@@ -328,6 +982,20 @@ impl Window {
 
     pub fn set_mode(&mut self, mode: WinMode) -> &mut Self {
         self.cursor.set_shape(mode.get_shape());
+        if self.get_state() != KeyState::Visual {
+            self.visual_start = self.cursor.pos();
+        } else if !matches!(mode, WinMode::Visual | WinMode::VisualLine | WinMode::VisualBlock) {
+            // The area is kept in the `'<` and `'>` marks
+            let (start, end) = (self.visual_start, self.cursor.pos());
+            let (start, end) = if (start.1, start.0) <= (end.1, end.0) {
+                (start, end)
+            } else {
+                (end, start)
+            };
+            let mut buffer = self.buffer.write();
+            buffer.set_mark('<', start);
+            buffer.set_mark('>', end);
+        }
         if matches!(self.mode, WinMode::Insert) {
             self.cursor_apply(Motion::Left);
         }
@@ -345,10 +1013,25 @@ impl Window {
     }
 
     #[inline(always)]
-    fn gutter_offset(&self) -> Pos {
+    fn foldcolumn_offset(&self) -> Pos {
         Pos(self.border_width(), self.border_width()) + self.area().pos()
     }
 
+    #[inline(always)]
+    fn foldcolumn_area(&self) -> Area {
+        self.foldcolumn_offset().area(
+            self.foldcolumn,
+            self.area()
+                .h
+                .saturating_sub(self.border_width() * 2 + self.status_height()),
+        )
+    }
+
+    #[inline(always)]
+    fn gutter_offset(&self) -> Pos {
+        self.foldcolumn_offset() + Pos(self.foldcolumn, 0)
+    }
+
     #[inline(always)]
     fn gutter_width(&self) -> usize {
         if self.window_props.gutter() {
@@ -394,9 +1077,10 @@ impl Window {
 
     #[inline(always)]
     fn status_offset(&self) -> Pos {
+        let area = self.area();
         Pos(
-            self.gutter_width() + self.linenum_width() + self.border_width(),
-            self.area().h.saturating_sub(self.border_width() + 1),
+            area.x + self.gutter_width() + self.linenum_width() + self.border_width(),
+            area.y + area.h.saturating_sub(self.border_width() + 1),
         )
     }
 
@@ -418,7 +1102,10 @@ impl Window {
     pub fn buffer_area(&self) -> Area {
         self.buffer_offset().area(
             self.area().w.saturating_sub(
-                self.border_width() * 2 + self.gutter_width() + self.linenum_width(),
+                self.border_width() * 2
+                    + self.foldcolumn
+                    + self.gutter_width()
+                    + self.linenum_width(),
             ),
             self.area()
                 .h
@@ -438,6 +1125,17 @@ impl Window {
     pub fn buffer(&self) -> &BufferRef {
         &self.buffer
     }
+
+    /// Shows another buffer in the window, which keeps its options and its place on the screen
+    pub fn set_buffer(&mut self, buffer: BufferRef) {
+        let area = self.area();
+        let options = std::mem::take(&mut self.options);
+        *self = Self {
+            options,
+            ..Self::new(self.id, buffer)
+        };
+        self.set_area(area);
+    }
 }
 
 impl Renderable for Window {
@@ -451,61 +1149,296 @@ impl Renderable for Window {
     }
 
     fn cursor_pos(&self) -> Cursor {
+        let (start, end) = self.cursor_cols();
+        let buf = self.buffer.read();
+        let area = self.buffer_area();
+        let row = self.fold_start(self.cursor.row());
+        let mut y = 0;
+        let mut line = self.fold_start(self.buffer_view.buffer_row);
+        while line < row {
+            y += self.line_height(&buf, line);
+            line = self.next_line(line);
+        }
+        if self.closed_fold(row).is_some() {
+            return Cursor::from_params(area.x, y + area.y, self.cursor().shape());
+        }
+        // Outside of insert mode, the cursor sits at the end of a tab
+        let on_tab = buf[self.cursor.row()]
+            .text()
+            .get(self.cursor.col()..)
+            .is_some_and(|t| t.starts_with('\t'));
+        let col = if !self.mode.insert() && on_tab {
+            end - 1
+        } else {
+            start
+        };
+        let rows = self.line_rows(&buf, self.cursor.row());
+        let r = row_of(&rows, self.cursor.col());
         Cursor::from_params(
-            self.cursor().col() - self.buffer_view.buffer_col + self.buffer_area().x,
-            self.cursor().row() - self.buffer_view.buffer_row + self.buffer_area().y,
+            (col.saturating_sub(rows[r].cols.start) + rows[r].indent).min(area.w.saturating_sub(1))
+                + area.x,
+            y + r + area.y,
             self.cursor().shape(),
         )
     }
 
-    fn draw<W: Write>(&mut self, term: &mut W) -> Result<()> {
+    fn draw<W: Write>(
+        &mut self,
+        term: &mut W,
+        options: &Options,
+        highlights: &Highlights,
+    ) -> Result<()> {
+        let width = WidthOpts::new(options);
+        let wrap = WrapOpts::new(options, &self.options);
+        let foldcolumn = self.options.foldcolumn.clamp(0, 12) as usize;
+        let fold_chars = FoldChars::parse(if self.options.fillchars.is_empty() {
+            &options.fillchars
+        } else {
+            &self.options.fillchars
+        });
+        if width != self.width
+            || wrap != self.wrap
+            || foldcolumn != self.foldcolumn
+            || fold_chars != self.fold_chars
+            || highlights.tick() != self.highlight_tick
+        {
+            self.highlight_tick = highlights.tick();
+            self.width = width;
+            self.wrap = wrap;
+            self.foldcolumn = foldcolumn;
+            self.fold_chars = fold_chars;
+            self.redraw_all();
+        }
+        self.scroll_opts = ScrollOpts::new(options);
+        self.shiftwidth = match options.shiftwidth {
+            n if n > 0 => n as usize,
+            _ => self.width.tabstop,
+        };
+        self.sync_folds();
+        let fits = self.buffer.with_read(|b| {
+            b.get_line(self.cursor.row())
+                .is_some_and(|l| self.cursor.col() <= l.len())
+        });
+        if !fits {
+            // The text under the cursor was changed by a command or another window
+            self.cursor_apply(Motion::SetRow(self.cursor.row()));
+        }
+        self.scroll_to_cursor();
+        let redraw_lines = self.cursor_redraw_lines();
+        if !redraw_lines.is_empty() {
+            self.window_updates.set_cursorline(true);
+        }
+        let cursorcolumn = self.options.cursorcolumn.then(|| self.cursor_cols().0);
+        let colorcolumn = color_columns(&self.options.colorcolumn, options.textwidth);
+        if cursorcolumn != self.cursorcolumn || colorcolumn != self.colorcolumn {
+            self.cursorcolumn = cursorcolumn;
+            self.colorcolumn = colorcolumn;
+            self.window_updates.set_buffer(true);
+        }
+        if self.wrap.wrap && self.window_updates.buffer() {
+            // Edits can change how many rows a line wraps onto
+            self.on_scroll();
+        }
+        let layout = self.layout(&self.buffer.read());
+        let lines = layout.iter().filter_map(ScreenRow::line);
+        if let (Some(first), Some(last)) = (lines.clone().min(), lines.max()) {
+            if self.buffer.write().highlight(first..last + 1, highlights) {
+                self.window_updates.set_buffer(true);
+            }
+        }
         let buf_read = self.buffer.read();
         if self.window_updates.border() && self.window_props.border() {
             todo!("Draw border")
         }
+        if self.window_updates.foldcolumn() && self.foldcolumn > 0 {
+            // Draw fold column
+            let area = self.foldcolumn_area();
+            let minlines = self
+                .options
+                .foldenable
+                .then(|| self.options.foldminlines.max(0) as usize);
+            let style = highlights.style_of("FoldColumn");
+            for (line, row) in area.lines().zip(layout.iter()) {
+                line.move_cursor(term)?;
+                let column = match row {
+                    ScreenRow::Text { line, first, .. } => {
+                        self.folds
+                            .column(*line, area.w, *first, minlines, &self.fold_chars)
+                    }
+                    ScreenRow::Fold { start, .. } => {
+                        self.folds
+                            .column(*start, area.w, true, minlines, &self.fold_chars)
+                    }
+                    _ => format!("{:width$}", "", width = area.w),
+                };
+                write!(term, "{}", style.apply(column))?;
+            }
+        }
         if self.window_updates.gutter() && self.window_props.gutter() {
             // Draw Gutter
             let area = self.gutter_area();
-            for (i, line) in area.lines().enumerate() {
+            for (line, row) in area.lines().zip(layout.iter()) {
                 line.move_cursor(term)?;
-                write!(
-                    term,
-                    "{:width$}",
-                    self.buffer()
-                        .read()
-                        .get_line(i + self.buffer_view.buffer_row)
-                        .map_or(&Signs::default(), |l| l.signs()),
-                    width = area.w as usize
-                )?;
-            }
-        }
-        if self.window_updates.linenum() && self.window_props.linenum() {
+                match row {
+                    ScreenRow::Text {
+                        line, first: true, ..
+                    }
+                    | ScreenRow::Fold { start: line, .. } => buf_read
+                        .get_line(*line)
+                        .map_or(&Signs::default(), |l| l.signs())
+                        .draw(term, area.w, highlights)?,
+                    _ => Signs::default().draw(term, area.w, highlights)?,
+                }
+            }
+        }
+        if (self.window_updates.linenum() || self.window_updates.cursorline())
+            && self.window_props.linenum()
+        {
             // Draw LineNums
             let area = self.linenum_area();
-            for (i, line) in area.lines().enumerate() {
+            let partial = !self.window_updates.linenum();
+            let style = highlights.style_of("LineNr");
+            for (line, row) in area.lines().zip(layout.iter()) {
+                if partial && !row.line().is_some_and(|l| redraw_lines.contains(&l)) {
+                    continue;
+                }
                 line.move_cursor(term)?;
-                let row = i + self.buffer_view.buffer_row;
-                if row < buf_read.len() {
-                    write!(term, "{row:width$} ", width = area.w as usize - 1)?;
-                } else {
-                    write!(term, "{:width$}", " ~ ", width = area.w as usize)?;
+                match row {
+                    ScreenRow::Text {
+                        line: row,
+                        first: true,
+                        ..
+                    }
+                    | ScreenRow::Fold { start: row, .. } => {
+                        let num = format!("{row:width$} ", width = area.w - 1);
+                        match self.cursorline {
+                            Some(c) if c.number && c.line == *row => {
+                                write!(term, "{}", highlights.style_of("CursorLineNr").apply(num))?
+                            }
+                            _ => write!(term, "{}", style.apply(num))?,
+                        }
+                    }
+                    ScreenRow::End => {
+                        let end = format!("{:width$}", " ~ ", width = area.w);
+                        write!(term, "{}", highlights.style_of("EndOfBuffer").apply(end))?
+                    }
+                    _ => write!(term, "{}", style.apply(format!("{:width$}", "", width = area.w)))?,
                 }
             }
         }
         if self.window_updates.status() && self.window_props.status() {
             // Draw status line
             self.status_offset().move_cursor(term)?;
-            write!(term, "{:width$} ", self.status(), width = self.area().w as usize)?;
+            let status = format!("{:width$} ", self.status().to_string(), width = self.area().w);
+            write!(term, "{}", highlights.style_of("StatusLine").apply(status))?;
         }
-        if self.window_updates.buffer() && self.window_props.buffer() {
+        if (self.window_updates.buffer() || self.window_updates.cursorline())
+            && self.window_props.buffer()
+        {
             // Draw buffer
             let area = self.buffer_area();
-            for (i, line) in area.lines().enumerate() {
+            let partial = !self.window_updates.buffer();
+            let mut overlay = (usize::MAX, Overlay::default());
+            for (line, row) in area.lines().zip(layout.iter()) {
+                if partial && !row.line().is_some_and(|l| redraw_lines.contains(&l)) {
+                    continue;
+                }
                 line.move_cursor(term)?;
-                if let Some(l) = buf_read.get_line(i + self.buffer_view.buffer_row) {
-                    l.draw(term, area.w as usize)?;
-                } else {
-                    write!(term, "{:width$}", "", width = area.w as usize)?;
+                match row {
+                    ScreenRow::Text {
+                        line, row, more, ..
+                    } => {
+                        let l = &buf_read[*line];
+                        if overlay.0 != *line {
+                            overlay = (*line, self.overlay(&buf_read, *line, highlights));
+                        }
+                        overlay.1.line = match self.cursorline {
+                            Some(c)
+                                if c.text
+                                    && c.line == *line
+                                    && c.row.is_none_or(|r| r == row.bytes.start) =>
+                            {
+                                highlights.get("CursorLine")
+                            }
+                            _ => None,
+                        };
+                        if row.indent > 0 {
+                            write!(term, "{}", self.wrap.prefix(l.text(), &self.width, area.w))?;
+                        }
+                        let width = area.w - row.indent;
+                        let non_text = highlights.style_of("NonText");
+                        match (more, self.wrap.lastline) {
+                            (true, LastLine::Truncate) => {
+                                let more = format!("{:width$}", "@@@", width = width);
+                                write!(term, "{}", non_text.apply(more))?
+                            }
+                            (true, _) => {
+                                l.draw(
+                                    term,
+                                    &self.width,
+                                    row.cols.clone(),
+                                    width.saturating_sub(3),
+                                    highlights,
+                                    &overlay.1,
+                                )?;
+                                write!(term, "{}", non_text.apply(&"@@@"[..width.min(3)]))?;
+                            }
+                            (false, _) => l.draw(
+                                term,
+                                &self.width,
+                                row.cols.clone(),
+                                width,
+                                highlights,
+                                &overlay.1,
+                            )?,
+                        }
+                    }
+                    ScreenRow::Fold { start, end } => {
+                        let level = self
+                            .folds
+                            .closed_level(*start, self.options.foldminlines.max(0) as usize)
+                            .unwrap_or(1);
+                        let text = match self.fold_text.get(start) {
+                            Some(text) => text.clone(),
+                            None => {
+                                let buf_opts = buf_read.options();
+                                let pending = (*start, *end, level);
+                                if buf_opts.foldtext != "foldtext()"
+                                    && !self.fold_text_missing.contains(&pending)
+                                {
+                                    self.fold_text_missing.push(pending);
+                                }
+                                fold::fold_text(
+                                    buf_read[*start].text(),
+                                    end - start + 1,
+                                    level,
+                                    &self.options.foldmarker,
+                                    &buf_opts.commentstring,
+                                )
+                            }
+                        };
+                        let mut used = 0;
+                        let mut folded = String::new();
+                        for cell in self.width.cells(&text) {
+                            if cell.col + cell.width > area.w {
+                                break;
+                            }
+                            folded += &cell.display();
+                            used = cell.col + cell.width;
+                        }
+                        for _ in used..area.w {
+                            folded.push(self.fold_chars.fold);
+                        }
+                        write!(term, "{}", highlights.style_of("Folded").apply(folded))?;
+                    }
+                    ScreenRow::Hidden => {
+                        let hidden = format!("{:width$}", "@", width = area.w);
+                        write!(term, "{}", highlights.style_of("NonText").apply(hidden))?
+                    }
+                    ScreenRow::End => {
+                        let end = format!("{:width$}", "", width = area.w);
+                        write!(term, "{}", highlights.text_style(None).apply(end))?
+                    }
                 }
             }
         }
@@ -514,6 +1447,442 @@ impl Renderable for Window {
     }
 }
 
+/// What is shown on a row of the buffer area
+enum ScreenRow {
+    Text {
+        line: usize,
+        row: Row,
+        first: bool,
+        /// The last row of a line that does not fit, with 'display' set to lastline or truncate
+        more: bool,
+    },
+    /// A closed fold
+    Fold { start: usize, end: usize },
+    /// Part of a line that does not fit in the window
+    Hidden,
+    /// Past the end of the buffer
+    End,
+}
+
+impl ScreenRow {
+    /// The line shown on this row, or the first line of a closed fold
+    fn line(&self) -> Option<usize> {
+        match self {
+            Self::Text { line, .. } | Self::Fold { start: line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+/// Index of the row the byte `col` is displayed on
+fn row_of(rows: &[Row], col: usize) -> usize {
+    rows.iter().rposition(|r| r.bytes.start <= col).unwrap_or(0)
+}
+
+impl Window {
+    /// Whether text in the cursor line is concealed in the current mode, from 'concealcursor'
+    fn conceal_cursor(&self) -> bool {
+        let mode = match self.mode {
+            WinMode::Normal | WinMode::Operation(_) => 'n',
+            WinMode::Insert | WinMode::Replace => 'i',
+            WinMode::Visual | WinMode::VisualLine | WinMode::VisualBlock => 'v',
+        };
+        self.options.concealcursor.contains(mode)
+    }
+
+    /// The lines that are drawn differently since the last redraw because the cursor moved
+    fn cursor_redraw_lines(&mut self) -> Vec<usize> {
+        let mut redraw_lines = vec![];
+        let conceal_row =
+            (self.options.conceallevel > 0 && !self.conceal_cursor()).then(|| self.cursor.row());
+        if conceal_row != self.conceal_row {
+            // The cursor line is shown without concealing
+            redraw_lines.extend(self.conceal_row.into_iter().chain(conceal_row));
+            self.conceal_row = conceal_row;
+        }
+        let cursorline = self.cursorline_opts();
+        if cursorline != self.cursorline {
+            redraw_lines.extend(self.cursorline.iter().chain(&cursorline).map(|c| c.line));
+            self.cursorline = cursorline;
+        }
+        redraw_lines
+    }
+
+    /// How the cursor line is highlighted, from 'cursorline' and 'cursorlineopt'
+    fn cursorline_opts(&self) -> Option<CursorLine> {
+        if !self.options.cursorline {
+            return None;
+        }
+        let (mut text, mut number, mut screenline) = (false, false, false);
+        for opt in self.options.cursorlineopt.split(',') {
+            match opt {
+                "line" => text = true,
+                "screenline" => screenline = true,
+                "number" => number = true,
+                "both" => {
+                    text = true;
+                    number = true;
+                }
+                _ => (),
+            }
+        }
+        let row = screenline.then(|| {
+            let rows = self.line_rows(&self.buffer.read(), self.cursor.row());
+            rows[row_of(&rows, self.cursor.col())].bytes.start
+        });
+        Some(CursorLine {
+            line: self.cursor.row(),
+            row,
+            text: text || screenline,
+            number,
+        })
+    }
+
+    /// The highlighting of the matches and highlighted columns in `line`
+    fn overlay(&self, buf: &Buffer, line: usize, highlights: &Highlights) -> Overlay {
+        let text = buf[line].text();
+        let mut overlay = Overlay::default();
+        let conceal = highlights.get("Conceal");
+        let level = self.options.conceallevel.clamp(0, 3);
+        let colorcolumn = highlights.get("ColorColumn");
+        overlay.columns = self
+            .colorcolumn
+            .iter()
+            .filter_map(|c| Some((*c, colorcolumn?)))
+            .chain(self.cursorcolumn.zip(highlights.get("CursorColumn")))
+            .collect();
+        let ctx = regex::Context {
+            cursor: Some(regex::Pos::new(self.cursor.row(), self.cursor.col())),
+            tabstop: self.width.tabstop,
+            ..regex::Context::default()
+        };
+        if let Some((regex, group)) = self.search.as_ref().zip(highlights.get("Search")) {
+            for range in search::line_spans(buf, regex, line, &ctx) {
+                overlay.spans.push((range, group));
+            }
+        }
+        for (range, m) in self.matches.line_spans(line, text) {
+            let group = highlights.get(&m.group);
+            if group.is_some() && group == conceal && level > 0 && self.conceal_row != Some(line) {
+                let c = match level {
+                    1 => Some(m.conceal.unwrap_or(' ')),
+                    2 => m.conceal,
+                    _ => None,
+                };
+                overlay.conceal.push((range, c));
+            } else if let Some(group) = group {
+                overlay.spans.push((range, group));
+            }
+        }
+        // The match of the search being typed is drawn over the other matches
+        match (self.incsearch, highlights.get("IncSearch")) {
+            (Some((start, end)), Some(group)) if (start.line..=end.line).contains(&line) => {
+                let from = if start.line == line { start.col } else { 0 };
+                let to = if end.line == line { end.col } else { text.len() };
+                overlay.spans.push((from..to, group));
+            }
+            _ => (),
+        }
+        overlay
+    }
+
+    fn layout(&self, buf: &Buffer) -> Vec<ScreenRow> {
+        let height = self.buffer_area().height();
+        let mut ret = Vec::with_capacity(height);
+        let mut line = self.fold_start(self.buffer_view.buffer_row);
+        while ret.len() < height && line < buf.len() {
+            if let Some(fold) = self.closed_fold(line) {
+                ret.push(ScreenRow::Fold {
+                    start: *fold.start(),
+                    end: *fold.end(),
+                });
+                line = fold.end() + 1;
+                continue;
+            }
+            let rows = self.line_rows(buf, line);
+            let left = height - ret.len();
+            let fits = rows.len() <= left || ret.is_empty();
+            if !fits && self.wrap.lastline == LastLine::Hide {
+                ret.extend((0..left).map(|_| ScreenRow::Hidden));
+            } else {
+                for (i, row) in rows.into_iter().take(left).enumerate() {
+                    ret.push(ScreenRow::Text {
+                        line,
+                        row,
+                        first: i == 0,
+                        more: !fits && i + 1 == left,
+                    });
+                }
+            }
+            line += 1;
+        }
+        ret.resize_with(height, || ScreenRow::End);
+        ret
+    }
+}
+
+impl Window {
+    /// The lines of the outermost closed fold that contains `line`
+    fn closed_fold(&self, line: usize) -> Option<RangeInclusive<usize>> {
+        if self.options.foldenable {
+            self.folds
+                .closed_at(line, self.options.foldminlines.max(0) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// The first line of the closed fold that contains `line`, or `line` if it is not folded
+    fn fold_start(&self, line: usize) -> usize {
+        self.closed_fold(line).map_or(line, |f| *f.start())
+    }
+
+    /// The line shown after `line`, skipping over closed folds
+    fn next_line(&self, line: usize) -> usize {
+        self.closed_fold(line).map_or(line, |f| *f.end()) + 1
+    }
+
+    /// Moves down `n` of the lines shown, without going past the end of the buffer
+    fn lines_below(&self, buf: &Buffer, line: usize, n: usize) -> usize {
+        let mut line = self.fold_start(line);
+        for _ in 0..n {
+            let next = self.next_line(line);
+            if next >= buf.len() {
+                break;
+            }
+            line = next;
+        }
+        line
+    }
+
+    /// Moves up `n` of the lines shown
+    fn lines_above(&self, line: usize, n: usize) -> usize {
+        let mut line = self.fold_start(line);
+        for _ in 0..n {
+            match line.checked_sub(1) {
+                Some(prev) => line = self.fold_start(prev),
+                None => break,
+            }
+        }
+        line
+    }
+
+    /// Brings the folds up to date with the buffer and the fold options
+    fn sync_folds(&mut self) {
+        let opts = FoldOpts::new(&self.options, self.shiftwidth);
+        let (tick, syntax_tick) = self.buffer.with_read(|b| (b.tick(), b.syntax().tick()));
+        let syntax = opts.method == FoldMethod::syntax;
+        if Some(&opts) != self.fold_opts.as_ref()
+            || tick != self.fold_tick
+            || syntax && syntax_tick != self.fold_syntax_tick
+        {
+            let syntax_levels = syntax.then(|| self.buffer.write().syntax_fold_levels());
+            let buf = self.buffer.read();
+            let mut old = std::mem::take(&mut self.folds);
+            if self.fold_opts.as_ref().map(|o| o.method) != Some(opts.method) {
+                old.clear();
+            }
+            for (line, delta) in buf.line_changes_since(self.fold_tick).flat_map(|c| c.shifts()) {
+                old.shift(line, delta);
+            }
+            let lines = (0..buf.len()).map(|l| buf[l].text());
+            let levels = match opts.method {
+                FoldMethod::manual => None,
+                FoldMethod::expr => {
+                    self.fold_expr_pending = true;
+                    None
+                }
+                FoldMethod::indent => Some(fold::indent_levels(
+                    lines,
+                    &opts.ignore,
+                    opts.shiftwidth,
+                    &self.width,
+                )),
+                FoldMethod::marker => Some(fold::marker_levels(lines, &opts.marker)),
+                FoldMethod::syntax => syntax_levels,
+                // There are no diffs to fold yet
+                FoldMethod::diff => Some(vec![]),
+            };
+            self.folds = match levels {
+                Some(levels) => {
+                    let mut folds = FoldTree::from_levels(&levels, opts.nestmax);
+                    folds.set_level(self.options.foldlevel.max(0) as usize);
+                    folds.keep_closed(&old);
+                    folds
+                }
+                None => old,
+            };
+            self.fold_tick = tick;
+            self.fold_syntax_tick = syntax_tick;
+            self.fold_opts = Some(opts);
+            self.fold_text.clear();
+            self.fold_text_missing.clear();
+            drop(buf);
+            self.on_scroll();
+        }
+        if self.options.foldlevel != self.fold_level {
+            self.fold_level = self.options.foldlevel;
+            self.folds.set_level(self.fold_level.max(0) as usize);
+            self.on_scroll();
+        }
+    }
+
+    /// Sets the fold level of every line, as given by 'foldexpr'
+    pub fn set_fold_levels(&mut self, levels: Vec<FoldLevel>) {
+        let mut folds = FoldTree::from_levels(&levels, self.options.foldnestmax.max(0) as usize);
+        folds.set_level(self.options.foldlevel.max(0) as usize);
+        folds.keep_closed(&self.folds);
+        self.folds = folds;
+        self.fold_expr_pending = false;
+        self.fold_text.clear();
+        self.fold_text_missing.clear();
+        self.on_scroll();
+    }
+
+    /// 'foldexpr', if it needs to be evaluated for every line
+    pub fn fold_expr_pending(&self) -> Option<&str> {
+        if self.fold_expr_pending && self.options.foldmethod == FoldMethod::expr {
+            Some(&self.options.foldexpr)
+        } else {
+            None
+        }
+    }
+
+    /// The first line, last line and level of the closed folds that need 'foldtext' evaluated
+    pub fn fold_text_pending(&self) -> &[(usize, usize, usize)] {
+        &self.fold_text_missing
+    }
+
+    pub fn set_fold_text(&mut self, start: usize, text: String) {
+        self.fold_text_missing.retain(|(s, _, _)| *s != start);
+        self.fold_text.insert(start, text);
+        self.window_updates.set_buffer(true);
+    }
+
+    /// The lines of the closed fold that contains `line`, if any
+    pub fn fold_closed(&self, line: usize) -> Option<RangeInclusive<usize>> {
+        self.closed_fold(line)
+    }
+
+    /// The buffer tick the folds were last brought up to date at
+    pub fn fold_tick(&self) -> usize {
+        self.fold_tick
+    }
+
+    /// Number of folds that contain `line`
+    pub fn fold_level(&self, line: usize) -> usize {
+        self.folds.level(line)
+    }
+
+    fn folds_changed(&mut self) {
+        self.on_scroll();
+        self.cursor_apply(Motion::SetRow(self.cursor.row()));
+    }
+
+    /// Creates a closed fold from `start` to `end` (`zf` and `:fold`)
+    pub fn create_fold(&mut self, start: usize, end: usize) -> std::result::Result<(), &'static str> {
+        if self.options.foldmethod != FoldMethod::manual {
+            return Err("E350: Cannot create fold with current 'foldmethod'");
+        }
+        self.sync_folds();
+        self.folds.create(start, end);
+        self.folds_changed();
+        Ok(())
+    }
+
+    /// Deletes the fold at the cursor (`zd` and `zD`)
+    pub fn delete_fold(&mut self, recursive: bool) -> std::result::Result<(), &'static str> {
+        if self.options.foldmethod != FoldMethod::manual {
+            return Err("E351: Cannot delete fold with current 'foldmethod'");
+        }
+        let minlines = self.options.foldminlines.max(0) as usize;
+        if !self.folds.delete(self.cursor.row(), recursive, minlines) {
+            return Err("E490: No fold found");
+        }
+        self.folds_changed();
+        Ok(())
+    }
+
+    /// Deletes every fold in the window (`zE`)
+    pub fn delete_all_folds(&mut self) -> std::result::Result<(), &'static str> {
+        if self.options.foldmethod != FoldMethod::manual {
+            return Err("E351: Cannot delete fold with current 'foldmethod'");
+        }
+        self.folds.clear();
+        self.folds_changed();
+        Ok(())
+    }
+
+    /// Opens `count` levels of folds at the cursor (`zo` and `zO`)
+    pub fn open_fold(&mut self, count: usize) -> std::result::Result<(), &'static str> {
+        if !self.folds.open(self.cursor.row(), count) {
+            return Err("E490: No fold found");
+        }
+        self.folds_changed();
+        Ok(())
+    }
+
+    /// Closes `count` levels of folds at the cursor (`zc` and `zC`)
+    pub fn close_fold(&mut self, count: usize) -> std::result::Result<(), &'static str> {
+        if !self.folds.close(self.cursor.row(), count) {
+            return Err("E490: No fold found");
+        }
+        self.folds_changed();
+        Ok(())
+    }
+
+    /// Opens the fold at the cursor if it is closed, and closes it otherwise (`za` and `zA`)
+    pub fn toggle_fold(&mut self, count: usize) -> std::result::Result<(), &'static str> {
+        let minlines = self.options.foldminlines.max(0) as usize;
+        if !self.folds.toggle(self.cursor.row(), count, minlines) {
+            return Err("E490: No fold found");
+        }
+        self.folds_changed();
+        Ok(())
+    }
+
+    /// Opens every fold (`zR`), or closes every fold (`zM`), by setting 'foldlevel'
+    pub fn set_all_folds(&mut self, open: bool) {
+        self.sync_folds();
+        self.options.foldlevel = if open { self.folds.depth() as isize } else { 0 };
+        self.fold_level = self.options.foldlevel;
+        self.folds.set_level(self.fold_level as usize);
+        self.folds_changed();
+    }
+
+    /// Opens or closes the folds in `range` (`:foldopen` and `:foldclose`). Only one level of
+    /// folds is changed, unless `all` is set.
+    pub fn set_folds_in(&mut self, range: RangeInclusive<usize>, open: bool, all: bool) {
+        self.sync_folds();
+        if open {
+            self.folds.open_range(range, all);
+        } else {
+            self.folds.close_range(range, all);
+        }
+        self.folds_changed();
+    }
+
+    /// Moves the cursor to the start of the next fold (`zj`), or to the end of the previous fold
+    /// (`zk`)
+    pub fn move_to_fold(&mut self, next: bool) {
+        self.sync_folds();
+        let row = self.cursor.row();
+        let target = if next {
+            self.folds.next_start(row)
+        } else {
+            self.folds.prev_end(row)
+        };
+        if let Some(line) = target {
+            self.cursor_apply(Motion::SetRow(line));
+        }
+    }
+
+    /// Where visual mode was started
+    pub fn visual_start(&self) -> Pos {
+        self.visual_start
+    }
+}
+
 pub struct StatusBar<'w> {
     buffer: &'w BufferRef,
 }
@@ -568,41 +1937,13 @@ impl EventReader for Window {
                 }
             }
             KeyCode::Backspace => {
-                if self.mode.insert() {
-                    if self.cursor.col() > 0 {
-                        self.cursor_apply(Motion::Left);
-                        self.buffer
-                            .write()
-                            .remove_char(self.cursor.row(), self.cursor.col());
-                        self.window_updates.set_buffer(true);
-                    } else if self.cursor.row() > 0 {
-                        self.cursor_apply(Motion::Up);
-                        self.cursor_apply(Motion::End);
-                        self.buffer().write().join_line(self.cursor.row());
-                        self.window_updates.set_buffer(true);
-                        self.window_updates.set_linenum(true);
-                        self.window_updates.set_gutter(true);
-                    }
-                } else if self.cursor.col() == 0 {
+                if self.cursor.col() == 0 {
                     self.cursor_apply(Motion::Up);
                     self.cursor_apply(Motion::End);
                 } else {
                     self.cursor_apply(Motion::Left);
                 }
             }
-            KeyCode::Delete => {
-                if self.cursor.col() < self.buffer.read()[self.cursor.row()].len() {
-                    self.buffer
-                        .write()
-                        .remove_char(self.cursor.row(), self.cursor.col());
-                    self.window_updates.set_buffer(true);
-                } else if self.cursor.row() + 1 < self.buffer.read().len() {
-                    self.buffer().write().join_line(self.cursor.row());
-                    self.window_updates.set_buffer(true);
-                    self.window_updates.set_linenum(true);
-                    self.window_updates.set_gutter(true);
-                }
-            }
             KeyCode::Enter => {
                 if self.mode.insert() {
                     self.buffer
@@ -671,3 +2012,119 @@ impl EventReader for Window {
         WinAction::None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vimscript::IdProcuder;
+
+    /// A window over `lines` numbered lines, showing `rows` of them
+    fn window(lines: usize, rows: usize) -> Window {
+        let mut ids = IdProcuder::default();
+        let buffer = BufferRef::empty(&mut ids);
+        buffer
+            .write()
+            .replace_lines(0..1, (0..lines).map(|i| i.to_string()).collect());
+        let mut win = Window::new(ids.get(), buffer);
+        // One extra row for the status line
+        win.set_area(Pos(0, 0).area(20, rows + 1));
+        win
+    }
+
+    fn view(win: &Window) -> (usize, usize) {
+        (win.view().row, win.cursor().row())
+    }
+
+    #[test]
+    fn scroll_half() {
+        let mut win = window(50, 20);
+        // CTRL-D without 'scroll' moves half a window
+        win.scroll_with_cursor(Scroll::Down);
+        assert_eq!(view(&win), (10, 10));
+        // `5<C-d>` sets 'scroll', which later CTRL-D and CTRL-U use
+        win.options_mut().scroll = 5;
+        win.scroll_with_cursor(Scroll::Down);
+        assert_eq!(view(&win), (15, 15));
+        win.scroll_with_cursor(Scroll::Up);
+        win.scroll_with_cursor(Scroll::Up);
+        assert_eq!(view(&win), (5, 5));
+        win.scroll_with_cursor(Scroll::Up);
+        win.scroll_with_cursor(Scroll::Up);
+        assert_eq!(view(&win), (0, 0));
+    }
+
+    #[test]
+    fn scroll_half_at_end() {
+        let mut win = window(30, 20);
+        win.scroll_with_cursor(Scroll::Down);
+        assert_eq!(view(&win), (10, 10));
+        // The last line stays at the bottom, only the cursor moves
+        win.scroll_with_cursor(Scroll::Down);
+        assert_eq!(view(&win), (10, 20));
+        win.scroll_with_cursor(Scroll::Down);
+        assert_eq!(view(&win), (10, 29));
+    }
+
+    #[test]
+    fn scrolloff() {
+        let mut win = window(50, 20);
+        win.scroll_opts.scrolloff = 3;
+        win.cursor_apply(Motion::SetRow(17));
+        assert_eq!(view(&win), (1, 17));
+        win.cursor_apply(Motion::SetRow(3));
+        assert_eq!(view(&win), (0, 3));
+        // No context lines past the ends of the buffer
+        win.cursor_apply(Motion::SetRow(49));
+        assert_eq!(view(&win), (30, 49));
+        win.cursor_apply(Motion::SetRow(0));
+        assert_eq!(view(&win), (0, 0));
+        // Scrolling moves the cursor out of the context lines
+        win.scroll(Scroll::Down, Dist::One);
+        assert_eq!(view(&win), (1, 4));
+    }
+
+    #[test]
+    fn scrolloff_clamped() {
+        let mut win = window(50, 20);
+        // More than half the window keeps the cursor line in the middle
+        win.scroll_opts.scrolloff = 100;
+        win.cursor_apply(Motion::SetRow(25));
+        assert_eq!(view(&win), (15, 25));
+        win.cursor_apply(Motion::SetRow(49));
+        assert_eq!(view(&win), (30, 49));
+        win.cursor_apply(Motion::SetRow(5));
+        assert_eq!(view(&win), (0, 5));
+    }
+
+    #[test]
+    fn colorcolumn() {
+        assert_eq!(color_columns("", 0), []);
+        assert_eq!(color_columns("1,80,0,x", 0), [0, 79]);
+        // Relative to 'textwidth', and ignored when it is not set
+        assert_eq!(color_columns("+1,-2,+0", 79), [79, 76, 78]);
+        assert_eq!(color_columns("+1,20", 0), [19]);
+        assert_eq!(color_columns("-5", 3), []);
+    }
+
+    #[test]
+    fn cursor_redraw_lines() {
+        let mut win = window(10, 5);
+        win.options_mut().cursorline = true;
+        assert_eq!(win.cursor_redraw_lines(), [0]);
+        assert_eq!(win.cursor_redraw_lines(), []);
+        // The line the highlighting moved from and the one it moved to
+        win.cursor_apply(Motion::SetRow(3));
+        assert_eq!(win.cursor_redraw_lines(), [0, 3]);
+        win.cursor_apply(Motion::SetCol(1));
+        assert_eq!(win.cursor_redraw_lines(), []);
+        win.options_mut().cursorline = false;
+        assert_eq!(win.cursor_redraw_lines(), [3]);
+        // The cursor line is drawn without concealing
+        win.options_mut().conceallevel = 2;
+        assert_eq!(win.cursor_redraw_lines(), [3]);
+        win.cursor_apply(Motion::SetRow(5));
+        assert_eq!(win.cursor_redraw_lines(), [3, 5]);
+        win.options_mut().concealcursor = "n".into();
+        assert_eq!(win.cursor_redraw_lines(), [5]);
+    }
+}
@@ -0,0 +1,254 @@
+//
+// wrap.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use std::ops::Range;
+
+use crate::options::{Options, WinOptions};
+use crate::width::WidthOpts;
+
+/// Settings for 'breakindentopt'
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakIndentOpt {
+    pub min: usize,
+    pub shift: isize,
+    pub sbr: bool,
+}
+
+impl Default for BreakIndentOpt {
+    fn default() -> Self {
+        Self {
+            min: 20,
+            shift: 0,
+            sbr: false,
+        }
+    }
+}
+
+impl BreakIndentOpt {
+    pub fn parse(s: &str) -> Self {
+        let mut ret = Self::default();
+        for part in s.split(',') {
+            match part.split_once(':') {
+                Some(("min", n)) => ret.min = n.parse().unwrap_or(ret.min),
+                Some(("shift", n)) => ret.shift = n.parse().unwrap_or(ret.shift),
+                None if part == "sbr" => ret.sbr = true,
+                _ => (),
+            }
+        }
+        ret
+    }
+}
+
+/// How a line that does not fit at the bottom of a window is shown ('display')
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LastLine {
+    /// Replace the whole line with rows of "@"
+    Hide,
+    /// Show as much as possible, with "@@@" at the end of the last row
+    Show,
+    /// Show as much as possible, with "@@@" at the start of the last row
+    Truncate,
+}
+
+/// The options that control how long lines are wrapped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrapOpts {
+    pub wrap: bool,
+    pub linebreak: bool,
+    pub breakat: String,
+    pub showbreak: String,
+    pub breakindent: bool,
+    pub briopt: BreakIndentOpt,
+    pub lastline: LastLine,
+}
+
+impl Default for WrapOpts {
+    fn default() -> Self {
+        Self {
+            wrap: true,
+            linebreak: false,
+            breakat: " \t!@*-+;:,./?".into(),
+            showbreak: String::new(),
+            breakindent: false,
+            briopt: BreakIndentOpt::default(),
+            lastline: LastLine::Show,
+        }
+    }
+}
+
+/// A single screen row of a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    /// Bytes of the line shown on this row
+    pub bytes: Range<usize>,
+    /// Screen columns of the line shown on this row
+    pub cols: Range<usize>,
+    /// Width of the 'showbreak' and 'breakindent' prefix before the text
+    pub indent: usize,
+}
+
+impl WrapOpts {
+    pub fn new(options: &Options, win: &WinOptions) -> Self {
+        let display = options.display.split(',');
+        let lastline = display.fold(LastLine::Hide, |acc, flag| match flag {
+            "truncate" => LastLine::Truncate,
+            "lastline" if acc == LastLine::Hide => LastLine::Show,
+            _ => acc,
+        });
+        Self {
+            wrap: win.wrap,
+            linebreak: win.linebreak,
+            breakat: options.breakat.clone(),
+            showbreak: options.showbreak.clone(),
+            breakindent: win.breakindent,
+            briopt: BreakIndentOpt::parse(&win.breakindentopt),
+            lastline,
+        }
+    }
+
+    /// The text drawn before every row of `text` after the first
+    pub fn prefix(&self, text: &str, width: &WidthOpts, screen_width: usize) -> String {
+        let sbr = width.str_width(&self.showbreak);
+        let indent = if self.breakindent {
+            let lead = &text[..text.len() - text.trim_start().len()];
+            let indent = (width.display_width(lead, 0) as isize + self.briopt.shift).max(0);
+            (indent as usize).min(screen_width.saturating_sub(self.briopt.min + sbr))
+        } else {
+            0
+        };
+        let prefix = if self.briopt.sbr {
+            format!("{}{:indent$}", self.showbreak, "")
+        } else {
+            format!("{:indent$}{}", "", self.showbreak)
+        };
+        if sbr + indent < screen_width {
+            prefix
+        } else {
+            String::new()
+        }
+    }
+
+    /// Splits `text` into the rows it takes up in a window `screen_width` columns wide
+    pub fn rows(&self, text: &str, width: &WidthOpts, screen_width: usize) -> Vec<Row> {
+        let screen_width = screen_width.max(1);
+        if !self.wrap {
+            return vec![Row {
+                bytes: 0..text.len(),
+                cols: 0..usize::MAX,
+                indent: 0,
+            }];
+        }
+        let prefix = width.str_width(&self.prefix(text, width, screen_width));
+        let cells: Vec<_> = width.cells(text).collect();
+        let mut rows = vec![];
+        let mut first = 0;
+        let mut start_col = 0;
+        let mut avail = screen_width;
+        let mut indent = 0;
+        let mut brk = None;
+        let mut i = 0;
+        while i < cells.len() {
+            let cell = &cells[i];
+            let fits = cell.col + cell.width <= start_col + avail
+                || (cell.text == "\t" && cell.col < start_col + avail);
+            if fits || i == first {
+                if self.linebreak && self.breakat.contains(cell.text) {
+                    brk = Some(i);
+                }
+                i += 1;
+                continue;
+            }
+            let (next, end_col) = match brk {
+                Some(b) => (b + 1, cells[b + 1].col),
+                None => (i, start_col + avail),
+            };
+            rows.push(Row {
+                bytes: cells[first].byte..cells[next].byte,
+                cols: start_col..end_col,
+                indent,
+            });
+            first = next;
+            start_col = cells[next].col;
+            indent = prefix;
+            avail = screen_width - prefix;
+            brk = None;
+            i = next;
+        }
+        rows.push(Row {
+            bytes: cells.get(first).map_or(text.len(), |c| c.byte)..text.len(),
+            cols: start_col..start_col + avail,
+            indent,
+        });
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(rows: &[Row]) -> Vec<Range<usize>> {
+        rows.iter().map(|r| r.bytes.clone()).collect()
+    }
+
+    #[test]
+    fn nowrap() {
+        let opts = WrapOpts {
+            wrap: false,
+            ..WrapOpts::default()
+        };
+        let rows = opts.rows("hello world", &WidthOpts::default(), 4);
+        assert_eq!(bytes(&rows), vec![0..11]);
+    }
+
+    #[test]
+    fn wrap() {
+        let opts = WrapOpts::default();
+        let w = WidthOpts::default();
+        assert_eq!(bytes(&opts.rows("", &w, 4)), vec![0..0]);
+        assert_eq!(bytes(&opts.rows("abcd", &w, 4)), vec![0..4]);
+        assert_eq!(bytes(&opts.rows("hello world", &w, 4)), vec![0..4, 4..8, 8..11]);
+        // A wide character that does not fit moves to the next row
+        let rows = opts.rows("abc日本", &w, 4);
+        assert_eq!(bytes(&rows), vec![0..3, 3..9]);
+        assert_eq!(rows[0].cols, 0..4);
+    }
+
+    #[test]
+    fn linebreak() {
+        let opts = WrapOpts {
+            linebreak: true,
+            ..WrapOpts::default()
+        };
+        let rows = opts.rows("hello world foo", &WidthOpts::default(), 8);
+        assert_eq!(bytes(&rows), vec![0..6, 6..12, 12..15]);
+        assert_eq!(rows[0].cols, 0..6);
+    }
+
+    #[test]
+    fn showbreak() {
+        let opts = WrapOpts {
+            showbreak: "> ".into(),
+            ..WrapOpts::default()
+        };
+        let rows = opts.rows("abcdefghij", &WidthOpts::default(), 6);
+        assert_eq!(bytes(&rows), vec![0..6, 6..10]);
+        assert_eq!(rows[1].indent, 2);
+    }
+
+    #[test]
+    fn breakindent() {
+        let opts = WrapOpts {
+            breakindent: true,
+            briopt: BreakIndentOpt::parse("min:2,shift:1"),
+            ..WrapOpts::default()
+        };
+        let w = WidthOpts::default();
+        assert_eq!(opts.prefix("  abcdefgh", &w, 6), "   ");
+        let rows = opts.rows("  abcdefgh", &w, 6);
+        assert_eq!(bytes(&rows), vec![0..6, 6..9, 9..10]);
+    }
+}
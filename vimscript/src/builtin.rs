@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use crate::{VimScriptCtx, BuiltinFunction, value::{Value, Function, VimType}, VimError, State, Command, CmdRange};
+use crate::{VimScriptCtx, BuiltinFunction, value::{Value, Function}, VimError, State, Command, CmdRange, regex::{self, Regex}};
 
 struct Eval;
 
@@ -28,6 +28,108 @@ impl<S, F: Fn(Vec<Value>, &mut VimScriptCtx<S>) -> Result<Value, VimError>> Buil
     }
 }
 
+/// A builtin that compiles patterns with the options from the state
+struct Regexp<F>(F);
+
+impl<S: State, F: Fn(Vec<Value>, &regex::Options, &mut VimScriptCtx<S>) -> Result<Value, VimError>> BuiltinFunction<S> for Regexp<F> {
+    fn execute(&self, args: Vec<Value>, ctx: &mut VimScriptCtx<S>, state: &mut S) -> Result<Value, VimError> {
+        self.0(args, &state.regex_options(), ctx)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatchKind {
+    Start,
+    End,
+    Str,
+    StrPos,
+    List,
+}
+
+/// match(), matchend(), matchstr(), matchstrpos() and matchlist()
+fn find_match<S>(args: Vec<Value>, opts: &regex::Options, ctx: &mut VimScriptCtx<S>, kind: MatchKind) -> Result<Value, VimError> {
+    let mut args = args.into_iter();
+    let (expr, pat) = match (args.next(), args.next()) {
+        (Some(expr), Some(pat)) => (expr, pat),
+        _ => return Err(VimError::WrongArgCount(2)),
+    };
+    let start = args.next().map(|s| s.to_int(ctx)).transpose()?;
+    let count = args.next().map(|c| c.to_int(ctx)).transpose()?;
+    if args.next().is_some() {
+        return Err(VimError::WrongArgCount(4));
+    }
+    // Patterns are always used like 'magic' is set
+    let opts = regex::Options { magic: true, ..opts.clone() };
+    let re = Regex::new(&pat.to_string(ctx), &opts).map_err(VimError::Pattern)?;
+    let mut nth = count.unwrap_or(1).max(1);
+    // The index of the List item, the text matched in and where it starts in the item, and the match
+    let found = match &expr {
+        Value::List(l) => {
+            let items: Vec<Value> = l.lock().unwrap().iter().cloned().collect();
+            let from = match start.unwrap_or(0) {
+                s if s < 0 => (items.len() as isize + s).max(0) as usize,
+                s => s as usize,
+            };
+            items.iter().enumerate().skip(from).find_map(|(i, item)| {
+                let text = item.to_string(ctx);
+                let m = re.find_str(&text, 0)?;
+                nth -= 1;
+                (nth == 0).then_some((Some(i), text, 0, m))
+            })
+        }
+        expr => {
+            let text = expr.to_string(ctx);
+            let mut start = start.unwrap_or(0).max(0) as usize;
+            while start < text.len() && !text.is_char_boundary(start) {
+                start += 1;
+            }
+            // Without a count the string is like it starts at {start}, so "^" matches there
+            let (base, text, mut col) = match count {
+                None if start <= text.len() => (start, text[start..].to_string(), 0),
+                _ => (0, text, start),
+            };
+            loop {
+                let m = match re.find_str(&text, col) {
+                    Some(m) => m,
+                    None => break None,
+                };
+                nth -= 1;
+                if nth == 0 {
+                    break Some((None, text, base, m));
+                }
+                col = m.start.col + text[m.start.col..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+    };
+    let int = |i: usize| Value::Integer(i as isize);
+    Ok(match (kind, found) {
+        (MatchKind::Start | MatchKind::End, Some((Some(i), ..))) => int(i),
+        (MatchKind::Start, Some((None, _, base, m))) => int(base + m.start.col),
+        (MatchKind::End, Some((None, _, base, m))) => int(base + m.end.col),
+        (MatchKind::Start | MatchKind::End, None) => Value::Integer(-1),
+        (MatchKind::Str, Some((_, text, _, m))) => Value::Str(text[m.range()].to_string()),
+        (MatchKind::Str, None) => Value::Str(String::new()),
+        (MatchKind::StrPos, Some((i, text, base, m))) => {
+            let s = Value::Str(text[m.range()].to_string());
+            match i {
+                Some(i) => Value::list([s, int(i), int(m.start.col), int(m.end.col)]),
+                None => Value::list([s, int(base + m.start.col), int(base + m.end.col)]),
+            }
+        }
+        (MatchKind::StrPos, None) => {
+            let mut ret = vec![Value::Str(String::new()), Value::Integer(-1), Value::Integer(-1)];
+            if matches!(expr, Value::List(_)) {
+                ret.push(Value::Integer(-1));
+            }
+            Value::list(ret)
+        }
+        (MatchKind::List, Some((_, text, _, m))) => {
+            Value::list((0..10).map(|n| Value::Str(m.group_text(text.as_str(), n).unwrap_or_default())))
+        }
+        (MatchKind::List, None) => Value::list(Vec::<Value>::new()),
+    })
+}
+
 impl<E> Into<Result<Value, E>> for Value {
     fn into(self) -> Result<Value, E> {
         Ok(self)
@@ -102,12 +204,17 @@ impl<S: State> VimScriptCtx<S> {
 // 	tolower()		turn a string to lowercase
         self.functions.insert_builtin("tolower", nargs!(|ctx, a| Value::Str(a.to_string(ctx).to_uppercase())));
 // 	toupper()		turn a string to uppercase
+        self.functions.insert_builtin("match", Function::Builtin(Arc::new(Regexp(|a, o: &_, ctx: &mut _| find_match(a, o, ctx, MatchKind::Start)))));
 // 	match()			position where a pattern matches in a string
+        self.functions.insert_builtin("matchend", Function::Builtin(Arc::new(Regexp(|a, o: &_, ctx: &mut _| find_match(a, o, ctx, MatchKind::End)))));
 // 	matchend()		position where a pattern match ends in a string
 // 	matchfuzzy()		fuzzy matches a string in a list of strings
 // 	matchfuzzypos()		fuzzy matches a string in a list of strings
+        self.functions.insert_builtin("matchstr", Function::Builtin(Arc::new(Regexp(|a, o: &_, ctx: &mut _| find_match(a, o, ctx, MatchKind::Str)))));
 // 	matchstr()		match of a pattern in a string
+        self.functions.insert_builtin("matchstrpos", Function::Builtin(Arc::new(Regexp(|a, o: &_, ctx: &mut _| find_match(a, o, ctx, MatchKind::StrPos)))));
 // 	matchstrpos()		match and positions of a pattern in a string
+        self.functions.insert_builtin("matchlist", Function::Builtin(Arc::new(Regexp(|a, o: &_, ctx: &mut _| find_match(a, o, ctx, MatchKind::List)))));
 // 	matchlist()		like matchstr() and also return submatches
 // 	stridx()		first index of a short string in a long string
 // 	strridx()		last index of a short string in a long string
@@ -163,7 +270,12 @@ impl<S: State> VimScriptCtx<S> {
 // 	reverse()		reverse the order of a List
         self.functions.insert_builtin("uniq", nargs!(|ctx, a, b = Nil, c = Nil| a.unique(b, c, ctx)));
 // 	uniq()			remove copies of repeated adjacent items
-        self.functions.insert_builtin("split", nargs!(|ctx, a, b = Nil, c = Nil| a.split(b, c, ctx)));
+        self.functions.insert_builtin("split", Function::Builtin(Arc::new(Regexp(|a: Vec<Value>, o: &_, ctx: &mut _| {
+            let mut a = a.into_iter();
+            let text = a.next().ok_or(VimError::WrongArgCount(1))?;
+            let (pattern, keepempty) = (a.next().unwrap_or(Nil), a.next().unwrap_or(Nil));
+            text.split(pattern, keepempty, o, ctx)
+        }))));
 // 	split()			split a String into a List
         self.functions.insert_builtin("join", nargs!(|ctx, a, b = Nil| a.join(b, ctx)));
 // 	join()			join List items into a String
@@ -314,10 +426,10 @@ impl<S: State> VimScriptCtx<S> {
 
 struct Cmd<F>(F);
 
-impl<S, F: Fn(CmdRange<'_>, bool, &str, &mut VimScriptCtx<S>, &mut S)> Command<S> for Cmd<F> {
+impl<S, F: Fn(CmdRange, bool, &str, &mut VimScriptCtx<S>, &mut S)> Command<S> for Cmd<F> {
     fn execute(
         &self,
-        range: CmdRange<'_>,
+        range: CmdRange,
         bang: bool,
         commands: &str,
         ctx: &mut VimScriptCtx<S>,
@@ -330,7 +442,7 @@ impl<S, F: Fn(CmdRange<'_>, bool, &str, &mut VimScriptCtx<S>, &mut S)> Command<S
 macro_rules! cmd {
     (|$range:ident, $bang:ident, $args:ident, $ctx:ident, $state:ident| $expr:expr) => {
         {
-            fn cmd_impl<S: State>($range: CmdRange<'_>, $bang: bool, $args: &str, $ctx: &mut VimScriptCtx<S>, $state: &mut S) {
+            fn cmd_impl<S: State>($range: CmdRange, $bang: bool, $args: &str, $ctx: &mut VimScriptCtx<S>, $state: &mut S) {
                 $expr;
             }
             Arc::new(Cmd(cmd_impl))
@@ -6,7 +6,7 @@
 
 use std::{collections::{HashMap, LinkedList}, sync::{Mutex, Arc}};
 
-use crate::{value::Value, State, VimError, VimScriptCtx};
+use crate::{regex, value::Value, State, VimError, VimScriptCtx};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ValueError {
@@ -49,15 +49,19 @@ impl<'a> ExprPeice<'a> {
                     .unwrap_or(expr.len());
                 Ok((Self::Value(Value::parse_num(&expr[..i])?), &expr[i..]))
             }
-            'a'..='z' | 'A'..='Z' => {
+            'a'..='z' | 'A'..='Z' | '_' => {
                 let i = expr
-                    .find(|c| !matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | ':'))
+                    .find(|c| !matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | ':'))
                     .unwrap_or(expr.len());
                 Ok((Self::Var(&expr[..i]), &expr[i..]))
             }
             '+' | '.' | '*' | '-' | '/' | '%' | '=' | '!' | '<' | '>' | ',' | '[' | ']' | '{'
             | '}' | '(' | ')' | ':' => {
-                if matches!(chars.next(), Some('=')) {
+                if matches!(first_char, '=' | '!') && expr[1..].starts_with('~') {
+                    // `=~` and `!~`, optionally followed by `#` or `?` to match case or not
+                    let len = if expr[2..].starts_with(['#', '?']) { 3 } else { 2 };
+                    Ok((Self::Op(&expr[..len]), &expr[len..]))
+                } else if matches!(chars.next(), Some('=')) {
                     Ok((Self::Op(&expr[..2]), &expr[2..]))
                 } else {
                     Ok((Self::Op(&expr[..1]), &expr[1..]))
@@ -94,6 +98,12 @@ pub fn parse<S: State + 'static>(
         expr = remaining.trim();
     }
     function_call_extract(&mut parsed);
+    let opts = state.regex_options();
+    let case = |ignorecase| regex::Options {
+        ignorecase,
+        ..opts.clone()
+    };
+    let (match_case, ignore_case) = (case(false), case(true));
     let mut last = &ExprPeice::Op("");
     for token in parsed.iter_mut() {
         if let ExprPeice::Var(s) = token {
@@ -142,6 +152,12 @@ pub fn parse<S: State + 'static>(
                 (">=", &|lhs, rhs| lhs.less(rhs, ctx)?.not(ctx)),
                 ("==", &|lhs, rhs| lhs.equal(rhs, ctx)),
                 ("!=", &|lhs, rhs| lhs.equal(rhs, ctx)?.not(ctx)),
+                ("=~", &|lhs, rhs| lhs.matches(rhs, &opts, ctx)),
+                ("=~#", &|lhs, rhs| lhs.matches(rhs, &match_case, ctx)),
+                ("=~?", &|lhs, rhs| lhs.matches(rhs, &ignore_case, ctx)),
+                ("!~", &|lhs, rhs| lhs.matches(rhs, &opts, ctx)?.not(ctx)),
+                ("!~#", &|lhs, rhs| lhs.matches(rhs, &match_case, ctx)?.not(ctx)),
+                ("!~?", &|lhs, rhs| lhs.matches(rhs, &ignore_case, ctx)?.not(ctx)),
             ],
         )?;
         if !changed {
@@ -436,20 +452,28 @@ mod tests {
         assert_eq!(Value::Number(1.1), test_parse("1.1"));
     }
 
+    #[test]
+    fn pattern_match() {
+        assert_eq!(Value::Bool(true), test_parse("'foobar' =~ 'o\\+b'"));
+        assert_eq!(Value::Bool(false), test_parse("'foobar' =~# 'FOO'"));
+        assert_eq!(Value::Bool(true), test_parse("'foobar' =~? 'FOO'"));
+        assert_eq!(Value::Bool(true), test_parse("'foobar' !~ '^b'"));
+    }
+
     #[test]
     fn literal_object() {
-        assert_eq!(Value::Object(HashMap::new()), test_parse("{}"));
+        assert_eq!(Value::Object(Arc::new(Mutex::new(HashMap::new()))), test_parse("{}"));
         assert_eq!(
-            Value::Object(HashMap::from_iter([("a".into(), Value::Integer(0))])),
+            Value::Object(Arc::new(Mutex::new(HashMap::from_iter([("a".into(), Value::Integer(0))])))),
             test_parse("{'a': 0}")
         );
     }
 
     #[test]
     fn literal_list() {
-        assert_eq!(Value::List(LinkedList::new()), test_parse("[]"));
+        assert_eq!(Value::list(Vec::<Value>::new()), test_parse("[]"));
         assert_eq!(
-            Value::List([Value::Integer(0)].iter().cloned().collect()),
+            Value::list([Value::Integer(0)]),
             test_parse("[0]")
         );
     }
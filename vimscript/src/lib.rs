@@ -1,13 +1,16 @@
-#![feature(iter_intersperse, pattern, ptr_to_from_bits)]
+#![feature(iter_intersperse, pattern)]
 
 pub mod builtin;
 mod expr;
 mod namespace;
+mod range;
+pub mod regex;
 mod value;
 
 use expr::ValueError;
 use namespace::NamespaceError;
 pub use namespace::{Id, IdProcuder};
+pub use range::{resolve_address, split as split_range, Address};
 use value::Names;
 use value::VimType;
 
@@ -29,6 +32,15 @@ pub trait State: 'static {
     fn set_silent(&mut self, silent: bool);
     fn echo(&mut self, msg: Arguments);
     fn get_option(&self, name: &str) -> Result<Value, VimError>;
+    /// The options patterns are compiled with
+    fn regex_options(&self) -> regex::Options {
+        regex::Options::default()
+    }
+    /// The line `address` refers to, counted from 1, where a search starts from `line`
+    fn address(&mut self, address: Address<'_>, line: usize) -> Result<usize, VimError> {
+        let _ = (address, line);
+        Err(VimError::InvalidRange)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -43,6 +55,8 @@ pub enum VimError {
     Exit,
     #[error("Command does not accept a range parameter")]
     RangeNotSupported,
+    #[error("E16: Invalid range")]
+    InvalidRange,
     #[error("Command does not accept a bang")]
     BangNotSupported,
     #[error("Expected {0}")]
@@ -73,6 +87,10 @@ pub enum VimError {
     ExpectedType(VimType),
     #[error("Not a boolean value")]
     NotABool,
+    #[error("{0}")]
+    Pattern(String),
+    #[error("{0}")]
+    Shell(String),
 
     #[error("Illegal Argument: {0}")]
     IllegalArgument(&'static str)
@@ -87,7 +105,7 @@ impl From<Infallible> for VimError {
 pub trait Command<S> {
     fn execute(
         &self,
-        range: CmdRange<'_>,
+        range: CmdRange,
         bang: bool,
         commands: &str,
         ctx: &mut VimScriptCtx<S>,
@@ -104,60 +122,19 @@ pub trait BuiltinFunction<S> {
     ) -> Result<Value, VimError>;
 }
 
+/// The lines a command is run on, with its addresses resolved
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum CmdRange<'a> {
+pub enum CmdRange {
+    /// No range was given
     CurrentLine,
-    Whole,
-    Select(&'a str),
-    RangeFrom(usize),
-    RangeTo(usize),
+    /// Lines `start` to `end`, counted from 1, where line 0 is before the first line
     Range { start: usize, end: usize },
 }
 
-#[derive(Debug)]
-pub enum CmdRangeOwned {
-    CurrentLine,
-    Whole,
-    Select(String),
-    RangeFrom(usize),
-    RangeTo(usize),
-    Range { start: usize, end: usize },
-}
-
-impl CmdRangeOwned {
-    pub fn as_ref(&self) -> CmdRange {
-        match self {
-            CmdRangeOwned::CurrentLine => CmdRange::CurrentLine,
-            CmdRangeOwned::Whole => CmdRange::Whole,
-            CmdRangeOwned::Select(s) => CmdRange::Select(s.as_str()),
-            CmdRangeOwned::RangeFrom(start) => CmdRange::RangeFrom(*start),
-            CmdRangeOwned::RangeTo(end) => CmdRange::RangeTo(*end),
-            CmdRangeOwned::Range { start, end } => CmdRange::Range {
-                start: *start,
-                end: *end,
-            },
-        }
-    }
-}
-
-impl<'a> CmdRange<'a> {
+impl CmdRange {
     pub fn is_some(&self) -> bool {
         !matches!(self, Self::CurrentLine)
     }
-
-    pub fn to_owned(&self) -> CmdRangeOwned {
-        match self {
-            CmdRange::CurrentLine => CmdRangeOwned::CurrentLine,
-            CmdRange::Whole => CmdRangeOwned::Whole,
-            CmdRange::Select(s) => CmdRangeOwned::Select(s.to_string()),
-            CmdRange::RangeFrom(start) => CmdRangeOwned::RangeFrom(*start),
-            CmdRange::RangeTo(end) => CmdRangeOwned::RangeTo(*end),
-            CmdRange::Range { start, end } => CmdRangeOwned::Range {
-                start: *start,
-                end: *end,
-            },
-        }
-    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -392,7 +369,7 @@ impl<S: State + 'static> VimScriptCtx<S> {
                 }
             }
             "let" => run.act(line, |line| {
-                if line.range.is_some() {
+                if !line.range.is_empty() {
                     Err(VimError::RangeNotSupported)
                 } else if line.bang {
                     Err(VimError::BangNotSupported)
@@ -439,7 +416,8 @@ impl<S: State + 'static> VimScriptCtx<S> {
             }
             _ => run.act(line, |line| {
                 if let Some(cmd) = self.commands.get(line.command) {
-                    Arc::clone(cmd).execute(line.range, line.bang, line.params, self, state);
+                    let range = range::resolve(line.range, state)?;
+                    Arc::clone(cmd).execute(range, line.bang, line.params, self, state);
                     Ok(())
                 } else {
                     Err(VimError::CommandUndefined(line.command.to_string()))
@@ -545,8 +523,46 @@ impl<S: State + 'static> VimScriptCtx<S> {
         self.variables.set_script(id);
         self.functions.set_script(id);
     }
+
+    /// The full names of the commands and keywords, without the abbreviations of commands
+    pub fn command_names(&self) -> Vec<&str> {
+        let abbreviates = |name: &str, cmd: &Arc<dyn Command<S>>| {
+            self.commands.iter().any(|(other, c)| {
+                other.len() > name.len() && other.starts_with(name) && Arc::ptr_eq(c, cmd)
+            })
+        };
+        self.commands
+            .iter()
+            .filter(|(name, cmd)| !abbreviates(name, cmd))
+            .map(|(name, _)| name.as_str())
+            .chain(KEYWORDS.iter().copied())
+            .collect()
+    }
+
+    /// The names of the functions that can be called here
+    pub fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions.names()
+    }
+
+    /// The names of the variables that can be used here
+    pub fn variable_names(&self) -> impl Iterator<Item = &str> {
+        self.variables.names()
+    }
 }
 
+/// The keywords that are run as commands
+const KEYWORDS: &[&str] = &[
+    "if", "elseif", "else", "endif", "for", "endfor", "while", "endwhile", "function",
+    "endfunction", "let", "silent", "unsilent", "execute", "finish", "exit", "return",
+];
+
+/// Commands whose argument runs to the end of the line, including any `|`
+const BAR_COMMANDS: &[&str] = &[
+    "g", "gl", "glo", "glob", "globa", "global", "v", "vg", "vgl", "vglo", "vglob", "vgloba",
+    "vglobal", "norm", "norma", "normal", "cdo", "cfdo", "ldo", "lfdo",
+    "au", "aut", "auto", "autoc", "autocm", "autocmd",
+];
+
 #[derive(Debug, Clone)]
 enum Tokenizer<'a> {
     Script(&'a str),
@@ -556,12 +572,25 @@ enum Tokenizer<'a> {
 impl<'a> Tokenizer<'a> {
     fn get_next(script: &mut &'a str) -> Result<Option<Line<'a>>, VimError> {
         let mut last = ' ';
+        let mut prev = ' ';
+        // Commands that take a command as an argument see the `|` as part of it, and so does a
+        // shell command, in `:!cmd` and `:r !cmd`
+        let first = script.split('\n').next().unwrap_or("").trim_start();
+        let takes_bar = Line::split_range(first).is_ok_and(|(_, rest)| {
+            let (command, args) = Line::split_command(rest);
+            BAR_COMMANDS.contains(&command)
+                || matches!(command, "" | "r" | "re" | "rea" | "read")
+                    && args.trim_start().starts_with('!')
+        });
         let (line, next) = script
             .split_once(|c: char| {
-                let result = (last != '\\' && c == '\n') || c == '|';
+                // A `\|` is part of the command, e.g. in a pattern
+                let result = (last != '\\' && c == '\n')
+                    || (!takes_bar && prev != '\\' && c == '|');
                 if !c.is_whitespace() {
                     last = c;
                 }
+                prev = c;
                 result
             })
             .unwrap_or((script, ""));
@@ -590,7 +619,8 @@ impl<'a> Tokenizer<'a> {
 
 #[derive(Debug)]
 struct Line<'a> {
-    range: CmdRange<'a>,
+    /// The addresses before the command, resolved when it runs
+    range: &'a str,
     command: &'a str,
     bang: bool,
     params: &'a str,
@@ -605,7 +635,8 @@ impl<'a> Line<'a> {
         let (range, line) = Self::split_range(line)?;
         let (command, line) = Self::split_command(line);
         let (bang, params) = Self::split_bang(line);
-        if !bang && command.is_empty() {
+        // A range on its own is the command named "", which moves to the line
+        if !bang && command.is_empty() && range.is_empty() {
             return Ok(None);
         }
         Ok(Some(Self {
@@ -616,46 +647,20 @@ impl<'a> Line<'a> {
         }))
     }
 
-    pub fn split_range(line: &str) -> Result<(CmdRange, &str), VimError> {
-        if let Some(line) = line.strip_prefix('/') {
-            let mut last = '/';
-            if let Some((pattern, line)) = line.split_once(|c: char| {
-                // Filter for \/ to allow escapes
-                let res = c == '/' && last != '\\';
-                last = c;
-                res
-            }) {
-                Ok((CmdRange::Select(pattern), line))
-            } else {
-                Err(VimError::Expected("/"))
-            }
-        } else if let Some(line) = line.strip_prefix('%') {
-            Ok((CmdRange::Whole, line))
-        } else {
-            let idx = line.find(|c: char| c.is_alphabetic()).unwrap_or(line.len());
-            let rem = &line[idx..];
-            match line[..idx].split_once(',') {
-                Some(("", "")) => Ok((CmdRange::Whole, rem)),
-                Some(("", end)) => str::parse(end)
-                    .map(|e| (CmdRange::RangeTo(e), rem))
-                    .map_err(|_| VimError::Expected("Number")),
-                Some((start, "")) => str::parse(start)
-                    .map(|s| (CmdRange::RangeFrom(s), rem))
-                    .map_err(|_| VimError::Expected("Number")),
-                Some((start, end)) => Ok((
-                    CmdRange::Range {
-                        start: str::parse(start).map_err(|_| VimError::Expected("Number"))?,
-                        end: str::parse(end).map_err(|_| VimError::Expected("Number"))?,
-                    },
-                    rem,
-                )),
-                None => Ok((CmdRange::CurrentLine, rem)),
-            }
-        }
+    pub fn split_range(line: &str) -> Result<(&str, &str), VimError> {
+        range::split(line)
     }
 
     pub fn split_command(line: &str) -> (&str, &str) {
-        if let Some(idx) = line.find(|c: char| !c.is_alphanumeric()) {
+        // `:&`, `:~`, `:<`, `:>` and `:#` are named by a symbol
+        if line.starts_with(['&', '~', '<', '>', '#']) {
+            return line.split_at(1);
+        }
+        // Only user commands, which start with an uppercase letter, have digits in their names,
+        // so `:t0` and `:d3` take an argument
+        let user = line.starts_with(|c: char| c.is_uppercase());
+        let end = |c: char| !(c.is_alphabetic() || user && c.is_alphanumeric());
+        if let Some(idx) = line.find(end) {
             (&line[..idx], &line[idx..])
         } else {
             (line, "")
@@ -672,7 +677,7 @@ impl<'a> Line<'a> {
 
     fn to_owned(&self) -> LineOwned {
         LineOwned {
-            range: self.range.to_owned(),
+            range: self.range.to_string(),
             command: self.command.to_string(),
             bang: self.bang,
             params: self.params.to_string(),
@@ -682,7 +687,7 @@ impl<'a> Line<'a> {
 
 #[derive(Debug)]
 struct LineOwned {
-    range: CmdRangeOwned,
+    range: String,
     command: String,
     bang: bool,
     params: String,
@@ -691,7 +696,7 @@ struct LineOwned {
 impl LineOwned {
     fn as_ref(&self) -> Line {
         Line {
-            range: self.range.as_ref(),
+            range: self.range.as_str(),
             command: self.command.as_str(),
             bang: self.bang,
             params: self.params.as_str(),
@@ -711,7 +716,17 @@ mod tests {
         fn set_silent(&mut self, _s: bool) {}
         fn echo(&mut self, _msg: Arguments) {}
         fn get_option(&self, _name: &str) -> Result<Value, VimError> {
-            Err(VimError::VariableUndefined)
+            Err(VimError::VariableUndefined(_name.to_string()))
+        }
+        fn address(&mut self, address: Address<'_>, line: usize) -> Result<usize, VimError> {
+            match address {
+                Address::Current => Ok(3),
+                Address::Last => Ok(10),
+                Address::Mark('a') => Ok(5),
+                Address::Search("smth", true) if line < 7 => Ok(7),
+                Address::Search("smt\\/h", true) if line < 8 => Ok(8),
+                _ => Err(VimError::InvalidRange),
+            }
         }
     }
 
@@ -720,12 +735,12 @@ mod tests {
     }
 
     type BoxedFn =
-        Box<dyn Fn(CmdRange<'_>, bool, &str, &mut VimScriptCtx<TestContext>, &mut TestContext)>;
+        Box<dyn Fn(CmdRange, bool, &str, &mut VimScriptCtx<TestContext>, &mut TestContext)>;
 
     pub struct ExpectCall(BoxedFn, AtomicUsize);
 
     pub fn command(
-        f: impl Fn(CmdRange<'_>, bool, &str, &mut VimScriptCtx<TestContext>, &mut TestContext) + 'static,
+        f: impl Fn(CmdRange, bool, &str, &mut VimScriptCtx<TestContext>, &mut TestContext) + 'static,
     ) -> (Arc<ExpectCall>, Arc<ExpectCall>) {
         let call = Arc::new(ExpectCall(Box::new(f), AtomicUsize::new(0)));
         (call.clone(), call)
@@ -734,7 +749,7 @@ mod tests {
     impl Command<TestContext> for ExpectCall {
         fn execute(
             &self,
-            range: CmdRange<'_>,
+            range: CmdRange,
             bang: bool,
             commands: &str,
             ctx: &mut VimScriptCtx<TestContext>,
@@ -778,26 +793,39 @@ mod tests {
             assert_eq!(r, CmdRange::CurrentLine);
         });
         check_command!("1,Test", "Test" => |r, _b, _a, _c, _s| {
-            assert_eq!(r, CmdRange::RangeFrom(1));
+            assert_eq!(r, CmdRange::Range { start: 1, end: 3 });
+        });
+        check_command!("2Test", "Test" => |r, _b, _a, _c, _s| {
+            assert_eq!(r, CmdRange::Range { start: 2, end: 2 });
         });
         check_command!(",1Test", "Test" => |r, _b, _a, _c, _s| {
-            assert_eq!(r, CmdRange::RangeTo(1));
+            assert_eq!(r, CmdRange::Range { start: 1, end: 3 });
         });
         check_command!("1,4Test", "Test" => |r, _b, _a, _c, _s| {
             assert_eq!(r, CmdRange::Range { start: 1, end: 4 });
         });
         check_command!(",Test", "Test" => |r, _b, _a, _c, _s| {
-            assert_eq!(r, CmdRange::Whole);
+            assert_eq!(r, CmdRange::Range { start: 3, end: 3 });
         });
         check_command!("%Test", "Test" => |r, _b, _a, _c, _s| {
-            assert_eq!(r, CmdRange::Whole);
+            assert_eq!(r, CmdRange::Range { start: 1, end: 10 });
         });
         check_command!("/smth/Test", "Test" => |r, _b, _a, _c, _s| {
-            assert_eq!(r, CmdRange::Select("smth"));
+            assert_eq!(r, CmdRange::Range { start: 7, end: 7 });
         });
         check_command!("/smt\\/h/Test", "Test" => |r, _b, _a, _c, _s| {
-            assert_eq!(r, CmdRange::Select("smt\\/h"));
+            assert_eq!(r, CmdRange::Range { start: 8, end: 8 });
         });
+        check_command!("'a+1;/smth/-1 Test", "Test" => |r, _b, _a, _c, _s| {
+            assert_eq!(r, CmdRange::Range { start: 6, end: 6 });
+        });
+        check_command!("/smth//smt\\/h/,$-2Test", "Test" => |r, _b, _a, _c, _s| {
+            assert_eq!(r, CmdRange::Range { start: 8, end: 8 });
+        });
+        check_command!(".,.+3", "" => |r, _b, _a, _c, _s| {
+            assert_eq!(r, CmdRange::Range { start: 3, end: 6 });
+        });
+        assert!(test_ctx().run("$+1p", &mut TestContext).is_err());
     }
 
     #[test]
@@ -836,6 +864,10 @@ mod tests {
     #[test]
     fn multi_command() {
         check_command!("Test | Test", "Test", 2 => |_, _, _, _c, _|());
+        check_command!("1,2!sort | uniq", "" => |_, b, a, _c, _| {
+            assert!(b);
+            assert_eq!(a, "sort | uniq");
+        });
     }
 
     #[test]
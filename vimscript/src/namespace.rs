@@ -29,6 +29,12 @@ impl IdProcuder {
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub struct Id(usize);
 
+impl Id {
+    pub fn number(&self) -> usize {
+        self.0
+    }
+}
+
 impl Display for Id {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -202,6 +208,26 @@ impl<T> NameSpaced<T> {
         self.script_id = id.into();
     }
 
+    /// The names of the items that can be looked up in the current context
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        let scoped = [
+            (&self.buffer, self.buffer_id),
+            (&self.window, self.window_id),
+            (&self.script, self.script_id),
+        ];
+        self.global
+            .keys()
+            .chain(self.builtin.keys())
+            .chain(self.local.last().into_iter().flat_map(|m| m.keys()))
+            .chain(
+                scoped
+                    .into_iter()
+                    .filter_map(|(maps, id)| maps.get(&id?))
+                    .flat_map(|m| m.keys()),
+            )
+            .map(String::as_str)
+    }
+
     pub fn enter_local(&mut self) {
         self.local.push(HashMap::new());
     }
@@ -0,0 +1,232 @@
+//
+// range.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+use crate::{CmdRange, State, VimError};
+
+/// The part of a line address that the editor resolves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address<'a> {
+    /// `.`, the cursor line
+    Current,
+    /// `$`
+    Last,
+    /// `'x`
+    Mark(char),
+    /// `/pat/` searching forward, or `?pat?` backward, from the line after (or before) the one
+    /// the address starts from
+    Search(&'a str, bool),
+    /// `\/` and `\?`, which search with the last search pattern
+    LastSearch(bool),
+    /// `\&`, which searches forward with the last substitute pattern
+    LastSubstitute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step<'a> {
+    Line(usize),
+    Address(Address<'a>),
+    Offset(isize),
+}
+
+/// The steps of one address, and whether it was followed by `;`
+type Spec<'a> = (Vec<Step<'a>>, bool);
+
+/// Splits `s` after the pattern that ends with an unescaped `delim`, or at the end
+fn split_pattern(s: &str, delim: char) -> (&str, &str) {
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == delim {
+            return (&s[..i], &s[i + c.len_utf8()..]);
+        }
+    }
+    (s, "")
+}
+
+fn number(s: &str) -> Option<(usize, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s[..end].parse().ok().map(|n| (n, &s[end..]))
+}
+
+/// Parses one address, like `'a+2` or `/a//b/-`
+fn parse_address(mut s: &str) -> Result<(Vec<Step<'_>>, &str), VimError> {
+    let mut steps = vec![];
+    loop {
+        s = s.trim_start_matches([' ', '\t']);
+        let first = steps.is_empty();
+        let mut chars = s.chars();
+        let (step, rest) = match chars.next() {
+            Some('.') if first => (Step::Address(Address::Current), chars.as_str()),
+            Some('$') if first => (Step::Address(Address::Last), chars.as_str()),
+            Some('\'') if first => match chars.next() {
+                Some(c) => (Step::Address(Address::Mark(c)), chars.as_str()),
+                None => return Err(VimError::Expected("mark")),
+            },
+            Some(delim @ ('/' | '?')) => {
+                let (pattern, rest) = split_pattern(chars.as_str(), delim);
+                (Step::Address(Address::Search(pattern, delim == '/')), rest)
+            }
+            Some('\\') => match chars.next() {
+                Some('/') => (Step::Address(Address::LastSearch(true)), chars.as_str()),
+                Some('?') => (Step::Address(Address::LastSearch(false)), chars.as_str()),
+                Some('&') => (Step::Address(Address::LastSubstitute), chars.as_str()),
+                _ => return Err(VimError::Expected("\\/, \\? or \\&")),
+            },
+            // A number after an address is added to it
+            Some(c) if c.is_ascii_digit() => {
+                let (n, rest) = number(s).ok_or(VimError::InvalidRange)?;
+                match first {
+                    true => (Step::Line(n), rest),
+                    false => (Step::Offset(n as isize), rest),
+                }
+            }
+            Some(sign @ ('+' | '-')) => {
+                let (n, rest) = number(chars.as_str()).unwrap_or((1, chars.as_str()));
+                let n = n as isize;
+                (Step::Offset(if sign == '+' { n } else { -n }), rest)
+            }
+            _ => return Ok((steps, s)),
+        };
+        steps.push(step);
+        s = rest;
+    }
+}
+
+/// Parses the addresses at the start of a command line, and returns the rest of it
+fn parse(line: &str) -> Result<(Vec<Spec<'_>>, &str), VimError> {
+    if let Some(rest) = line.strip_prefix('%') {
+        let whole = vec![
+            (vec![Step::Line(1)], false),
+            (vec![Step::Address(Address::Last)], false),
+        ];
+        return Ok((whole, rest));
+    }
+    let mut specs = vec![];
+    let mut s = line;
+    loop {
+        let (steps, rest) = parse_address(s)?;
+        let rest = rest.trim_start_matches([' ', '\t']);
+        match rest.chars().next() {
+            Some(sep @ (',' | ';')) => {
+                specs.push((steps, sep == ';'));
+                s = &rest[1..];
+            }
+            _ => {
+                if !steps.is_empty() || !specs.is_empty() {
+                    specs.push((steps, false));
+                }
+                return Ok((specs, rest));
+            }
+        }
+    }
+}
+
+/// Splits the range off the start of `line`
+pub fn split(line: &str) -> Result<(&str, &str), VimError> {
+    let (_, rest) = parse(line)?;
+    Ok((line[..line.len() - rest.len()].trim_end(), rest))
+}
+
+fn resolve_steps<S: State>(
+    steps: &[Step],
+    mut line: usize,
+    state: &mut S,
+) -> Result<usize, VimError> {
+    for step in steps {
+        line = match *step {
+            Step::Line(n) => n,
+            Step::Address(address) => state.address(address, line)?,
+            Step::Offset(n) => {
+                usize::try_from(line as isize + n).map_err(|_| VimError::InvalidRange)?
+            }
+        };
+    }
+    Ok(line)
+}
+
+/// Resolves the address at the start of `text`, like the destination of `:move`, and returns
+/// the rest of `text`
+pub fn resolve_address<'a, S: State>(
+    text: &'a str,
+    state: &mut S,
+) -> Result<(usize, &'a str), VimError> {
+    let (steps, rest) = parse_address(text.trim_start())?;
+    if steps.is_empty() {
+        return Err(VimError::InvalidRange);
+    }
+    let cur = state.address(Address::Current, 0)?;
+    let line = resolve_steps(&steps, cur, state)?;
+    if line > state.address(Address::Last, cur)? {
+        return Err(VimError::InvalidRange);
+    }
+    Ok((line, rest))
+}
+
+/// Resolves a range split off with `split`, where an empty address is the cursor line, or the
+/// address before a `;`. The last two addresses are used when there are more.
+pub(crate) fn resolve<S: State>(range: &str, state: &mut S) -> Result<CmdRange, VimError> {
+    let (specs, _) = parse(range)?;
+    if specs.is_empty() {
+        return Ok(CmdRange::CurrentLine);
+    }
+    let mut cur = state.address(Address::Current, 0)?;
+    let mut lines = vec![];
+    for (steps, semicolon) in specs {
+        let line = resolve_steps(&steps, cur, state)?;
+        if semicolon {
+            cur = line;
+        }
+        lines.push(line);
+    }
+    let last = state.address(Address::Last, cur)?;
+    if lines.iter().any(|&l| l > last) {
+        return Err(VimError::InvalidRange);
+    }
+    let end = lines[lines.len() - 1];
+    let start = lines.len().checked_sub(2).map_or(end, |i| lines[i]);
+    Ok(CmdRange::Range {
+        start: start.min(end),
+        end: start.max(end),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_ranges() {
+        assert_eq!(split("Test").unwrap(), ("", "Test"));
+        assert_eq!(split("'<,'>s/a/b/").unwrap(), ("'<,'>", "s/a/b/"));
+        assert_eq!(split("/a//b/,?c\\?d? p").unwrap(), ("/a//b/,?c\\?d?", "p"));
+        assert_eq!(split(".;+3 d").unwrap(), (".;+3", "d"));
+        assert_eq!(split("\\/-,\\&y").unwrap(), ("\\/-,\\&", "y"));
+        assert_eq!(split("%g/x/").unwrap(), ("%", "g/x/"));
+        assert!(split("\\x").is_err());
+    }
+
+    #[test]
+    fn addresses() {
+        let (steps, rest) = parse_address("/a//b/-2+ 3x").unwrap();
+        assert_eq!(rest, "x");
+        assert_eq!(
+            steps,
+            [
+                Step::Address(Address::Search("a", true)),
+                Step::Address(Address::Search("b", true)),
+                Step::Offset(-2),
+                Step::Offset(1),
+                Step::Offset(3),
+            ]
+        );
+        let (specs, _) = parse("'a,$").unwrap();
+        assert_eq!(specs[0].0, [Step::Address(Address::Mark('a'))]);
+        assert_eq!(specs[1].0, [Step::Address(Address::Last)]);
+        let (specs, _) = parse("5;").unwrap();
+        assert_eq!(specs, [(vec![Step::Line(5)], true), (vec![], false)]);
+    }
+}
@@ -0,0 +1,1929 @@
+//
+// regex.rs
+// Copyright (C) 2022 matthew <matthew@matthew-VirtualBox>
+// Distributed under terms of the MIT license.
+//
+
+//! Vim regular expressions, as described in `:help pattern`. A pattern is compiled into a
+//! small program, which is run by a backtracking matcher or by a Pike VM (the NFA engine).
+
+use std::ops::Range;
+
+/// A position in the text being matched. `col` is a byte index into the line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Pos {
+    pub fn new(line: usize, col: usize) -> Self {
+        Self { line, col }
+    }
+}
+
+/// Text that a pattern can match in, which can span several lines
+pub trait Haystack {
+    fn line_count(&self) -> usize;
+    fn line(&self, n: usize) -> &str;
+}
+
+/// A single line. A newline character in it is matched by `\n`.
+impl Haystack for str {
+    fn line_count(&self) -> usize {
+        1
+    }
+
+    fn line(&self, _n: usize) -> &str {
+        self
+    }
+}
+
+impl<S: AsRef<str>> Haystack for [S] {
+    fn line_count(&self) -> usize {
+        self.len()
+    }
+
+    fn line(&self, n: usize) -> &str {
+        self[n].as_ref()
+    }
+}
+
+/// The text between two positions, with the lines joined by newlines
+pub fn slice<H: Haystack + ?Sized>(text: &H, from: Pos, to: Pos) -> String {
+    if to <= from {
+        return String::new();
+    }
+    if from.line == to.line {
+        return text.line(from.line)[from.col..to.col].to_string();
+    }
+    let mut ret = text.line(from.line)[from.col..].to_string();
+    for line in from.line + 1..to.line {
+        ret.push('\n');
+        ret.push_str(text.line(line));
+    }
+    ret.push('\n');
+    ret.push_str(&text.line(to.line)[..to.col]);
+    ret
+}
+
+/// A set of characters given by an option like 'iskeyword'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharSet {
+    bits: [u64; 4],
+    /// Whether all characters above 255 are included, instead of only alphanumeric ones
+    wide: bool,
+}
+
+impl CharSet {
+    /// Parses a comma separated list of characters, character codes, ranges like `48-57` and
+    /// `@` for the alphabetic characters. A part starting with `^` removes characters.
+    pub fn parse(spec: &str, wide: bool) -> Result<Self, String> {
+        let mut set = Self { bits: [0; 4], wide };
+        let err = || format!("E474: Invalid argument: {spec}");
+        let mut rest = spec;
+        while !rest.is_empty() {
+            let (exclude, part) = match rest.strip_prefix('^') {
+                Some(r) if !r.is_empty() && !r.starts_with(',') => (true, r),
+                _ => (false, rest),
+            };
+            let (from, part) = Self::item(part).ok_or_else(err)?;
+            let (to, part, range) = match part.strip_prefix('-') {
+                Some(r) => {
+                    let (to, part) = Self::item(r).ok_or_else(err)?;
+                    (to, part, true)
+                }
+                None => (from, part, false),
+            };
+            rest = match part.strip_prefix(',') {
+                Some(r) => r,
+                None if part.is_empty() => part,
+                None => return Err(err()),
+            };
+            if from > to || to > 255 {
+                return Err(err());
+            }
+            if !range && from == '@' as u32 {
+                for n in 0..256 {
+                    if char::from_u32(n).is_some_and(char::is_alphabetic) {
+                        set.set(n, !exclude);
+                    }
+                }
+            } else {
+                for n in from..=to {
+                    set.set(n, !exclude);
+                }
+            }
+        }
+        Ok(set)
+    }
+
+    fn item(s: &str) -> Option<(u32, &str)> {
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        if digits > 0 {
+            return Some((s[..digits].parse().ok()?, &s[digits..]));
+        }
+        let c = s.chars().next()?;
+        Some((c as u32, &s[c.len_utf8()..]))
+    }
+
+    fn set(&mut self, n: u32, on: bool) {
+        let bit = 1 << (n % 64);
+        if on {
+            self.bits[n as usize / 64] |= bit;
+        } else {
+            self.bits[n as usize / 64] &= !bit;
+        }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        let n = c as u32;
+        if n < 256 {
+            (self.bits[n as usize / 64] >> (n % 64)) & 1 == 1
+        } else {
+            self.wide || c.is_alphanumeric()
+        }
+    }
+}
+
+/// Which matcher runs a pattern, from 'regexpengine' or `\%#=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// The NFA, unless the pattern uses back references or `\@>`
+    Auto,
+    Backtrack,
+    Nfa,
+}
+
+impl Engine {
+    pub fn from_option(n: isize) -> Self {
+        match n {
+            1 => Self::Backtrack,
+            2 => Self::Nfa,
+            _ => Self::Auto,
+        }
+    }
+}
+
+/// The option values a pattern is compiled with
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub ignorecase: bool,
+    pub magic: bool,
+    pub engine: Engine,
+    pub iskeyword: CharSet,
+    pub isident: CharSet,
+    pub isfname: CharSet,
+    pub isprint: CharSet,
+    /// The last substitute string, which `~` matches
+    pub substitute: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            ignorecase: false,
+            magic: true,
+            engine: Engine::Auto,
+            iskeyword: CharSet::parse("@,48-57,_,192-255", false).unwrap(),
+            isident: CharSet::parse("@,48-57,_,192-255", false).unwrap(),
+            isfname: CharSet::parse("@,48-57,/,.,-,_,+,,,#,$,%,~,=", true).unwrap(),
+            isprint: CharSet::parse("@,161-255", true).unwrap(),
+            substitute: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualMode {
+    Char,
+    Line,
+    Block,
+}
+
+/// The area matched by `\%V`. The end is included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visual {
+    pub start: Pos,
+    pub end: Pos,
+    pub mode: VisualMode,
+}
+
+impl Visual {
+    fn contains(&self, pos: Pos) -> bool {
+        let (start, end) = (self.start.min(self.end), self.start.max(self.end));
+        match self.mode {
+            VisualMode::Char => start <= pos && pos <= end,
+            VisualMode::Line => (start.line..=end.line).contains(&pos.line),
+            VisualMode::Block => {
+                let cols = self.start.col.min(self.end.col)..=self.start.col.max(self.end.col);
+                (start.line..=end.line).contains(&pos.line) && cols.contains(&pos.col)
+            }
+        }
+    }
+}
+
+/// The editor state used by `\%#`, `\%V` and the line and column items. Positions are in the
+/// lines of the text being matched.
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    /// The line number of the first line of the text, used by `\%23l`
+    pub first_line: usize,
+    pub cursor: Option<Pos>,
+    pub visual: Option<Visual>,
+    pub tabstop: usize,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self {
+            first_line: 0,
+            cursor: None,
+            visual: None,
+            tabstop: 8,
+        }
+    }
+}
+
+/// A match of a pattern. `start` and `end` are moved by `\zs` and `\ze`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub start: Pos,
+    pub end: Pos,
+    /// Where the pattern started matching, which is before `start` with `\zs`
+    pub origin: Pos,
+    groups: [Option<(Pos, Pos)>; 9],
+}
+
+impl Match {
+    /// The positions of `\0` to `\9`, where `\0` is the whole match
+    pub fn group(&self, n: usize) -> Option<(Pos, Pos)> {
+        match n {
+            0 => Some((self.start, self.end)),
+            n => self.groups.get(n - 1).copied().flatten(),
+        }
+    }
+
+    pub fn group_text<H: Haystack + ?Sized>(&self, text: &H, n: usize) -> Option<String> {
+        self.group(n).map(|(from, to)| slice(text, from, to))
+    }
+
+    /// The bytes of a match that starts and ends on the same line
+    pub fn range(&self) -> Range<usize> {
+        self.start.col..self.end.col
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+const ZS: usize = 20;
+const ZE: usize = 21;
+/// The slots of the whole match, the groups, `\zs` and `\ze`. The slots of the empty loop
+/// checks come after these.
+const SLOTS: usize = 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cmp {
+    Lt,
+    Eq,
+    Gt,
+}
+
+impl Cmp {
+    fn test(self, a: usize, b: usize) -> bool {
+        match self {
+            Self::Lt => a < b,
+            Self::Eq => a == b,
+            Self::Gt => a > b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assert {
+    Bol,
+    Eol,
+    BufStart,
+    BufEnd,
+    WordStart,
+    WordEnd,
+    /// `\%23l`, or the cursor line for `\%.l` when the number is `None`
+    Line(Cmp, Option<usize>),
+    Col(Cmp, Option<usize>),
+    VCol(Cmp, Option<usize>),
+    Visual,
+    Cursor,
+}
+
+/// The option a character class like `\k` uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SetKind {
+    Keyword,
+    Ident,
+    Fname,
+    Print,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Named {
+    Space,
+    Digit,
+    Hex,
+    Octal,
+    Word,
+    Head,
+    Alpha,
+    Lower,
+    Upper,
+    Alnum,
+    Cntrl,
+    Graph,
+    Punct,
+    PosixLower,
+    PosixUpper,
+    PosixSpace,
+    Return,
+    Tab,
+    Escape,
+    Backspace,
+    /// The characters of an option, without digits for `\I`, `\K`, `\F` and `\P`
+    Set(SetKind, bool),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Range(char, char),
+    Named(Named),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Class {
+    items: Vec<Item>,
+    negate: bool,
+}
+
+impl Class {
+    fn named(named: Named, negate: bool) -> Self {
+        Self {
+            items: vec![Item::Named(named)],
+            negate,
+        }
+    }
+
+    /// The class of `\s`, `\k` and the other backslash classes
+    fn escape(c: char) -> Option<Self> {
+        let (named, negate) = match c {
+            'i' => (Named::Set(SetKind::Ident, false), false),
+            'I' => (Named::Set(SetKind::Ident, true), false),
+            'k' => (Named::Set(SetKind::Keyword, false), false),
+            'K' => (Named::Set(SetKind::Keyword, true), false),
+            'f' => (Named::Set(SetKind::Fname, false), false),
+            'F' => (Named::Set(SetKind::Fname, true), false),
+            'p' => (Named::Set(SetKind::Print, false), false),
+            'P' => (Named::Set(SetKind::Print, true), false),
+            's' => (Named::Space, false),
+            'S' => (Named::Space, true),
+            'd' => (Named::Digit, false),
+            'D' => (Named::Digit, true),
+            'x' => (Named::Hex, false),
+            'X' => (Named::Hex, true),
+            'o' => (Named::Octal, false),
+            'O' => (Named::Octal, true),
+            'w' => (Named::Word, false),
+            'W' => (Named::Word, true),
+            'h' => (Named::Head, false),
+            'H' => (Named::Head, true),
+            'a' => (Named::Alpha, false),
+            'A' => (Named::Alpha, true),
+            'l' => (Named::Lower, false),
+            'L' => (Named::Lower, true),
+            'u' => (Named::Upper, false),
+            'U' => (Named::Upper, true),
+            _ => return None,
+        };
+        Some(Self::named(named, negate))
+    }
+}
+
+/// A `[:name:]` item in a collection
+fn posix(name: &str) -> Option<Named> {
+    Some(match name {
+        "alnum" => Named::Alnum,
+        "alpha" => Named::Alpha,
+        "blank" => Named::Space,
+        "cntrl" => Named::Cntrl,
+        "digit" => Named::Digit,
+        "graph" => Named::Graph,
+        "lower" => Named::PosixLower,
+        "print" => Named::Set(SetKind::Print, false),
+        "punct" => Named::Punct,
+        "space" => Named::PosixSpace,
+        "upper" => Named::PosixUpper,
+        "xdigit" => Named::Hex,
+        "return" => Named::Return,
+        "tab" => Named::Tab,
+        "escape" => Named::Escape,
+        "backspace" => Named::Backspace,
+        "ident" => Named::Set(SetKind::Ident, false),
+        "keyword" => Named::Set(SetKind::Keyword, false),
+        "fname" => Named::Set(SetKind::Fname, false),
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Look {
+    Ahead,
+    NotAhead,
+    Behind,
+    NotBehind,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Char(char),
+    /// `.`, or `\_.` which also matches a newline
+    Any(bool),
+    Class(Class, bool),
+    Newline,
+    Assert(Assert),
+    /// `\zs` and `\ze`
+    Save(usize),
+    Group(Option<usize>, Box<Node>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+    Backref(usize),
+    /// A lookaround, with the byte limit of a look-behind
+    Look(Look, usize, Box<Node>),
+    Atomic(Box<Node>),
+}
+
+impl Node {
+    fn literal(c: char) -> Self {
+        match c {
+            '\n' => Self::Newline,
+            c => Self::Char(c),
+        }
+    }
+
+    fn can_be_empty(&self) -> bool {
+        match self {
+            Self::Char(_) | Self::Any(_) | Self::Class(..) | Self::Newline => false,
+            Self::Group(_, node) | Self::Atomic(node) => node.can_be_empty(),
+            Self::Concat(nodes) => nodes.iter().all(Self::can_be_empty),
+            Self::Alt(nodes) => nodes.iter().any(Self::can_be_empty),
+            Self::Repeat { node, min, .. } => *min == 0 || node.can_be_empty(),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Magic {
+    Very,
+    On,
+    Off,
+    VeryOff,
+}
+
+/// The characters with a special meaning after `\v`
+const VERY_MAGIC: &str = "()|+=?{@<>%*.[~^$&";
+const MULTI: &str = "*+=?{@";
+
+#[derive(Debug, Clone, Copy)]
+struct Tok {
+    c: char,
+    /// Whether the character has its special meaning
+    magic: bool,
+    len: usize,
+}
+
+struct Parser<'a> {
+    pat: &'a str,
+    substitute: &'a str,
+    pos: usize,
+    magic: Magic,
+    icase: Option<bool>,
+    engine: Option<Engine>,
+    groups: usize,
+    backref: usize,
+    /// Whether the pattern needs the backtracking engine
+    backtrack: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn new(pat: &'a str, magic: bool, substitute: &'a str) -> Self {
+        Self {
+            pat,
+            substitute,
+            pos: 0,
+            magic: if magic { Magic::On } else { Magic::Off },
+            icase: None,
+            engine: None,
+            groups: 0,
+            backref: 0,
+            backtrack: false,
+        }
+    }
+
+    fn parse(&mut self) -> Result<Node, String> {
+        if let Some(rest) = self.pat.strip_prefix("\\%#=") {
+            self.engine = Some(match rest.chars().next() {
+                Some('0') => Engine::Auto,
+                Some('1') => Engine::Backtrack,
+                Some('2') => Engine::Nfa,
+                _ => return Err("E864: \\%#= can only be followed by 0, 1, or 2".into()),
+            });
+            self.pos = 5;
+        }
+        let node = self.alternation()?;
+        if self.pos < self.pat.len() {
+            return Err("E55: Unmatched \\)".into());
+        }
+        if self.backref > self.groups {
+            return Err("E65: Illegal back reference".into());
+        }
+        Ok(node)
+    }
+
+    fn special(&self, c: char) -> bool {
+        match self.magic {
+            Magic::Very => VERY_MAGIC.contains(c),
+            Magic::On => "^$.*[~".contains(c),
+            Magic::Off | Magic::VeryOff => "^$".contains(c),
+        }
+    }
+
+    fn peek(&self) -> Option<Tok> {
+        let mut chars = self.pat[self.pos..].chars();
+        let c = chars.next()?;
+        if c != '\\' {
+            return Some(Tok {
+                c,
+                magic: self.special(c),
+                len: c.len_utf8(),
+            });
+        }
+        Some(match chars.next() {
+            None => Tok {
+                c,
+                magic: false,
+                len: 1,
+            },
+            Some(d) => Tok {
+                c: d,
+                magic: if d.is_ascii_alphanumeric() || d == '_' {
+                    true
+                } else {
+                    d != '\\' && !self.special(d) && VERY_MAGIC.contains(d)
+                },
+                len: 1 + d.len_utf8(),
+            },
+        })
+    }
+
+    fn is_magic(&self, c: char) -> bool {
+        self.peek().is_some_and(|t| t.magic && t.c == c)
+    }
+
+    fn char_at(&self, pos: usize) -> Option<char> {
+        self.pat.get(pos..)?.chars().next()
+    }
+
+    /// The next character, without a magic meaning
+    fn raw(&mut self) -> Option<char> {
+        let c = self.char_at(self.pos)?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn number(&mut self, radix: u32, max: usize) -> Option<u32> {
+        let rest = &self.pat[self.pos..];
+        let len = rest
+            .chars()
+            .take(max)
+            .take_while(|c| c.is_digit(radix))
+            .count();
+        let n = u32::from_str_radix(&rest[..len], radix).ok()?;
+        self.pos += len;
+        Some(n)
+    }
+
+    fn close(&mut self, err: &str) -> Result<(), String> {
+        if self.is_magic(')') {
+            self.pos += self.peek().map_or(0, |t| t.len);
+            Ok(())
+        } else {
+            Err(err.into())
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut alts = vec![self.branch()?];
+        while let Some(tok) = self.peek().filter(|t| t.magic && t.c == '|') {
+            self.pos += tok.len;
+            alts.push(self.branch()?);
+        }
+        Ok(match alts.len() {
+            1 => alts.remove(0),
+            _ => Node::Alt(alts),
+        })
+    }
+
+    /// Concats separated by `\&`, where all but the last one only have to match
+    fn branch(&mut self) -> Result<Node, String> {
+        let mut concat = self.concat()?;
+        let mut parts = vec![];
+        while let Some(tok) = self.peek().filter(|t| t.magic && t.c == '&') {
+            self.pos += tok.len;
+            parts.push(Node::Look(Look::Ahead, 0, Box::new(concat)));
+            concat = self.concat()?;
+        }
+        if parts.is_empty() {
+            return Ok(concat);
+        }
+        parts.push(concat);
+        Ok(Node::Concat(parts))
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = vec![];
+        // Whether `^` is at the start of a line here
+        let mut at_start = true;
+        while let Some(tok) = self.peek() {
+            if tok.magic && matches!(tok.c, '|' | '&' | ')') {
+                break;
+            }
+            if tok.magic && self.flag(tok.c) {
+                self.pos += tok.len;
+                continue;
+            }
+            let (atom, start) = self.atom(tok, at_start)?;
+            at_start = start;
+            nodes.push(match atom {
+                Node::Assert(Assert::Bol) if start => atom,
+                atom => self.multi(atom)?,
+            });
+        }
+        Ok(match nodes.len() {
+            1 => nodes.remove(0),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn flag(&mut self, c: char) -> bool {
+        match c {
+            'c' => self.icase = Some(true),
+            'C' => self.icase = self.icase.or(Some(false)),
+            'v' => self.magic = Magic::Very,
+            'm' => self.magic = Magic::On,
+            'M' => self.magic = Magic::Off,
+            'V' => self.magic = Magic::VeryOff,
+            'Z' => (),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Parses an atom, and returns whether a `^` after it is at the start of a line
+    fn atom(&mut self, tok: Tok, at_start: bool) -> Result<(Node, bool), String> {
+        let pat = self.pat;
+        let text = &pat[self.pos..self.pos + tok.len];
+        self.pos += tok.len;
+        if !tok.magic {
+            return Ok((Node::literal(tok.c), false));
+        }
+        let node = match tok.c {
+            '^' if at_start => return Ok((Node::Assert(Assert::Bol), true)),
+            '$' if self
+                .peek()
+                .is_none_or(|t| t.magic && matches!(t.c, '|' | '&' | ')' | 'n')) =>
+            {
+                Node::Assert(Assert::Eol)
+            }
+            '*' if at_start => Node::Char('*'),
+            '^' | '$' => Node::Char(tok.c),
+            '.' => Node::Any(false),
+            '[' => self.collection(false)?.unwrap_or(Node::Char('[')),
+            '~' => Node::Concat(self.substitute.chars().map(Node::literal).collect()),
+            '(' => {
+                self.groups += 1;
+                if self.groups > 9 {
+                    return Err("E51: Too many \\(".into());
+                }
+                let n = self.groups;
+                let inner = self.alternation()?;
+                self.close("E54: Unmatched \\(")?;
+                Node::Group(Some(n), Box::new(inner))
+            }
+            '%' => self.percent()?,
+            '<' => Node::Assert(Assert::WordStart),
+            '>' => Node::Assert(Assert::WordEnd),
+            'z' => match self.raw() {
+                Some('s') => Node::Save(ZS),
+                Some('e') => Node::Save(ZE),
+                Some('(') => return Err("E66: \\z( not allowed here".into()),
+                Some('1'..='9') => return Err("E67: \\z1 - \\z9 not allowed here".into()),
+                _ => return Err("E68: Invalid character after \\z".into()),
+            },
+            '_' => self.underscore()?,
+            'n' => return Ok((Node::Newline, true)),
+            't' => Node::Char('\t'),
+            'e' => Node::Char('\x1b'),
+            'r' => Node::Char('\r'),
+            'b' => Node::Char('\x08'),
+            '1'..='9' => {
+                let n = tok.c as usize - '0' as usize;
+                self.backref = self.backref.max(n);
+                self.backtrack = true;
+                Node::Backref(n)
+            }
+            c if MULTI.contains(c) => return Err(format!("E64: {text} follows nothing")),
+            c => match Class::escape(c) {
+                Some(class) => Node::Class(class, false),
+                None => Node::Char(c),
+            },
+        };
+        Ok((node, false))
+    }
+
+    fn underscore(&mut self) -> Result<Node, String> {
+        let err = || "E63: Invalid use of \\_".to_string();
+        Ok(match self.raw().ok_or_else(err)? {
+            '^' => Node::Assert(Assert::Bol),
+            '$' => Node::Assert(Assert::Eol),
+            '.' => Node::Any(true),
+            '[' => self
+                .collection(true)?
+                .ok_or_else(|| "E769: Missing ] after \\_[".to_string())?,
+            c => Node::Class(Class::escape(c).ok_or_else(err)?, true),
+        })
+    }
+
+    fn percent(&mut self) -> Result<Node, String> {
+        let err = || "E71: Invalid character after \\%".to_string();
+        let c = self.raw().ok_or_else(err)?;
+        let code = |n: Option<u32>| {
+            n.and_then(char::from_u32)
+                .map(Node::literal)
+                .ok_or_else(err)
+        };
+        Ok(match c {
+            '(' => {
+                let inner = self.alternation()?;
+                self.close("E53: Unmatched \\%(")?;
+                Node::Group(None, Box::new(inner))
+            }
+            '^' => Node::Assert(Assert::BufStart),
+            '$' => Node::Assert(Assert::BufEnd),
+            'V' => Node::Assert(Assert::Visual),
+            '#' => Node::Assert(Assert::Cursor),
+            'C' => Node::Empty,
+            '[' => self.optional()?,
+            'd' => code(self.number(10, 10))?,
+            'x' => code(self.number(16, 2))?,
+            'u' => code(self.number(16, 4))?,
+            'U' => code(self.number(16, 8))?,
+            'o' => code(self.number(8, 4))?,
+            '<' | '>' | '.' | '0'..='9' => {
+                let cmp = match c {
+                    '<' => Cmp::Lt,
+                    '>' => Cmp::Gt,
+                    _ => {
+                        self.pos -= 1;
+                        Cmp::Eq
+                    }
+                };
+                let n = if self.char_at(self.pos) == Some('.') {
+                    self.pos += 1;
+                    None
+                } else {
+                    Some(self.number(10, 10).ok_or_else(err)? as usize)
+                };
+                match self.raw() {
+                    Some('l') => Node::Assert(Assert::Line(cmp, n)),
+                    Some('c') => Node::Assert(Assert::Col(cmp, n)),
+                    Some('v') => Node::Assert(Assert::VCol(cmp, n)),
+                    _ => return Err(err()),
+                }
+            }
+            _ => return Err(err()),
+        })
+    }
+
+    /// `\%[abc]`, a sequence of optionally matched atoms
+    fn optional(&mut self) -> Result<Node, String> {
+        let mut items = vec![];
+        loop {
+            match self.char_at(self.pos) {
+                None => return Err("E69: Missing ] after \\%[".into()),
+                Some(']') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some('[') => {
+                    self.pos += 1;
+                    items.push(self.collection(false)?.unwrap_or(Node::Char('[')));
+                }
+                Some(_) => {
+                    let tok = self.peek().unwrap();
+                    items.push(self.atom(tok, false)?.0);
+                }
+            }
+        }
+        if items.is_empty() {
+            return Err("E70: Empty \\%[]".into());
+        }
+        let mut node: Option<Node> = None;
+        for item in items.into_iter().rev() {
+            let inner = match node {
+                Some(node) => Node::Concat(vec![item, node]),
+                None => item,
+            };
+            node = Some(Node::Repeat {
+                node: Box::new(inner),
+                min: 0,
+                max: Some(1),
+                greedy: true,
+            });
+        }
+        Ok(node.unwrap())
+    }
+
+    /// The character of a backslash escape in a collection, like `\t` or `\x20`
+    fn escape(&self, pos: usize) -> Option<(char, usize)> {
+        let c = self.char_at(pos)?;
+        let (radix, max) = match c {
+            'e' => return Some(('\x1b', 1)),
+            't' => return Some(('\t', 1)),
+            'r' => return Some(('\r', 1)),
+            'b' => return Some(('\x08', 1)),
+            'n' => return Some(('\n', 1)),
+            '\\' | ']' | '^' | '-' => return Some((c, 1)),
+            'd' => (10, 10),
+            'o' => (8, 4),
+            'x' => (16, 2),
+            'u' => (16, 4),
+            'U' => (16, 8),
+            _ => return None,
+        };
+        let rest = &self.pat[pos + 1..];
+        let len = rest
+            .chars()
+            .take(max)
+            .take_while(|c| c.is_digit(radix))
+            .count();
+        let n = u32::from_str_radix(&rest[..len], radix).ok()?;
+        Some((char::from_u32(n)?, len + 1))
+    }
+
+    /// A `[:alpha:]`, `[=a=]` or `[.a.]` item in a collection, starting after the `[`
+    fn bracket(&self, pos: usize) -> Option<(Item, usize)> {
+        let rest = &self.pat[pos..];
+        if let Some(r) = rest.strip_prefix(':') {
+            let end = r.find(":]")?;
+            return Some((Item::Named(posix(&r[..end])?), end + 3));
+        }
+        let d = rest.chars().next().filter(|d| matches!(d, '=' | '.'))?;
+        let c = rest[1..].chars().next()?;
+        let close = &rest[1 + c.len_utf8()..];
+        (close.starts_with(d) && close[1..].starts_with(']'))
+            .then(|| (Item::Range(c, c), c.len_utf8() + 4))
+    }
+
+    /// Parses a collection after the `[`. Returns `None` when there is no `]`.
+    fn collection(&mut self, newline: bool) -> Result<Option<Node>, String> {
+        let mut pos = self.pos;
+        let mut newline = newline;
+        let mut items = vec![];
+        let negate = self.char_at(pos) == Some('^');
+        if negate {
+            pos += 1;
+        }
+        if self.char_at(pos) == Some(']') {
+            items.push(Item::Range(']', ']'));
+            pos += 1;
+        }
+        loop {
+            let c = match self.char_at(pos) {
+                Some(c) => c,
+                None => return Ok(None),
+            };
+            pos += c.len_utf8();
+            let lo = match c {
+                ']' => break,
+                '[' => match self.bracket(pos) {
+                    Some((item, len)) => {
+                        items.push(item);
+                        pos += len;
+                        continue;
+                    }
+                    None => '[',
+                },
+                '\\' => match self.escape(pos) {
+                    Some(('\n', len)) => {
+                        newline = true;
+                        pos += len;
+                        continue;
+                    }
+                    Some((c, len)) => {
+                        pos += len;
+                        c
+                    }
+                    None => '\\',
+                },
+                c => c,
+            };
+            if self.char_at(pos) == Some('-') && self.char_at(pos + 1).is_some_and(|c| c != ']') {
+                pos += 1;
+                let c = self.char_at(pos).unwrap();
+                pos += c.len_utf8();
+                let hi = match self.escape(pos).filter(|_| c == '\\') {
+                    Some((c, len)) => {
+                        pos += len;
+                        c
+                    }
+                    None => c,
+                };
+                if hi < lo {
+                    return Err("E944: Reverse range in character class".into());
+                }
+                items.push(Item::Range(lo, hi));
+            } else {
+                items.push(Item::Range(lo, lo));
+            }
+        }
+        self.pos = pos;
+        Ok(Some(Node::Class(Class { items, negate }, newline)))
+    }
+
+    fn multi(&mut self, atom: Node) -> Result<Node, String> {
+        let tok = match self.peek() {
+            Some(tok) if tok.magic && MULTI.contains(tok.c) => tok,
+            _ => return Ok(atom),
+        };
+        self.pos += tok.len;
+        let (min, max) = match tok.c {
+            '*' => (0, None),
+            '+' => (1, None),
+            '=' | '?' => (0, Some(1)),
+            '{' => return self.brace(atom).and_then(|node| self.nested(node)),
+            _ => return self.look(atom).and_then(|node| self.nested(node)),
+        };
+        self.nested(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy: true,
+        })
+    }
+
+    /// Fails when a multi follows another one
+    fn nested(&self, node: Node) -> Result<Node, String> {
+        match self.peek() {
+            Some(tok) if tok.magic && tok.c == '*' => Err("E61: Nested *".into()),
+            Some(tok) if tok.magic && MULTI.contains(tok.c) => Err(format!(
+                "E62: Nested {}",
+                &self.pat[self.pos..self.pos + tok.len]
+            )),
+            _ => Ok(node),
+        }
+    }
+
+    fn brace(&mut self, atom: Node) -> Result<Node, String> {
+        let err = || "E554: Syntax error in \\{...}".to_string();
+        let pat = self.pat;
+        let rest = &pat[self.pos..];
+        let end = rest.find('}').ok_or_else(err)?;
+        let inner = rest[..end].strip_suffix('\\').unwrap_or(&rest[..end]);
+        self.pos += end + 1;
+        let (greedy, inner) = match inner.strip_prefix('-') {
+            Some(inner) => (false, inner),
+            None => (true, inner),
+        };
+        let num = |s: &str| match s {
+            "" => Ok(None),
+            s => s.parse().map(Some).map_err(|_| err()),
+        };
+        let (min, max) = match inner.split_once(',') {
+            Some((min, max)) => (num(min)?.unwrap_or(0), num(max)?),
+            None => match num(inner)? {
+                Some(n) => (n, Some(n)),
+                None => (0, None),
+            },
+        };
+        let (min, max) = match max {
+            Some(max) if max < min => (max, Some(min)),
+            max => (min, max),
+        };
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// The multis starting with `\@`
+    fn look(&mut self, atom: Node) -> Result<Node, String> {
+        let err = || "E59: Invalid character after \\@".to_string();
+        let limit = self.number(10, 10);
+        let look = match (self.raw(), limit) {
+            (Some('>'), None) => {
+                self.backtrack = true;
+                return Ok(Node::Atomic(Box::new(atom)));
+            }
+            (Some('='), None) => Look::Ahead,
+            (Some('!'), None) => Look::NotAhead,
+            (Some('<'), _) => match self.raw() {
+                Some('=') => Look::Behind,
+                Some('!') => Look::NotBehind,
+                _ => return Err(err()),
+            },
+            _ => return Err(err()),
+        };
+        Ok(Node::Look(
+            look,
+            limit.unwrap_or(0) as usize,
+            Box::new(atom),
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any(bool),
+    Class(Box<Class>, bool),
+    Newline,
+    Assert(Assert),
+    Save(usize),
+    /// Records where an iteration of a loop that can match nothing starts
+    Mark(usize),
+    /// Fails when the loop iteration started with `Mark` didn't move
+    Check(usize),
+    Split(usize, usize),
+    Jmp(usize),
+    Backref(usize),
+    /// A lookaround, with the limit of a look-behind and where its body starts
+    Look(Look, usize, usize),
+    Atomic(usize),
+    Match,
+}
+
+struct Compiler<'a> {
+    prog: Vec<Inst>,
+    /// The lookarounds and atomic groups, which are compiled after the main program
+    pending: Vec<(usize, &'a Node)>,
+    slots: usize,
+    icase: bool,
+}
+
+impl<'a> Compiler<'a> {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.prog.push(inst);
+        self.prog.len() - 1
+    }
+
+    fn split(first: usize, second: usize, greedy: bool) -> Inst {
+        if greedy {
+            Inst::Split(first, second)
+        } else {
+            Inst::Split(second, first)
+        }
+    }
+
+    fn compile(&mut self, node: &'a Node) {
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => {
+                let c = if self.icase { fold(*c) } else { *c };
+                self.emit(Inst::Char(c));
+            }
+            Node::Any(nl) => {
+                self.emit(Inst::Any(*nl));
+            }
+            Node::Class(class, nl) => {
+                self.emit(Inst::Class(Box::new(class.clone()), *nl));
+            }
+            Node::Newline => {
+                self.emit(Inst::Newline);
+            }
+            Node::Assert(a) => {
+                self.emit(Inst::Assert(*a));
+            }
+            Node::Save(slot) => {
+                self.emit(Inst::Save(*slot));
+            }
+            Node::Group(n, inner) => {
+                if let Some(n) = n {
+                    self.emit(Inst::Save(2 * n));
+                }
+                self.compile(inner);
+                if let Some(n) = n {
+                    self.emit(Inst::Save(2 * n + 1));
+                }
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node);
+                }
+            }
+            Node::Alt(alts) => {
+                let mut jumps = vec![];
+                for alt in &alts[..alts.len() - 1] {
+                    let split = self.emit(Inst::Split(0, 0));
+                    self.compile(alt);
+                    jumps.push(self.emit(Inst::Jmp(0)));
+                    self.prog[split] = Inst::Split(split + 1, self.prog.len());
+                }
+                self.compile(&alts[alts.len() - 1]);
+                for jump in jumps {
+                    self.prog[jump] = Inst::Jmp(self.prog.len());
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+                match max {
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0));
+                        let mark = node.can_be_empty().then_some(self.slots);
+                        if let Some(slot) = mark {
+                            self.slots += 1;
+                            self.emit(Inst::Mark(slot));
+                        }
+                        self.compile(node);
+                        if let Some(slot) = mark {
+                            self.emit(Inst::Check(slot));
+                        }
+                        self.emit(Inst::Jmp(split));
+                        self.prog[split] = Self::split(split + 1, self.prog.len(), *greedy);
+                    }
+                    Some(max) => {
+                        let mut splits = vec![];
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0)));
+                            self.compile(node);
+                        }
+                        for split in splits {
+                            self.prog[split] = Self::split(split + 1, self.prog.len(), *greedy);
+                        }
+                    }
+                }
+            }
+            Node::Backref(n) => {
+                self.emit(Inst::Backref(*n));
+            }
+            Node::Look(look, limit, inner) => {
+                let at = self.emit(Inst::Look(*look, *limit, 0));
+                self.pending.push((at, inner));
+            }
+            Node::Atomic(inner) => {
+                let at = self.emit(Inst::Atomic(0));
+                self.pending.push((at, inner));
+            }
+        }
+    }
+}
+
+fn fold(c: char) -> char {
+    if c.is_ascii() {
+        c.to_ascii_lowercase()
+    } else {
+        c.to_lowercase().next().unwrap_or(c)
+    }
+}
+
+/// A compiled pattern
+#[derive(Debug, Clone)]
+pub struct Regex {
+    prog: Vec<Inst>,
+    slots: usize,
+    icase: bool,
+    engine: Engine,
+    sets: [CharSet; 4],
+}
+
+impl Regex {
+    pub fn new(pat: &str, opts: &Options) -> Result<Self, String> {
+        let mut parser = Parser::new(pat, opts.magic, &opts.substitute);
+        let node = parser.parse()?;
+        let icase = parser.icase.unwrap_or(opts.ignorecase);
+        let mut compiler = Compiler {
+            prog: vec![Inst::Save(0)],
+            pending: vec![],
+            slots: SLOTS,
+            icase,
+        };
+        compiler.compile(&node);
+        compiler.emit(Inst::Save(1));
+        compiler.emit(Inst::Match);
+        while let Some((at, node)) = compiler.pending.pop() {
+            let body = compiler.prog.len();
+            compiler.compile(node);
+            compiler.emit(Inst::Match);
+            compiler.prog[at] = match compiler.prog[at] {
+                Inst::Look(look, limit, _) => Inst::Look(look, limit, body),
+                _ => Inst::Atomic(body),
+            };
+        }
+        let engine = match parser.engine.unwrap_or(opts.engine) {
+            Engine::Backtrack => Engine::Backtrack,
+            _ if parser.backtrack => Engine::Backtrack,
+            _ => Engine::Nfa,
+        };
+        Ok(Self {
+            prog: compiler.prog,
+            slots: compiler.slots,
+            icase,
+            engine,
+            sets: [
+                opts.iskeyword.clone(),
+                opts.isident.clone(),
+                opts.isfname.clone(),
+                opts.isprint.clone(),
+            ],
+        })
+    }
+
+    /// The engine that runs the pattern, which is never `Auto`
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn ignorecase(&self) -> bool {
+        self.icase
+    }
+
+    /// The first match that starts on `line` at or after the byte `col`
+    pub fn find_on_line<H: Haystack + ?Sized>(
+        &self,
+        text: &H,
+        line: usize,
+        col: usize,
+        ctx: &Context,
+    ) -> Option<Match> {
+        if line >= text.line_count() || col > text.line(line).len() {
+            return None;
+        }
+        Exec {
+            re: self,
+            text,
+            ctx,
+        }
+        .find(Pos::new(line, col))
+    }
+
+    /// The first match that starts at or after `from`
+    pub fn find_from<H: Haystack + ?Sized>(
+        &self,
+        text: &H,
+        from: Pos,
+        ctx: &Context,
+    ) -> Option<Match> {
+        (from.line..text.line_count()).find_map(|line| {
+            let col = if line == from.line { from.col } else { 0 };
+            self.find_on_line(text, line, col, ctx)
+        })
+    }
+
+    /// The first match in a string that starts at or after the byte `from`
+    pub fn find_str(&self, text: &str, from: usize) -> Option<Match> {
+        self.find_on_line(text, 0, from, &Context::default())
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find_str(text, 0).is_some()
+    }
+}
+
+type Slots = Vec<Option<Pos>>;
+
+enum Job {
+    Run(usize, Pos),
+    Restore(usize, Option<Pos>),
+}
+
+struct Exec<'a, H: Haystack + ?Sized> {
+    re: &'a Regex,
+    text: &'a H,
+    ctx: &'a Context,
+}
+
+impl<'a, H: Haystack + ?Sized> Exec<'a, H> {
+    fn find(&self, from: Pos) -> Option<Match> {
+        let empty = vec![None; self.re.slots];
+        let (_, slots) = match self.re.engine {
+            Engine::Nfa => self.pike(0, from, false, None, &empty)?,
+            _ => {
+                let line = self.text.line(from.line);
+                let mut col = from.col;
+                loop {
+                    if let Some(found) = self.run(0, Pos::new(from.line, col), None, &empty) {
+                        break found;
+                    }
+                    col += line[col..].chars().next()?.len_utf8();
+                }
+            }
+        };
+        let origin = slots[0]?;
+        let start = slots[ZS].unwrap_or(origin);
+        let end = slots[ZE].or(slots[1])?.max(start);
+        let mut groups = [None; 9];
+        for (n, group) in groups.iter_mut().enumerate() {
+            *group = slots[2 * n + 2].zip(slots[2 * n + 3]);
+        }
+        Some(Match {
+            start,
+            end,
+            origin,
+            groups,
+        })
+    }
+
+    /// The character at `pos` and the position after it. The character is `None` at the end
+    /// of a line that is followed by another one.
+    fn next(&self, pos: Pos) -> Option<(Option<char>, Pos)> {
+        match self.text.line(pos.line)[pos.col..].chars().next() {
+            Some(c) => Some((Some(c), Pos::new(pos.line, pos.col + c.len_utf8()))),
+            None if pos.line + 1 < self.text.line_count() => {
+                Some((None, Pos::new(pos.line + 1, 0)))
+            }
+            None => None,
+        }
+    }
+
+    fn is_word(&self, c: Option<char>) -> bool {
+        c.is_some_and(|c| self.re.sets[SetKind::Keyword as usize].contains(c))
+    }
+
+    fn vcol(&self, pos: Pos) -> usize {
+        if pos.line >= self.text.line_count() {
+            return 0;
+        }
+        let line = self.text.line(pos.line);
+        let ts = self.ctx.tabstop.max(1);
+        line[..pos.col.min(line.len())]
+            .chars()
+            .fold(0, |v, c| match c {
+                '\t' => v + ts - v % ts,
+                _ => v + 1,
+            })
+    }
+
+    fn assert(&self, a: Assert, pos: Pos) -> bool {
+        let line = self.text.line(pos.line);
+        let ctx = self.ctx;
+        let cursor = |f: &dyn Fn(Pos) -> usize| ctx.cursor.map(f);
+        match a {
+            Assert::Bol => pos.col == 0,
+            Assert::Eol => pos.col == line.len(),
+            Assert::BufStart => pos == Pos::default(),
+            Assert::BufEnd => pos.line + 1 >= self.text.line_count() && pos.col == line.len(),
+            Assert::WordStart => {
+                self.is_word(line[pos.col..].chars().next())
+                    && !self.is_word(line[..pos.col].chars().next_back())
+            }
+            Assert::WordEnd => {
+                !self.is_word(line[pos.col..].chars().next())
+                    && self.is_word(line[..pos.col].chars().next_back())
+            }
+            Assert::Line(cmp, n) => n
+                .or_else(|| cursor(&|c| ctx.first_line + c.line + 1))
+                .is_some_and(|n| cmp.test(ctx.first_line + pos.line + 1, n)),
+            Assert::Col(cmp, n) => n
+                .or_else(|| cursor(&|c| c.col + 1))
+                .is_some_and(|n| cmp.test(pos.col + 1, n)),
+            Assert::VCol(cmp, n) => n
+                .or_else(|| cursor(&|c| self.vcol(c) + 1))
+                .is_some_and(|n| cmp.test(self.vcol(pos) + 1, n)),
+            Assert::Visual => ctx.visual.is_some_and(|v| v.contains(pos)),
+            Assert::Cursor => ctx.cursor == Some(pos),
+        }
+    }
+
+    fn has(&self, item: &Item, c: char) -> bool {
+        let named = match item {
+            Item::Range(lo, hi) => return (*lo..=*hi).contains(&c),
+            Item::Named(named) => named,
+        };
+        match named {
+            Named::Space => c == ' ' || c == '\t',
+            Named::Digit => c.is_ascii_digit(),
+            Named::Hex => c.is_ascii_hexdigit(),
+            Named::Octal => ('0'..='7').contains(&c),
+            Named::Word => c.is_ascii_alphanumeric() || c == '_',
+            Named::Head => c.is_ascii_alphabetic() || c == '_',
+            Named::Alpha => c.is_ascii_alphabetic(),
+            Named::Lower => c.is_ascii_lowercase(),
+            Named::Upper => c.is_ascii_uppercase(),
+            Named::Alnum => c.is_ascii_alphanumeric(),
+            Named::Cntrl => c.is_control(),
+            Named::Graph => !c.is_control() && !c.is_whitespace(),
+            Named::Punct => c.is_ascii_punctuation(),
+            // These match all letters when ignoring case
+            Named::PosixLower => c.is_lowercase() || self.re.icase && c.is_uppercase(),
+            Named::PosixUpper => c.is_uppercase() || self.re.icase && c.is_lowercase(),
+            Named::PosixSpace => matches!(c, ' ' | '\t' | '\n' | '\x0b' | '\x0c' | '\r'),
+            Named::Return => c == '\r',
+            Named::Tab => c == '\t',
+            Named::Escape => c == '\x1b',
+            Named::Backspace => c == '\x08',
+            Named::Set(kind, no_digit) => {
+                !(*no_digit && c.is_ascii_digit()) && self.re.sets[*kind as usize].contains(c)
+            }
+        }
+    }
+
+    fn class_has(&self, class: &Class, c: char) -> bool {
+        let hit = class.items.iter().any(|item| {
+            self.has(item, c)
+                || self.re.icase
+                    && matches!(item, Item::Range(..))
+                    && (c.to_lowercase().any(|c| self.has(item, c))
+                        || c.to_uppercase().any(|c| self.has(item, c)))
+        });
+        hit != class.negate
+    }
+
+    /// Whether an instruction that consumes a character matches `c`, where `None` is the end of
+    /// a line
+    fn consumes(&self, inst: &Inst, c: Option<char>) -> bool {
+        let newline = matches!(c, None | Some('\n'));
+        match inst {
+            Inst::Char(p) => c.is_some_and(|c| c == *p || self.re.icase && fold(c) == *p),
+            Inst::Any(nl) => *nl || !newline,
+            Inst::Class(class, nl) => match c {
+                Some(c) if !newline => self.class_has(class, c),
+                _ => *nl,
+            },
+            Inst::Newline => newline,
+            _ => false,
+        }
+    }
+
+    fn backref(&self, n: usize, pos: Pos, slots: &[Option<Pos>]) -> Option<Pos> {
+        let (mut from, to) = match (slots[2 * n], slots[2 * n + 1]) {
+            (Some(from), Some(to)) if from <= to => (from, to),
+            // A group that didn't match matches nothing
+            _ => return Some(pos),
+        };
+        let mut pos = pos;
+        while from < to {
+            let (a, next_from) = self.next(from)?;
+            let (b, next_pos) = self.next(pos)?;
+            let same = match (a, b) {
+                (Some(a), Some(b)) => a == b || self.re.icase && fold(a) == fold(b),
+                (a, b) => a == b,
+            };
+            if !same {
+                return None;
+            }
+            from = next_from;
+            pos = next_pos;
+        }
+        Some(pos)
+    }
+
+    /// Where a look-behind can start, nearest first. It can start in the previous line.
+    fn behind(&self, pos: Pos, limit: usize) -> Vec<Pos> {
+        let mut ret = vec![pos];
+        let mut at = pos;
+        let mut bytes = 0;
+        loop {
+            at = if at.col > 0 {
+                let c = self.text.line(at.line)[..at.col]
+                    .chars()
+                    .next_back()
+                    .unwrap();
+                bytes += c.len_utf8();
+                Pos::new(at.line, at.col - c.len_utf8())
+            } else if at.line == pos.line && at.line > 0 {
+                bytes += 1;
+                Pos::new(at.line - 1, self.text.line(at.line - 1).len())
+            } else {
+                break;
+            };
+            if limit > 0 && bytes > limit {
+                break;
+            }
+            ret.push(at);
+        }
+        ret
+    }
+
+    /// Runs a lookaround, and returns the slots to continue with when it holds
+    fn look(
+        &self,
+        look: Look,
+        limit: usize,
+        body: usize,
+        pos: Pos,
+        slots: &[Option<Pos>],
+    ) -> Option<Slots> {
+        let found = match look {
+            Look::Ahead | Look::NotAhead => self.run(body, pos, None, slots),
+            Look::Behind | Look::NotBehind => self
+                .behind(pos, limit)
+                .into_iter()
+                .find_map(|from| self.run(body, from, Some(pos), slots)),
+        };
+        match (look, found) {
+            (Look::Ahead | Look::Behind, Some((_, slots))) => Some(slots),
+            (Look::NotAhead | Look::NotBehind, None) => Some(slots.to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Matches the program starting at `pc` at `at`, which has to end at `end_at` when given
+    fn run(
+        &self,
+        pc: usize,
+        at: Pos,
+        end_at: Option<Pos>,
+        slots: &[Option<Pos>],
+    ) -> Option<(Pos, Slots)> {
+        match self.re.engine {
+            Engine::Nfa => self.pike(pc, at, true, end_at, slots),
+            _ => {
+                let mut slots = slots.to_vec();
+                let end = self.backtrack(pc, at, end_at, &mut slots)?;
+                Some((end, slots))
+            }
+        }
+    }
+
+    fn backtrack(
+        &self,
+        pc: usize,
+        at: Pos,
+        end_at: Option<Pos>,
+        slots: &mut [Option<Pos>],
+    ) -> Option<Pos> {
+        let prog = &self.re.prog;
+        let mut stack = vec![Job::Run(pc, at)];
+        while let Some(job) = stack.pop() {
+            let (mut pc, mut pos) = match job {
+                Job::Restore(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                }
+                Job::Run(pc, pos) => (pc, pos),
+            };
+            loop {
+                match &prog[pc] {
+                    Inst::Match => {
+                        if end_at.is_none_or(|end| end == pos) {
+                            return Some(pos);
+                        }
+                        break;
+                    }
+                    Inst::Save(slot) | Inst::Mark(slot) => {
+                        stack.push(Job::Restore(*slot, slots[*slot]));
+                        slots[*slot] = Some(pos);
+                    }
+                    Inst::Check(slot) => {
+                        if slots[*slot] == Some(pos) {
+                            break;
+                        }
+                    }
+                    Inst::Split(first, second) => {
+                        stack.push(Job::Run(*second, pos));
+                        pc = *first;
+                        continue;
+                    }
+                    Inst::Jmp(to) => {
+                        pc = *to;
+                        continue;
+                    }
+                    Inst::Assert(a) => {
+                        if !self.assert(*a, pos) {
+                            break;
+                        }
+                    }
+                    Inst::Backref(n) => match self.backref(*n, pos, slots) {
+                        Some(next) => pos = next,
+                        None => break,
+                    },
+                    Inst::Look(look, limit, body) => {
+                        match self.look(*look, *limit, *body, pos, slots) {
+                            Some(new) => Self::adopt(&mut stack, slots, &new),
+                            None => break,
+                        }
+                    }
+                    Inst::Atomic(body) => match self.run(*body, pos, None, slots) {
+                        Some((end, new)) => {
+                            Self::adopt(&mut stack, slots, &new);
+                            pos = end;
+                        }
+                        None => break,
+                    },
+                    inst => match self.next(pos) {
+                        Some((c, next)) if self.consumes(inst, c) => pos = next,
+                        _ => break,
+                    },
+                }
+                pc += 1;
+            }
+        }
+        None
+    }
+
+    /// Takes over the slots set by a lookaround or atomic group, so they are restored when
+    /// backtracking past it
+    fn adopt(stack: &mut Vec<Job>, slots: &mut [Option<Pos>], new: &[Option<Pos>]) {
+        for (n, (slot, new)) in slots.iter_mut().zip(new).enumerate() {
+            if slot != new {
+                stack.push(Job::Restore(n, *slot));
+                *slot = *new;
+            }
+        }
+    }
+
+    /// Adds the thread at `pc` and the threads it leads to without consuming a character, in
+    /// order of priority
+    fn add(
+        &self,
+        list: &mut Vec<(usize, Slots)>,
+        seen: &mut [usize],
+        gen: usize,
+        pc: usize,
+        pos: Pos,
+        slots: Slots,
+    ) {
+        let mut stack = vec![(pc, slots)];
+        while let Some((pc, mut slots)) = stack.pop() {
+            if seen[pc] == gen {
+                continue;
+            }
+            seen[pc] = gen;
+            match &self.re.prog[pc] {
+                Inst::Jmp(to) => stack.push((*to, slots)),
+                Inst::Split(first, second) => {
+                    stack.push((*second, slots.clone()));
+                    stack.push((*first, slots));
+                }
+                Inst::Save(slot) | Inst::Mark(slot) => {
+                    slots[*slot] = Some(pos);
+                    stack.push((pc + 1, slots));
+                }
+                Inst::Check(slot) => {
+                    if slots[*slot] != Some(pos) {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::Assert(a) => {
+                    if self.assert(*a, pos) {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::Look(look, limit, body) => {
+                    if let Some(slots) = self.look(*look, *limit, *body, pos, &slots) {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                _ => list.push((pc, slots)),
+            }
+        }
+    }
+
+    /// The Pike VM, which runs all threads in lockstep. Unless `anchored`, a new thread is
+    /// started at each position of the first line.
+    fn pike(
+        &self,
+        pc: usize,
+        start: Pos,
+        anchored: bool,
+        end_at: Option<Pos>,
+        init: &[Option<Pos>],
+    ) -> Option<(Pos, Slots)> {
+        let prog = &self.re.prog;
+        let mut seen = vec![0; prog.len()];
+        let mut gen = 1;
+        let mut threads = vec![];
+        self.add(&mut threads, &mut seen, gen, pc, start, init.to_vec());
+        let mut pos = start;
+        let mut matched = None;
+        loop {
+            let next = self.next(pos);
+            gen += 1;
+            let mut next_threads = vec![];
+            for (tpc, slots) in threads {
+                match (&prog[tpc], next) {
+                    (Inst::Match, _) => {
+                        if end_at.is_none_or(|end| end == pos) {
+                            // The threads after this one have a lower priority
+                            matched = Some((pos, slots));
+                            break;
+                        }
+                    }
+                    (inst, Some((c, to))) if self.consumes(inst, c) => {
+                        self.add(&mut next_threads, &mut seen, gen, tpc + 1, to, slots);
+                    }
+                    _ => (),
+                }
+            }
+            pos = match next {
+                Some((_, to)) if end_at.is_none_or(|end| to <= end) => to,
+                _ => break,
+            };
+            let seeding = matched.is_none() && !anchored && pos.line == start.line;
+            if seeding {
+                self.add(&mut next_threads, &mut seen, gen, pc, pos, init.to_vec());
+            }
+            if next_threads.is_empty() && !seeding {
+                break;
+            }
+            threads = next_threads;
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text matched with both engines, which have to agree
+    fn find(pat: &str, text: &str) -> Option<String> {
+        let found: Vec<_> = ["\\%#=1", "\\%#=2"]
+            .iter()
+            .map(|engine| {
+                let re = Regex::new(&format!("{engine}{pat}"), &Options::default()).unwrap();
+                re.find_str(text, 0).map(|m| text[m.range()].to_string())
+            })
+            .collect();
+        assert_eq!(found[0], found[1], "engines differ for {pat}");
+        found[0].clone()
+    }
+
+    fn s(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn magic() {
+        assert_eq!(find("a\\+b*", "xaaabbc"), s("aaabb"));
+        assert_eq!(find("\\v(a|b)+", "xabbac"), s("abba"));
+        assert_eq!(find("\\(a\\|b\\)\\{2,}", "xabbac"), s("abba"));
+        assert_eq!(find("\\Va*", "baa*"), s("a*"));
+        assert_eq!(find("\\Ma.", "abc a."), s("a."));
+        assert_eq!(find("a\\{2,3}", "aaaa"), s("aaa"));
+        assert_eq!(find("a\\{-1,}", "aaaa"), s("a"));
+        assert_eq!(find("\\<if\\>", "elif if"), s("if"));
+        assert_eq!(find("a$b", "a$b"), s("a$b"));
+        assert_eq!(find("^*", "*a"), s("*"));
+        assert_eq!(find("x*", "abc"), s(""));
+        assert_eq!(find("\\(a*\\)*b", "aab"), s("aab"));
+        assert_eq!(find("fu\\%[nction]", "func("), s("func"));
+        assert_eq!(find("FOO\\c", "a foo"), s("foo"));
+        assert_eq!(find("\\vfoo(bar)@=", "foo foobar"), s("foo"));
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(find("\\d\\+", "ab123c"), s("123"));
+        assert_eq!(find("[^\"\\\\]\\+", "\"ab\\"), s("ab"));
+        assert_eq!(find("[[:upper:]]\\+", "abCDe"), s("CD"));
+        assert_eq!(find("\\c[a-c]\\+", "xABCy"), s("ABC"));
+        assert_eq!(find("\\k\\+", "  foo_bar!"), s("foo_bar"));
+        assert_eq!(find("\\I\\i*", "9a1"), s("a1"));
+        assert_eq!(find("[\\x41-\\x43]\\+", "zABCD"), s("ABC"));
+        assert_eq!(find("[]a]\\+", "x]a]"), s("]a]"));
+        assert_eq!(find("[-a]\\+", "x-a"), s("-a"));
+        assert_eq!(find("[abc", "x[abc"), s("[abc"));
+    }
+
+    #[test]
+    fn groups() {
+        let re = Regex::new("foo\\zsbar\\zebaz", &Options::default()).unwrap();
+        let m = re.find_str("xfoobarbaz", 0).unwrap();
+        assert_eq!((m.origin.col, m.range()), (1, 4..7));
+        let re = Regex::new("\\(\\a\\+\\)-\\(\\d\\)", &Options::default()).unwrap();
+        let m = re.find_str("abc-1", 0).unwrap();
+        assert_eq!(m.group_text("abc-1", 1), s("abc"));
+        assert_eq!(m.group_text("abc-1", 2), s("1"));
+        assert_eq!(m.group(3), None);
+        assert_eq!(find("\\(x\\)\\zs\\1", "axx"), s("x"));
+        assert_eq!(find("\\(a\\|b\\)\\1", "abba"), s("bb"));
+        assert_eq!(find("a\\(b\\)\\@!", "abac"), s("a"));
+        assert_eq!(find("\\(foo\\)\\@<=bar", "xbar foobar"), s("bar"));
+        assert_eq!(find("\\(x\\)\\@<!bar", "xbar bar").map(|_| ()), Some(()));
+        assert_eq!(find(".*bar\\&foo", "foobar"), s("foo"));
+        assert_eq!(find("\\(a*\\)\\@>a", "aaa"), None);
+        assert_eq!(find("\\(a*\\)\\@>b", "aab"), s("aab"));
+        let opts = Options { substitute: "b.".into(), ..Options::default() };
+        let m = Regex::new("a~", &opts).unwrap().find_str("abc ab.", 0).unwrap();
+        assert_eq!(m.range(), 4..7);
+    }
+
+    #[test]
+    fn engines() {
+        let engine = |p: &str, e| {
+            Regex::new(
+                p,
+                &Options {
+                    engine: e,
+                    ..Options::default()
+                },
+            )
+            .unwrap()
+            .engine()
+        };
+        assert_eq!(engine("a*", Engine::Auto), Engine::Nfa);
+        assert_eq!(engine("\\(a\\)\\1", Engine::Auto), Engine::Backtrack);
+        assert_eq!(engine("\\(a\\)\\1", Engine::Nfa), Engine::Backtrack);
+        assert_eq!(engine("a*", Engine::Backtrack), Engine::Backtrack);
+        assert_eq!(engine("\\%#=1a*", Engine::Nfa), Engine::Backtrack);
+        let err = |p: &str| Regex::new(p, &Options::default()).unwrap_err();
+        assert_eq!(err("\\(a"), "E54: Unmatched \\(");
+        assert_eq!(err("a\\)"), "E55: Unmatched \\)");
+        assert_eq!(err("\\%(a"), "E53: Unmatched \\%(");
+        assert_eq!(err("a**"), "E61: Nested *");
+        assert_eq!(err("\\(a\\)\\2"), "E65: Illegal back reference");
+        assert_eq!(err("a\\{x}"), "E554: Syntax error in \\{...}");
+    }
+
+    #[test]
+    fn lines() {
+        let text = ["foo", "bar", "baz"];
+        let re = |p: &str| Regex::new(p, &Options::default()).unwrap();
+        let ctx = Context::default();
+        let found = |p: &str, from: Pos| {
+            re(p)
+                .find_from(&text[..], from, &ctx)
+                .map(|m| (m.start, m.end))
+        };
+        assert_eq!(
+            found("o\\nb", Pos::default()),
+            Some((Pos::new(0, 2), Pos::new(1, 1)))
+        );
+        assert_eq!(
+            found("r\\_.*z", Pos::default()),
+            Some((Pos::new(1, 2), Pos::new(2, 3)))
+        );
+        assert_eq!(
+            found("^b", Pos::new(0, 1)),
+            Some((Pos::new(1, 0), Pos::new(1, 1)))
+        );
+        assert_eq!(
+            found("\\%3l.", Pos::default()),
+            Some((Pos::new(2, 0), Pos::new(2, 1)))
+        );
+        assert_eq!(found("\\%>1l\\%2cz", Pos::default()), None);
+        assert_eq!(
+            found("\\%$", Pos::default()),
+            Some((Pos::new(2, 3), Pos::new(2, 3)))
+        );
+        assert_eq!(found("\\%^.", Pos::new(1, 0)), None);
+        assert_eq!(found("o\\n\\%#", Pos::default()), None);
+        let ctx = Context {
+            cursor: Some(Pos::new(1, 1)),
+            visual: Some(Visual {
+                start: Pos::new(1, 2),
+                end: Pos::new(2, 0),
+                mode: VisualMode::Char,
+            }),
+            ..Context::default()
+        };
+        let found = |p: &str| {
+            re(p)
+                .find_from(&text[..], Pos::default(), &ctx)
+                .map(|m| m.start)
+        };
+        assert_eq!(found("\\%#."), Some(Pos::new(1, 1)));
+        assert_eq!(found("\\%Va"), None);
+        assert_eq!(found("\\%Vb"), Some(Pos::new(2, 0)));
+        assert_eq!(found("\\%.l\\%>2v."), Some(Pos::new(1, 2)));
+        // A newline in a string is matched by \n
+        assert_eq!(find("a\\nb", "a\nb"), s("a\nb"));
+    }
+
+    #[test]
+    fn charset() {
+        let isf = CharSet::parse("@,48-57,/,.,-,_,+,,,#,$,%,~,=", false).unwrap();
+        assert!(isf.contains(',') && isf.contains('/') && isf.contains('Z'));
+        assert!(!isf.contains(' ') && !isf.contains(';'));
+        let set = CharSet::parse("@,^a,@-@,^", false).unwrap();
+        assert!(set.contains('b') && set.contains('@') && set.contains('^'));
+        assert!(!set.contains('a'));
+        assert!(CharSet::parse("300", false).is_err());
+        assert!(CharSet::parse("b-a", false).is_err());
+    }
+}
@@ -16,6 +16,7 @@ use crate::Tokenizer;
 use crate::VimError;
 use crate::VimScriptCtx;
 use crate::namespace::NameSpaced;
+use crate::regex::{self, Regex};
 use std::borrow::Cow;
 use std::collections::hash_map;
 use std::collections::linked_list;
@@ -259,6 +260,12 @@ impl Value {
         )))
     }
 
+    pub fn object<K: Into<String>, S: Into<Value>>(o: impl IntoIterator<Item = (K, S)>) -> Self {
+        Self::Object(Arc::new(Mutex::new(
+            o.into_iter().map(|(k, s)| (k.into(), s.into())).collect(),
+        )))
+    }
+
     pub const TRUE: Self = Value::Bool(true);
     pub const FALSE: Self = Value::Bool(false);
     pub const NULL: Self = Value::Integer(0);
@@ -508,6 +515,12 @@ impl Value {
         Ok(Self::Bool(self == rhs))
     }
 
+    /// `=~`, whether the pattern `rhs` matches this string
+    pub fn matches<S>(self, rhs: Self, opts: &regex::Options, ctx: &VimScriptCtx<S>) -> Result<Self, VimError> {
+        let re = Regex::new(&rhs.to_string(ctx), opts).map_err(VimError::Pattern)?;
+        Ok(Self::Bool(re.is_match(&self.to_string(ctx))))
+    }
+
     pub fn index<S>(&self, idx: &Self, ctx: &VimScriptCtx<S>) -> Result<Self, VimError> {
         Ok(match self {
             Self::List(l) => {
@@ -606,7 +619,7 @@ impl Value {
     fn contains<T>(&self, list: &Arc<T>) -> bool {
         match self {
             Self::Object(v) => {
-                if Arc::as_ptr(v).to_bits() == Arc::as_ptr(list).to_bits() {
+                if Arc::as_ptr(v).addr() == Arc::as_ptr(list).addr() {
                     true
                 } else {
                     for (_, inner) in v.lock().unwrap().iter() {
@@ -618,7 +631,7 @@ impl Value {
                 }
             }
             Self::List(v) => {
-                if Arc::as_ptr(v).to_bits() == Arc::as_ptr(list).to_bits() {
+                if Arc::as_ptr(v).addr() == Arc::as_ptr(list).addr() {
                     true
                 } else {
                     for inner in v.lock().unwrap().iter() {
@@ -678,14 +691,14 @@ impl Value {
         }
     }
 
-    pub fn starts_with<'a, P: Pattern<'a>>(&'a self, pat: P) -> bool {
+    pub fn starts_with<P: Pattern>(&self, pat: P) -> bool {
         match self {
             Self::Str(s) => s.starts_with(pat),
             _ => false,
         }
     }
 
-    pub fn contains_str<'a, P: Pattern<'a>>(&'a self, pat: P) -> bool {
+    pub fn contains_str<P: Pattern>(&self, pat: P) -> bool {
         match self {
             Self::Str(s) => s.strip_prefix(pat) == Some(""),
             _ => false,
@@ -740,8 +753,48 @@ impl Value {
         todo!("range")
     }
 
-    pub fn split<S>(&self, pattern: Self, stride: Self, ctx: &VimScriptCtx<S>) -> Result<Self, VimError> {
-        todo!("split")
+    pub fn split<S: State>(&self, pattern: Self, keepempty: Self, opts: &regex::Options, ctx: &VimScriptCtx<S>) -> Result<Self, VimError> {
+        let text = self.to_string(ctx);
+        let pattern = match pattern.to_string(ctx) {
+            p if p.is_empty() || pattern == Self::Nil => "\\_s\\+".to_string(),
+            p => p,
+        };
+        let keepempty = keepempty != Self::Nil && keepempty.to_bool(ctx)?;
+        // 'ignorecase' is not used, like in Vim
+        let opts = regex::Options {
+            ignorecase: false,
+            magic: true,
+            ..opts.clone()
+        };
+        let re = Regex::new(&pattern, &opts).map_err(VimError::Pattern)?;
+        let mut items = vec![];
+        let (mut start, mut col) = (0, 0);
+        while start < text.len() || keepempty {
+            let found = if start < text.len() {
+                re.find_str(&text[start..], col)
+            } else {
+                None
+            };
+            let end = found.as_ref().map_or(text.len(), |m| start + m.start.col);
+            // An empty item is kept before a match that isn't empty, but not at the start
+            let nonempty = found.as_ref().is_some_and(|m| !m.is_empty());
+            if keepempty || end > start || (!items.is_empty() && start < text.len() && nonempty) {
+                items.push(Self::Str(text[start..end].to_string()));
+            }
+            let m = match found {
+                Some(m) => m,
+                None => break,
+            };
+            let m_end = start + m.end.col;
+            // Don't get stuck at an empty match
+            col = if m_end > start {
+                0
+            } else {
+                text[m_end..].chars().next().map_or(1, char::len_utf8)
+            };
+            start = m_end;
+        }
+        Ok(Self::list(items))
     }
 
     pub fn unique<S>(&self, b: Self, c: Self, ctx: &VimScriptCtx<S>) -> Result<Self, VimError> {